@@ -1,4 +1,14 @@
 // For more comments about what's going on here, check out the `hello_world`
 // example.
 import('./pkg')
-  .catch(console.error);
\ No newline at end of file
+  .then(pkg => {
+    // Kept on `window` so the demo can be stopped from the console with `engine.stop()`.
+    window.engine = pkg.start(JSON.stringify({ canvasSelector: '#canvas' }));
+  })
+  .catch(error => {
+    console.error(error);
+
+    const fallback = document.getElementById('fallback');
+    fallback.textContent = `The demo could not start: ${error.message || error}`;
+    fallback.hidden = false;
+  });
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// Bumped whenever a message changes shape; the server turns away clients on another version.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Spawned effects stay in the world state for this many ticks, so clients that fall a little
+// behind still hear about them.
+pub const EFFECT_TICKS: u64 = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+    Encode(String),
+    Decode(String),
+    // A delta against a tick the receiver never had.
+    MissingBase { base: u64, tick: u64 },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Encode(error) => write!(f, "could not encode message: {}", error),
+            ProtocolError::Decode(error) => write!(f, "could not decode message: {}", error),
+            ProtocolError::MissingBase { base, tick } => write!(f, "delta is against tick {} but the state is at tick {}", base, tick),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Vec2 {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PlayerId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EntityId(pub u32);
+
+// What part of the world a player is looking at.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct View {
+    pub center: Vec2,
+    pub zoom: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Player {
+    pub name: String,
+    pub view: View,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    pub owner: PlayerId,
+    pub position: Vec2,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectSpawn {
+    pub tick: u64,
+    pub player: PlayerId,
+    pub name: String,
+    pub position: Vec2,
+}
+
+// What a player does; the server applies commands in the order they arrive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    // Moves the player's own entity.
+    Move { position: Vec2 },
+    SetView(View),
+    SpawnEffect { name: String, position: Vec2 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Hello { version: u32, name: String },
+    Input { sequence: u32, command: Command },
+    // The newest state the client has, for the server to send changes against.
+    Ack { tick: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Welcome { player: PlayerId, entity: EntityId, tick: u64 },
+    Rejected { reason: String },
+    State(Delta),
+}
+
+// Messages go over the wire as bincode.
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, ProtocolError> {
+    bincode::serialize(message).map_err(|error| ProtocolError::Encode(error.to_string()))
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ProtocolError> {
+    bincode::deserialize(bytes).map_err(|error| ProtocolError::Decode(error.to_string()))
+}
+
+// The shared part of the world at one server tick.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorldState {
+    pub tick: u64,
+    pub players: BTreeMap<PlayerId, Player>,
+    pub entities: BTreeMap<EntityId, Entity>,
+    // Spawned in the last `EFFECT_TICKS` ticks, oldest first.
+    pub effects: Vec<EffectSpawn>,
+}
+
+// How to get from the state at `base` to the state at `tick`; everything when there is no base.
+// Values are absolute, so a delta also applies to any state between its base and its tick, as long
+// as `left` and `removed` also name what appeared and went away in between. `WorldState::diff` only
+// sees the two ends; the server adds the rest from the states it published in between.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Delta {
+    pub base: Option<u64>,
+    pub tick: u64,
+    pub players: Vec<(PlayerId, Player)>,
+    pub left: Vec<PlayerId>,
+    pub entities: Vec<(EntityId, Entity)>,
+    pub removed: Vec<EntityId>,
+    pub effects: Vec<EffectSpawn>,
+}
+
+impl Delta {
+    // Whether nothing but the tick changed.
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty() && self.left.is_empty() && self.entities.is_empty() && self.removed.is_empty() && self.effects.is_empty()
+    }
+}
+
+impl WorldState {
+    // Moves on to `tick`, forgetting effects that have been around long enough.
+    pub fn advance(&mut self, tick: u64) {
+        self.tick = tick;
+        self.effects.retain(|effect| effect.tick + EFFECT_TICKS > tick);
+    }
+
+    pub fn diff(&self, base: Option<&WorldState>) -> Delta {
+        let Some(base) = base else {
+            return Delta {
+                base: None,
+                tick: self.tick,
+                players: self.players.iter().map(|(id, player)| (*id, player.clone())).collect(),
+                entities: self.entities.iter().map(|(id, entity)| (*id, *entity)).collect(),
+                effects: self.effects.clone(),
+                ..Delta::default()
+            };
+        };
+
+        Delta {
+            base: Some(base.tick),
+            tick: self.tick,
+            players: changed(&base.players, &self.players),
+            left: base.players.keys().filter(|id| !self.players.contains_key(id)).copied().collect(),
+            entities: changed(&base.entities, &self.entities),
+            removed: base.entities.keys().filter(|id| !self.entities.contains_key(id)).copied().collect(),
+            effects: self.effects.iter().filter(|effect| effect.tick > base.tick).cloned().collect(),
+        }
+    }
+
+    // Brings the state up to the delta's tick and returns the effects that are new to it. Deltas
+    // older than the state are ignored.
+    pub fn apply(&mut self, delta: Delta) -> Result<Vec<EffectSpawn>, ProtocolError> {
+        if delta.tick <= self.tick && delta.base.is_some() {
+            return Ok(vec![]);
+        }
+
+        match delta.base {
+            None => {
+                let seen = self.tick;
+                self.players = delta.players.into_iter().collect();
+                self.entities = delta.entities.into_iter().collect();
+                self.effects = delta.effects;
+                self.advance(delta.tick);
+                Ok(self.effects.iter().filter(|effect| effect.tick > seen).cloned().collect())
+            }
+            Some(base) if base > self.tick => Err(ProtocolError::MissingBase { base, tick: self.tick }),
+            Some(_) => {
+                self.players.extend(delta.players);
+                for id in delta.left {
+                    self.players.remove(&id);
+                }
+                self.entities.extend(delta.entities);
+                for id in delta.removed {
+                    self.entities.remove(&id);
+                }
+
+                let fresh: Vec<EffectSpawn> = delta.effects.into_iter().filter(|effect| effect.tick > self.tick).collect();
+                self.effects.extend(fresh.iter().cloned());
+                self.advance(delta.tick);
+                Ok(fresh)
+            }
+        }
+    }
+}
+
+// Entries that are new or different in `current`.
+fn changed<K: Ord + Copy, V: PartialEq + Clone>(base: &BTreeMap<K, V>, current: &BTreeMap<K, V>) -> Vec<(K, V)> {
+    current
+        .iter()
+        .filter(|(id, value)| base.get(id) != Some(value))
+        .map(|(id, value)| (*id, value.clone()))
+        .collect()
+}
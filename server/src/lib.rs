@@ -0,0 +1,305 @@
+// Connection errors are tungstenite's own and only ever travel a frame or two up the stack.
+#![allow(clippy::result_large_err)]
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use protocol::{
+    decode, encode, ClientMessage, Command, Delta, EffectSpawn, Entity, EntityId, Player, PlayerId, ServerMessage, Vec2, View,
+    WorldState, PROTOCOL_VERSION,
+};
+use tungstenite::error::ProtocolError;
+use tungstenite::{Error, Message, WebSocket};
+
+// Published states kept to send changes against; clients further behind get the whole state.
+pub const HISTORY_TICKS: usize = 64;
+
+// How long connection threads wait for a message before checking for a new tick to send.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+const MAX_NAME_LENGTH: usize = 32;
+
+// How long a new connection gets to finish the WebSocket handshake and say hello.
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The shared scene. Commands change the current state straight away; every tick publishes it for
+// the connections to send out.
+#[derive(Debug, Default)]
+pub struct World {
+    state: WorldState,
+    // Oldest first; the last one is the newest published state.
+    history: VecDeque<WorldState>,
+    next_id: u32,
+}
+
+impl World {
+    // Adds a player with an entity of their own in the middle of the scene.
+    pub fn join(&mut self, name: &str) -> (PlayerId, EntityId) {
+        self.next_id += 1;
+        let (player, entity) = (PlayerId(self.next_id), EntityId(self.next_id));
+
+        let name: String = name.trim().chars().take(MAX_NAME_LENGTH).collect();
+        let name = if name.is_empty() { format!("Player {}", player.0) } else { name };
+        let origin = Vec2 { x: 0., y: 0. };
+        self.state.players.insert(player, Player { name, view: View { center: origin, zoom: 1. } });
+        self.state.entities.insert(entity, Entity { owner: player, position: origin });
+        (player, entity)
+    }
+
+    pub fn leave(&mut self, player: PlayerId) {
+        self.state.players.remove(&player);
+        self.state.entities.retain(|_, entity| entity.owner != player);
+    }
+
+    pub fn command(&mut self, player: PlayerId, command: Command) {
+        if !self.state.players.contains_key(&player) {
+            return;
+        }
+
+        match command {
+            Command::Move { position } => {
+                for entity in self.state.entities.values_mut().filter(|entity| entity.owner == player) {
+                    entity.position = position;
+                }
+            }
+            Command::SetView(view) => {
+                if let Some(joined) = self.state.players.get_mut(&player) {
+                    joined.view = view;
+                }
+            }
+            Command::SpawnEffect { name, position } => {
+                // Goes out with the next tick.
+                let tick = self.state.tick + 1;
+                self.state.effects.push(EffectSpawn { tick, player, name, position });
+            }
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.state.advance(self.state.tick + 1);
+        self.history.push_back(self.state.clone());
+        if self.history.len() > HISTORY_TICKS {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn published(&self) -> Option<&WorldState> {
+        self.history.back()
+    }
+
+    // The state published at `tick`, while it is still in the history.
+    pub fn published_at(&self, tick: u64) -> Option<&WorldState> {
+        self.history.iter().find(|state| state.tick == tick)
+    }
+
+    // What a client that has the state at `acked` needs to catch up with the newest published state.
+    // The client may already have states after `acked`, so anything that came and went since then is
+    // listed as gone too.
+    pub fn delta_for(&self, acked: Option<u64>) -> Option<Delta> {
+        let latest = self.published()?;
+        let Some(base) = acked.and_then(|tick| self.published_at(tick)) else {
+            return Some(latest.diff(None));
+        };
+
+        let mut delta = latest.diff(Some(base));
+        for state in self.history.iter().filter(|state| state.tick > base.tick && state.tick < latest.tick) {
+            for id in state.players.keys().filter(|id| !latest.players.contains_key(id)) {
+                if !delta.left.contains(id) {
+                    delta.left.push(*id);
+                }
+            }
+            for id in state.entities.keys().filter(|id| !latest.entities.contains_key(id)) {
+                if !delta.removed.contains(id) {
+                    delta.removed.push(*id);
+                }
+            }
+        }
+        Some(delta)
+    }
+}
+
+// A WebSocket server for any number of players sharing one `World`.
+pub struct Server {
+    listener: TcpListener,
+    tick_interval: Duration,
+    hello_timeout: Duration,
+    world: Arc<Mutex<World>>,
+    stopped: Arc<AtomicBool>,
+}
+
+// Lets the code that started a server look at its world and shut it down.
+#[derive(Clone)]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    world: Arc<Mutex<World>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl ServerHandle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn world(&self) -> MutexGuard<'_, World> {
+        lock(&self.world)
+    }
+
+    // Stops ticking, accepting and serving; connections close within a poll interval.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, tick_interval: Duration) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            tick_interval,
+            hello_timeout: HELLO_TIMEOUT,
+            world: Arc::new(Mutex::new(World::default())),
+            stopped: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn with_hello_timeout(mut self, timeout: Duration) -> Self {
+        self.hello_timeout = timeout;
+        self
+    }
+
+    pub fn handle(&self) -> io::Result<ServerHandle> {
+        Ok(ServerHandle { addr: self.listener.local_addr()?, world: self.world.clone(), stopped: self.stopped.clone() })
+    }
+
+    // Runs the server on threads of its own.
+    pub fn spawn(self) -> io::Result<ServerHandle> {
+        let handle = self.handle()?;
+        thread::spawn(move || self.run());
+        Ok(handle)
+    }
+
+    // Ticks and accepts players until stopped.
+    pub fn run(self) {
+        let (world, stopped, interval) = (self.world.clone(), self.stopped.clone(), self.tick_interval);
+        thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                thread::sleep(interval);
+                lock(&world).tick();
+            }
+        });
+
+        while !self.stopped.load(Ordering::SeqCst) {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let (world, stopped, timeout) = (self.world.clone(), self.stopped.clone(), self.hello_timeout);
+                    thread::spawn(move || {
+                        if let Err(error) = serve(stream, &world, &stopped, timeout) {
+                            eprintln!("connection failed: {}", error);
+                        }
+                    });
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(error) => eprintln!("could not accept a connection: {}", error),
+            }
+        }
+    }
+}
+
+// A poisoned lock only means a connection thread panicked; the world itself is still usable.
+fn lock(world: &Mutex<World>) -> MutexGuard<'_, World> {
+    world.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// The next message from the client, or `None` if none came within the poll interval.
+fn receive(socket: &mut WebSocket<TcpStream>) -> Result<Option<ClientMessage>, Error> {
+    match socket.read() {
+        Ok(Message::Binary(bytes)) => Ok(decode(&bytes).ok()),
+        Ok(_) => Ok(None),
+        Err(Error::Io(error)) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: &ServerMessage) -> Result<(), Error> {
+    let bytes = encode(message).map_err(|error| Error::Io(io::Error::new(ErrorKind::InvalidData, error)))?;
+    socket.send(Message::Binary(bytes))
+}
+
+// One player's connection: the handshake, then their commands in and the world's changes out.
+// Connections that don't get as far as a hello within `hello_timeout` are dropped.
+fn serve(stream: TcpStream, world: &Mutex<World>, stopped: &AtomicBool, hello_timeout: Duration) -> Result<(), Error> {
+    let deadline = Instant::now() + hello_timeout;
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(hello_timeout))?;
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        // The handshake didn't finish within the timeout.
+        Err(tungstenite::HandshakeError::Interrupted(_)) => return Ok(()),
+        Err(tungstenite::HandshakeError::Failure(error)) => return Err(error),
+    };
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let name = loop {
+        if stopped.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return socket.close(None).or_else(ignore_closed);
+        }
+        match receive(&mut socket)? {
+            Some(ClientMessage::Hello { version, name }) if version == PROTOCOL_VERSION => break name,
+            Some(ClientMessage::Hello { version, .. }) => {
+                let reason = format!("protocol version {} is not supported, this server speaks {}", version, PROTOCOL_VERSION);
+                send(&mut socket, &ServerMessage::Rejected { reason })?;
+                return socket.close(None).or_else(ignore_closed);
+            }
+            _ => {}
+        }
+    };
+
+    let (player, entity) = lock(world).join(&name);
+    let tick = lock(world).published().map_or(0, |state| state.tick);
+    let result = send(&mut socket, &ServerMessage::Welcome { player, entity, tick }).and_then(|_| play(&mut socket, world, stopped, player));
+    lock(world).leave(player);
+    result.or_else(ignore_closed)
+}
+
+fn play(socket: &mut WebSocket<TcpStream>, world: &Mutex<World>, stopped: &AtomicBool, player: PlayerId) -> Result<(), Error> {
+    let mut acked = None;
+    let mut sent = 0;
+
+    while !stopped.load(Ordering::SeqCst) {
+        match receive(socket)? {
+            Some(ClientMessage::Input { command, .. }) => lock(world).command(player, command),
+            Some(ClientMessage::Ack { tick }) => acked = acked.max(Some(tick)),
+            Some(ClientMessage::Hello { .. }) | None => {}
+        }
+
+        let delta = {
+            let world = lock(world);
+            match world.published() {
+                Some(latest) if latest.tick > sent => world.delta_for(acked),
+                _ => None,
+            }
+        };
+        if let Some(delta) = delta {
+            sent = delta.tick;
+            send(socket, &ServerMessage::State(delta))?;
+        }
+    }
+    socket.close(None)
+}
+
+// Players going away, politely or by closing the page, isn't a failure.
+fn ignore_closed(error: Error) -> Result<(), Error> {
+    match error {
+        Error::ConnectionClosed | Error::AlreadyClosed | Error::Protocol(ProtocolError::ResetWithoutClosingHandshake) => Ok(()),
+        error => Err(error),
+    }
+}
@@ -0,0 +1,39 @@
+use std::process;
+use std::time::Duration;
+use server::Server;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9001";
+const DEFAULT_TICK_MS: u64 = 50;
+
+// Usage: server [address] [--tick-ms milliseconds]
+fn main() {
+    let mut address = DEFAULT_ADDRESS.to_string();
+    let mut tick_ms = DEFAULT_TICK_MS;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tick-ms" => match args.next().and_then(|value| value.parse().ok()).filter(|&ms| ms > 0) {
+                Some(ms) => tick_ms = ms,
+                None => fail("--tick-ms takes a number of milliseconds above 0"),
+            },
+            "-h" | "--help" => {
+                println!("usage: server [address] [--tick-ms milliseconds]");
+                return;
+            }
+            _ => address = arg,
+        }
+    }
+
+    let server = Server::bind(&address, Duration::from_millis(tick_ms)).unwrap_or_else(|error| fail(&format!("could not listen on {}: {}", address, error)));
+    match server.handle() {
+        Ok(handle) => println!("listening on ws://{}, ticking every {}ms", handle.addr(), tick_ms),
+        Err(error) => fail(&error.to_string()),
+    }
+    server.run();
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
@@ -0,0 +1,291 @@
+use std::io::Read;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use protocol::{
+    decode, encode, ClientMessage, Command, Delta, EffectSpawn, Entity, EntityId, Player, PlayerId, ServerMessage, Vec2, View,
+    WorldState, PROTOCOL_VERSION,
+};
+use server::{Server, ServerHandle};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+const TICK: Duration = Duration::from_millis(5);
+const PATIENCE: Duration = Duration::from_secs(5);
+
+fn start() -> ServerHandle {
+    Server::bind("127.0.0.1:0", TICK).and_then(Server::spawn).expect("server starts")
+}
+
+// A player as the engine would be one: mirrors the world from the deltas and acknowledges each.
+struct Client {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    player: PlayerId,
+    entity: EntityId,
+    state: WorldState,
+    deltas: Vec<Delta>,
+    effects: Vec<EffectSpawn>,
+    sequence: u32,
+}
+
+impl Client {
+    fn connect(server: &ServerHandle, name: &str) -> Self {
+        let mut socket = open(server);
+        socket.send(Message::Binary(encode(&ClientMessage::Hello { version: PROTOCOL_VERSION, name: name.to_string() }).unwrap())).unwrap();
+
+        match read(&mut socket) {
+            Some(ServerMessage::Welcome { player, entity, tick }) => {
+                let mut state = WorldState::default();
+                state.advance(tick);
+                Self { socket, player, entity, state, deltas: vec![], effects: vec![], sequence: 0 }
+            }
+            other => panic!("expected a welcome, got {:?}", other),
+        }
+    }
+
+    fn send(&mut self, command: Command) {
+        self.sequence += 1;
+        let message = ClientMessage::Input { sequence: self.sequence, command };
+        self.socket.send(Message::Binary(encode(&message).unwrap())).unwrap();
+    }
+
+    // Applies deltas as they come until `done` holds for the mirrored state.
+    fn wait_for(&mut self, what: &str, done: impl Fn(&Client) -> bool) {
+        let deadline = Instant::now() + PATIENCE;
+        while !done(self) {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            match read(&mut self.socket) {
+                Some(ServerMessage::State(delta)) => {
+                    self.deltas.push(delta.clone());
+                    let effects = self.state.apply(delta).expect("delta applies");
+                    self.effects.extend(effects);
+                    let ack = ClientMessage::Ack { tick: self.state.tick };
+                    self.socket.send(Message::Binary(encode(&ack).unwrap())).unwrap();
+                }
+                other => panic!("expected state, got {:?}", other),
+            }
+        }
+    }
+
+    fn entity_of(&self, player: PlayerId) -> Option<&Entity> {
+        self.state.entities.values().find(|entity| entity.owner == player)
+    }
+}
+
+fn open(server: &ServerHandle) -> WebSocket<MaybeTlsStream<TcpStream>> {
+    let (socket, _) = tungstenite::connect(format!("ws://{}", server.addr())).expect("connects over loopback");
+    if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+        stream.set_read_timeout(Some(PATIENCE)).unwrap();
+    }
+    socket
+}
+
+// The next message from the server; `None` once it has closed the connection.
+fn read(socket: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> Option<ServerMessage> {
+    loop {
+        match socket.read() {
+            Ok(Message::Binary(bytes)) => return Some(decode(&bytes).expect("server messages decode")),
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+fn point(x: f64, y: f64) -> Vec2 {
+    Vec2 { x, y }
+}
+
+#[test]
+fn first_state_is_the_whole_world() {
+    let server = start();
+    let mut alice = Client::connect(&server, "alice");
+    alice.wait_for("the first state", |client| !client.deltas.is_empty());
+
+    let first = &alice.deltas[0];
+    assert_eq!(first.base, None);
+    assert_eq!(alice.state.players.get(&alice.player).map(|player| player.name.as_str()), Some("alice"));
+    assert_eq!(alice.state.entities.get(&alice.entity).map(|entity| entity.owner), Some(alice.player));
+    server.stop();
+}
+
+#[test]
+fn moves_views_and_effects_reach_other_players() {
+    let server = start();
+    let mut alice = Client::connect(&server, "alice");
+    let mut bob = Client::connect(&server, "bob");
+    let alice_id = alice.player;
+    bob.wait_for("alice to show up", |client| client.entity_of(alice_id).is_some());
+
+    let view = View { center: point(40., -25.), zoom: 1.5 };
+    alice.send(Command::Move { position: point(120., 80.) });
+    alice.send(Command::SetView(view));
+    alice.send(Command::SpawnEffect { name: "fireworks".to_string(), position: point(120., 80.) });
+
+    bob.wait_for("alice's changes", |client| {
+        client.entity_of(alice_id).map(|entity| entity.position) == Some(point(120., 80.))
+            && client.state.players.get(&alice_id).map(|player| player.view) == Some(view)
+            && !client.effects.is_empty()
+    });
+    assert_eq!(bob.effects.len(), 1);
+    assert_eq!(bob.effects[0].name, "fireworks");
+    assert_eq!(bob.effects[0].player, alice_id);
+
+    // Later deltas, even ones against states from before the effect, don't report it again.
+    let tick = bob.state.tick;
+    bob.wait_for("a few more ticks", |client| client.state.tick >= tick + 5);
+    assert_eq!(bob.effects.len(), 1);
+
+    // The mirror is exactly what the server published at the same tick.
+    let published = server.world().published_at(bob.state.tick).cloned();
+    assert_eq!(Some(&bob.state), published.as_ref());
+    server.stop();
+}
+
+#[test]
+fn deltas_only_carry_what_changed() {
+    let server = start();
+    let mut alice = Client::connect(&server, "alice");
+    let mut bob = Client::connect(&server, "bob");
+    let bob_id = bob.player;
+    alice.wait_for("bob to show up", |client| client.entity_of(bob_id).is_some());
+
+    // With every state acknowledged and nobody doing anything, there is nothing to send but ticks.
+    let seen = alice.deltas.len();
+    alice.wait_for("quiet ticks", |client| client.deltas.len() >= seen + 10);
+    let quiet: Vec<&Delta> = alice.deltas[seen + 5..].iter().collect();
+    assert!(quiet.iter().all(|delta| delta.base.is_some() && delta.is_empty()), "{:?}", quiet);
+
+    bob.send(Command::Move { position: point(-64., 32.) });
+    alice.wait_for("bob's move", |client| client.entity_of(bob_id).map(|entity| entity.position) == Some(point(-64., 32.)));
+    let delta = alice.deltas.iter().rev().find(|delta| !delta.is_empty()).expect("a delta with the move");
+    assert_eq!(delta.entities, vec![(bob.entity, Entity { owner: bob_id, position: point(-64., 32.) })]);
+    assert!(delta.players.is_empty() && delta.removed.is_empty() && delta.effects.is_empty());
+    server.stop();
+}
+
+#[test]
+fn leaving_removes_the_player_and_their_entity() {
+    let server = start();
+    let mut alice = Client::connect(&server, "alice");
+    let bob = Client::connect(&server, "bob");
+    let (bob_id, bob_entity) = (bob.player, bob.entity);
+    alice.wait_for("bob to show up", |client| client.entity_of(bob_id).is_some());
+
+    drop(bob);
+    alice.wait_for("bob to leave", |client| !client.state.players.contains_key(&bob_id));
+    assert!(!alice.state.entities.contains_key(&bob_entity));
+    assert!(alice.deltas.iter().any(|delta| delta.left == vec![bob_id] && delta.removed == vec![bob_entity]));
+    server.stop();
+}
+
+#[test]
+fn other_protocol_versions_are_turned_away() {
+    let server = start();
+    let mut socket = open(&server);
+    socket.send(Message::Binary(encode(&ClientMessage::Hello { version: PROTOCOL_VERSION + 1, name: "time traveller".to_string() }).unwrap())).unwrap();
+
+    match read(&mut socket) {
+        Some(ServerMessage::Rejected { reason }) => assert!(reason.contains("not supported"), "{}", reason),
+        other => panic!("expected a rejection, got {:?}", other),
+    }
+    assert_eq!(read(&mut socket), None);
+    assert!(server.world().published().is_none_or(|state| state.players.is_empty()));
+    server.stop();
+}
+
+#[test]
+fn connections_that_never_say_hello_are_dropped() {
+    let server = Server::bind("127.0.0.1:0", TICK)
+        .map(|server| server.with_hello_timeout(Duration::from_millis(50)))
+        .and_then(Server::spawn)
+        .expect("server starts");
+
+    // Neither a WebSocket that stays quiet nor a connection that never even shakes hands is kept.
+    let mut quiet = open(&server);
+    let mut silent = TcpStream::connect(server.addr()).unwrap();
+    silent.set_read_timeout(Some(PATIENCE)).unwrap();
+
+    let started = Instant::now();
+    assert_eq!(read(&mut quiet), None);
+    assert!(matches!(silent.read(&mut [0; 16]), Ok(0) | Err(_)));
+    assert!(started.elapsed() < PATIENCE);
+    assert!(server.world().published().is_none_or(|state| state.players.is_empty()));
+    server.stop();
+}
+
+#[test]
+fn messages_survive_the_wire_unchanged() {
+    let player = PlayerId(7);
+    let delta = Delta {
+        base: Some(3),
+        tick: 9,
+        players: vec![(player, Player { name: "ünïcødé".to_string(), view: View { center: point(-0.1, 1e9), zoom: 0.25 } })],
+        left: vec![PlayerId(2)],
+        entities: vec![(EntityId(7), Entity { owner: player, position: point(f64::MIN_POSITIVE, -3.5) })],
+        removed: vec![EntityId(2)],
+        effects: vec![EffectSpawn { tick: 8, player, name: "smoke".to_string(), position: point(1., 2.) }],
+    };
+
+    for message in [ServerMessage::State(delta), ServerMessage::Welcome { player, entity: EntityId(7), tick: 9 }] {
+        assert_eq!(decode::<ServerMessage>(&encode(&message).unwrap()), Ok(message));
+    }
+    assert!(decode::<ServerMessage>(&[0xff, 0xff, 0xff]).is_err());
+}
+
+#[test]
+fn deltas_apply_to_any_state_since_their_base() {
+    let mut world = server::World::default();
+    let (alice, _) = world.join("alice");
+    let (bob, _) = world.join("bob");
+    world.tick();
+    let base = world.published().cloned().unwrap();
+
+    world.command(alice, Command::Move { position: point(1., 1.) });
+    world.command(bob, Command::SpawnEffect { name: "dust".to_string(), position: point(0., 0.) });
+    world.tick();
+    let middle = world.published().cloned().unwrap();
+
+    world.command(alice, Command::Move { position: point(2., 2.) });
+    world.leave(bob);
+    world.tick();
+    let latest = world.published().cloned().unwrap();
+
+    // A client that already has the middle state gets a delta against the base, as it hasn't
+    // acknowledged the middle one yet.
+    let mut client = base.clone();
+    assert_eq!(client.apply(middle.diff(Some(&base))).unwrap().len(), 1);
+    assert_eq!(client, middle);
+    assert_eq!(client.apply(world.delta_for(Some(base.tick)).unwrap()), Ok(vec![]));
+    assert_eq!(client, latest);
+
+    // Deltas against a state the client never had are refused; a full state always applies.
+    let mut behind = WorldState::default();
+    assert!(behind.apply(latest.diff(Some(&middle))).is_err());
+    behind.apply(world.delta_for(None).unwrap()).unwrap();
+    assert_eq!(behind, latest);
+}
+
+#[test]
+fn deltas_remove_what_came_and_went_since_their_base() {
+    let mut world = server::World::default();
+    world.join("alice");
+    while world.published().map_or(0, |state| state.tick) < 10 {
+        world.tick();
+    }
+
+    // The client is at tick 12 but has only acknowledged tick 10 when bob joins at 11 and leaves at 13.
+    let (bob, bob_entity) = world.join("bob");
+    world.tick();
+    world.tick();
+    let mut client = world.published_at(12).cloned().unwrap();
+    assert!(client.players.contains_key(&bob) && client.entities.contains_key(&bob_entity));
+
+    world.leave(bob);
+    world.tick();
+    world.tick();
+    let delta = world.delta_for(Some(10)).unwrap();
+    assert_eq!((delta.base, delta.tick), (Some(10), 14));
+    assert_eq!((delta.left.clone(), delta.removed.clone()), (vec![bob], vec![bob_entity]));
+
+    client.apply(delta).unwrap();
+    assert_eq!(Some(&client), world.published());
+}
@@ -0,0 +1,513 @@
+use std::ops::Add;
+use rgb::RGB;
+use serde::{Deserialize, Serialize};
+
+use crate::Draw;
+use crate::game_engine::View;
+use crate::ray::Ray;
+use crate::renderer::Renderer;
+use crate::rng::Rng;
+use crate::shapes::{Line, Point2d};
+
+const ZERO: Point2d = Point2d { x: 0., y: 0. };
+
+// The side feelers point this far off the heading, in radians, and reach this fraction as far.
+const FEELER_ANGLE: f64 = 0.5;
+const SIDE_FEELER: f64 = 0.6;
+
+// How quickly, per second, behaviours that want a certain velocity close the gap to it.
+const RESPONSE: f64 = 8.;
+
+// How far ahead of the agent the wander circle sits, and its radius, in agent radii.
+const WANDER_DISTANCE: f64 = 4.;
+const WANDER_RADIUS: f64 = 2.;
+
+// Something that moves by steering: every behaviour returns a force, in world units per second
+// squared, and `steer` applies their sum within the agent's limits. Speeds are in world units per
+// second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Agent {
+    pub position: Point2d,
+    pub velocity: Point2d,
+    pub max_speed: f64,
+    pub max_force: f64,
+    pub radius: f64,
+    pub color: RGB<u8>,
+    // Where on the wander circle the agent is heading for.
+    wander_angle: f64,
+}
+
+#[allow(dead_code)]
+impl Agent {
+    pub fn new(position: Point2d, color: RGB<u8>) -> Self {
+        Self { position, velocity: ZERO, max_speed: 120., max_force: 240., radius: 6., color, wander_angle: 0. }
+    }
+
+    pub fn with_velocity(mut self, velocity: Point2d) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn with_limits(mut self, max_speed: f64, max_force: f64) -> Self {
+        self.max_speed = max_speed;
+        self.max_force = max_force;
+        self
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    // The direction the agent is moving in, or facing right while it stands still.
+    pub fn heading(&self) -> Point2d {
+        self.velocity.unit().unwrap_or(Point2d { x: 1., y: 0. })
+    }
+
+    // The force that turns the agent's velocity into `desired`.
+    fn towards(&self, desired: Point2d) -> Point2d {
+        (desired - self.velocity) * RESPONSE
+    }
+
+    // Straight for `target` at full speed.
+    pub fn seek(&self, target: Point2d) -> Point2d {
+        self.towards((target - self.position).unit().unwrap_or(ZERO) * self.max_speed)
+    }
+
+    // Straight away from `target` while it is within `panic_distance`.
+    pub fn flee(&self, target: Point2d, panic_distance: f64) -> Point2d {
+        if self.position.distance(target) > panic_distance {
+            return ZERO;
+        }
+        self.towards((self.position - target).unit().unwrap_or(self.heading()) * self.max_speed)
+    }
+
+    // Like seeking, but slowing down within `slowing_radius` to stop on the target.
+    pub fn arrive(&self, target: Point2d, slowing_radius: f64) -> Point2d {
+        let offset = target - self.position;
+        let distance = offset.length();
+        if distance == 0. {
+            return self.towards(ZERO);
+        }
+
+        let speed = self.max_speed * (distance / slowing_radius).min(1.);
+        self.towards(offset * (speed / distance))
+    }
+
+    // Aimless but smooth: heads for a point on a circle ahead that drifts by up to `jitter` radians
+    // each call.
+    pub fn wander(&mut self, jitter: f64, rng: &mut Rng) -> Point2d {
+        self.drift(jitter, rng);
+        self.wandering()
+    }
+
+    fn drift(&mut self, jitter: f64, rng: &mut Rng) {
+        self.wander_angle += rng.range(-jitter, jitter);
+    }
+
+    fn wandering(&self) -> Point2d {
+        let heading = self.heading();
+        let ahead = heading * (self.radius * WANDER_DISTANCE);
+        let offset = Point2d { x: self.wander_angle.cos(), y: self.wander_angle.sin() } * (self.radius * WANDER_RADIUS);
+        (ahead + offset).unit().unwrap_or(heading) * self.max_force
+    }
+
+    // Turns away from walls that feelers cast ahead and to either side run into, harder the closer
+    // the wall is. The feelers reach `feeler_length` at full speed and shrink as the agent slows.
+    pub fn avoid_walls(&self, walls: &[Line], feeler_length: f64) -> Point2d {
+        let reach = self.radius + feeler_length * (self.velocity.length() / self.max_speed).min(1.);
+        let heading = self.heading();
+
+        [(0., 1.), (FEELER_ANGLE, SIDE_FEELER), (-FEELER_ANGLE, SIDE_FEELER)]
+            .iter()
+            .filter_map(|&(angle, share)| {
+                let reach = reach * share;
+                let ray = Ray::new(self.position, self.position + heading.rotate(angle));
+                let hit = walls
+                    .iter()
+                    .filter_map(|wall| ray.intersects_line(wall))
+                    .filter(|hit| hit.distance <= reach)
+                    .min_by(|a, b| a.distance.total_cmp(&b.distance))?;
+
+                // Out along the wall's normal on this side of it.
+                let along = hit.target.to - hit.target.from;
+                let mut normal = Point2d { x: -along.y, y: along.x }.unit()?;
+                if normal.dot(self.position - hit.point) < 0. {
+                    normal = -normal;
+                }
+                Some(normal * (self.max_force * (1. - hit.distance / reach)))
+            })
+            .fold(ZERO, Add::add)
+    }
+
+    // Away from neighbours closer than `distance`, the closer the harder.
+    pub fn separation(&self, neighbours: &[&Agent], distance: f64) -> Point2d {
+        neighbours
+            .iter()
+            .filter_map(|other| {
+                let away = self.position - other.position;
+                let apart = away.length();
+                (apart > 0. && apart < distance).then(|| away * (self.max_force * (1. - apart / distance) / apart))
+            })
+            .fold(ZERO, Add::add)
+    }
+
+    // Towards the neighbours' average velocity.
+    pub fn alignment(&self, neighbours: &[&Agent]) -> Point2d {
+        if neighbours.is_empty() {
+            return ZERO;
+        }
+        let average = neighbours.iter().map(|other| other.velocity).fold(ZERO, Add::add) * (1. / neighbours.len() as f64);
+        self.towards(average.unit().unwrap_or(ZERO) * self.max_speed)
+    }
+
+    // Towards the neighbours' centre.
+    pub fn cohesion(&self, neighbours: &[&Agent]) -> Point2d {
+        if neighbours.is_empty() {
+            return ZERO;
+        }
+        let center = neighbours.iter().map(|other| other.position).fold(ZERO, Add::add) * (1. / neighbours.len() as f64);
+        self.seek(center)
+    }
+
+    // Along the path's waypoints in turn, arriving at the last.
+    pub fn follow(&self, path: &mut PathFollower) -> Point2d {
+        while path.current + 1 < path.points.len() && path.points[path.current].distance(self.position) <= path.reach {
+            path.current += 1;
+        }
+
+        match path.points.get(path.current) {
+            Some(&waypoint) if path.current + 1 == path.points.len() => self.arrive(waypoint, path.reach * 4.),
+            Some(&waypoint) => self.seek(waypoint),
+            None => ZERO,
+        }
+    }
+
+    // Applies `force`, capped at `max_force`, for `delta` milliseconds.
+    pub fn steer(&mut self, force: Point2d, delta: f64) {
+        let seconds = delta / 1000.;
+        let force = force.limit(self.max_force);
+        self.velocity = (self.velocity + force * seconds).limit(self.max_speed);
+        self.position = self.position + self.velocity * seconds;
+    }
+}
+
+// Progress along a list of waypoints, such as a `navigation::Path`'s. A waypoint counts as reached
+// within `reach` of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathFollower {
+    pub points: Vec<Point2d>,
+    pub reach: f64,
+    current: usize,
+}
+
+impl PathFollower {
+    pub fn new(points: Vec<Point2d>, reach: f64) -> Self {
+        Self { points, reach, current: 0 }
+    }
+}
+
+// A circle with a line out along its heading.
+impl Draw for Agent {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        let center = view.transform(&self.position);
+        let nose = view.transform(&(self.position + self.heading() * (self.radius * 2.)));
+
+        renderer.circle(center, view.scale(self.radius), self.color);
+        renderer.line(center, nose, self.color);
+    }
+
+    fn in_view(&self, view: &View) -> bool {
+        let center = view.transform(&self.position);
+        let reach = view.scale(self.radius * 2.);
+        center.x > -reach && center.y > -reach && center.x < view.size.x + reach && center.y < view.size.y + reach
+    }
+}
+
+// How a flock weighs its behaviours. Distances are in world units.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct FlockSettings {
+    // Other agents within this distance are neighbours.
+    pub neighbour_radius: f64,
+    pub separation_distance: f64,
+    pub feeler_length: f64,
+    // Radians the wander target may drift per update.
+    pub wander_jitter: f64,
+    // Agents only flee the threat while it is closer than this.
+    pub panic_distance: f64,
+    // A path waypoint counts as reached this close to it.
+    pub waypoint_reach: f64,
+    pub separation: f64,
+    pub alignment: f64,
+    pub cohesion: f64,
+    pub wander: f64,
+    pub avoidance: f64,
+    pub flee: f64,
+    pub follow: f64,
+}
+
+impl Default for FlockSettings {
+    fn default() -> Self {
+        Self {
+            neighbour_radius: 60.,
+            separation_distance: 20.,
+            feeler_length: 40.,
+            wander_jitter: 0.3,
+            panic_distance: 80.,
+            waypoint_reach: 24.,
+            separation: 1.5,
+            alignment: 1.,
+            cohesion: 0.8,
+            wander: 0.5,
+            avoidance: 3.,
+            flee: 2.,
+            follow: 1.,
+        }
+    }
+}
+
+// Boids: agents that keep apart, line up and stick together while wandering about, steering clear
+// of walls and the threat and, if there is one, taking the path together.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Flock {
+    pub agents: Vec<Agent>,
+    pub settings: FlockSettings,
+    pub threat: Option<Point2d>,
+    // Shared, so the flock moves on to the next waypoint as soon as any agent reaches it.
+    pub path: Option<PathFollower>,
+}
+
+impl Flock {
+    #[allow(dead_code)]
+    pub fn new(settings: FlockSettings) -> Self {
+        Self { agents: vec![], settings, threat: None, path: None }
+    }
+
+    pub fn add(&mut self, agent: Agent) {
+        self.agents.push(agent);
+    }
+
+    // The agents' average position.
+    pub fn center(&self) -> Option<Point2d> {
+        if self.agents.is_empty() {
+            return None;
+        }
+        Some(self.agents.iter().map(|agent| agent.position).fold(ZERO, Add::add) * (1. / self.agents.len() as f64))
+    }
+
+    // Takes the path from the flock's waypoints, or leaves it with `None`.
+    pub fn set_path(&mut self, points: Option<Vec<Point2d>>) {
+        self.path = points.map(|points| PathFollower::new(points, self.settings.waypoint_reach));
+    }
+
+    // Every agent steers by where the others were at the start of the update: all the forces are
+    // worked out before anyone moves. Staying clear of walls and each other comes first; the other
+    // behaviours share whatever force is left.
+    pub fn update(&mut self, walls: &[Line], rng: &mut Rng, delta: f64) {
+        let settings = &self.settings;
+        for agent in self.agents.iter_mut() {
+            agent.drift(settings.wander_jitter, rng);
+        }
+
+        let mut forces = Vec::with_capacity(self.agents.len());
+        for (index, agent) in self.agents.iter().enumerate() {
+            let neighbours: Vec<&Agent> = self.agents
+                .iter()
+                .enumerate()
+                .filter(|&(other, neighbour)| other != index && neighbour.position.distance(agent.position) < settings.neighbour_radius)
+                .map(|(_, neighbour)| neighbour)
+                .collect();
+
+            // In order of importance, each getting what is left of the agent's force.
+            let mut wanted = vec![
+                agent.avoid_walls(walls, settings.feeler_length) * settings.avoidance,
+                agent.separation(&neighbours, settings.separation_distance) * settings.separation,
+            ];
+            if let Some(threat) = self.threat {
+                wanted.push(agent.flee(threat, settings.panic_distance) * settings.flee);
+            }
+            if let Some(path) = self.path.as_mut() {
+                wanted.push(agent.follow(path) * settings.follow);
+            }
+            wanted.push(agent.alignment(&neighbours) * settings.alignment);
+            wanted.push(agent.cohesion(&neighbours) * settings.cohesion);
+            wanted.push(agent.wandering() * settings.wander);
+
+            forces.push(wanted.into_iter().fold(ZERO, |total, force| accumulate(total, force, agent.max_force)));
+        }
+
+        for (agent, force) in self.agents.iter_mut().zip(forces) {
+            agent.steer(force, delta);
+        }
+    }
+}
+
+impl Draw for Flock {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        for agent in self.agents.iter().filter(|agent| agent.in_view(view)) {
+            agent.draw(renderer, view);
+        }
+    }
+
+    fn in_view(&self, _view: &View) -> bool {
+        true
+    }
+}
+
+// Adds as much of `force` as fits before `total` reaches `max`.
+fn accumulate(total: Point2d, force: Point2d, max: f64) -> Point2d {
+    let left = max - total.length();
+    if left <= 0. {
+        return total;
+    }
+    total + force.limit(left)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: RGB<u8> = RGB { r: 0, g: 0, b: 0 };
+
+    fn point(x: f64, y: f64) -> Point2d {
+        Point2d { x, y }
+    }
+
+    fn agent(x: f64, y: f64) -> Agent {
+        Agent::new(point(x, y), BLACK)
+    }
+
+    fn close(a: Point2d, b: Point2d) -> bool {
+        a.distance(b) < 1e-9
+    }
+
+    #[test]
+    fn seeking_and_fleeing_point_the_right_way() {
+        let agent = agent(0., 0.);
+        assert!(close(agent.seek(point(10., 0.)), point(agent.max_speed * RESPONSE, 0.)));
+
+        assert_eq!(agent.flee(point(100., 0.), 50.), ZERO, "too far away to worry about");
+        let away = agent.flee(point(10., 0.), 50.);
+        assert!(away.x < 0. && away.y.abs() < 1e-9);
+    }
+
+    #[test]
+    fn steering_stays_within_the_limits() {
+        let mut agent = agent(0., 0.).with_limits(100., 50.);
+        agent.steer(point(1000., 0.), 1000.);
+        assert!(close(agent.velocity, point(50., 0.)), "the force is capped");
+        for _ in 0..10 {
+            agent.steer(point(1000., 1000.), 1000.);
+        }
+        assert!((agent.velocity.length() - 100.).abs() < 1e-9, "and so is the speed");
+    }
+
+    #[test]
+    fn arriving_stops_on_the_target() {
+        let target = point(200., 0.);
+        let mut agent = agent(0., 0.);
+        let mut furthest: f64 = 0.;
+        for _ in 0..600 {
+            let force = agent.arrive(target, 60.);
+            agent.steer(force, 16.);
+            furthest = furthest.max(agent.position.x);
+        }
+        assert!(agent.position.distance(target) < 1., "{:?}", agent.position);
+        assert!(agent.velocity.length() < 1.);
+        assert!(furthest < target.x + 5., "barely overshoots: {}", furthest);
+    }
+
+    #[test]
+    fn wandering_drifts_smoothly() {
+        let mut rng = Rng::new(2);
+        let mut agent = agent(0., 0.).with_velocity(point(50., 0.));
+        let mut last = agent.wander(0.2, &mut rng);
+        for _ in 0..50 {
+            let force = agent.wander(0.2, &mut rng);
+            assert!((force.length() - agent.max_force).abs() < 1e-9);
+            assert!(force.unit().unwrap().dot(last.unit().unwrap()) > 0.9, "turns a little at a time");
+            last = force;
+        }
+    }
+
+    #[test]
+    fn feelers_turn_away_from_walls_ahead() {
+        let wall = Line { from: point(30., -50.), to: point(30., 50.), color: BLACK };
+        let moving = agent(0., 0.).with_velocity(point(120., 0.));
+        let push = moving.avoid_walls(&[wall], 40.);
+        assert!(push.x < 0., "{:?}", push);
+
+        let slow = agent(0., 0.).with_velocity(point(10., 0.));
+        assert_eq!(slow.avoid_walls(&[wall], 40.), ZERO, "slow agents look less far ahead");
+        assert_eq!(moving.avoid_walls(&[], 40.), ZERO);
+    }
+
+    #[test]
+    fn boids_keep_apart_line_up_and_stay_together() {
+        let agent = agent(0., 0.);
+        let near = self::agent(5., 0.).with_velocity(point(0., 30.));
+        let far = self::agent(40., 0.).with_velocity(point(0., 30.));
+
+        assert!(agent.separation(&[&near], 20.).x < 0.);
+        assert_eq!(agent.separation(&[&far], 20.), ZERO);
+        assert!(agent.alignment(&[&near, &far]).y > 0.);
+        assert!(agent.cohesion(&[&near, &far]).x > 0.);
+        assert_eq!(agent.cohesion(&[]), ZERO);
+    }
+
+    #[test]
+    fn followers_move_on_once_a_waypoint_is_reached() {
+        let mut path = PathFollower::new(vec![point(0., 0.), point(100., 0.), point(100., 100.)], 10.);
+        let force = agent(2., 0.).follow(&mut path);
+        assert_eq!(path.current, 1);
+        assert!(force.x > 0.);
+
+        let force = agent(95., 0.).follow(&mut path);
+        assert_eq!(path.current, 2, "the last waypoint is arrived at rather than passed");
+        assert!(force.y > 0.);
+    }
+
+    #[test]
+    fn a_flock_moves_the_same_whatever_order_its_agents_are_in() {
+        let settings = FlockSettings { wander_jitter: 0., ..FlockSettings::default() };
+        let agents = [
+            agent(0., 0.).with_velocity(point(30., 0.)),
+            agent(12., 4.).with_velocity(point(0., 30.)),
+            agent(-8., 10.).with_velocity(point(-20., 5.)),
+        ];
+        let walls = [Line { from: point(40., -100.), to: point(40., 100.), color: BLACK }];
+
+        let mut forwards = Flock::new(settings.clone());
+        let mut backwards = Flock::new(settings);
+        agents.iter().cloned().for_each(|agent| forwards.add(agent));
+        agents.iter().rev().cloned().for_each(|agent| backwards.add(agent));
+        for flock in [&mut forwards, &mut backwards] {
+            flock.threat = Some(point(-30., 0.));
+            for _ in 0..30 {
+                flock.update(&walls, &mut Rng::new(1), 16.);
+            }
+        }
+
+        backwards.agents.reverse();
+        for (a, b) in forwards.agents.iter().zip(&backwards.agents) {
+            assert!(close(a.position, b.position), "{:?} vs {:?}", a.position, b.position);
+        }
+    }
+
+    #[test]
+    fn a_flock_takes_its_path_to_the_end() {
+        let mut flock = Flock::default();
+        flock.add(agent(0., 0.));
+        flock.add(agent(10., 0.));
+        flock.set_path(Some(vec![point(0., 0.), point(0., 150.), point(150., 150.)]));
+
+        let mut rng = Rng::new(4);
+        for _ in 0..1000 {
+            flock.update(&[], &mut rng, 16.);
+        }
+        let center = flock.center().unwrap();
+        assert!(center.distance(point(150., 150.)) < 30., "{:?}", center);
+        assert_eq!(flock.path.as_ref().map(|path| path.current), Some(2));
+    }
+}
@@ -0,0 +1,479 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::rc::Rc;
+use serde::Deserialize;
+use wasm_bindgen::JsValue;
+use web_sys::HtmlImageElement;
+
+use crate::scene::Scene;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetError {
+    Fetch { path: String, reason: String },
+    Decode { name: String, reason: String },
+    InvalidManifest(String),
+    // The name is already taken by an asset loaded from somewhere else.
+    Conflict { name: String, path: String, loaded: String },
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::Fetch { path, reason } => write!(f, "could not load `{}`: {}", path, reason),
+            AssetError::Decode { name, reason } => write!(f, "could not decode asset `{}`: {}", name, reason),
+            AssetError::InvalidManifest(error) => write!(f, "invalid asset manifest: {}", error),
+            AssetError::Conflict { name, path, loaded } => {
+                write!(f, "asset `{}` can't be loaded from `{}`, it was already loaded from `{}`", name, path, loaded)
+            }
+        }
+    }
+}
+
+impl From<AssetError> for JsValue {
+    fn from(error: AssetError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AssetKind {
+    Image,
+    Sound,
+    Scene,
+    Font,
+}
+
+// Marker types for typed handles. Images become sprites and fonts page fonts under the asset's
+// name, and sounds can be played by it; scenes are kept to hand out.
+pub trait Asset {
+    const KIND: AssetKind;
+}
+
+pub struct ImageAsset;
+pub struct SoundAsset;
+pub struct SceneAsset;
+pub struct FontAsset;
+
+impl Asset for ImageAsset {
+    const KIND: AssetKind = AssetKind::Image;
+}
+
+impl Asset for SoundAsset {
+    const KIND: AssetKind = AssetKind::Sound;
+}
+
+impl Asset for SceneAsset {
+    const KIND: AssetKind = AssetKind::Scene;
+}
+
+impl Asset for FontAsset {
+    const KIND: AssetKind = AssetKind::Font;
+}
+
+pub struct Handle<T> {
+    id: u32,
+    kind: PhantomData<T>,
+}
+
+// Derived impls would require `T` to be `Clone`, `PartialEq` and so on as well.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({})", self.id)
+    }
+}
+
+impl<T> Handle<T> {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetStatus {
+    Loading,
+    Ready,
+    Failed(AssetError),
+}
+
+// What a finished asset holds on to. Images are `None` outside the browser, where they can't be decoded.
+#[derive(Clone)]
+pub enum AssetData {
+    Image(Option<HtmlImageElement>),
+    Sound,
+    Scene(Rc<Scene>),
+    Font,
+}
+
+// Everything to load before the game starts, as JSON objects of asset name to path per kind.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub images: BTreeMap<String, String>,
+    pub sounds: BTreeMap<String, String>,
+    pub scenes: BTreeMap<String, String>,
+    pub fonts: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn from_json(json: &str) -> Result<Self, AssetError> {
+        serde_json::from_str(json).map_err(|error| AssetError::InvalidManifest(error.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Progress {
+    pub loaded: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl Progress {
+    // How much of the loading is over, failures included; 1 when there is nothing to load.
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            1.
+        } else {
+            (self.loaded + self.failed) as f64 / self.total as f64
+        }
+    }
+}
+
+pub type Fetched = Box<dyn FnOnce(Result<Vec<u8>, AssetError>)>;
+
+// Where asset bytes come from, and how the platform turns them into images and fonts. `done` may
+// be called straight away or later on.
+pub trait AssetLoader {
+    fn fetch(&self, path: &str, done: Fetched);
+
+    fn decode_image(&self, _name: &str, _data: &[u8], done: Box<dyn FnOnce(Result<Option<HtmlImageElement>, String>)>) {
+        done(Ok(None))
+    }
+
+    fn add_font(&self, _family: &str, _data: &[u8], done: Box<dyn FnOnce(Result<(), String>)>) {
+        done(Ok(()))
+    }
+}
+
+// Reads assets from disk relative to `root`, for native runs and tests. Images and fonts are
+// accepted without being decoded.
+pub struct FsLoader {
+    root: PathBuf,
+}
+
+impl FsLoader {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl AssetLoader for FsLoader {
+    fn fetch(&self, path: &str, done: Fetched) {
+        let result = std::fs::read(self.root.join(path)).map_err(|error| AssetError::Fetch { path: path.to_string(), reason: error.to_string() });
+        done(result);
+    }
+}
+
+struct Entry {
+    kind: AssetKind,
+    name: String,
+    path: String,
+    // Handles given out and not yet released; the entry goes when this reaches 0.
+    refs: u32,
+    status: AssetStatus,
+    data: Option<AssetData>,
+}
+
+// The asset cache. Loading the same name again hands out the cached asset and counts another
+// reference; the engine does the fetching and decoding and reports back with `finish`.
+pub struct Assets {
+    loader: Rc<dyn AssetLoader>,
+    entries: HashMap<u32, Entry>,
+    by_name: HashMap<(AssetKind, String), u32>,
+    next_id: u32,
+}
+
+impl Assets {
+    pub fn new(loader: Rc<dyn AssetLoader>) -> Self {
+        Self { loader, entries: HashMap::new(), by_name: HashMap::new(), next_id: 0 }
+    }
+
+    pub fn loader(&self) -> Rc<dyn AssetLoader> {
+        self.loader.clone()
+    }
+
+    pub fn set_loader(&mut self, loader: Rc<dyn AssetLoader>) {
+        self.loader = loader;
+    }
+
+    // The handle for `name` loaded from `path`, and whether it is new and still has to be fetched.
+    // A name can only stand for one path at a time.
+    pub fn insert<T: Asset>(&mut self, name: &str, path: &str) -> Result<(Handle<T>, bool), AssetError> {
+        if let Some(&id) = self.by_name.get(&(T::KIND, name.to_string())) {
+            if let Some(entry) = self.entries.get_mut(&id) {
+                if entry.path != path {
+                    return Err(AssetError::Conflict { name: name.to_string(), path: path.to_string(), loaded: entry.path.clone() });
+                }
+                entry.refs += 1;
+            }
+            return Ok((Handle { id, kind: PhantomData }, false));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(id, Entry {
+            kind: T::KIND,
+            name: name.to_string(),
+            path: path.to_string(),
+            refs: 1,
+            status: AssetStatus::Loading,
+            data: None,
+        });
+        self.by_name.insert((T::KIND, name.to_string()), id);
+
+        Ok((Handle { id, kind: PhantomData }, true))
+    }
+
+    // Drops a reference; returns true if that was the last one and the asset is gone. The engine's
+    // `release_asset` also drops the sprite or sound made from it.
+    pub fn release<T: Asset>(&mut self, handle: Handle<T>) -> bool {
+        let Some(entry) = self.entries.get_mut(&handle.id) else { return false };
+        entry.refs = entry.refs.saturating_sub(1);
+        if entry.refs > 0 {
+            return false;
+        }
+
+        if let Some(entry) = self.entries.remove(&handle.id) {
+            self.by_name.remove(&(entry.kind, entry.name));
+        }
+        true
+    }
+
+    pub fn find<T: Asset>(&self, name: &str) -> Option<Handle<T>> {
+        self.by_name.get(&(T::KIND, name.to_string())).map(|&id| Handle { id, kind: PhantomData })
+    }
+
+    // Kind and name of an asset still waiting on its data.
+    pub fn pending(&self, id: u32) -> Option<(AssetKind, String)> {
+        self.entries
+            .get(&id)
+            .filter(|entry| entry.status == AssetStatus::Loading)
+            .map(|entry| (entry.kind, entry.name.clone()))
+    }
+
+    // Ignored, returning false, if the asset was released while it loaded.
+    pub fn finish(&mut self, id: u32, result: Result<AssetData, AssetError>) -> bool {
+        let Some(entry) = self.entries.get_mut(&id) else { return false };
+        match result {
+            Ok(data) => {
+                entry.status = AssetStatus::Ready;
+                entry.data = Some(data);
+            }
+            Err(error) => entry.status = AssetStatus::Failed(error),
+        }
+        true
+    }
+
+    #[allow(dead_code)]
+    pub fn status<T: Asset>(&self, handle: Handle<T>) -> Option<AssetStatus> {
+        self.entries.get(&handle.id).map(|entry| entry.status.clone())
+    }
+
+    #[allow(dead_code)]
+    pub fn is_ready<T: Asset>(&self, handle: Handle<T>) -> bool {
+        self.status(handle) == Some(AssetStatus::Ready)
+    }
+
+    pub fn name<T: Asset>(&self, handle: Handle<T>) -> Option<&str> {
+        self.entries.get(&handle.id).map(|entry| entry.name.as_str())
+    }
+
+    pub fn scene(&self, handle: Handle<SceneAsset>) -> Option<Rc<Scene>> {
+        match self.entries.get(&handle.id)?.data.as_ref()? {
+            AssetData::Scene(scene) => Some(scene.clone()),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn image(&self, handle: Handle<ImageAsset>) -> Option<HtmlImageElement> {
+        match self.entries.get(&handle.id)?.data.as_ref()? {
+            AssetData::Image(image) => image.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let mut progress = Progress { total: self.entries.len(), ..Progress::default() };
+        for entry in self.entries.values() {
+            match entry.status {
+                AssetStatus::Loading => {}
+                AssetStatus::Ready => progress.loaded += 1,
+                AssetStatus::Failed(_) => progress.failed += 1,
+            }
+        }
+        progress
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.entries.values().any(|entry| entry.status == AssetStatus::Loading)
+    }
+
+    // Every failure so far, to report once loading is over.
+    pub fn errors(&self) -> Vec<AssetError> {
+        let mut errors: Vec<(u32, AssetError)> = self
+            .entries
+            .iter()
+            .filter_map(|(id, entry)| match &entry.status {
+                AssetStatus::Failed(error) => Some((*id, error.clone())),
+                _ => None,
+            })
+            .collect();
+        errors.sort_by_key(|(id, _)| *id);
+        errors.into_iter().map(|(_, error)| error).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use super::*;
+    use crate::audio::NullAudio;
+    use crate::config::EngineConfig;
+    use crate::game_engine::GameEngine;
+    use crate::shapes::Point2d;
+
+    // Reads from disk like `FsLoader`, but only when told to, so loads can be watched half-way.
+    struct Deferred {
+        files: FsLoader,
+        queue: RefCell<VecDeque<(String, Fetched)>>,
+    }
+
+    impl Deferred {
+        fn fetches(&self) -> usize {
+            self.queue.borrow().len()
+        }
+
+        fn run_all(&self) {
+            while let Some((path, done)) = self.queue.borrow_mut().pop_front() {
+                self.files.fetch(&path, done);
+            }
+        }
+    }
+
+    impl AssetLoader for Deferred {
+        fn fetch(&self, path: &str, done: Fetched) {
+            self.queue.borrow_mut().push_back((path.to_string(), done));
+        }
+    }
+
+    // A headless engine loading from a fresh directory with a sound, a scene and a broken scene in it.
+    fn engine(test: &str) -> (GameEngine, Rc<Deferred>, NullAudio) {
+        let root = std::env::temp_dir().join(format!("assets-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("blip.wav"), [0u8; 16]).unwrap();
+        std::fs::write(root.join("level.json"), r#"{"walls": []}"#).unwrap();
+        std::fs::write(root.join("broken.json"), "{").unwrap();
+
+        let audio = NullAudio::default();
+        let engine = GameEngine::headless_with_audio(EngineConfig::default(), Point2d { x: 400., y: 300. }, audio.clone());
+        let loader = Rc::new(Deferred { files: FsLoader::new(root), queue: RefCell::new(VecDeque::new()) });
+        engine.assets().set_loader(loader.clone());
+        (engine, loader, audio)
+    }
+
+    #[test]
+    fn progress_counts_loaded_and_failed_assets() {
+        let (engine, loader, _) = engine("progress");
+        let manifest = Manifest::from_json(
+            r#"{"sounds": {"blip": "blip.wav"}, "scenes": {"level": "level.json", "broken": "broken.json", "missing": "missing.json"}}"#,
+        )
+        .unwrap();
+        engine.preload(&manifest).unwrap();
+
+        assert_eq!(engine.assets().progress(), Progress { loaded: 0, failed: 0, total: 4 });
+        assert_eq!(engine.assets().progress().fraction(), 0.);
+        assert!(engine.assets().is_loading());
+
+        loader.run_all();
+        let assets = engine.assets();
+        assert_eq!(assets.progress(), Progress { loaded: 2, failed: 2, total: 4 });
+        assert_eq!(assets.progress().fraction(), 1.);
+        assert!(!assets.is_loading());
+        assert!(matches!(assets.errors().as_slice(), [AssetError::Decode { .. }, AssetError::Fetch { .. }]));
+
+        let level = assets.find::<SceneAsset>("level").unwrap();
+        assert!(assets.scene(level).is_some());
+        assert_eq!(Progress::default().fraction(), 1.);
+    }
+
+    #[test]
+    fn the_same_asset_is_fetched_once_and_shared() {
+        let (engine, loader, _) = engine("dedup");
+        let first = engine.load_asset::<SceneAsset>("level", "level.json").unwrap();
+        let second = engine.load_asset::<SceneAsset>("level", "level.json").unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(loader.fetches(), 1);
+        assert_eq!(engine.assets().progress().total, 1);
+
+        // The same name for another kind is another asset.
+        engine.load_asset::<SoundAsset>("level", "blip.wav").unwrap();
+        assert_eq!(loader.fetches(), 2);
+
+        let conflict = engine.load_asset::<SceneAsset>("level", "broken.json");
+        let loaded = "level.json".to_string();
+        assert_eq!(conflict, Err(AssetError::Conflict { name: "level".to_string(), path: "broken.json".to_string(), loaded }));
+        assert!(engine.preload(&Manifest::from_json(r#"{"scenes": {"level": "broken.json"}}"#).unwrap()).is_err());
+    }
+
+    #[test]
+    fn releasing_the_last_reference_unloads_the_asset() {
+        let (engine, loader, audio) = engine("release");
+        let blip = engine.load_asset::<SoundAsset>("blip", "blip.wav").unwrap();
+        engine.load_asset::<SoundAsset>("blip", "blip.wav").unwrap();
+        loader.run_all();
+        assert!(audio.is_loaded("blip"));
+
+        engine.release_asset(blip);
+        assert!(audio.is_loaded("blip"));
+        assert!(engine.assets().is_ready(blip));
+
+        engine.release_asset(blip);
+        assert!(!audio.is_loaded("blip"));
+        assert_eq!(engine.assets().status(blip), None);
+        assert_eq!(engine.assets().find::<SoundAsset>("blip"), None);
+
+        // Loading it again fetches it again, from wherever it is now.
+        let again = engine.load_asset::<SoundAsset>("blip", "elsewhere.wav").unwrap();
+        assert_ne!(again, blip);
+        assert_eq!(loader.fetches(), 1);
+    }
+
+    #[test]
+    fn assets_released_while_loading_are_dropped_once_they_arrive() {
+        let (engine, loader, audio) = engine("release-loading");
+        let blip = engine.load_asset::<SoundAsset>("blip", "blip.wav").unwrap();
+        engine.release_asset(blip);
+        loader.run_all();
+
+        assert!(!audio.is_loaded("blip"));
+        assert_eq!(engine.assets().progress(), Progress::default());
+    }
+}
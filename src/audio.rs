@@ -0,0 +1,472 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+
+use crate::Point2d;
+
+// Each bus is scaled by its own volume and then by master's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Bus {
+    Master,
+    Sfx,
+    Music,
+}
+
+impl Bus {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "master" => Some(Bus::Master),
+            "sfx" => Some(Bus::Sfx),
+            "music" => Some(Bus::Music),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct AudioSettings {
+    pub master: f64,
+    pub sfx: f64,
+    pub music: f64,
+    // World distance from the view centre at which positioned sounds fade out completely.
+    pub range: f64,
+    // Horizontal distance at which a sound is panned fully to one side.
+    pub pan_width: f64,
+    // Most voices playing at once. A new sound stops the oldest one-shot to make room, or doesn't
+    // play if every voice loops.
+    pub max_voices: usize,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self { master: 1., sfx: 1., music: 0.6, range: 800., pan_width: 400., max_voices: 32 }
+    }
+}
+
+impl AudioSettings {
+    pub fn volume(&self, bus: Bus) -> f64 {
+        match bus {
+            Bus::Master => self.master,
+            Bus::Sfx => self.sfx,
+            Bus::Music => self.music,
+        }
+    }
+
+    pub fn set_volume(&mut self, bus: Bus, volume: f64) {
+        let volume = volume.max(0.);
+        match bus {
+            Bus::Master => self.master = volume,
+            Bus::Sfx => self.sfx = volume,
+            Bus::Music => self.music = volume,
+        }
+    }
+
+    // The bus volume after master's.
+    pub fn bus_gain(&self, bus: Bus) -> f64 {
+        match bus {
+            Bus::Master => self.master,
+            bus => self.master * self.volume(bus),
+        }
+    }
+}
+
+// How loud a sound at `position` is for a listener at `listener`, and where it sits from -1 (left)
+// to 1 (right). Loudness falls off with the square of the remaining range, so it fades out smoothly.
+pub fn spatialize(position: Point2d, listener: Point2d, settings: &AudioSettings) -> (f64, f64) {
+    let offset = position - listener;
+
+    let gain = (1. - offset.length() / settings.range).clamp(0., 1.).powi(2);
+    let pan = (offset.x / settings.pan_width).clamp(-1., 1.);
+    (gain, pan)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoiceId(u32);
+
+// Ids cross into JS as plain numbers.
+impl VoiceId {
+    pub fn to_u32(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_u32(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+// How to play a sound: on which bus, how loud, whether it loops, and where in the world it is.
+// Sounds without a position play centred at full volume.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundOptions {
+    pub bus: Bus,
+    pub volume: f64,
+    pub looping: bool,
+    pub position: Option<Point2d>,
+}
+
+impl Default for SoundOptions {
+    fn default() -> Self {
+        Self { bus: Bus::Sfx, volume: 1., looping: false, position: None }
+    }
+}
+
+#[allow(dead_code)]
+impl SoundOptions {
+    pub fn music() -> Self {
+        Self { bus: Bus::Music, looping: true, ..Self::default() }
+    }
+
+    pub fn with_volume(mut self, volume: f64) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn looping(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+
+    pub fn at(mut self, position: Point2d) -> Self {
+        self.position = Some(position);
+        self
+    }
+}
+
+// Where the sound actually comes out. Gains are final: bus, voice volume and distance already applied.
+pub trait AudioBackend {
+    fn has_sound(&self, name: &str) -> bool;
+
+    // Returns false if the sound couldn't be started.
+    fn start(&self, voice: VoiceId, sound: &str, looping: bool, gain: f64, pan: f64) -> bool;
+
+    fn set(&self, voice: VoiceId, gain: f64, pan: f64);
+
+    fn stop(&self, voice: VoiceId);
+
+    // Voices that have ended by themselves since the last call.
+    fn take_finished(&self) -> Vec<VoiceId>;
+
+    // Decodes encoded audio (wav, ogg, mp3) to keep under `name`, calling `done` once it is playable
+    // or with why it isn't.
+    fn load(&self, name: &str, data: &[u8], done: Box<dyn FnOnce(Result<(), String>)>);
+
+    // Forgets a loaded sound; voices already playing it may play on.
+    fn unload(&self, name: &str);
+}
+
+struct Voice {
+    id: VoiceId,
+    options: SoundOptions,
+}
+
+// The engine's sound: tracks the playing voices and keeps their gain and pan up to date as volumes
+// change and the view moves.
+pub struct Mixer {
+    backend: Box<dyn AudioBackend>,
+    settings: AudioSettings,
+    listener: Point2d,
+    voices: Vec<Voice>,
+    next_id: u32,
+}
+
+impl Mixer {
+    pub fn new(backend: Box<dyn AudioBackend>, settings: AudioSettings) -> Self {
+        Self { backend, settings, listener: Point2d { x: 0., y: 0. }, voices: vec![], next_id: 0 }
+    }
+
+    // Swaps where the sound goes; whatever was playing is stopped.
+    pub fn set_backend(&mut self, backend: Box<dyn AudioBackend>) {
+        self.stop_all();
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> &dyn AudioBackend {
+        self.backend.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn settings(&self) -> &AudioSettings {
+        &self.settings
+    }
+
+    // `None` if the sound isn't loaded, every voice is taken by a looping sound, or it couldn't start.
+    pub fn play(&mut self, sound: &str, options: SoundOptions) -> Option<VoiceId> {
+        if !self.backend.has_sound(sound) {
+            return None;
+        }
+        if self.voices.len() >= self.settings.max_voices {
+            let oldest = self.voices.iter().find(|voice| !voice.options.looping)?.id;
+            self.stop(oldest);
+        }
+
+        let id = VoiceId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let (gain, pan) = self.mix(&options);
+        if !self.backend.start(id, sound, options.looping, gain, pan) {
+            return None;
+        }
+        self.voices.push(Voice { id, options });
+        Some(id)
+    }
+
+    pub fn stop(&mut self, id: VoiceId) {
+        if let Some(index) = self.voices.iter().position(|voice| voice.id == id) {
+            self.voices.remove(index);
+            self.backend.stop(id);
+        }
+    }
+
+    pub fn stop_all(&mut self) {
+        for voice in self.voices.drain(..) {
+            self.backend.stop(voice.id);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_playing(&self, id: VoiceId) -> bool {
+        self.voices.iter().any(|voice| voice.id == id)
+    }
+
+    #[allow(dead_code)]
+    pub fn volume(&self, bus: Bus) -> f64 {
+        self.settings.volume(bus)
+    }
+
+    pub fn set_volume(&mut self, bus: Bus, volume: f64) {
+        self.settings.set_volume(bus, volume);
+        self.remix();
+    }
+
+    #[allow(dead_code)]
+    // Moves a positioned voice, say a looping engine hum that follows its source.
+    pub fn set_position(&mut self, id: VoiceId, position: Point2d) {
+        let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id) else { return };
+        voice.options.position = Some(position);
+
+        let (id, options) = (voice.id, voice.options);
+        let (gain, pan) = self.mix(&options);
+        self.backend.set(id, gain, pan);
+    }
+
+    // Called once a frame with the view centre: forgets finished voices and re-pans the rest.
+    pub fn update(&mut self, listener: Point2d) {
+        let finished = self.backend.take_finished();
+        self.voices.retain(|voice| !finished.contains(&voice.id));
+
+        if listener != self.listener {
+            self.listener = listener;
+            self.remix();
+        }
+    }
+
+    // Final gain and pan for a voice.
+    pub fn mix(&self, options: &SoundOptions) -> (f64, f64) {
+        let gain = self.settings.bus_gain(options.bus) * options.volume;
+        match options.position {
+            Some(position) => {
+                let (attenuation, pan) = spatialize(position, self.listener, &self.settings);
+                (gain * attenuation, pan)
+            }
+            None => (gain, 0.),
+        }
+    }
+
+    fn remix(&self) {
+        for voice in self.voices.iter() {
+            let (gain, pan) = self.mix(&voice.options);
+            self.backend.set(voice.id, gain, pan);
+        }
+    }
+}
+
+// What a voice would sound like if there were speakers; the null backend keeps these to inspect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NullVoice {
+    pub id: VoiceId,
+    pub sound: String,
+    pub looping: bool,
+    pub gain: f64,
+    pub pan: f64,
+}
+
+// Plays nothing but keeps track of what would be playing, for headless runs and tests; keep a clone
+// to look at it once the mixer owns it. One-shots end on the next update, and every sound counts as
+// loaded unless some were added explicitly.
+#[derive(Clone, Default)]
+pub struct NullAudio {
+    sounds: Rc<RefCell<HashSet<String>>>,
+    voices: Rc<RefCell<Vec<NullVoice>>>,
+}
+
+#[allow(dead_code)]
+impl NullAudio {
+    pub fn add_sound(&self, name: &str) {
+        self.sounds.borrow_mut().insert(name.to_string());
+    }
+
+    pub fn voices(&self) -> Vec<NullVoice> {
+        self.voices.borrow().clone()
+    }
+
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.sounds.borrow().contains(name)
+    }
+}
+
+impl AudioBackend for NullAudio {
+    fn has_sound(&self, name: &str) -> bool {
+        let sounds = self.sounds.borrow();
+        sounds.is_empty() || sounds.contains(name)
+    }
+
+    fn start(&self, voice: VoiceId, sound: &str, looping: bool, gain: f64, pan: f64) -> bool {
+        self.voices.borrow_mut().push(NullVoice { id: voice, sound: sound.to_string(), looping, gain, pan });
+        true
+    }
+
+    fn set(&self, voice: VoiceId, gain: f64, pan: f64) {
+        if let Some(playing) = self.voices.borrow_mut().iter_mut().find(|playing| playing.id == voice) {
+            playing.gain = gain;
+            playing.pan = pan;
+        }
+    }
+
+    fn stop(&self, voice: VoiceId) {
+        self.voices.borrow_mut().retain(|playing| playing.id != voice);
+    }
+
+    fn load(&self, name: &str, _data: &[u8], done: Box<dyn FnOnce(Result<(), String>)>) {
+        self.add_sound(name);
+        done(Ok(()));
+    }
+
+    fn unload(&self, name: &str) {
+        self.sounds.borrow_mut().remove(name);
+    }
+
+    fn take_finished(&self) -> Vec<VoiceId> {
+        let mut finished = vec![];
+        self.voices.borrow_mut().retain(|playing| {
+            if !playing.looping {
+                finished.push(playing.id);
+            }
+            playing.looping
+        });
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::game_engine::GameEngine;
+
+    fn mixer(settings: AudioSettings) -> (Mixer, NullAudio) {
+        let audio = NullAudio::default();
+        (Mixer::new(Box::new(audio.clone()), settings), audio)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn loudness_falls_off_with_the_square_of_the_remaining_range() {
+        let settings = AudioSettings::default();
+        let at = |x: f64, y: f64| spatialize(Point2d { x, y }, Point2d { x: 0., y: 0. }, &settings);
+
+        assert_eq!(at(0., 0.), (1., 0.));
+        assert!(close(at(0., 400.).0, 0.25));
+        assert!(close(at(0., -200.).0, 0.5625));
+        assert_eq!(at(800., 0.).0, 0.);
+        assert_eq!(at(0., 5000.).0, 0.);
+
+        assert!(close(at(200., 0.).1, 0.5));
+        assert_eq!(at(-400., 0.).1, -1.);
+        assert_eq!(at(650., 0.).1, 1.);
+        assert_eq!(at(0., 300.).1, 0.);
+    }
+
+    #[test]
+    fn bus_volumes_scale_with_master_and_remix_playing_voices() {
+        let (mut mixer, audio) = mixer(AudioSettings::default());
+        let effect = mixer.play("hit", SoundOptions::default().with_volume(0.5)).unwrap();
+        let music = mixer.play("theme", SoundOptions::music()).unwrap();
+        let gains = || audio.voices().iter().map(|voice| voice.gain).collect::<Vec<_>>();
+        assert_eq!(gains(), [0.5, 0.6]);
+
+        mixer.set_volume(Bus::Master, 0.5);
+        mixer.set_volume(Bus::Music, 0.4);
+        assert_eq!(gains(), [0.25, 0.2]);
+        assert_eq!(mixer.volume(Bus::Master), 0.5);
+
+        mixer.set_volume(Bus::Sfx, -1.);
+        assert_eq!(mixer.volume(Bus::Sfx), 0.);
+        assert_eq!(gains(), [0., 0.2]);
+
+        mixer.stop(effect);
+        assert!(!mixer.is_playing(effect) && mixer.is_playing(music));
+        assert_eq!(audio.voices().len(), 1);
+    }
+
+    #[test]
+    fn positioned_voices_follow_the_listener() {
+        let (mut mixer, audio) = mixer(AudioSettings::default());
+        let hum = mixer.play("hum", SoundOptions::default().looping().at(Point2d { x: 200., y: 0. })).unwrap();
+        assert!(close(audio.voices()[0].pan, 0.5));
+
+        mixer.update(Point2d { x: 200., y: 0. });
+        assert_eq!((audio.voices()[0].gain, audio.voices()[0].pan), (1., 0.));
+
+        mixer.set_position(hum, Point2d { x: 200., y: 400. });
+        assert!(close(audio.voices()[0].gain, 0.25));
+        assert!(mixer.is_playing(hum));
+    }
+
+    #[test]
+    fn a_full_mixer_stops_its_oldest_one_shot() {
+        let (mut mixer, audio) = mixer(AudioSettings { max_voices: 3, ..AudioSettings::default() });
+        let theme = mixer.play("theme", SoundOptions::music()).unwrap();
+        let first = mixer.play("hit", SoundOptions::default()).unwrap();
+        let second = mixer.play("hit", SoundOptions::default()).unwrap();
+
+        let third = mixer.play("hit", SoundOptions::default()).unwrap();
+        assert!(!mixer.is_playing(first));
+        assert!([theme, second, third].iter().all(|id| mixer.is_playing(*id)));
+        assert_eq!(audio.voices().len(), 3);
+
+        // With nothing but loops playing there is nothing to give way.
+        let (mut mixer, _) = self::mixer(AudioSettings { max_voices: 1, ..AudioSettings::default() });
+        mixer.play("theme", SoundOptions::music()).unwrap();
+        assert_eq!(mixer.play("hit", SoundOptions::default()), None);
+    }
+
+    #[test]
+    fn sounds_that_are_not_loaded_do_not_play() {
+        let (mut mixer, audio) = mixer(AudioSettings::default());
+        audio.add_sound("hit");
+        assert_eq!(mixer.play("missing", SoundOptions::default()), None);
+        assert!(mixer.play("hit", SoundOptions::default()).is_some());
+    }
+
+    #[test]
+    fn the_engine_listens_from_the_view_centre() {
+        let audio = NullAudio::default();
+        let engine = GameEngine::headless_with_audio(EngineConfig::default(), Point2d { x: 400., y: 300. }, audio.clone());
+        let hum = engine.audio().play("hum", SoundOptions::default().looping().at(Point2d { x: -100., y: 0. })).unwrap();
+        engine.audio().play("hit", SoundOptions::default()).unwrap();
+
+        engine.set_view_center(Point2d { x: -100., y: 0. });
+        engine.step(16., &mut |_: &GameEngine| {});
+
+        // The one-shot has ended and the hum is now right at the listener.
+        let voices = audio.voices();
+        assert_eq!(voices.len(), 1);
+        assert_eq!((voices[0].id, voices[0].gain, voices[0].pan), (hum, 1., 0.));
+    }
+}
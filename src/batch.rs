@@ -0,0 +1,222 @@
+use std::f64;
+use rgb::RGB;
+use crate::Point2d;
+
+// x, y, r, g, b, a
+pub const VERTEX_SIZE: usize = 6;
+// x, y, width, height, r, g, b, a
+pub const QUAD_INSTANCE_SIZE: usize = 8;
+
+const MIN_CIRCLE_SEGMENTS: usize = 12;
+const MAX_CIRCLE_SEGMENTS: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Primitive {
+    Lines,
+    Quads,
+    Triangles,
+}
+
+// A run of consecutive primitives of the same kind, so draw order is kept when flushing.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Run {
+    pub primitive: Primitive,
+    pub start: usize,
+    pub count: usize,
+}
+
+#[derive(Default)]
+pub struct Batch {
+    line_vertices: Vec<f32>,
+    quad_instances: Vec<f32>,
+    triangle_vertices: Vec<f32>,
+    runs: Vec<Run>,
+}
+
+pub fn color_to_f32(color: RGB<u8>, alpha: f64) -> [f32; 4] {
+    [
+        color.r as f32 / 255.,
+        color.g as f32 / 255.,
+        color.b as f32 / 255.,
+        alpha.clamp(0., 1.) as f32,
+    ]
+}
+
+pub fn circle_segments(radius: f64) -> usize {
+    ((radius * f64::consts::PI / 2.).ceil() as usize).clamp(MIN_CIRCLE_SEGMENTS, MAX_CIRCLE_SEGMENTS)
+}
+
+pub fn circle_outline(center: Point2d, radius: f64) -> Vec<Point2d> {
+    let segments = circle_segments(radius);
+
+    (0..=segments)
+        .map(|segment| {
+            let angle = segment as f64 / segments as f64 * f64::consts::PI * 2.;
+            Point2d { x: center.x + angle.cos() * radius, y: center.y + angle.sin() * radius }
+        })
+        .collect()
+}
+
+impl Batch {
+    pub fn push_line(&mut self, from: Point2d, to: Point2d, color: RGB<u8>, alpha: f64) {
+        let color = color_to_f32(color, alpha);
+
+        for point in [from, to] {
+            self.line_vertices.extend_from_slice(&[point.x as f32, point.y as f32]);
+            self.line_vertices.extend_from_slice(&color);
+        }
+
+        self.extend_run(Primitive::Lines, 1);
+    }
+
+    pub fn push_triangle(&mut self, vertices: [(Point2d, [f32; 4]); 3]) {
+        for (point, color) in vertices {
+            self.triangle_vertices.extend_from_slice(&[point.x as f32, point.y as f32]);
+            self.triangle_vertices.extend_from_slice(&color);
+        }
+
+        self.extend_run(Primitive::Triangles, 1);
+    }
+
+    // Triangulates a polygon that is star-shaped around the hub, such as a light's visibility polygon.
+    pub fn push_fan(&mut self, hub: (Point2d, [f32; 4]), rim: &[(Point2d, [f32; 4])]) {
+        for edge in rim.windows(2) {
+            self.push_triangle([hub, edge[0], edge[1]]);
+        }
+    }
+
+    pub fn push_circle(&mut self, center: Point2d, radius: f64, color: RGB<u8>, alpha: f64) {
+        let outline = circle_outline(center, radius);
+
+        for segment in outline.windows(2) {
+            self.push_line(segment[0], segment[1], color, alpha);
+        }
+    }
+
+    pub fn push_rect(&mut self, position: Point2d, size: Point2d, color: RGB<u8>, alpha: f64) {
+        self.quad_instances.extend_from_slice(&[position.x as f32, position.y as f32, size.x as f32, size.y as f32]);
+        self.quad_instances.extend_from_slice(&color_to_f32(color, alpha));
+
+        self.extend_run(Primitive::Quads, 1);
+    }
+
+    fn extend_run(&mut self, primitive: Primitive, count: usize) {
+        match self.runs.last_mut() {
+            Some(run) if run.primitive == primitive => run.count += count,
+            _ => {
+                let start = match primitive {
+                    Primitive::Lines => self.line_count() - count,
+                    Primitive::Quads => self.quad_count() - count,
+                    Primitive::Triangles => self.triangle_count() - count,
+                };
+                self.runs.push(Run { primitive, start, count });
+            }
+        }
+    }
+
+    pub fn line_vertices(&self) -> &[f32] {
+        &self.line_vertices
+    }
+
+    pub fn quad_instances(&self) -> &[f32] {
+        &self.quad_instances
+    }
+
+    pub fn triangle_vertices(&self) -> &[f32] {
+        &self.triangle_vertices
+    }
+
+    pub fn runs(&self) -> &[Run] {
+        &self.runs
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_vertices.len() / (VERTEX_SIZE * 2)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangle_vertices.len() / (VERTEX_SIZE * 3)
+    }
+
+    pub fn quad_count(&self) -> usize {
+        self.quad_instances.len() / QUAD_INSTANCE_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.line_vertices.clear();
+        self.quad_instances.clear();
+        self.triangle_vertices.clear();
+        self.runs.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: RGB<u8> = RGB { r: 255, g: 0, b: 0 };
+    const ORIGIN: Point2d = Point2d { x: 0., y: 0. };
+    const ONE: Point2d = Point2d { x: 1., y: 1. };
+
+    #[test]
+    fn runs_merge_neighbours_of_a_kind_and_keep_draw_order() {
+        let mut batch = Batch::default();
+        batch.push_line(ORIGIN, ONE, RED, 1.);
+        batch.push_line(ONE, ORIGIN, RED, 1.);
+        batch.push_rect(ORIGIN, ONE, RED, 0.5);
+        batch.push_line(ORIGIN, ONE, RED, 1.);
+        batch.push_triangle([(ORIGIN, [1.; 4]), (ONE, [1.; 4]), (ORIGIN, [1.; 4])]);
+        batch.push_rect(ONE, ONE, RED, 1.);
+
+        assert_eq!(batch.runs(), &[
+            Run { primitive: Primitive::Lines, start: 0, count: 2 },
+            Run { primitive: Primitive::Quads, start: 0, count: 1 },
+            Run { primitive: Primitive::Lines, start: 2, count: 1 },
+            Run { primitive: Primitive::Triangles, start: 0, count: 1 },
+            Run { primitive: Primitive::Quads, start: 1, count: 1 },
+        ]);
+        assert_eq!((batch.line_count(), batch.quad_count(), batch.triangle_count()), (3, 2, 1));
+        assert_eq!(batch.line_vertices().len(), 3 * 2 * VERTEX_SIZE);
+        assert_eq!(&batch.quad_instances()[..QUAD_INSTANCE_SIZE], &[0., 0., 1., 1., 1., 0., 0., 0.5]);
+
+        batch.clear();
+        assert!(batch.is_empty());
+        assert_eq!((batch.line_count(), batch.quad_count(), batch.triangle_count()), (0, 0, 0));
+        batch.push_rect(ORIGIN, ONE, RED, 1.);
+        assert_eq!(batch.runs(), &[Run { primitive: Primitive::Quads, start: 0, count: 1 }]);
+    }
+
+    #[test]
+    fn fans_and_circles_become_triangles_and_lines() {
+        let mut batch = Batch::default();
+        let rim: Vec<_> = circle_outline(ORIGIN, 10.).into_iter().map(|point| (point, [1.; 4])).collect();
+        batch.push_fan((ORIGIN, [1.; 4]), &rim);
+        assert_eq!(batch.triangle_count(), rim.len() - 1);
+
+        batch.push_circle(ORIGIN, 10., RED, 1.);
+        assert_eq!(batch.line_count(), circle_segments(10.));
+        assert_eq!(batch.runs().len(), 2);
+    }
+
+    #[test]
+    fn circles_get_more_segments_as_they_grow() {
+        assert_eq!(circle_segments(0.), MIN_CIRCLE_SEGMENTS);
+        assert_eq!(circle_segments(10.), 16);
+        assert_eq!(circle_segments(1000.), MAX_CIRCLE_SEGMENTS);
+
+        let outline = circle_outline(Point2d { x: 5., y: 5. }, 2.);
+        assert_eq!(outline.len(), MIN_CIRCLE_SEGMENTS + 1);
+        assert!((outline[0].x - outline[MIN_CIRCLE_SEGMENTS].x).abs() < 1e-9, "the outline closes");
+        assert!(outline.iter().all(|point| ((point.x - 5.).hypot(point.y - 5.) - 2.).abs() < 1e-9));
+    }
+
+    #[test]
+    fn colors_become_unit_floats_with_clamped_alpha() {
+        assert_eq!(color_to_f32(RGB { r: 255, g: 0, b: 51 }, 2.), [1., 0., 0.2, 1.]);
+        assert_eq!(color_to_f32(RED, -1.), [1., 0., 0., 0.]);
+    }
+}
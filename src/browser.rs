@@ -1,8 +1,13 @@
-use web_sys::{CanvasRenderingContext2d, Document, HtmlCanvasElement, Window};
+use std::cell::RefCell;
+use std::rc::Rc;
+use js_sys::Promise;
+use web_sys::{CanvasRenderingContext2d, Document, Element, Event, EventTarget, HtmlCanvasElement, Storage, WebGl2RenderingContext, Window};
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
+use crate::error::{describe, EngineError};
+
 #[allow(dead_code)]
 pub fn console_log(log: &str) {
     let array = js_sys::Array::new();
@@ -14,37 +19,194 @@ pub struct Browser {
 }
 
 impl Browser {
-    pub fn window() -> Window {
-        web_sys::window().expect("no global `window` exists")
+    pub fn window() -> Result<Window, EngineError> {
+        web_sys::window().ok_or(EngineError::NoWindow)
+    }
+
+    pub fn device_pixel_ratio() -> Result<f64, EngineError> {
+        Ok(Self::window()?.device_pixel_ratio())
     }
 
-    pub fn document() -> Document {
-        Self::window()
+    // Eight bytes from `crypto.getRandomValues`, where there is a Web Crypto API.
+    pub fn random_u64() -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        Self::window().ok()?.crypto().ok()?.get_random_values_with_u8_array(&mut bytes).ok()?;
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    pub fn document() -> Result<Document, EngineError> {
+        Self::window()?
             .document()
-            .expect("should have a document on window")
+            .ok_or(EngineError::NoDocument)
     }
 
-    pub fn canvas(id: &str) -> HtmlCanvasElement {
-        Self::document()
-            .query_selector(id)
-            .unwrap()
-            .unwrap()
+    pub fn canvas(selector: &str) -> Result<HtmlCanvasElement, EngineError> {
+        Self::document()?
+            .query_selector(selector)
+            .map_err(|_| EngineError::InvalidSelector(selector.to_string()))?
+            .ok_or_else(|| EngineError::SelectorNotFound(selector.to_string()))?
             .dyn_into::<HtmlCanvasElement>()
-            .expect("No canvas found.")
+            .map_err(|_| EngineError::WrongElementType { selector: selector.to_string(), expected: "canvas" })
     }
 
-    pub fn context(canvas: &HtmlCanvasElement) -> CanvasRenderingContext2d {
+    pub fn context(canvas: &HtmlCanvasElement) -> Result<CanvasRenderingContext2d, EngineError> {
         canvas
             .get_context("2d")
-            .expect("No context created.")
-            .unwrap()
-            .dyn_into::<CanvasRenderingContext2d>()
-            .expect("Could not be fetched as internal object.")
+            .ok()
+            .flatten()
+            .and_then(|context| context.dyn_into::<CanvasRenderingContext2d>().ok())
+            .ok_or(EngineError::ContextUnavailable("2d"))
+    }
+
+    pub fn webgl2_context(canvas: &HtmlCanvasElement) -> Option<WebGl2RenderingContext> {
+        canvas
+            .get_context("webgl2")
+            .ok()
+            .flatten()
+            .and_then(|context| context.dyn_into::<WebGl2RenderingContext>().ok())
+    }
+
+    // Swaps the canvas for a blank copy with the same attributes. A canvas keeps the first kind of
+    // context it hands out, so this is the only way back to 2D once a WebGL context was taken.
+    pub fn replace_canvas(canvas: &HtmlCanvasElement) -> Result<HtmlCanvasElement, EngineError> {
+        let fresh = canvas
+            .clone_node()
+            .ok()
+            .and_then(|node| node.dyn_into::<HtmlCanvasElement>().ok())
+            .ok_or(EngineError::ContextUnavailable("2d"))?;
+        canvas.replace_with_with_node_1(&fresh).map_err(|_| EngineError::ContextUnavailable("2d"))?;
+        Ok(fresh)
     }
 
-    pub fn request_animation_frame(f: &Closure<dyn FnMut()>) {
-        Self::window()
+    pub fn request_animation_frame(f: &Closure<dyn FnMut(f64)>) -> Result<i32, EngineError> {
+        Self::window()?
             .request_animation_frame(f.as_ref().unchecked_ref())
-            .expect("should register `requestAnimationFrame` OK");
+            .map_err(|error| EngineError::AnimationFrame(describe(&error)))
+    }
+
+    // The inner size of the element the canvas sits in, if it has been laid out.
+    pub fn container_size(canvas: &HtmlCanvasElement) -> Option<(f64, f64)> {
+        let parent = canvas.parent_element()?;
+        let (width, height) = (parent.client_width(), parent.client_height());
+        if width > 0 && height > 0 {
+            Some((width as f64, height as f64))
+        } else {
+            None
+        }
+    }
+
+    pub fn local_storage() -> Result<Storage, EngineError> {
+        Self::window()?
+            .local_storage()
+            .map_err(|error| EngineError::Storage(describe(&error)))?
+            .ok_or_else(|| EngineError::Storage("disabled by the browser".to_string()))
+    }
+
+    pub fn load(key: &str) -> Result<Option<String>, EngineError> {
+        Self::local_storage()?
+            .get_item(key)
+            .map_err(|error| EngineError::Storage(describe(&error)))
+    }
+
+    pub fn store(key: &str, value: &str) -> Result<(), EngineError> {
+        Self::local_storage()?
+            .set_item(key, value)
+            .map_err(|error| EngineError::Storage(describe(&error)))
+    }
+
+    // Calls `done` once `promise` resolves or rejects. Whichever callback doesn't run is leaked, as
+    // JS can't tell Rust that it has been collected; `done` itself is dropped once it has run.
+    pub fn settle<F: FnOnce(Result<JsValue, JsValue>) + 'static>(promise: Promise, done: F) {
+        let done = Rc::new(RefCell::new(Some(done)));
+        let rejected = done.clone();
+
+        let on_resolve = Closure::once(move |value: JsValue| {
+            if let Some(done) = done.borrow_mut().take() {
+                done(Ok(value));
+            }
+        });
+        let on_reject = Closure::once(move |error: JsValue| {
+            if let Some(done) = rejected.borrow_mut().take() {
+                done(Err(error));
+            }
+        });
+
+        let _ = promise.then2(&on_resolve, &on_reject);
+        on_resolve.forget();
+        on_reject.forget();
+    }
+
+    pub fn cancel_animation_frame(id: i32) {
+        if let Ok(window) = Self::window() {
+            window.cancel_animation_frame(id).ok();
+        }
+    }
+}
+
+// A DOM event listener that is removed again when dropped, instead of being leaked with `forget()`.
+pub struct EventListener {
+    target: EventTarget,
+    event: &'static str,
+    closure: Closure<dyn FnMut(Event)>,
+}
+
+impl EventListener {
+    pub fn new<E, F>(target: &EventTarget, event: &'static str, mut handler: F) -> Result<Self, EngineError>
+    where
+        E: JsCast,
+        F: FnMut(E) + 'static,
+    {
+        let closure = Closure::<dyn FnMut(Event)>::new(move |event: Event| handler(event.unchecked_into::<E>()));
+        target
+            .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())
+            .map_err(|error| EngineError::EventListener { event, error: describe(&error) })?;
+
+        Ok(Self { target: target.clone(), event, closure })
+    }
+}
+
+impl Drop for EventListener {
+    fn drop(&mut self) {
+        self.target
+            .remove_event_listener_with_callback(self.event, self.closure.as_ref().unchecked_ref())
+            .ok();
+    }
+}
+
+// The web-sys version in use only has `ResizeObserver` behind `web_sys_unstable_apis`.
+#[wasm_bindgen]
+extern "C" {
+    type ResizeObserver;
+
+    #[wasm_bindgen(constructor, catch)]
+    fn new(callback: &js_sys::Function) -> Result<ResizeObserver, JsValue>;
+
+    #[wasm_bindgen(method)]
+    fn observe(this: &ResizeObserver, target: &Element);
+
+    #[wasm_bindgen(method)]
+    fn disconnect(this: &ResizeObserver);
+}
+
+// Calls `handler` after layout whenever `target` changes size, until dropped.
+pub struct SizeObserver {
+    observer: ResizeObserver,
+    _closure: Closure<dyn FnMut()>,
+}
+
+impl SizeObserver {
+    pub fn new<F: FnMut() + 'static>(target: &Element, handler: F) -> Result<Self, EngineError> {
+        let closure = Closure::<dyn FnMut()>::new(handler);
+        let observer = ResizeObserver::new(closure.as_ref().unchecked_ref())
+            .map_err(|error| EngineError::EventListener { event: "resize", error: describe(&error) })?;
+        observer.observe(target);
+
+        Ok(Self { observer, _closure: closure })
+    }
+}
+
+impl Drop for SizeObserver {
+    fn drop(&mut self) {
+        self.observer.disconnect();
     }
 }
@@ -0,0 +1,432 @@
+use std::fmt;
+use rgb::RGB8;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use wasm_bindgen::JsValue;
+
+use crate::audio::AudioSettings;
+use crate::navigation::NavSettings;
+use crate::shapes::rgb;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    InvalidJson(String),
+    InvalidValue { field: &'static str, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidJson(error) => write!(f, "invalid engine config: {}", error),
+            ConfigError::InvalidValue { field, reason } => write!(f, "invalid engine config `{}`: {}", field, reason),
+        }
+    }
+}
+
+impl From<ConfigError> for JsValue {
+    fn from(error: ConfigError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+// Colours are written as "#RRGGBB" in JSON.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color(pub RGB8);
+
+impl Color {
+    pub fn parse(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#')?;
+        if hex.len() != 6 {
+            return None;
+        }
+
+        let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+        Some(Color(RGB8 { r: channel(0)?, g: channel(2)?, b: channel(4)? }))
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&rgb(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        Color::parse(&hex).ok_or_else(|| serde::de::Error::custom(format!("`{}` is not a #RRGGBB colour", hex)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RendererPreference {
+    Auto,
+    WebGl2,
+    Canvas2d,
+}
+
+// How the canvas backing store relates to `window.devicePixelRatio`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DprMode {
+    Ignore,
+    Auto,
+    Fixed(f64),
+}
+
+// What stays put when the canvas changes size: the world centre, or the top-left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResizeAnchor {
+    Center,
+    TopLeft,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct Palette {
+    pub wall: Color,
+    pub ray: Color,
+    pub hit: Color,
+    pub marker: Color,
+    pub particle: Color,
+    pub light: Color,
+    pub hit_light: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            wall: Color(RGB8 { r: 0, g: 0, b: 0 }),
+            ray: Color(RGB8 { r: 0, g: 255, b: 0 }),
+            hit: Color(RGB8 { r: 0, g: 0, b: 0 }),
+            marker: Color(RGB8 { r: 0, g: 0, b: 0 }),
+            particle: Color(RGB8 { r: 255, g: 0, b: 0 }),
+            light: Color(RGB8 { r: 255, g: 255, b: 255 }),
+            hit_light: Color(RGB8 { r: 255, g: 160, b: 60 }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct EngineConfig {
+    pub canvas_selector: String,
+    pub renderer: RendererPreference,
+    pub max_particles: usize,
+    // Base size of the demo's impact sparks; their embers and streaks scale with it.
+    pub particle_size: f64,
+    pub pan_step: f64,
+    pub background: Option<Color>,
+    pub colors: Palette,
+    pub dpr: DprMode,
+    pub target_fps: Option<f64>,
+    // Milliseconds of engine time per game update; without one the game updates once per frame.
+    pub fixed_timestep: Option<f64>,
+    // Fills the canvas's parent element and follows its size; otherwise the canvas attributes are used.
+    pub auto_resize: bool,
+    pub resize_anchor: ResizeAnchor,
+    // How many recent frames the profiler keeps; 0 turns it off.
+    pub profile_frames: usize,
+    // Seeds every random stream; without one each run picks its own.
+    pub seed: Option<u64>,
+    pub audio: AudioSettings,
+    pub navigation: NavSettings,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            canvas_selector: "#canvas".to_string(),
+            renderer: RendererPreference::Auto,
+            max_particles: 2000,
+            particle_size: 2.,
+            pan_step: 10.,
+            background: None,
+            colors: Palette::default(),
+            dpr: DprMode::Auto,
+            target_fps: None,
+            fixed_timestep: None,
+            auto_resize: true,
+            resize_anchor: ResizeAnchor::Center,
+            profile_frames: 120,
+            seed: None,
+            audio: AudioSettings::default(),
+            navigation: NavSettings::default(),
+        }
+    }
+}
+
+fn invalid(field: &'static str, reason: &str) -> ConfigError {
+    ConfigError::InvalidValue { field, reason: reason.to_string() }
+}
+
+impl EngineConfig {
+    pub fn builder() -> EngineConfigBuilder {
+        EngineConfigBuilder { config: EngineConfig::default() }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ConfigError> {
+        let config: EngineConfig = serde_json::from_str(json).map_err(|error| ConfigError::InvalidJson(error.to_string()))?;
+        config.validate()
+    }
+
+    pub fn validate(self) -> Result<Self, ConfigError> {
+        if self.canvas_selector.trim().is_empty() {
+            return Err(invalid("canvasSelector", "must not be empty"));
+        }
+        if self.max_particles == 0 {
+            return Err(invalid("maxParticles", "must be at least 1"));
+        }
+        if !(self.particle_size.is_finite() && self.particle_size > 0.) {
+            return Err(invalid("particleSize", "must be a positive number"));
+        }
+        if !(self.pan_step.is_finite() && self.pan_step > 0.) {
+            return Err(invalid("panStep", "must be a positive number"));
+        }
+        if let DprMode::Fixed(ratio) = self.dpr {
+            if !(ratio.is_finite() && ratio > 0.) {
+                return Err(invalid("dpr", "a fixed ratio must be a positive number"));
+            }
+        }
+        if let Some(fps) = self.target_fps {
+            if !(fps.is_finite() && fps > 0.) {
+                return Err(invalid("targetFps", "must be a positive number"));
+            }
+        }
+        for (field, volume) in [("audio.master", self.audio.master), ("audio.sfx", self.audio.sfx), ("audio.music", self.audio.music)] {
+            if !(volume.is_finite() && volume >= 0.) {
+                return Err(invalid(field, "must be zero or more"));
+            }
+        }
+        if !(self.audio.range.is_finite() && self.audio.range > 0.) {
+            return Err(invalid("audio.range", "must be a positive number"));
+        }
+        if !(self.audio.pan_width.is_finite() && self.audio.pan_width > 0.) {
+            return Err(invalid("audio.panWidth", "must be a positive number"));
+        }
+        if self.audio.max_voices == 0 {
+            return Err(invalid("audio.maxVoices", "must be at least 1"));
+        }
+        if !(self.navigation.cell_size.is_finite() && self.navigation.cell_size > 0.) {
+            return Err(invalid("navigation.cellSize", "must be a positive number"));
+        }
+        if !(self.navigation.clearance.is_finite() && self.navigation.clearance >= 0.) {
+            return Err(invalid("navigation.clearance", "must be zero or more"));
+        }
+        if let Some(timestep) = self.fixed_timestep {
+            if !(timestep.is_finite() && timestep > 0.) {
+                return Err(invalid("fixedTimestep", "must be a positive number"));
+            }
+        }
+
+        Ok(self)
+    }
+
+    // Minimum time between ticks when a target frame rate is set.
+    pub fn frame_interval(&self) -> Option<f64> {
+        self.target_fps.map(|fps| 1000. / fps)
+    }
+}
+
+// Sets up a config field by field, starting from the defaults or from a config read from JSON, and
+// validates it all at once in `build`.
+pub struct EngineConfigBuilder {
+    config: EngineConfig,
+}
+
+impl From<EngineConfig> for EngineConfigBuilder {
+    fn from(config: EngineConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[allow(dead_code)]
+impl EngineConfigBuilder {
+    pub fn canvas_selector(mut self, selector: &str) -> Self {
+        self.config.canvas_selector = selector.to_string();
+        self
+    }
+
+    pub fn renderer(mut self, renderer: RendererPreference) -> Self {
+        self.config.renderer = renderer;
+        self
+    }
+
+    pub fn max_particles(mut self, max_particles: usize) -> Self {
+        self.config.max_particles = max_particles;
+        self
+    }
+
+    pub fn particle_size(mut self, particle_size: f64) -> Self {
+        self.config.particle_size = particle_size;
+        self
+    }
+
+    pub fn pan_step(mut self, pan_step: f64) -> Self {
+        self.config.pan_step = pan_step;
+        self
+    }
+
+    pub fn background(mut self, background: RGB8) -> Self {
+        self.config.background = Some(Color(background));
+        self
+    }
+
+    pub fn colors(mut self, colors: Palette) -> Self {
+        self.config.colors = colors;
+        self
+    }
+
+    pub fn dpr(mut self, dpr: DprMode) -> Self {
+        self.config.dpr = dpr;
+        self
+    }
+
+    pub fn target_fps(mut self, fps: f64) -> Self {
+        self.config.target_fps = Some(fps);
+        self
+    }
+
+    pub fn fixed_timestep(mut self, timestep: f64) -> Self {
+        self.config.fixed_timestep = Some(timestep);
+        self
+    }
+
+    pub fn auto_resize(mut self, auto_resize: bool) -> Self {
+        self.config.auto_resize = auto_resize;
+        self
+    }
+
+    pub fn resize_anchor(mut self, anchor: ResizeAnchor) -> Self {
+        self.config.resize_anchor = anchor;
+        self
+    }
+
+    pub fn profile_frames(mut self, frames: usize) -> Self {
+        self.config.profile_frames = frames;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    pub fn audio(mut self, audio: AudioSettings) -> Self {
+        self.config.audio = audio;
+        self
+    }
+
+    pub fn navigation(mut self, navigation: NavSettings) -> Self {
+        self.config.navigation = navigation;
+        self
+    }
+
+    pub fn build(self) -> Result<EngineConfig, ConfigError> {
+        self.config.validate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(json: &str) -> Option<&'static str> {
+        match EngineConfig::from_json(json) {
+            Err(ConfigError::InvalidValue { field, .. }) => Some(field),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn the_defaults_are_valid_and_survive_json() {
+        let config = EngineConfig::default().validate().unwrap();
+        assert_eq!(EngineConfig::from_json(&serde_json::to_string(&config).unwrap()), Ok(config));
+        assert_eq!(EngineConfig::from_json("{}"), Ok(EngineConfig::default()));
+    }
+
+    #[test]
+    fn json_sets_only_the_fields_it_names() {
+        let config = EngineConfig::from_json(r##"{"maxParticles": 50, "dpr": {"fixed": 2}, "background": "#102030", "audio": {"music": 0}}"##).unwrap();
+        assert_eq!(config.max_particles, 50);
+        assert_eq!(config.dpr, DprMode::Fixed(2.));
+        assert_eq!(config.background, Some(Color(RGB8 { r: 16, g: 32, b: 48 })));
+        assert_eq!(config.audio.music, 0.);
+        assert_eq!(config.audio.sfx, AudioSettings::default().sfx);
+        assert_eq!(config.pan_step, EngineConfig::default().pan_step);
+    }
+
+    #[test]
+    fn out_of_range_values_name_their_field() {
+        let cases = [
+            (r#"{"canvasSelector": "  "}"#, "canvasSelector"),
+            (r#"{"maxParticles": 0}"#, "maxParticles"),
+            (r#"{"particleSize": 0}"#, "particleSize"),
+            (r#"{"panStep": -1}"#, "panStep"),
+            (r#"{"dpr": {"fixed": 0}}"#, "dpr"),
+            (r#"{"targetFps": 0}"#, "targetFps"),
+            (r#"{"fixedTimestep": -16}"#, "fixedTimestep"),
+            (r#"{"audio": {"sfx": -0.5}}"#, "audio.sfx"),
+            (r#"{"audio": {"range": 0}}"#, "audio.range"),
+            (r#"{"audio": {"panWidth": 0}}"#, "audio.panWidth"),
+            (r#"{"audio": {"maxVoices": 0}}"#, "audio.maxVoices"),
+            (r#"{"navigation": {"cellSize": 0}}"#, "navigation.cellSize"),
+            (r#"{"navigation": {"clearance": -1}}"#, "navigation.clearance"),
+        ];
+        for (json, field) in cases {
+            assert_eq!(rejected(json), Some(field), "{}", json);
+        }
+
+        // JSON can't spell NaN or infinity, but a config built in code can.
+        let config = EngineConfig { pan_step: f64::NAN, ..EngineConfig::default() };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue { field: "panStep", .. })));
+        let config = EngineConfig { target_fps: Some(f64::INFINITY), ..EngineConfig::default() };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue { field: "targetFps", .. })));
+    }
+
+    #[test]
+    fn malformed_json_is_not_a_config() {
+        for json in [r#"{"maxParticle": 10}"#, r#"{"background": "red"}"#, r##"{"colors": {"wall": "#12345"}}"##, "[]", "{"] {
+            assert!(matches!(EngineConfig::from_json(json), Err(ConfigError::InvalidJson(_))), "{}", json);
+        }
+    }
+
+    #[test]
+    fn colours_are_six_hex_digits() {
+        assert_eq!(Color::parse("#ff8000"), Some(Color(RGB8 { r: 255, g: 128, b: 0 })));
+        for hex in ["ff8000", "#ff800", "#ff80000", "#gg8000", "#ff80é"] {
+            assert_eq!(Color::parse(hex), None, "{}", hex);
+        }
+    }
+
+    #[test]
+    fn the_builder_sets_fields_and_validates_them_together() {
+        let config = EngineConfig::builder()
+            .canvas_selector("#game")
+            .renderer(RendererPreference::Canvas2d)
+            .max_particles(50)
+            .fixed_timestep(10.)
+            .seed(7)
+            .build()
+            .unwrap();
+        assert_eq!(config, EngineConfig {
+            canvas_selector: "#game".to_string(),
+            renderer: RendererPreference::Canvas2d,
+            max_particles: 50,
+            fixed_timestep: Some(10.),
+            seed: Some(7),
+            ..EngineConfig::default()
+        });
+
+        assert_eq!(EngineConfig::builder().build(), Ok(EngineConfig::default()));
+        assert!(matches!(EngineConfig::builder().pan_step(f64::NAN).build(), Err(ConfigError::InvalidValue { field: "panStep", .. })));
+        assert!(matches!(EngineConfig::builder().target_fps(0.).build(), Err(ConfigError::InvalidValue { field: "targetFps", .. })));
+
+        // A config from JSON can be adjusted further before it is checked again.
+        let from_json = EngineConfig::from_json(r#"{"maxParticles": 50}"#).unwrap();
+        let config = EngineConfigBuilder::from(from_json).seed(3).build().unwrap();
+        assert_eq!((config.max_particles, config.seed), (50, Some(3)));
+        assert!(matches!(EngineConfigBuilder::from(config).max_particles(0).build(), Err(ConfigError::InvalidValue { field: "maxParticles", .. })));
+    }
+}
@@ -1,7 +1,31 @@
-use web_sys::CanvasRenderingContext2d;
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use crate::game_engine::View;
+use crate::renderer::Renderer;
 
 pub trait Draw {
-    fn draw(&self, context: &CanvasRenderingContext2d, view: &View) -> ();
+    fn draw(&self, renderer: &dyn Renderer, view: &View);
     fn in_view(&self, view: &View) -> bool;
 }
+
+// Shared state, like a flock a game state keeps updating, is drawn as it is when the layer renders.
+impl<T: Draw + ?Sized> Draw for Rc<T> {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        self.as_ref().draw(renderer, view);
+    }
+
+    fn in_view(&self, view: &View) -> bool {
+        self.as_ref().in_view(view)
+    }
+}
+
+impl<T: Draw + ?Sized> Draw for RefCell<T> {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        self.borrow().draw(renderer, view);
+    }
+
+    fn in_view(&self, view: &View) -> bool {
+        self.borrow().in_view(view)
+    }
+}
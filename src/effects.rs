@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::Point2d;
+use crate::config::Color;
+use crate::emitter::Emitter;
+use crate::particle_system::ParticleSystem;
+use crate::rng::Rng;
+
+// The effects every engine starts with; loading a definition with the same name replaces one.
+const PRESETS: &str = include_str!("effects.json");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EffectError {
+    Json(String),
+    InvalidValue { field: String, reason: String },
+    Unknown(String),
+}
+
+impl fmt::Display for EffectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EffectError::Json(error) => write!(f, "invalid effect definitions: {}", error),
+            EffectError::InvalidValue { field, reason } => write!(f, "invalid effect `{}`: {}", field, reason),
+            EffectError::Unknown(name) => write!(f, "no effect named `{}`", name),
+        }
+    }
+}
+
+pub fn invalid(field: String, reason: &str) -> EffectError {
+    EffectError::InvalidValue { field, reason: reason.to_string() }
+}
+
+impl From<EffectError> for JsValue {
+    fn from(error: EffectError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+// A named particle effect: emitters that burst when it starts, and keep emitting at their rate
+// for `duration` milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct Effect {
+    pub emitters: Vec<Emitter>,
+    pub duration: f64,
+}
+
+impl Effect {
+    // Fields are named from the effect down, e.g. `impact.emitters[0].onDeath.size`.
+    pub fn validate(&self, name: &str) -> Result<(), EffectError> {
+        if !(self.duration.is_finite() && self.duration >= 0.) {
+            return Err(invalid(format!("{}.duration", name), "must be zero or more"));
+        }
+        for (index, emitter) in self.emitters.iter().enumerate() {
+            emitter.validate(&format!("{}.emitters[{}]", name, index))?;
+        }
+        Ok(())
+    }
+
+    // The same effect in another base colour and size, e.g. to match the configured palette.
+    pub fn tinted(&self, color: Color, size: f64) -> Self {
+        Self { emitters: self.emitters.iter().map(|emitter| emitter.tinted(color, size)).collect(), ..self.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct EffectLibrary {
+    effects: HashMap<String, Rc<Effect>>,
+}
+
+impl Default for EffectLibrary {
+    fn default() -> Self {
+        let mut library = Self { effects: HashMap::new() };
+        library.load(PRESETS).expect("the built-in effects are valid");
+        library
+    }
+}
+
+impl EffectLibrary {
+    // Adds the effects in a JSON object of name to definition. Nothing is added if any is invalid.
+    pub fn load(&mut self, json: &str) -> Result<Vec<String>, EffectError> {
+        let effects: HashMap<String, Effect> = serde_json::from_str(json).map_err(|error| EffectError::Json(error.to_string()))?;
+
+        let mut names: Vec<String> = effects.keys().cloned().collect();
+        names.sort();
+        for name in names.iter() {
+            effects[name].validate(name)?;
+        }
+        for (name, effect) in effects {
+            self.effects.insert(name, Rc::new(effect));
+        }
+
+        Ok(names)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Rc<Effect>, EffectError> {
+        self.effects.get(name).cloned().ok_or_else(|| EffectError::Unknown(name.to_string()))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.effects.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+// An effect that has been triggered, saved along with the world so emission carries on after a restore.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveEffect {
+    pub effect: Rc<Effect>,
+    pub position: Point2d,
+    pub direction: Point2d,
+    // Milliseconds since it started; `None` until the first tick fires the bursts.
+    pub elapsed: Option<f64>,
+    // Particles emitted at each emitter's rate so far.
+    pub emitted: Vec<u32>,
+}
+
+impl ActiveEffect {
+    pub fn new(effect: Rc<Effect>, position: Point2d, direction: Point2d) -> Self {
+        let emitted = vec![0; effect.emitters.len()];
+        Self { effect, position, direction, elapsed: None, emitted }
+    }
+
+    // Emits what is due after `delta` more milliseconds; false once the effect is over.
+    pub fn advance(&mut self, particle_system: &ParticleSystem, rng: &mut Rng, delta: f64) -> bool {
+        let elapsed = match self.elapsed {
+            None => {
+                for emitter in self.effect.emitters.iter() {
+                    emitter.emit(particle_system, rng, self.position, self.direction);
+                }
+                0.
+            }
+            Some(elapsed) => (elapsed + delta).min(self.effect.duration),
+        };
+        self.elapsed = Some(elapsed);
+
+        for (emitter, emitted) in self.effect.emitters.iter().zip(self.emitted.iter_mut()) {
+            let due = (emitter.rate * elapsed / 1000.).floor() as u32;
+            if due > *emitted {
+                emitter.spawn(particle_system, rng, self.position, self.direction, due - *emitted);
+                *emitted = due;
+            }
+        }
+
+        elapsed < self.effect.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn the_built_in_effects_parse() {
+        let presets: HashMap<String, Effect> = serde_json::from_str(PRESETS).unwrap();
+        assert!(presets.values().all(|effect| !effect.emitters.is_empty()));
+
+        let library = EffectLibrary::default();
+        assert_eq!(library.names(), ["dust", "explosion", "fireworks", "impact", "rain", "smoke", "sparks"]);
+
+        // Ray hits in the demo play this one; its sparks crackle into embers.
+        let impact = library.get("impact").unwrap();
+        assert_eq!(impact.duration, 0.);
+        assert!(impact.emitters[0].on_death.is_some());
+    }
+
+    #[test]
+    fn loading_replaces_effects_by_name_or_adds_none_at_all() {
+        let mut library = EffectLibrary::default();
+        let names = library.names();
+
+        let invalid = r##"{"new": {"emitters": []}, "impact": {"emitters": [{"colour": "#FFFFFF"}]}}"##;
+        assert!(matches!(library.load(invalid), Err(EffectError::Json(_))));
+        assert_eq!(library.names(), names);
+        assert_eq!(library.get("new"), Err(EffectError::Unknown("new".to_string())));
+
+        let loaded = library.load(r#"{"impact": {"duration": 200}, "new": {}}"#).unwrap();
+        assert_eq!(loaded, ["impact", "new"]);
+        assert_eq!(library.get("impact").unwrap().duration, 200.);
+        assert_eq!(library.names().len(), names.len() + 1);
+    }
+
+    #[test]
+    fn effects_emit_at_their_rate_until_their_duration_is_up() {
+        let particle_system = ParticleSystem::new(1000);
+        let smoke = EffectLibrary::default().get("smoke").unwrap();
+        let mut active = ActiveEffect::new(smoke, Point2d { x: 0., y: 0. }, Point2d { x: 0., y: -1. });
+        let mut rng = Rng::new(1);
+
+        assert!(active.advance(&particle_system, &mut rng, 0.));
+        assert_eq!(particle_system.particles().len(), 0, "smoke has no burst");
+
+        assert!(active.advance(&particle_system, &mut rng, 500.));
+        assert_eq!(particle_system.particles().len(), 15);
+
+        assert!(!active.advance(&particle_system, &mut rng, 5000.));
+        assert_eq!(particle_system.particles().len(), 45);
+    }
+
+    #[test]
+    fn out_of_range_emitters_name_their_field() {
+        let cases = [
+            (r#"{"duration": -1}"#, "bad.duration"),
+            (r#"{"emitters": [{}, {"rate": -2}]}"#, "bad.emitters[1].rate"),
+            (r#"{"emitters": [{"size": -1}]}"#, "bad.emitters[0].size"),
+            (r#"{"emitters": [{"speed": [0.7, 0.4]}]}"#, "bad.emitters[0].speed"),
+            (r#"{"emitters": [{"speed": [-1, 0.4]}]}"#, "bad.emitters[0].speed"),
+            (r#"{"emitters": [{"spread": -0.5}]}"#, "bad.emitters[0].spread"),
+            (r#"{"emitters": [{"style": {"trail": {"length": -10}}}]}"#, "bad.emitters[0].style.trail.length"),
+            (r#"{"emitters": [{"onDeath": {"onDeath": {"size": -1}}}]}"#, "bad.emitters[0].onDeath.onDeath.size"),
+        ];
+        for (effect, field) in cases {
+            let mut library = EffectLibrary::default();
+            match library.load(&format!(r#"{{"fine": {{}}, "bad": {}}}"#, effect)) {
+                Err(EffectError::InvalidValue { field: rejected, .. }) => assert_eq!(rejected, field, "{}", effect),
+                other => panic!("{} gave {:?}", effect, other),
+            }
+            assert_eq!(library.get("fine"), Err(EffectError::Unknown("fine".to_string())), "nothing is added");
+        }
+
+        // JSON can't spell NaN, but an emitter built in code can.
+        let effect = Effect { emitters: vec![Emitter { size: f64::NAN, ..Emitter::default() }], duration: 0. };
+        assert!(matches!(effect.validate("nan"), Err(EffectError::InvalidValue { field, .. }) if field == "nan.emitters[0].size"));
+    }
+
+    #[test]
+    fn tinting_recolours_and_resizes_every_emitter_down_to_the_embers() {
+        let impact = EffectLibrary::default().get("impact").unwrap();
+        let blue = Color(rgb::RGB8 { r: 0, g: 0, b: 255 });
+        let tinted = impact.tinted(blue, 6.);
+
+        let sparks = &tinted.emitters[0];
+        assert_eq!((sparks.color, sparks.size), (blue, 6.));
+        let gradient = sparks.style.color.as_ref().unwrap();
+        assert_eq!(gradient.sample(1.), Some(blue), "the sparks cool into the tint");
+        assert_eq!(gradient.sample(0.), impact.emitters[0].style.color.as_ref().unwrap().sample(0.));
+
+        let embers = sparks.on_death.as_ref().unwrap();
+        assert_eq!((embers.color, embers.size), (blue, 3.), "embers stay half the size of the sparks");
+        assert_eq!(sparks.speed, impact.emitters[0].speed);
+    }
+}
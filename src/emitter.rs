@@ -0,0 +1,145 @@
+use std::rc::Rc;
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+use crate::Point2d;
+use crate::config::Color;
+use crate::effects::{invalid, EffectError};
+use crate::forces::ForceField;
+use crate::particle::{Particle, ParticlePixel, ParticleStyle};
+use crate::particle_animation::{ParticleMotion, VelocityCurve};
+use crate::particle_system::ParticleSystem;
+use crate::rng::Rng;
+
+// A recipe for spawning particles: how many, how fast, which way and how they look. Speeds are in
+// pixels per tick before the velocity curve, the spread is the full angle in radians around the
+// direction the emitter is fired in, and lifetimes are in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct Emitter {
+    // Fired all at once when the emitter starts.
+    pub count: u32,
+    // Particles per second for as long as the effect it's part of runs.
+    pub rate: f64,
+    // Width and height of the box around the position that particles start in.
+    pub area: Point2d,
+    // Overrides the direction the emitter is fired in, e.g. straight down for rain.
+    pub direction: Option<Point2d>,
+    pub speed: (f64, f64),
+    pub spread: f64,
+    pub lifetime: u32,
+    pub size: f64,
+    pub color: Color,
+    pub motion: ParticleMotion,
+    pub velocity_curve: VelocityCurve,
+    pub style: Rc<ParticleStyle>,
+    // Act on these particles on top of the system's own force fields.
+    pub forces: Rc<Vec<ForceField>>,
+    // Fired in the direction each particle was heading when it dies.
+    pub on_death: Option<Rc<Emitter>>,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            rate: 0.,
+            area: Point2d { x: 0., y: 0. },
+            direction: None,
+            speed: (0.5, 0.5),
+            spread: 0.,
+            lifetime: 1000,
+            size: 2.,
+            color: Color(RGB8 { r: 255, g: 255, b: 255 }),
+            motion: ParticleMotion::MoveAndFadeOut,
+            velocity_curve: VelocityCurve::Decreasing,
+            style: Rc::new(ParticleStyle::default()),
+            forces: Rc::new(vec![]),
+            on_death: None,
+        }
+    }
+}
+
+impl Emitter {
+    // The same emitter with a new base colour and size. The colour style fades into the colour, and
+    // the particles fired on death are resized in proportion.
+    pub fn tinted(&self, color: Color, size: f64) -> Self {
+        let scale = if self.size > 0. { size / self.size } else { 1. };
+        let style = ParticleStyle { color: self.style.color.as_ref().map(|gradient| gradient.ending_in(color)), ..self.style.as_ref().clone() };
+
+        Self {
+            color,
+            size,
+            style: Rc::new(style),
+            on_death: self.on_death.as_ref().map(|emitter| Rc::new(emitter.tinted(color, emitter.size * scale))),
+            ..self.clone()
+        }
+    }
+
+    // Checks the numbers serde can't, naming the field by its path below `path`.
+    pub fn validate(&self, path: &str) -> Result<(), EffectError> {
+        let field = |name: &str| format!("{}.{}", path, name);
+
+        if !(self.rate.is_finite() && self.rate >= 0.) {
+            return Err(invalid(field("rate"), "must be zero or more"));
+        }
+        if !(self.size.is_finite() && self.size >= 0.) {
+            return Err(invalid(field("size"), "must be zero or more"));
+        }
+        let (slowest, fastest) = self.speed;
+        if !(slowest.is_finite() && fastest.is_finite() && slowest >= 0.) {
+            return Err(invalid(field("speed"), "must be zero or more"));
+        }
+        if slowest > fastest {
+            return Err(invalid(field("speed"), "the slowest speed must come first"));
+        }
+        if !(self.spread.is_finite() && self.spread >= 0.) {
+            return Err(invalid(field("spread"), "must be zero or more"));
+        }
+        if let Some(trail) = &self.style.trail {
+            if !(trail.length.is_finite() && trail.length >= 0.) {
+                return Err(invalid(field("style.trail.length"), "must be zero or more"));
+            }
+            if !(trail.width.is_finite() && trail.width >= 0.) {
+                return Err(invalid(field("style.trail.width"), "must be zero or more"));
+            }
+        }
+        match &self.on_death {
+            Some(emitter) => emitter.validate(&field("onDeath")),
+            None => Ok(()),
+        }
+    }
+
+    // Fires the burst of `count` particles.
+    pub fn emit(&self, particle_system: &ParticleSystem, rng: &mut Rng, position: Point2d, direction: Point2d) {
+        self.spawn(particle_system, rng, position, direction, self.count);
+    }
+
+    // Draws the placement, spread and speed of every particle from `rng`, so emission replays exactly.
+    pub fn spawn(&self, particle_system: &ParticleSystem, rng: &mut Rng, position: Point2d, direction: Point2d, count: u32) {
+        let direction = self.direction.unwrap_or(direction);
+
+        for _ in 0..count {
+            let position = position + Point2d {
+                x: rng.range(-self.area.x / 2., self.area.x / 2.),
+                y: rng.range(-self.area.y / 2., self.area.y / 2.),
+            };
+            let heading = direction.rotate(rng.range(-self.spread / 2., self.spread / 2.));
+            let speed = rng.range(self.speed.0, self.speed.1);
+
+            particle_system.add_particle(
+                Particle::new(
+                    ParticlePixel { position, color: self.color.0, alpha: 1.0, size: self.size, rotation: 0. },
+                    heading,
+                    speed,
+                    self.lifetime,
+                    self.velocity_curve,
+                    self.motion,
+                )
+                .with_style(self.style.clone())
+                .with_forces(self.forces.clone())
+                .with_on_death(self.on_death.clone()),
+            );
+        }
+    }
+}
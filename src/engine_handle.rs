@@ -0,0 +1,130 @@
+use std::{cell::Cell, cell::RefCell, rc::Rc};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::EventTarget;
+
+use crate::browser::{console_log, Browser, EventListener, SizeObserver};
+use crate::error::EngineError;
+use crate::game_engine::GameEngine;
+use crate::game_loop::Game;
+
+type FrameClosure = Closure<dyn FnMut(f64)>;
+
+// A `requestAnimationFrame` loop that cancels its pending frame and frees its closure when stopped or dropped.
+pub struct AnimationLoop {
+    closure: Rc<RefCell<Option<FrameClosure>>>,
+    frame_id: Rc<Cell<Option<i32>>>,
+}
+
+impl AnimationLoop {
+    pub fn start<F: FnMut(f64) + 'static>(mut frame: F) -> Result<Self, EngineError> {
+        let closure: Rc<RefCell<Option<FrameClosure>>> = Rc::new(RefCell::new(None));
+        let frame_id = Rc::new(Cell::new(None));
+
+        // The closure only holds a weak reference to itself, so there is no cycle keeping it alive.
+        let this = Rc::downgrade(&closure);
+        let id = frame_id.clone();
+
+        *closure.borrow_mut() = Some(Closure::new(move |timestamp: f64| {
+            id.set(None);
+            frame(timestamp);
+
+            // `frame` may have stopped the loop, in which case there is nothing left to schedule.
+            let Some(cell) = this.upgrade() else { return };
+            let scheduled = cell.borrow().as_ref().map(Browser::request_animation_frame);
+            // There's no caller to return to from inside the loop, so a failure just ends it.
+            match scheduled {
+                Some(Ok(next)) => id.set(Some(next)),
+                Some(Err(error)) => console_log(&error.to_string()),
+                None => {}
+            }
+        }));
+
+        frame_id.set(Some(Browser::request_animation_frame(closure.borrow().as_ref().unwrap())?));
+
+        Ok(Self { closure, frame_id })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.closure.borrow().is_some()
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(id) = self.frame_id.take() {
+            Browser::cancel_animation_frame(id);
+        }
+        self.closure.borrow_mut().take();
+    }
+}
+
+impl Drop for AnimationLoop {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Owns everything an engine has registered with the page, so several engines can run side by side
+// and each can be torn down without leaking listeners or frames.
+pub struct EngineHandle {
+    engine: GameEngine,
+    listeners: Vec<EventListener>,
+    resize: Option<SizeObserver>,
+    animation: Option<AnimationLoop>,
+}
+
+impl EngineHandle {
+    pub fn new(engine: GameEngine) -> Self {
+        Self { engine, listeners: Vec::new(), resize: None, animation: None }
+    }
+
+    pub fn engine(&self) -> &GameEngine {
+        &self.engine
+    }
+
+    pub fn listen<E, F>(&mut self, target: &EventTarget, event: &'static str, mut handler: F) -> Result<(), EngineError>
+    where
+        E: JsCast,
+        F: FnMut(&GameEngine, E) + 'static,
+    {
+        let engine = self.engine.clone();
+        let listener = EventListener::new(target, event, move |event: E| handler(&engine, event))?;
+        self.listeners.push(listener);
+        Ok(())
+    }
+
+    // Follows the size of the canvas's container, if the engine is set to.
+    pub fn observe_size(&mut self) -> Result<(), EngineError> {
+        self.resize = self.engine.observe_size()?;
+        Ok(())
+    }
+
+    pub fn run<G: Game + 'static>(&mut self, game: G) -> Result<(), EngineError> {
+        self.stop_loop();
+        self.animation = Some(self.engine.run(game)?);
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.animation.as_ref().is_some_and(AnimationLoop::is_running)
+    }
+
+    // Cancels the animation frame and removes every listener; the engine's state is freed once the
+    // last handle to it goes.
+    pub fn stop(&mut self) {
+        self.stop_loop();
+        self.listeners.clear();
+        self.resize = None;
+    }
+
+    fn stop_loop(&mut self) {
+        if let Some(mut animation) = self.animation.take() {
+            animation.stop();
+        }
+    }
+}
+
+impl Drop for EngineHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
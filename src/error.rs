@@ -0,0 +1,67 @@
+use std::fmt;
+use wasm_bindgen::JsValue;
+
+use crate::config::ConfigError;
+use crate::snapshot::SnapshotError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineError {
+    NoWindow,
+    NoDocument,
+    InvalidSelector(String),
+    SelectorNotFound(String),
+    WrongElementType { selector: String, expected: &'static str },
+    ContextUnavailable(&'static str),
+    AnimationFrame(String),
+    EventListener { event: &'static str, error: String },
+    InvalidConfig(ConfigError),
+    Storage(String),
+    InvalidSnapshot(SnapshotError),
+    Audio(String),
+    Network(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::NoWindow => write!(f, "no global `window` exists"),
+            EngineError::NoDocument => write!(f, "no document on `window`"),
+            EngineError::InvalidSelector(selector) => write!(f, "`{}` is not a valid selector", selector),
+            EngineError::SelectorNotFound(selector) => write!(f, "no element matches `{}`", selector),
+            EngineError::WrongElementType { selector, expected } => write!(f, "`{}` is not a {}", selector, expected),
+            EngineError::ContextUnavailable(kind) => write!(f, "could not create a `{}` context", kind),
+            EngineError::AnimationFrame(error) => write!(f, "could not request an animation frame: {}", error),
+            EngineError::EventListener { event, error } => write!(f, "could not listen for `{}` events: {}", event, error),
+            EngineError::InvalidConfig(error) => error.fmt(f),
+            EngineError::Storage(error) => write!(f, "local storage unavailable: {}", error),
+            EngineError::InvalidSnapshot(error) => error.fmt(f),
+            EngineError::Audio(error) => write!(f, "audio unavailable: {}", error),
+            EngineError::Network(error) => write!(f, "could not connect: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<ConfigError> for EngineError {
+    fn from(error: ConfigError) -> Self {
+        EngineError::InvalidConfig(error)
+    }
+}
+
+impl From<SnapshotError> for EngineError {
+    fn from(error: SnapshotError) -> Self {
+        EngineError::InvalidSnapshot(error)
+    }
+}
+
+impl From<EngineError> for JsValue {
+    fn from(error: EngineError) -> Self {
+        js_sys::Error::new(&error.to_string()).into()
+    }
+}
+
+// Describes a JS exception for the error message; falls back to the debug form for non-strings.
+pub fn describe(value: &JsValue) -> String {
+    value.as_string().unwrap_or_else(|| format!("{:?}", value))
+}
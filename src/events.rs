@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use serde::Serialize;
+
+use crate::Point2d;
+use crate::forces::CollisionResponse;
+use crate::game_engine::GameEngine;
+use crate::ray::Intersection;
+use crate::replay::InputEvent;
+use crate::shapes::Line;
+use crate::snapshot::ViewState;
+
+// Handlers that keep queueing events for each other are cut off after this many rounds per frame;
+// whatever is left is delivered on the next one.
+pub const MAX_FLUSH_ROUNDS: usize = 8;
+
+// Something that happened in the engine or the game. Serialized with a `type` field for JS.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum EngineEvent {
+    RayHit(Intersection),
+    #[serde(rename_all = "camelCase")]
+    ParticleDied { position: Point2d, heading: Point2d },
+    ViewChanged(ViewState),
+    InputAction { input: InputEvent },
+    Collision { position: Point2d, wall: Line, response: CollisionResponse },
+    #[serde(rename_all = "camelCase")]
+    Resize { size: Point2d, pixel_ratio: f64 },
+    // Anything the game or JS wants to announce, with an arbitrary JSON payload.
+    Custom { name: String, data: serde_json::Value },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    RayHit,
+    ParticleDied,
+    ViewChanged,
+    InputAction,
+    Collision,
+    Resize,
+    Custom,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 7] = [
+        EventKind::RayHit,
+        EventKind::ParticleDied,
+        EventKind::ViewChanged,
+        EventKind::InputAction,
+        EventKind::Collision,
+        EventKind::Resize,
+        EventKind::Custom,
+    ];
+
+    // The name used for the `type` field in JS.
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::RayHit => "rayHit",
+            EventKind::ParticleDied => "particleDied",
+            EventKind::ViewChanged => "viewChanged",
+            EventKind::InputAction => "inputAction",
+            EventKind::Collision => "collision",
+            EventKind::Resize => "resize",
+            EventKind::Custom => "custom",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+impl EngineEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            EngineEvent::RayHit(_) => EventKind::RayHit,
+            EngineEvent::ParticleDied { .. } => EventKind::ParticleDied,
+            EngineEvent::ViewChanged(_) => EventKind::ViewChanged,
+            EngineEvent::InputAction { .. } => EventKind::InputAction,
+            EngineEvent::Collision { .. } => EventKind::Collision,
+            EngineEvent::Resize { .. } => EventKind::Resize,
+            EngineEvent::Custom { .. } => EventKind::Custom,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
+
+// Immediate handlers run as soon as an event is emitted; deferred ones get it when the queue is
+// flushed at the end of the frame, which is the safe place to call back into JS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Immediate,
+    Deferred,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u32);
+
+// Ids cross into JS as plain numbers.
+impl SubscriptionId {
+    pub fn to_u32(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_u32(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+pub type Handler = Rc<RefCell<dyn FnMut(&GameEngine, &EngineEvent)>>;
+
+struct Subscription {
+    id: SubscriptionId,
+    // `None` receives every event.
+    kind: Option<EventKind>,
+    delivery: Delivery,
+    handler: Handler,
+}
+
+// Queued events go to every matching subscriber, or just to `target` when one was busy or deferred.
+pub struct Queued {
+    pub event: EngineEvent,
+    pub target: Option<SubscriptionId>,
+}
+
+// Who listens to what. Dispatch itself lives on `GameEngine`, as handlers get the engine and may
+// subscribe, unsubscribe or emit while an event is being delivered.
+#[derive(Default)]
+pub struct EventBus {
+    subscriptions: Vec<Subscription>,
+    queue: VecDeque<Queued>,
+    next_id: u32,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self, kind: Option<EventKind>, delivery: Delivery, handler: Handler) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscriptions.push(Subscription { id, kind, delivery, handler });
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions.retain(|subscription| subscription.id != id);
+        self.queue.retain(|queued| queued.target != Some(id));
+        self.subscriptions.len() != before
+    }
+
+    pub fn is_subscribed(&self, id: SubscriptionId) -> bool {
+        self.subscriptions.iter().any(|subscription| subscription.id == id)
+    }
+
+    // Matching subscribers in the order they subscribed, with how each wants the event.
+    pub fn handlers(&self, kind: EventKind) -> Vec<(SubscriptionId, Delivery, Handler)> {
+        self.subscriptions
+            .iter()
+            .filter(|subscription| subscription.kind.is_none_or(|wanted| wanted == kind))
+            .map(|subscription| (subscription.id, subscription.delivery, subscription.handler.clone()))
+            .collect()
+    }
+
+    pub fn handler(&self, id: SubscriptionId) -> Option<Handler> {
+        self.subscriptions.iter().find(|subscription| subscription.id == id).map(|subscription| subscription.handler.clone())
+    }
+
+    pub fn push(&mut self, event: EngineEvent, target: Option<SubscriptionId>) {
+        self.queue.push_back(Queued { event, target });
+    }
+
+    pub fn take_queue(&mut self) -> VecDeque<Queued> {
+        std::mem::take(&mut self.queue)
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::config::EngineConfig;
+
+    fn engine() -> GameEngine {
+        GameEngine::headless(EngineConfig { seed: Some(1), ..EngineConfig::default() }, Point2d { x: 640., y: 480. })
+    }
+
+    fn custom(name: &str) -> EngineEvent {
+        EngineEvent::Custom { name: name.to_string(), data: serde_json::Value::Null }
+    }
+
+    fn name(event: &EngineEvent) -> String {
+        match event {
+            EngineEvent::Custom { name, .. } => name.clone(),
+            other => other.kind().name().to_string(),
+        }
+    }
+
+    // Subscribes a handler that notes down the name of every event it gets.
+    fn listen(engine: &GameEngine, delivery: Delivery, log: &Rc<RefCell<Vec<String>>>, who: &'static str) -> SubscriptionId {
+        let log = log.clone();
+        engine.subscribe(Some(EventKind::Custom), delivery, move |_: &GameEngine, event: &EngineEvent| {
+            log.borrow_mut().push(format!("{} {}", who, name(event)));
+        })
+    }
+
+    #[test]
+    fn deferred_handlers_wait_for_the_flush() {
+        let engine = engine();
+        let log = Rc::new(RefCell::new(vec![]));
+        listen(&engine, Delivery::Deferred, &log, "later");
+        listen(&engine, Delivery::Immediate, &log, "now");
+        engine.subscribe(Some(EventKind::RayHit), Delivery::Immediate, |_: &GameEngine, _: &EngineEvent| panic!("wrong kind"));
+
+        engine.emit(custom("a"));
+        assert_eq!(*log.borrow(), ["now a"]);
+        engine.flush_events();
+        assert_eq!(*log.borrow(), ["now a", "later a"]);
+    }
+
+    #[test]
+    fn handlers_unsubscribed_mid_dispatch_miss_the_event() {
+        let engine = engine();
+        let log = Rc::new(RefCell::new(vec![]));
+        let victim = Rc::new(Cell::new(None));
+
+        let target = victim.clone();
+        let first = engine.subscribe(Some(EventKind::Custom), Delivery::Immediate, move |engine: &GameEngine, _: &EngineEvent| {
+            if let Some(id) = target.take() {
+                assert!(engine.unsubscribe(id));
+            }
+        });
+        victim.set(Some(listen(&engine, Delivery::Immediate, &log, "second")));
+        let deferred = listen(&engine, Delivery::Deferred, &log, "deferred");
+
+        engine.emit(custom("a"));
+        assert!(log.borrow().is_empty());
+
+        // Unsubscribing also drops what was already queued for it.
+        assert!(engine.unsubscribe(deferred));
+        engine.flush_events();
+        assert!(log.borrow().is_empty());
+        assert!(!engine.unsubscribe(deferred));
+        assert!(engine.unsubscribe(first));
+    }
+
+    #[test]
+    fn re_entrant_emits_reach_the_busy_handler_once_it_returns() {
+        let engine = engine();
+        let log = Rc::new(RefCell::new(vec![]));
+        let inner = log.clone();
+        engine.subscribe(Some(EventKind::Custom), Delivery::Immediate, move |engine: &GameEngine, event: &EngineEvent| {
+            let name = name(event);
+            inner.borrow_mut().push(format!("echo {}", name));
+            if name.len() < 3 {
+                engine.emit(custom(&format!("{}!", name)));
+            }
+        });
+        listen(&engine, Delivery::Immediate, &log, "other");
+
+        engine.emit(custom("a"));
+        // The other handler hears the echo straight away; the echoing one gets it after the flush.
+        assert_eq!(*log.borrow(), ["echo a", "other a!", "other a"]);
+        engine.flush_events();
+        assert_eq!(*log.borrow(), ["echo a", "other a!", "other a", "echo a!", "other a!!", "echo a!!"]);
+    }
+
+    #[test]
+    fn flushing_stops_after_the_round_limit_and_carries_on_next_frame() {
+        let engine = engine();
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        engine.subscribe(Some(EventKind::Custom), Delivery::Deferred, move |engine: &GameEngine, _: &EngineEvent| {
+            counter.set(counter.get() + 1);
+            engine.defer(custom("again"));
+        });
+
+        engine.defer(custom("start"));
+        engine.flush_events();
+        assert_eq!(count.get(), MAX_FLUSH_ROUNDS);
+        engine.flush_events();
+        assert_eq!(count.get(), 2 * MAX_FLUSH_ROUNDS);
+    }
+
+    #[test]
+    fn events_go_to_js_with_their_type() {
+        let json = EngineEvent::Resize { size: Point2d { x: 1., y: 2. }, pixel_ratio: 2. }.to_json().unwrap();
+        assert_eq!(json, r#"{"type":"resize","size":{"x":1.0,"y":2.0},"pixelRatio":2.0}"#);
+        for kind in EventKind::ALL {
+            assert_eq!(EventKind::parse(kind.name()), Some(kind));
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+use serde::{Deserialize, Serialize};
+
+use crate::Point2d;
+use crate::shapes::Line;
+
+// Forces acting on every particle in a `ParticleSystem`. Strengths are accelerations in pixels per
+// second squared; fields with a radius have no effect beyond it and fall off linearly towards it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ForceField {
+    Gravity { acceleration: Point2d },
+    // Pulls particles towards moving with the wind; `drag` is how quickly, per second.
+    Wind { velocity: Point2d, drag: f64 },
+    // A negative strength pushes particles away instead.
+    Attractor { position: Point2d, strength: f64, radius: f64 },
+    // Swirls particles around the center, clockwise for a positive strength on screen.
+    Vortex { center: Point2d, strength: f64, radius: f64 },
+    // Smooth noise over position and time; `scale` is the size of a swirl in pixels.
+    Turbulence { strength: f64, scale: f64, speed: f64 },
+}
+
+impl ForceField {
+    // `velocity` is what the particle has picked up from forces so far, in pixels per second;
+    // `time` is the engine time in seconds.
+    pub fn acceleration(&self, position: Point2d, velocity: Point2d, time: f64) -> Point2d {
+        match *self {
+            ForceField::Gravity { acceleration } => acceleration,
+            ForceField::Wind { velocity: wind, drag } => Point2d { x: (wind.x - velocity.x) * drag, y: (wind.y - velocity.y) * drag },
+            ForceField::Attractor { position: target, strength, radius } => {
+                let Some((direction, weight)) = falloff(position, target, radius) else { return ZERO };
+                Point2d { x: -direction.x * strength * weight, y: -direction.y * strength * weight }
+            }
+            ForceField::Vortex { center, strength, radius } => {
+                let Some((direction, weight)) = falloff(position, center, radius) else { return ZERO };
+                Point2d { x: -direction.y * strength * weight, y: direction.x * strength * weight }
+            }
+            ForceField::Turbulence { strength, scale, speed } => {
+                let (x, y) = (position.x / scale, position.y / scale);
+                let drift = time * speed;
+                Point2d {
+                    x: value_noise(x + drift, y) * strength,
+                    y: value_noise(x - drift + NOISE_OFFSET, y + NOISE_OFFSET) * strength,
+                }
+            }
+        }
+    }
+}
+
+const ZERO: Point2d = Point2d { x: 0., y: 0. };
+// Samples the second axis of turbulence somewhere unrelated to the first.
+const NOISE_OFFSET: f64 = 71.3;
+
+// The unit vector from `origin` out to `position` and a weight fading from 1 to 0 at `radius`.
+fn falloff(position: Point2d, origin: Point2d, radius: f64) -> Option<(Point2d, f64)> {
+    let offset = position - origin;
+    let distance = offset.length();
+    if distance == 0. || distance >= radius {
+        return None;
+    }
+
+    Some((offset * (1. / distance), 1. - distance / radius))
+}
+
+fn lattice(x: i64, y: i64) -> f64 {
+    let mut hash = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash ^= hash >> 32;
+    (hash >> 11) as f64 / (1u64 << 53) as f64 * 2. - 1.
+}
+
+// Smoothly interpolated random values on an integer grid, in [-1, 1]. Depends only on its inputs,
+// so it never draws from the engine's random streams.
+fn value_noise(x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (ix, iy) = (x0 as i64, y0 as i64);
+    let smooth = |t: f64| t * t * (3. - 2. * t);
+    let (sx, sy) = (smooth(x - x0), smooth(y - y0));
+
+    let top = lattice(ix, iy) + (lattice(ix + 1, iy) - lattice(ix, iy)) * sx;
+    let bottom = lattice(ix, iy + 1) + (lattice(ix + 1, iy + 1) - lattice(ix, iy + 1)) * sx;
+    top + (bottom - top) * sy
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CollisionResponse {
+    Bounce,
+    Stick,
+    Die,
+}
+
+// How particles react when they cross a scene wall. On a bounce, `restitution` is the share of the
+// speed into the wall that is kept, `friction` the share of the speed along it that is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Collisions {
+    pub response: CollisionResponse,
+    pub restitution: f64,
+    pub friction: f64,
+}
+
+impl Collisions {
+    pub fn bounce(restitution: f64, friction: f64) -> Self {
+        Self { response: CollisionResponse::Bounce, restitution, friction }
+    }
+
+    pub fn stick() -> Self {
+        Self { response: CollisionResponse::Stick, restitution: 0., friction: 1. }
+    }
+
+    pub fn die() -> Self {
+        Self { response: CollisionResponse::Die, restitution: 0., friction: 1. }
+    }
+
+    // Splits `velocity` against a wall with unit `normal` and scales both parts.
+    pub fn reflect(&self, velocity: Point2d, normal: Point2d) -> Point2d {
+        let into = velocity.dot(normal);
+        let along = velocity - normal * into;
+        let keep = 1. - self.friction.clamp(0., 1.);
+
+        along * keep - normal * (into * self.restitution)
+    }
+}
+
+// A particle meeting a wall, reported back to the system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub position: Point2d,
+    pub wall: Line,
+    pub response: CollisionResponse,
+}
+
+// What a particle needs from its system for one step. Times are in seconds.
+pub struct ForceContext<'a> {
+    pub fields: &'a [ForceField],
+    pub collisions: Option<Collisions>,
+    pub walls: &'a [Line],
+    pub delta: f64,
+    pub time: f64,
+    pub hits: RefCell<Vec<Hit>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64) -> Point2d {
+        Point2d { x, y }
+    }
+
+    fn close(a: Point2d, b: Point2d) -> bool {
+        a.distance(b) < 1e-9
+    }
+
+    #[test]
+    fn bounces_keep_some_speed_into_the_wall_and_lose_some_along_it() {
+        let up = point(0., -1.);
+        let velocity = point(30., 40.);
+        assert!(close(Collisions::bounce(1., 0.).reflect(velocity, up), point(30., -40.)));
+        assert!(close(Collisions::bounce(0.5, 0.2).reflect(velocity, up), point(24., -20.)));
+        assert!(close(Collisions::bounce(0.5, 2.).reflect(velocity, up), point(0., -20.)), "friction is at most everything");
+        assert!(close(Collisions::stick().reflect(velocity, up), ZERO));
+    }
+
+    #[test]
+    fn fields_fade_out_towards_their_radius() {
+        assert_eq!(falloff(point(0., 0.), point(0., 0.), 10.), None, "no direction from the middle");
+        assert_eq!(falloff(point(10., 0.), point(0., 0.), 10.), None);
+        let (direction, weight) = falloff(point(0., 4.), point(0., 0.), 10.).unwrap();
+        assert!(close(direction, point(0., 1.)));
+        assert!((weight - 0.6).abs() < 1e-12);
+
+        let attractor = ForceField::Attractor { position: ZERO, strength: 100., radius: 10. };
+        assert!(close(attractor.acceleration(point(5., 0.), ZERO, 0.), point(-50., 0.)));
+        let vortex = ForceField::Vortex { center: ZERO, strength: 100., radius: 10. };
+        assert!(close(vortex.acceleration(point(5., 0.), ZERO, 0.), point(0., 50.)));
+        let wind = ForceField::Wind { velocity: point(10., 0.), drag: 2. };
+        assert!(close(wind.acceleration(ZERO, point(4., 3.), 0.), point(12., -6.)));
+    }
+
+    #[test]
+    fn noise_is_smooth_bounded_and_the_same_every_time() {
+        let mut last = value_noise(-7., 0.3);
+        for step in 1..2000 {
+            let x = step as f64 * 0.01 - 7.;
+            let value = value_noise(x, 0.3);
+            assert!((-1. ..=1.).contains(&value));
+            assert!((value - last).abs() < 0.05, "jumped at {}", x);
+            assert_eq!(value, value_noise(x, 0.3));
+            last = value;
+        }
+
+        // On the grid itself it is just the lattice value, which varies from point to point.
+        assert_eq!(value_noise(3., -2.), lattice(3, -2));
+        assert_ne!(lattice(3, -2), lattice(-2, 3));
+    }
+}
@@ -3,7 +3,6 @@
 use rgb::*;
 use std::cell::{RefCell, Ref};
 use std::rc::Rc;
-use js_sys::Math::random;
 use web_sys::{MouseEvent};
 use crate::browser::console_log;
 
@@ -13,19 +12,35 @@ use crate::particle_animation::*;
 use crate::particle::*;
 use crate::game_engine::View;
 use crate::particle_system::ParticleSystem;
-use crate::ray::Ray;
-use std::borrow::Borrow;
-
-const RED: RGB8 = RGB8 { r: 255, g: 0, b: 0 };
-const GREEN: RGB8 = RGB8 { r: 0, g: 255, b: 0 };
-const WHITE: RGB8 = RGB8 { r: 255, g: 255, b: 255 };
-const BLACK: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+use crate::ray::{Intersection, Ray};
+use crate::lighting::Light;
+use crate::rng::RngStream;
+use crate::scene::Scene;
+use crate::tween::{Easing, Parallel, Tween};
+use crate::forces::{CollisionResponse, Collisions, ForceField};
+use crate::events::{Delivery, EngineEvent, EventKind};
+use crate::replay::InputEvent;
+use crate::states::{GameState, LoadingScreen, ScreenTint, Transition};
+use crate::game_loop::Game;
+use crate::audio::SoundOptions;
+use crate::assets::SceneAsset;
+use crate::navigation::{NavDebug, NavGrid, Path, PathOptions};
+use crate::ai::{Agent, Flock};
+use std::f64;
 
 const DIR_UP: Point2d = Point2d { x: 0., y: -1. };
 const DIR_DOWN: Point2d = Point2d { x: 0., y: 1. };
 const DIR_LEFT: Point2d = Point2d { x: -1., y: 0. };
 const DIR_RIGHT: Point2d = Point2d { x: 1., y: 0. };
 
+// Least time in milliseconds between two impact sounds.
+const IMPACT_SOUND_INTERVAL: f64 = 150.;
+
+const BOIDS_PER_RELEASE: usize = 8;
+
+// Radians per millisecond: one breath every four seconds or so.
+const BREATHING_SPEED: f64 = f64::consts::TAU / 4000.;
+
 impl From<MouseEvent> for Point2d {
     fn from(e: MouseEvent) -> Self {
         Self {
@@ -35,126 +50,374 @@ impl From<MouseEvent> for Point2d {
     }
 }
 
-pub fn handle_keypress(game_engine: &GameEngine, key_code: u32) -> () {
+pub fn handle_keypress(game_engine: &GameEngine, key_code: u32) {
+    let step: f64 = game_engine.config().pan_step;
+
     match key_code {
-        37 => { game_engine.shift_view_by(Point2d { x: 10., y: 0. }); } // left
-        38 => { game_engine.shift_view_by(Point2d { x: 0., y: 10. }); } // up
-        39 => { game_engine.shift_view_by(Point2d { x: -10., y: 0. }); } // right
-        40 => { game_engine.shift_view_by(Point2d { x: 0., y: -10. }); } // down
-        67 => { game_engine.reset_view(); } // "C"
+        37 => { game_engine.shift_view_by(Point2d { x: step, y: 0. }); } // left
+        38 => { game_engine.shift_view_by(Point2d { x: 0., y: step }); } // up
+        39 => { game_engine.shift_view_by(Point2d { x: -step, y: 0. }); } // right
+        40 => { game_engine.shift_view_by(Point2d { x: 0., y: -step }); } // down
+        67 => { recenter(game_engine); } // "C"
+        187 => { zoom_by(game_engine, 1.25); } // "+"
+        189 => { zoom_by(game_engine, 0.8); } // "-"
+        68 => { game_engine.layers().toggle("debug"); } // "D"
+        76 => { game_engine.layers().toggle("lighting"); } // "L"
+        49 => { play_at_mouse(game_engine, "explosion"); } // "1"
+        50 => { play_at_mouse(game_engine, "fireworks"); } // "2"
+        51 => { play_at_mouse(game_engine, "smoke"); } // "3"
+        52 => { play_at_mouse(game_engine, "dust"); } // "4"
+        53 => { make_it_rain(game_engine); } // "5"
+        75 => { cycle_collisions(game_engine); } // "K"
         _ => ()
     }
 }
 
-pub fn tick(game_engine: &GameEngine) {
-    let mouse: Option<Point2d> = game_engine.mouse();
-    let view: Ref<View> = game_engine.view();
+fn play_at_mouse(game_engine: &GameEngine, name: &str) {
+    let Some(mouse) = game_engine.mouse() else { return };
+    let position = game_engine.view().to_world(&mouse);
+    game_engine.spawn_effect(name, position).ok();
+}
 
-    // For ray detection.
-    let mut lines: Vec<Box<Line>> = vec![];
+// Rain falls from just above the top of the view.
+fn make_it_rain(game_engine: &GameEngine) {
+    let top = game_engine.view().to_world(&Point2d { x: game_engine.view().size.x / 2., y: 0. });
+    game_engine.particle_system().play("rain", top, DIR_DOWN).ok();
+}
 
-    // All items that will be rendered.
-    let mut items: Vec<Box<dyn Draw>> = vec![];
+// Sparks go from bouncing off the walls to sticking to them, to burning out on them, and back.
+fn cycle_collisions(game_engine: &GameEngine) {
+    let particle_system = game_engine.particle_system();
+    let next = match particle_system.collisions().map(|collisions| collisions.response) {
+        Some(CollisionResponse::Bounce) => Collisions::stick(),
+        Some(CollisionResponse::Stick) => Collisions::die(),
+        _ => Collisions::bounce(0.5, 0.2),
+    };
+    particle_system.set_collisions(Some(next));
+}
 
-    let line: Box<Line> = Line::new(
-        Point2d { x: 200., y: -200. },
-        Point2d { x: 200., y: 200. },
-        BLACK,
-    );
-    let line2: Box<Line> = Line::new(
-        Point2d { x: -200., y: -200. },
-        Point2d { x: -200., y: 200. },
-        BLACK,
-    );
-    let line3: Box<Line> = Line::new(
-        Point2d { x: -200., y: 200. },
-        Point2d { x: 200., y: 200. },
-        BLACK,
-    );
-    let line4: Box<Line> = Line::new(
-        Point2d { x: -200., y: -200. },
-        Point2d { x: 200., y: -200. },
-        BLACK,
-    );
+// Glides the view back to the origin at the default zoom.
+fn recenter(game_engine: &GameEngine) {
+    let (center, zoom) = {
+        let view = game_engine.view();
+        (view.center, view.zoom)
+    };
 
-    items.push(line.clone());
-    items.push(line2.clone());
-    items.push(line3.clone());
-    items.push(line4.clone());
-
-    items.push(
-        Circle::new(
-            view.center,
-            10,
-            BLACK,
-        )
+    game_engine.animate(
+        Parallel::new()
+            .with(Tween::new(center, Point2d { x: 0., y: 0. }, 400., |engine: &GameEngine, center| engine.set_view_center(center)).with_easing(Easing::CubicInOut))
+            .with(Tween::new(zoom, 1., 400., |engine: &GameEngine, zoom| engine.set_zoom(zoom)).with_easing(Easing::CubicInOut))
     );
+}
+
+fn zoom_by(game_engine: &GameEngine, factor: f64) {
+    let zoom = game_engine.view().zoom;
+    game_engine.animate(Tween::new(zoom, zoom * factor, 200., |engine: &GameEngine, zoom| engine.set_zoom(zoom)).with_easing(Easing::QuadOut));
+}
+
+// Mirrors `incoming` about `wall`, so sparks fly back off it.
+fn glance_off(incoming: Point2d, wall: &Line) -> Point2d {
+    match (wall.to - wall.from).unit() {
+        Some(along) => Collisions::bounce(1., 0.).reflect(incoming, along.perpendicular()),
+        None => -incoming,
+    }
+}
+
+pub fn setup(game_engine: &GameEngine) {
+    let mut scene = Scene::default();
+    let wall: RGB8 = game_engine.config().colors.wall.0;
+
+    scene.add_wall(Line { from: Point2d { x: 200., y: -200. }, to: Point2d { x: 200., y: 200. }, color: wall });
+    scene.add_wall(Line { from: Point2d { x: -200., y: -200. }, to: Point2d { x: -200., y: 200. }, color: wall });
+    scene.add_wall(Line { from: Point2d { x: -200., y: 200. }, to: Point2d { x: 200., y: 200. }, color: wall });
+    scene.add_wall(Line { from: Point2d { x: -200., y: -200. }, to: Point2d { x: 200., y: -200. }, color: wall });
+
+    game_engine.set_scene(scene);
+
+    // Sparks sag, flutter a little and bounce off the walls until they burn out.
+    let particle_system = game_engine.particle_system();
+    particle_system.add_force(ForceField::Gravity { acceleration: Point2d { x: 0., y: 120. } });
+    particle_system.add_force(ForceField::Turbulence { strength: 60., scale: 80., speed: 0.5 });
+    particle_system.set_collisions(Some(Collisions::bounce(0.5, 0.2)));
+
+    game_engine.subscribe(Some(EventKind::RayHit), Delivery::Immediate, on_ray_hit);
+
+    // Sweeping the ray across a corner hits wall after wall, so the "impact" sound, once loaded,
+    // only plays when it hasn't for a while.
+    let mut last_impact: Option<f64> = None;
+    game_engine.subscribe(Some(EventKind::RayHit), Delivery::Immediate, move |game_engine: &GameEngine, event: &EngineEvent| {
+        let EngineEvent::RayHit(hit) = event else { return };
+        let now = game_engine.time().elapsed;
+        if last_impact.is_some_and(|last| now - last < IMPACT_SOUND_INTERVAL) {
+            return;
+        }
+        if game_engine.audio().play("impact", SoundOptions::default().at(hit.point)).is_some() {
+            last_impact = Some(now);
+        }
+    });
+    // Assets from the preload manifest hold the game back behind a loading screen.
+    if game_engine.assets().is_loading() {
+        game_engine.push_state(LoadingScreen::new(PlayState::default(), Transition::fade(400.)), Transition::Cut);
+    } else {
+        game_engine.push_state(PlayState::default(), Transition::Cut);
+    }
+}
+
+// Where the ray lands the wall glows briefly and throws sparks in the configured particle colour and size.
+fn on_ray_hit(game_engine: &GameEngine, event: &EngineEvent) {
+    let EngineEvent::RayHit(hit) = event else { return };
+    let config = game_engine.config();
+
+    game_engine.lights().add(Light::point(hit.point, config.colors.hit_light.0, 40.).with_lifetime(300));
+    let particle_system = game_engine.particle_system();
+    let Ok(impact) = particle_system.effects().get("impact") else { return };
+    particle_system.start(Rc::new(impact.tinted(config.colors.particle, config.particle_size)), hit.point, glance_off(hit.direction, &hit.target));
+}
+
+// Everything the demo does happens in its states, so there is nothing left for the game itself.
+pub struct Demo;
+
+impl Game for Demo {
+    fn update(&mut self, _game_engine: &GameEngine, _dt: f64) {}
+}
+
+// Aiming the ray at the walls. Escape or "P" pauses, Home restarts with a fade, "N" shows the way
+// from the centre to the mouse and "B" lets loose a few boids there. Boids scatter from the mouse,
+// and while the way is shown they find their own back to the centre. The light at the view centre
+// slowly breathes.
+#[derive(Default)]
+pub struct PlayState {
+    // Breathing phase in radians.
+    breath: f64,
+    aim: Option<(Ray, Option<Intersection>)>,
+    navigate: bool,
+    // Set after the first update, so a wall the ray is already on when the state starts, or is
+    // restored, isn't taken for a new hit.
+    aiming: bool,
+    route: Option<Route>,
+    flock: Rc<RefCell<Flock>>,
+    // Where the flock's path was last looked for, found or not.
+    herd_goal: Option<Point2d>,
+}
+
+// The path shown with "N", kept until its ends or the level change.
+struct Route {
+    from: Point2d,
+    to: Point2d,
+    grid: Rc<NavGrid>,
+    path: Option<Path>,
+}
+
+impl PlayState {
+    fn release_boids(&mut self, game_engine: &GameEngine) {
+        let Some(mouse) = game_engine.mouse() else { return };
+        let position = game_engine.view().to_world(&mouse);
+        let color = game_engine.config().colors.marker.0;
+
+        let mut rng = game_engine.rng(RngStream::Gameplay);
+        for _ in 0..BOIDS_PER_RELEASE {
+            let direction = rng.unit_vector();
+            let agent = Agent::new(Point2d { x: position.x + direction.x * 10., y: position.y + direction.y * 10. }, color)
+                .with_velocity(Point2d { x: direction.x * 60., y: direction.y * 60. });
+            self.flock.borrow_mut().add(agent);
+        }
+    }
+
+    // Points the flock away from the mouse and, while the way is shown, along a path to the centre.
+    fn herd(&mut self, game_engine: &GameEngine) {
+        let mut flock = self.flock.borrow_mut();
+        if flock.agents.is_empty() {
+            return;
+        }
+
+        let view = game_engine.view();
+        flock.threat = game_engine.mouse().map(|mouse| view.to_world(&mouse));
+
+        let goal = self.navigate.then_some(view.center);
+        if goal != self.herd_goal {
+            let path = goal.and_then(|goal| game_engine.find_path(flock.center().unwrap_or(goal), goal, PathOptions::default()));
+            flock.set_path(path.map(|path| path.points));
+            self.herd_goal = goal;
+        }
+    }
+}
 
-    lines.push(line);
-    lines.push(line2);
-    lines.push(line3);
-    lines.push(line4);
+impl GameState for PlayState {
+    // A scene called "level" in the preload manifest replaces the walled box.
+    fn enter(&mut self, game_engine: &GameEngine) {
+        let level = {
+            let assets = game_engine.assets();
+            assets.find::<SceneAsset>("level").and_then(|level| assets.scene(level))
+        };
+        if let Some(level) = level {
+            game_engine.set_scene((*level).clone());
+        }
+    }
 
-    if mouse.is_some() {
-        let mouse = mouse.unwrap();
-        let view_mouse: Point2d = Point2d { x: mouse.x - view.offset.x, y: mouse.y - view.offset.y };
-        let ray = Ray::new(view.center, view_mouse);
+    fn handle_input(&mut self, game_engine: &GameEngine, input: &InputEvent) {
+        let InputEvent::KeyDown(key) = *input else { return };
 
-        let mut intersection = None;
+        match key {
+            27 | 80 => game_engine.push_state(PauseState, Transition::Cut), // Escape, "P"
+            36 => {
+                // Home: the view glides back while the screen fades out.
+                recenter(game_engine);
+                game_engine.replace_state(PlayState::default(), Transition::fade(600.));
+            }
+            78 => self.navigate = !self.navigate, // "N"
+            66 => self.release_boids(game_engine), // "B"
+            key => handle_keypress(game_engine, key),
+        }
+    }
 
-        for line in lines.iter() {
-            intersection = ray.intersects_line(&line);
-            if intersection.is_some() {
-                break;
+    fn update(&mut self, game_engine: &GameEngine, delta: f64) {
+        self.breath += delta * BREATHING_SPEED;
+        self.herd(game_engine);
+        {
+            let mut flock = self.flock.borrow_mut();
+            if !flock.agents.is_empty() {
+                let _scope = game_engine.profile("ai");
+                flock.update(&game_engine.scene().walls, &mut game_engine.rng(RngStream::Gameplay), delta);
             }
         }
 
+        let resting_on = self.aim.take().and_then(|(_, hit)| hit).map(|hit| hit.target);
+        let aiming = std::mem::replace(&mut self.aiming, true);
+        let Some(mouse) = game_engine.mouse() else { return };
+
+        let ray = {
+            let view = game_engine.view();
+            Ray::new(view.center, view.to_world(&mouse))
+        };
+
+        let intersection = {
+            let _scope = game_engine.profile("rays");
+            game_engine.scene().walls.iter().find_map(|line| ray.intersects_line(line))
+        };
+
+        // Only landing on a wall is a hit; resting on it or sliding along it is not.
+        if let Some(intersection) = intersection.filter(|hit| aiming && resting_on != Some(hit.target)) {
+            game_engine.emit(EngineEvent::RayHit(intersection));
+        }
+        self.aim = Some((ray, intersection));
+    }
+
+    fn draw(&mut self, game_engine: &GameEngine) {
+        let view: Ref<View> = game_engine.view();
+        let colors = &game_engine.config().colors;
+        game_engine.draw_light(Light::point(view.center, colors.light.0, 120. + 15. * self.breath.sin()));
+        game_engine.draw(Shapes { items: vec![Circle::new(view.center, 10, colors.marker.0)] });
+
+        let Some((ray, intersection)) = &self.aim else { return };
+        let direction = ray.direction();
+
+        // All items that will be rendered.
+        let mut items: Vec<Box<dyn Draw>> = vec![];
+
+        // Annotations that can be toggled off.
+        let mut debug: Vec<Box<dyn Draw>> = vec![];
+
+        game_engine.draw_light(Light::spot(view.center, direction, f64::consts::PI / 4., colors.light.0, 400.).with_falloff(1.5));
+
         match intersection {
             Some(intersection) => {
-                items.push(Line::new(
-                    view.center,
+                items.push(Line::new(view.center, intersection.point, colors.ray.0));
+
+                let spark_direction = glance_off(direction, &intersection.target);
+                debug.push(Line::new(
                     intersection.point,
-                    GREEN,
+                    Point2d {
+                        x: intersection.point.x + spark_direction.x * 10.,
+                        y: intersection.point.y + spark_direction.y * 10.,
+                    },
+                    colors.hit.0,
                 ));
-
-                let dir_towards_center = Point2d { x: ray.direction().x * -1., y: ray.direction().y * -1. };
-
-                items.push(
-                    Line::new(
-                        intersection.point,
-                        Point2d {
-                            x: intersection.point.x + intersection.angle_direction.x * 10.,
-                            y: intersection.point.y + intersection.angle_direction.y * 10.,
-                        },
-                        BLACK,
-                    ),
-                );
-
-                let particle_system: &ParticleSystem = game_engine.particle_system();
-                particle_system.add_particle(
-                    Particle::new(
-                        ParticlePixel { position: intersection.point, color: RED, alpha: 1.0 },
-                        intersection.angle_direction,
-                        0.4 + random() * 0.3,
-                        1500,
-                        particle_velocity_increasing,
-                        particle_tick_move_and_fade_out,
-                    )
-                );
             }
             None => {
                 items.push(Line::new(
                     view.center,
-                    Point2d { x: view.center.x + ray.direction().x * 1000., y: view.center.y + ray.direction().y * 1000. },
-                    BLACK,
+                    Point2d { x: view.center.x + direction.x * 1000., y: view.center.y + direction.y * 1000. },
+                    colors.ray.0,
                 ));
             }
         }
 
-        let ray_line: Box<Line> = Line::new(view.center, Point2d { x: view.center.x + ray.direction().x * 10., y: view.center.y + ray.direction().y * 10. }, BLACK);
-        items.push(ray_line);
+        debug.push(Line::new(view.center, Point2d { x: view.center.x + direction.x * 10., y: view.center.y + direction.y * 10. }, colors.hit.0));
+
+        game_engine.draw(Shapes { items });
+        game_engine.draw_on("debug", Box::new(Shapes { items: debug }));
+        if !self.flock.borrow().agents.is_empty() {
+            game_engine.draw_on("world", Box::new(self.flock.clone()));
+        }
+
+        if self.navigate {
+            let target = game_engine.mouse().map(|mouse| view.to_world(&mouse)).unwrap_or(view.center);
+            let grid = game_engine.nav_grid();
+            let stale = self.route.as_ref().is_none_or(|route| route.from != view.center || route.to != target || !Rc::ptr_eq(&route.grid, &grid));
+            if stale {
+                let path = game_engine.find_path(view.center, target, PathOptions::default());
+                self.route = Some(Route { from: view.center, to: target, grid: grid.clone(), path });
+            }
+            let path = self.route.as_ref().and_then(|route| route.path.clone());
+            game_engine.draw_on("debug", Box::new(NavDebug { grid, path, color: colors.particle.0, path_color: colors.ray.0 }));
+        }
+    }
+}
+
+// Freezes the world under a dimmed screen until Escape or "P" is pressed again.
+pub struct PauseState;
+
+impl GameState for PauseState {
+    fn update(&mut self, _game_engine: &GameEngine, _delta: f64) {}
+
+    fn handle_input(&mut self, game_engine: &GameEngine, input: &InputEvent) {
+        if let InputEvent::KeyDown(27 | 80) = input {
+            game_engine.pop_state(Transition::Cut);
+        }
     }
 
-    game_engine.clear();
-    game_engine.draw(&Shapes { items });
-}
\ No newline at end of file
+    fn draw(&mut self, game_engine: &GameEngine) {
+        game_engine.draw_on("ui", Box::new(ScreenTint { color: RGB8 { r: 0, g: 0, b: 0 }, alpha: 0.5 }));
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
+    }
+
+    fn pauses_world(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use super::*;
+    use crate::config::EngineConfig;
+
+    #[test]
+    fn the_ray_lights_up_a_wall_once_when_it_lands_on_it() {
+        let engine = GameEngine::headless(EngineConfig { seed: Some(1), ..EngineConfig::default() }, Point2d { x: 640., y: 480. });
+        setup(&engine);
+        let mut game = Demo;
+        let hits = Rc::new(Cell::new(0));
+        let counter = hits.clone();
+        engine.subscribe(Some(EventKind::RayHit), Delivery::Immediate, move |_: &GameEngine, _: &EngineEvent| counter.set(counter.get() + 1));
+
+        // A frame before the mouse comes in, then aimed right, at the wall at x = 200, and moved along it.
+        engine.step(16., &mut game);
+        for y in [240., 240., 250., 260.] {
+            engine.input(InputEvent::MouseMove(Point2d { x: 630., y }));
+            engine.step(16., &mut game);
+        }
+        assert_eq!(hits.get(), 1);
+        assert_eq!(engine.lights().lights().len(), 1);
+
+        // Off to the top wall and back again are two more hits.
+        for point in [Point2d { x: 320., y: 5. }, Point2d { x: 630., y: 240. }] {
+            engine.input(InputEvent::MouseMove(point));
+            engine.step(16., &mut game);
+        }
+        assert_eq!(hits.get(), 3);
+    }
+}
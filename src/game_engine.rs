@@ -1,21 +1,62 @@
-use std::{cell::RefCell, cell::Ref, rc::Rc, f64, cell::RefMut};
-use wasm_bindgen::closure::Closure;
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+use std::{cell::Cell, cell::RefCell, cell::Ref, rc::Rc, f64, cell::RefMut};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use protocol::Command;
+use web_sys::{HtmlCanvasElement, HtmlImageElement};
 
 use crate::{Browser, Draw, Point2d};
+use crate::browser::{console_log, SizeObserver};
+use crate::assets::{Asset, AssetData, AssetError, AssetKind, Assets, FsLoader, Handle, ImageAsset, FontAsset, Manifest, SceneAsset, SoundAsset};
+use crate::audio::{Mixer, NullAudio};
+use crate::engine_handle::AnimationLoop;
+use crate::game_loop::{self, Game};
+use crate::config::{DprMode, EngineConfig, EngineConfigBuilder, RendererPreference, ResizeAnchor};
+use crate::effects::EffectError;
+use crate::error::EngineError;
+use crate::events::{Delivery, EngineEvent, EventBus, EventKind, Handler, Queued, SubscriptionId, MAX_FLUSH_ROUNDS};
+use crate::layers::Layers;
+use crate::lighting::{Light, LightSystem};
+use crate::navigation::{NavGrid, Path, PathOptions};
+use crate::network::{NetSession, Transport};
+use crate::particle::Particle;
 use crate::particle_system::ParticleSystem;
+use crate::profiler::{BrowserClock, ManualClock, Profiler, Scope};
+use crate::replay::{InputEvent, Replay, ReplayError, ReplayFrame, StateHasher};
+use crate::rng::{Rng, RngStream};
+use crate::renderer::{Canvas2dRenderer, NullRenderer, Renderer};
+use crate::scene::Scene;
 use crate::shapes::Shapes;
+use crate::states::{GameState, StateChange, StateStack, Transition};
+use crate::tilemap::Tilemap;
+use crate::snapshot::{Snapshot, SnapshotError, ViewState, SNAPSHOT_VERSION};
+use crate::time::{from_millis, quantize_delta, EngineTime};
+use crate::tween::{Animation, AnimationId, Animations};
+use crate::web_audio::WebAudio;
+use crate::web_loader::FetchLoader;
+use crate::web_socket::WebSocketTransport;
+use crate::webgl_renderer::WebGl2Renderer;
 
+// Engines that have had to pick their own seed from the clock.
+static UNSEEDED_ENGINES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Default)]
 struct Inner {
     mouse: Option<Point2d>,
+    // Lights that only exist for the frame they were added in.
+    frame_lights: Vec<Light>,
+    // Input received since the last frame, applied when it starts.
+    pending: Vec<InputEvent>,
+    recording: Option<Replay>,
+    playback: Option<Playback>,
+    // Set when the view moves, zooms or resizes; announced once at the end of the frame.
+    view_changed: bool,
+    // Engine time owed to the game but not yet simulated, with a fixed timestep.
+    lag: f64,
 }
 
-impl Default for Inner {
-    fn default() -> Self {
-        Inner {
-            mouse: None
-        }
-    }
+struct Playback {
+    replay: Replay,
+    frame: usize,
 }
 
 #[derive(Clone)]
@@ -23,6 +64,8 @@ pub struct View {
     pub offset: Point2d,
     pub center: Point2d,
     pub size: Point2d,
+    // Scales the world around the middle of the view.
+    pub zoom: f64,
 }
 
 impl View {
@@ -33,10 +76,27 @@ impl View {
             offset,
             size,
             center: Point2d { x: 0., y: 0. },
+            zoom: 1.,
         }
     }
     pub fn transform(&self, point: &Point2d) -> Point2d {
-        Point2d { x: point.x + self.offset.x, y: point.y + self.offset.y }
+        Point2d {
+            x: (point.x + self.offset.x - self.size.x / 2.) * self.zoom + self.size.x / 2.,
+            y: (point.y + self.offset.y - self.size.y / 2.) * self.zoom + self.size.y / 2.,
+        }
+    }
+
+    // From canvas coordinates back into the world.
+    pub fn to_world(&self, point: &Point2d) -> Point2d {
+        Point2d {
+            x: (point.x - self.size.x / 2.) / self.zoom + self.size.x / 2. - self.offset.x,
+            y: (point.y - self.size.y / 2.) / self.zoom + self.size.y / 2. - self.offset.y,
+        }
+    }
+
+    // A world length in canvas pixels.
+    pub fn scale(&self, length: f64) -> f64 {
+        length * self.zoom
     }
 }
 
@@ -44,40 +104,292 @@ impl View {
 pub struct GameEngine {
     inner: Rc<RefCell<Inner>>,
     view: Rc<RefCell<View>>,
-    canvas: HtmlCanvasElement,
-    context: CanvasRenderingContext2d,
+    layers: Rc<RefCell<Layers>>,
+    scene: Rc<RefCell<Scene>>,
+    lights: Rc<RefCell<LightSystem>>,
+    // `None` for headless engines.
+    canvas: Option<HtmlCanvasElement>,
+    renderer: Rc<dyn Renderer>,
+    pixel_ratio: Rc<Cell<f64>>,
+    // The container size last reported by the resize observer, until the next frame takes it.
+    observed_size: Rc<Cell<Option<Point2d>>>,
+    profiler: Rc<RefCell<Profiler>>,
+    time: Rc<Cell<EngineTime>>,
+    animations: Rc<RefCell<Animations>>,
+    seed: Rc<Cell<u64>>,
+    rngs: Rc<RefCell<Vec<Rng>>>,
     particle_system: ParticleSystem,
+    events: Rc<RefCell<EventBus>>,
+    states: Rc<RefCell<StateStack>>,
+    audio: Rc<RefCell<Mixer>>,
+    assets: Rc<RefCell<Assets>>,
+    // Built from the scene when a path is first asked for, and dropped when the scene changes.
+    nav_grid: Rc<RefCell<Option<Rc<NavGrid>>>>,
+    // The shared scene this engine has joined, if any.
+    network: Rc<RefCell<Option<NetSession>>>,
+    config: Rc<EngineConfig>,
 }
 
 impl GameEngine {
-    pub fn create(id: &str) -> Self {
-        let canvas: HtmlCanvasElement = Browser::canvas(id);
-        let context: CanvasRenderingContext2d = Browser::context(&canvas);
+    pub fn create(config: EngineConfigBuilder) -> Result<Self, EngineError> {
+        let config = config.build()?;
+        let canvas: HtmlCanvasElement = Browser::canvas(&config.canvas_selector)?;
+        let (canvas, renderer) = Self::create_renderer(canvas, config.renderer)?;
 
-        let size: Point2d = Point2d { x: canvas.width() as f64, y: canvas.height() as f64 };
+        let size: Point2d = Self::container_size(&canvas, &config)
+            .unwrap_or(Point2d { x: canvas.width() as f64, y: canvas.height() as f64 });
         let view: View = View::new(size);
 
-        let particle_system = ParticleSystem::default();
+        let pixel_ratio = Self::pixel_ratio_for(config.dpr)?;
+        Self::scale_canvas(&canvas, size, pixel_ratio);
+        renderer.set_scale(pixel_ratio);
 
+        let seed = config.seed.unwrap_or_else(Self::unseeded);
+        let profiler = Profiler::new(Box::new(BrowserClock), config.profile_frames);
+
+        let engine = Self::assemble(config, Some(canvas), renderer, view, pixel_ratio, profiler, seed);
+        engine.assets().set_loader(Rc::new(FetchLoader::new("")));
+        match WebAudio::new() {
+            Ok(audio) => engine.audio().set_backend(Box::new(audio)),
+            Err(error) => console_log(&error.to_string()),
+        }
+
+        Ok(engine)
+    }
+
+    // An engine with no canvas that draws nothing, for replays and tests outside the browser.
+    // Without a configured seed it uses 0, so headless runs are reproducible by default.
+    #[allow(dead_code)]
+    pub fn headless(config: EngineConfig, size: Point2d) -> Self {
+        Self::headless_with_audio(config, size, NullAudio::default())
+    }
+
+    // A headless engine whose sound can be inspected through the `NullAudio` clone kept by the caller.
+    #[allow(dead_code)]
+    pub fn headless_with_audio(config: EngineConfig, size: Point2d, audio: NullAudio) -> Self {
+        let seed = config.seed.unwrap_or(0);
+        let profiler = Profiler::new(Box::new(ManualClock::default()), 0);
+
+        let engine = Self::assemble(config, None, Rc::new(NullRenderer), View::new(size), 1., profiler, seed);
+        engine.audio().set_backend(Box::new(audio));
+        engine
+    }
+
+    fn assemble(
+        config: EngineConfig,
+        canvas: Option<HtmlCanvasElement>,
+        renderer: Rc<dyn Renderer>,
+        view: View,
+        pixel_ratio: f64,
+        profiler: Profiler,
+        seed: u64,
+    ) -> Self {
         Self {
             inner: Rc::new(RefCell::new(Inner::default())),
             view: Rc::new(RefCell::new(view)),
-            context,
+            layers: Rc::new(RefCell::new(Layers::default())),
+            scene: Rc::new(RefCell::new(Scene::default())),
+            lights: Rc::new(RefCell::new(LightSystem::default())),
+            renderer,
+            pixel_ratio: Rc::new(Cell::new(pixel_ratio)),
+            observed_size: Rc::new(Cell::new(None)),
+            profiler: Rc::new(RefCell::new(profiler)),
             canvas,
-            particle_system,
+            time: Rc::new(Cell::new(EngineTime::default())),
+            animations: Rc::new(RefCell::new(Animations::default())),
+            seed: Rc::new(Cell::new(seed)),
+            rngs: Rc::new(RefCell::new(Self::seed_streams(seed))),
+            particle_system: ParticleSystem::new(config.max_particles),
+            events: Rc::new(RefCell::new(EventBus::default())),
+            states: Rc::new(RefCell::new(StateStack::default())),
+            audio: Rc::new(RefCell::new(Mixer::new(Box::new(NullAudio::default()), config.audio.clone()))),
+            assets: Rc::new(RefCell::new(Assets::new(Rc::new(FsLoader::new("."))))),
+            nav_grid: Rc::new(RefCell::new(None)),
+            network: Rc::new(RefCell::new(None)),
+            config: Rc::new(config),
         }
     }
 
-    pub fn draw(&self, shapes: &Shapes) -> () {
-        shapes.draw(&self.context, &self.view.borrow());
-        self.particle_system.tick(self);
+    // A seed for a run without a configured one. Where there's no Web Crypto the clock is used,
+    // on a stream of its own per engine, so engines started in the same millisecond still differ.
+    fn unseeded() -> u64 {
+        Browser::random_u64().unwrap_or_else(|| {
+            let engine = UNSEEDED_ENGINES.fetch_add(1, Ordering::Relaxed);
+            Rng::with_stream(js_sys::Date::now() as u64, engine).next_u64()
+        })
+    }
+
+    fn seed_streams(seed: u64) -> Vec<Rng> {
+        RngStream::ALL.iter().map(|stream| Rng::for_stream(seed, *stream)).collect()
     }
 
-    pub fn clear(&self) -> () {
-        self.context.clear_rect(0., 0., self.canvas.width() as f64, self.canvas.height() as f64);
+    // Prefers the batched WebGL2 backend, falling back to Canvas2D when it is unavailable. If WebGL2
+    // fails after the canvas gave out its context, Canvas2D draws on a fresh canvas put in its place,
+    // which is returned along with the renderer.
+    fn create_renderer(canvas: HtmlCanvasElement, preference: RendererPreference) -> Result<(HtmlCanvasElement, Rc<dyn Renderer>), EngineError> {
+        if preference == RendererPreference::Canvas2d {
+            return Ok((canvas.clone(), Rc::new(Canvas2dRenderer::new(canvas)?)));
+        }
+
+        let Some(gl) = Browser::webgl2_context(&canvas) else {
+            return Ok((canvas.clone(), Rc::new(Canvas2dRenderer::new(canvas)?)));
+        };
+        match WebGl2Renderer::new(gl) {
+            Ok(renderer) => Ok((canvas, Rc::new(renderer))),
+            Err(error) => {
+                console_log(&format!("WebGL2 renderer unavailable: {}", error));
+                let fresh = Browser::replace_canvas(&canvas)?;
+                Ok((fresh.clone(), Rc::new(Canvas2dRenderer::new(fresh)?)))
+            }
+        }
+    }
+
+    // Keeps the canvas at its CSS size while giving it a backing store of `pixel_ratio` times that.
+    fn scale_canvas(canvas: &HtmlCanvasElement, size: Point2d, pixel_ratio: f64) {
+        let style = canvas.style();
+        style.set_property("width", &format!("{}px", size.x)).ok();
+        style.set_property("height", &format!("{}px", size.y)).ok();
+
+        canvas.set_width((size.x * pixel_ratio).round() as u32);
+        canvas.set_height((size.y * pixel_ratio).round() as u32);
+    }
+
+    fn pixel_ratio_for(dpr: DprMode) -> Result<f64, EngineError> {
+        Ok(match dpr {
+            DprMode::Ignore => 1.,
+            DprMode::Auto => Browser::device_pixel_ratio()?,
+            DprMode::Fixed(ratio) => ratio,
+        })
+    }
+
+    fn container_size(canvas: &HtmlCanvasElement, config: &EngineConfig) -> Option<Point2d> {
+        if !config.auto_resize {
+            return None;
+        }
+        Browser::container_size(canvas).map(|(x, y)| Point2d { x, y })
+    }
+
+    // Records the container's size whenever it is laid out at a new one, for `sync_size` to pick up.
+    // Reading it there instead would force a layout every frame.
+    pub fn observe_size(&self) -> Result<Option<SizeObserver>, EngineError> {
+        let Some(canvas) = self.canvas.clone().filter(|_| self.config.auto_resize) else { return Ok(None) };
+        let Some(container) = canvas.parent_element() else { return Ok(None) };
+
+        let observed = self.observed_size.clone();
+        let observer = SizeObserver::new(&container, move || {
+            if let Some((x, y)) = Browser::container_size(&canvas) {
+                observed.set(Some(Point2d { x, y }));
+            }
+        })?;
+        Ok(Some(observer))
+    }
+
+    // Checked once per frame. A new size changes the simulation, so it is returned to go through the
+    // input queue; the pixel ratio (zoom, moving between monitors) only affects how frames are drawn
+    // and is applied straight away.
+    fn sync_size(&self) -> Option<Point2d> {
+        self.canvas.as_ref()?;
+        let current = self.view.borrow().size;
+
+        let pixel_ratio = Self::pixel_ratio_for(self.config.dpr).unwrap_or(self.pixel_ratio.get());
+        if pixel_ratio != self.pixel_ratio.get() {
+            self.resize(current, pixel_ratio);
+        }
+
+        self.observed_size
+            .take()
+            .map(|size| Point2d { x: size.x as f32 as f64, y: size.y as f32 as f64 })
+            .filter(|size| *size != current)
+    }
+
+    pub fn resize(&self, size: Point2d, pixel_ratio: f64) {
+        if let Some(canvas) = &self.canvas {
+            Self::scale_canvas(canvas, size, pixel_ratio);
+        }
+        self.renderer.set_scale(pixel_ratio);
+        self.pixel_ratio.set(pixel_ratio);
+
+        {
+            let mut view = self.view.borrow_mut();
+            if self.config.resize_anchor == ResizeAnchor::Center {
+                view.offset = Point2d {
+                    x: view.offset.x + (size.x - view.size.x) / 2.,
+                    y: view.offset.y + (size.y - view.size.y) / 2.,
+                };
+            }
+            view.size = size;
+        }
+
+        self.layers.borrow_mut().discard_buffers();
+        self.view_changed();
+        self.defer(EngineEvent::Resize { size, pixel_ratio });
+    }
+
+    pub fn draw(&self, shapes: Shapes) {
+        self.draw_on("world", Box::new(shapes));
+    }
+
+    pub fn draw_on(&self, layer: &str, item: Box<dyn Draw>) {
+        self.layers.borrow_mut().submit(layer, item);
+    }
+
+    pub fn draw_light(&self, light: Light) {
+        self.inner.borrow_mut().frame_lights.push(light);
+    }
+
+    // Composites every layer submitted during the frame, back to front.
+    pub fn render(&self) {
+        self.clear();
+
+        if self.layers.borrow().needs_redraw("background") {
+            self.draw_on("background", Box::new(self.scene.borrow().clone()));
+        }
+
+        {
+            let _scope = self.profile("particles");
+            if !self.world_paused() {
+                self.particle_system.tick(self.time().instant(), &self.scene.borrow().walls, &mut self.rng(RngStream::Particles));
+            }
+            self.draw_on("particles", Box::new(self.particle_system.clone()));
+        }
+
+        if let Some(session) = self.network.borrow().as_ref() {
+            let remote = session.remote_entities(self.config.colors.marker.0);
+            if !remote.is_empty() {
+                self.draw_on("world", Box::new(remote));
+            }
+        }
+
+        if self.layers.borrow().needs_redraw("lighting") {
+            let _scope = self.profile("lighting");
+            let frame_lights = std::mem::take(&mut self.inner.borrow_mut().frame_lights);
+            let mut lights = self.lights.borrow_mut();
+            lights.tick(self.time().instant());
+            let light_map = lights.light_map(&self.scene.borrow().walls, &frame_lights);
+            self.draw_on("lighting", Box::new(light_map));
+        } else {
+            self.inner.borrow_mut().frame_lights.clear();
+        }
+
+        {
+            let _scope = self.profile("draw");
+            self.layers.borrow_mut().render(self.renderer.as_ref(), &self.view.borrow());
+        }
+
+        let _scope = self.profile("flush");
+        self.renderer.flush();
+    }
+
+    pub fn clear(&self) {
+        let size = self.view.borrow().size;
+        self.renderer.clear(size);
+
+        if let Some(background) = self.config.background {
+            self.renderer.rect(Point2d { x: 0., y: 0. }, size, background.0, 1.0);
+        }
     }
 
-    pub fn set_mouse<T: Into<Point2d>>(&self, mouse: T) -> () {
+    pub fn set_mouse<T: Into<Point2d>>(&self, mouse: T) {
         let mut inner: RefMut<Inner> = self.inner.borrow_mut();
         inner.mouse = Some(mouse.into());
     }
@@ -86,45 +398,779 @@ impl GameEngine {
         self.inner.borrow().mouse
     }
 
-    pub fn view(&self) -> Ref<View> {
+    pub fn view(&self) -> Ref<'_, View> {
         self.view.borrow()
     }
 
-    pub fn context(&self) -> &CanvasRenderingContext2d {
-        &self.context
+    pub fn layers(&self) -> RefMut<'_, Layers> {
+        self.layers.borrow_mut()
     }
 
-    pub fn shift_view_by(&self, offset: Point2d) -> () {
+    pub fn scene(&self) -> Ref<'_, Scene> {
+        self.scene.borrow()
+    }
+
+    pub fn set_scene(&self, scene: Scene) {
+        *self.scene.borrow_mut() = scene;
+        self.layers.borrow_mut().invalidate("background");
+        self.nav_grid.borrow_mut().take();
+    }
+
+    // Where the scene can be walked, by the `navigation` settings.
+    pub fn nav_grid(&self) -> Rc<NavGrid> {
+        self.nav_grid
+            .borrow_mut()
+            .get_or_insert_with(|| Rc::new(NavGrid::from_walls(&self.scene.borrow().walls, &self.config.navigation)))
+            .clone()
+    }
+
+    pub fn find_path(&self, from: Point2d, to: Point2d, options: PathOptions) -> Option<Path> {
+        let _scope = self.profile("navigation");
+        self.nav_grid().find_path(from, to, options)
+    }
+
+    // Makes the tilemap the level, with walls around its solid tiles for rays, light and particles.
+    pub fn set_tilemap(&self, tilemap: Tilemap) {
+        self.set_scene(Scene::from_tilemap(tilemap, self.config.colors.wall.0));
+    }
+
+    // Joins the shared scene served over a WebSocket at `url`, leaving the one joined before.
+    pub fn connect(&self, url: &str, name: &str) -> Result<(), EngineError> {
+        self.join(Box::new(WebSocketTransport::open(url)?), name);
+        Ok(())
+    }
+
+    pub fn join(&self, transport: Box<dyn Transport>, name: &str) {
+        if let Some(session) = self.network.borrow_mut().replace(NetSession::new(transport, name)) {
+            session.close();
+        }
+    }
+
+    pub fn disconnect(&self) {
+        if let Some(session) = self.network.borrow_mut().take() {
+            session.close();
+        }
+    }
+
+    pub fn network(&self) -> Ref<'_, Option<NetSession>> {
+        self.network.borrow()
+    }
+
+    // Plays an effect, for everyone in the shared scene when one has been joined.
+    pub fn spawn_effect(&self, name: &str, position: Point2d) -> Result<(), EffectError> {
+        self.particle_system.play(name, position, Point2d { x: 0., y: -1. })?;
+        self.share_effect(name, position);
+        Ok(())
+    }
+
+    // Like `spawn_effect`, but for callers outside the simulation such as JS event handlers: the
+    // effect goes in as input for the next frame, so recordings replay it.
+    pub fn queue_effect(&self, name: &str, position: Point2d) -> Result<(), EffectError> {
+        self.particle_system.effects().get(name)?;
+        self.share_effect(name, position);
+        self.input(InputEvent::Effect { name: name.to_string(), position });
+        Ok(())
+    }
+
+    fn share_effect(&self, name: &str, position: Point2d) {
+        if let Some(session) = self.network.borrow_mut().as_mut() {
+            session.send(Command::SpawnEffect { name: name.to_string(), position: position.into() });
+        }
+    }
+
+    // Queues what other players spawned as input for the next frame, so recordings replay it, and
+    // tells them where this one is and looks.
+    fn sync_network(&self) {
+        let effects = {
+            let mut network = self.network.borrow_mut();
+            let Some(session) = network.as_mut() else { return };
+            let effects = session.poll();
+
+            let view = self.view.borrow();
+            session.set_view(view.center, view.zoom);
+            if let Some(mouse) = self.mouse() {
+                session.move_to(view.to_world(&mouse));
+            }
+            effects
+        };
+
+        for effect in effects {
+            self.input(InputEvent::RemoteEffect { name: effect.name, position: effect.position.into() });
+        }
+    }
+
+    pub fn lights(&self) -> RefMut<'_, LightSystem> {
+        self.lights.borrow_mut()
+    }
+
+    #[allow(dead_code)]
+    pub fn renderer(&self) -> &dyn Renderer {
+        self.renderer.as_ref()
+    }
+
+    pub fn shift_view_by(&self, offset: Point2d) {
         let mut view = self.view.borrow_mut();
         view.offset = Point2d { x: view.offset.x + offset.x, y: view.offset.y + offset.y };
         view.center = Point2d { x: view.center.x - offset.x, y: view.center.y - offset.y };
+        self.view_changed();
     }
 
-    pub fn reset_view(&self) -> () {
+    #[allow(dead_code)]
+    pub fn reset_view(&self) {
         let mut view = self.view.borrow_mut();
-        view.offset = Point2d { x: self.canvas.width() as f64 / 2., y: self.canvas.height() as f64 / 2. };
+        view.offset = Point2d { x: view.size.x / 2., y: view.size.y / 2. };
+        self.view_changed();
+    }
+
+    // Moves the view so `center` is in the middle of the canvas.
+    pub fn set_view_center(&self, center: Point2d) {
+        let mut view = self.view.borrow_mut();
+        view.offset = Point2d { x: view.size.x / 2. - center.x, y: view.size.y / 2. - center.y };
+        view.center = center;
+        self.view_changed();
+    }
+
+    pub fn set_zoom(&self, zoom: f64) {
+        self.view.borrow_mut().zoom = zoom.max(0.01);
+        self.view_changed();
+    }
+
+    // Everything cached depends on the view, so any change redraws all layers.
+    fn view_changed(&self) {
+        self.layers.borrow_mut().invalidate_all();
+        self.inner.borrow_mut().view_changed = true;
+    }
+
+    pub fn view_state(&self) -> ViewState {
+        let view = self.view.borrow();
+        ViewState { offset: view.offset, center: view.center, size: view.size, zoom: view.zoom }
+    }
+
+    // Calls `handler` for events of `kind`, or for every event with `None`.
+    pub fn subscribe<F: FnMut(&GameEngine, &EngineEvent) + 'static>(&self, kind: Option<EventKind>, delivery: Delivery, handler: F) -> SubscriptionId {
+        self.events.borrow_mut().subscribe(kind, delivery, Rc::new(RefCell::new(handler)))
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.events.borrow_mut().unsubscribe(id)
+    }
+
+    // Delivers `event` to immediate subscribers now and queues it for deferred ones.
+    pub fn emit(&self, event: EngineEvent) {
+        let handlers = self.events.borrow().handlers(event.kind());
+        for (id, delivery, handler) in handlers {
+            match delivery {
+                Delivery::Immediate => self.deliver(id, &handler, &event),
+                Delivery::Deferred => self.events.borrow_mut().push(event.clone(), Some(id)),
+            }
+        }
+    }
+
+    // Queues `event` for every subscriber until the end of the frame.
+    pub fn defer(&self, event: EngineEvent) {
+        self.events.borrow_mut().push(event, None);
+    }
+
+    fn deliver(&self, id: SubscriptionId, handler: &Handler, event: &EngineEvent) {
+        // Handlers unsubscribed by an earlier one in the same dispatch don't get the event.
+        if !self.events.borrow().is_subscribed(id) {
+            return;
+        }
+
+        match handler.try_borrow_mut() {
+            Ok(mut handler) => (*handler)(self, event),
+            // The handler emitted this itself; it gets it once it has returned.
+            Err(_) => self.events.borrow_mut().push(event.clone(), Some(id)),
+        }
+    }
+
+    // Delivers everything queued, including what handlers queue meanwhile, up to a limit.
+    pub fn flush_events(&self) {
+        for _ in 0..MAX_FLUSH_ROUNDS {
+            let queue = self.events.borrow_mut().take_queue();
+            if queue.is_empty() {
+                return;
+            }
+
+            for Queued { event, target } in queue {
+                let handlers = match target {
+                    Some(id) => self.events.borrow().handler(id).map(|handler| vec![(id, handler)]).unwrap_or_default(),
+                    None => self.events.borrow().handlers(event.kind()).into_iter().map(|(id, _, handler)| (id, handler)).collect(),
+                };
+                for (id, handler) in handlers {
+                    self.deliver(id, &handler, &event);
+                }
+            }
+        }
+    }
+
+    // State changes are queued and made between state updates, never while a state is running.
+    pub fn push_state<S: GameState + 'static>(&self, state: S, transition: Transition) {
+        self.states.borrow_mut().request(StateChange::Push(Box::new(state)), transition);
+    }
+
+    pub fn pop_state(&self, transition: Transition) {
+        self.states.borrow_mut().request(StateChange::Pop, transition);
+    }
+
+    pub fn replace_state<S: GameState + 'static>(&self, state: S, transition: Transition) {
+        self.states.borrow_mut().request(StateChange::Replace(Box::new(state)), transition);
     }
 
-    pub fn canvas(&self) -> &HtmlCanvasElement {
-        &self.canvas
+    // True while the state on top stops engine time.
+    pub fn world_paused(&self) -> bool {
+        self.states.borrow().pauses_world()
+    }
+
+    // How many states are on the stack, counting any that are entering, exiting or running.
+    #[allow(dead_code)]
+    pub fn state_count(&self) -> usize {
+        self.states.borrow().len()
+    }
+
+    // A state entering is on the stack already, and one exiting still is.
+    fn change_state(&self, change: StateChange) {
+        let (leaving, entering) = match change {
+            StateChange::Push(state) => (false, Some(state)),
+            StateChange::Pop => (true, None),
+            StateChange::Replace(state) => (true, Some(state)),
+        };
+
+        if leaving {
+            let mut top = self.states.borrow_mut().take_top(1);
+            for state in top.iter_mut() {
+                state.exit(self);
+            }
+            self.states.borrow_mut().remove(top);
+        }
+        if let Some(state) = entering {
+            self.states.borrow_mut().push(state);
+            self.run_top(|state| state.enter(self));
+        }
+    }
+
+    fn run_top(&self, run: impl FnOnce(&mut dyn GameState)) {
+        let mut top = self.states.borrow_mut().take_top(1);
+        if let Some(state) = top.last_mut() {
+            run(state.as_mut());
+        }
+        self.states.borrow_mut().put_back(top);
+    }
+
+    // Gives the frame's input to the top state, once per frame however many updates it runs.
+    fn state_input(&self, inputs: &[InputEvent]) {
+        self.make_state_changes(0.);
+        self.run_top(|top| {
+            for input in inputs.iter() {
+                top.handle_input(self, input);
+            }
+        });
+    }
+
+    // Moves a running fade on by `delta` and runs the top state. Changes it asks for are made
+    // straight after, so a pause holds from the next frame.
+    fn update_states(&self, delta: f64) {
+        self.make_state_changes(delta);
+        self.run_top(|top| top.update(self, delta));
+        self.make_state_changes(0.);
+    }
+
+    fn make_state_changes(&self, delta: f64) {
+        let due = self.states.borrow_mut().due_changes(delta);
+        for change in due {
+            self.change_state(change);
+        }
+    }
+
+    // Draws the top state over any it overlays, and the fade if one is running.
+    fn draw_states(&self) {
+        let drawn = self.states.borrow().drawn();
+        let mut states = self.states.borrow_mut().take_top(drawn);
+        for state in states.iter_mut() {
+            state.draw(self);
+        }
+        self.states.borrow_mut().put_back(states);
+
+        let tint = self.states.borrow().fade_tint();
+        if let Some(tint) = tint {
+            self.draw_on("ui", Box::new(tint));
+        }
+    }
+
+    // Starts an animation, advanced by engine time at the start of every frame.
+    pub fn animate<A: Animation + 'static>(&self, animation: A) -> AnimationId {
+        self.animations.borrow_mut().add(Box::new(animation))
+    }
+
+    #[allow(dead_code)]
+    pub fn cancel_animation(&self, id: AnimationId) {
+        self.animations.borrow_mut().cancel(id);
+    }
+
+    fn advance_animations(&self, delta: f64) {
+        let mut running = self.animations.borrow_mut().take();
+        running.retain_mut(|(_, animation)| animation.advance(self, delta).is_none());
+        self.animations.borrow_mut().merge(running);
+    }
+
+    // Times the named phase of the current frame until the returned scope is dropped.
+    pub fn profile(&self, name: &'static str) -> Scope {
+        Scope::new(&self.profiler, name)
+    }
+
+    pub fn profiler(&self) -> RefMut<'_, Profiler> {
+        self.profiler.borrow_mut()
+    }
+
+    pub fn rng(&self, stream: RngStream) -> RefMut<'_, Rng> {
+        RefMut::map(self.rngs.borrow_mut(), |rngs| &mut rngs[stream as usize])
+    }
+
+    // The seed actually in use, so a run without a configured seed can still be reproduced.
+    pub fn seed(&self) -> u64 {
+        self.seed.get()
+    }
+
+    pub fn time(&self) -> EngineTime {
+        self.time.get()
+    }
+
+    // Makes `image` available to particles with a `Sprite` shape of the same name.
+    pub fn add_sprite(&self, name: &str, image: HtmlImageElement) {
+        self.renderer.add_sprite(name, image);
+    }
+
+    pub fn canvas(&self) -> Option<&HtmlCanvasElement> {
+        self.canvas.as_ref()
     }
 
     pub fn particle_system(&self) -> &ParticleSystem {
         &self.particle_system
     }
 
-    pub fn run(&self, tick: fn(game_engine: &GameEngine)) {
-        let f = Rc::new(RefCell::new(None));
-        let g = f.clone();
+    pub fn audio(&self) -> RefMut<'_, Mixer> {
+        self.audio.borrow_mut()
+    }
+
+    pub fn assets(&self) -> RefMut<'_, Assets> {
+        self.assets.borrow_mut()
+    }
+
+    // Starts loading `path` as `name`, or hands out another reference to the asset already loaded
+    // under that name. Progress shows in `assets()`.
+    pub fn load_asset<T: Asset>(&self, name: &str, path: &str) -> Result<Handle<T>, AssetError> {
+        let (handle, fresh) = self.assets.borrow_mut().insert::<T>(name, path)?;
+        if fresh {
+            let loader = self.assets.borrow().loader();
+            let engine = self.clone();
+            let id = handle.id();
+            loader.fetch(path, Box::new(move |result| engine.asset_fetched(id, result)));
+        }
+        Ok(handle)
+    }
+
+    // Drops a reference to an asset. Once the last one goes, so does the sprite or sound made from it.
+    #[allow(dead_code)]
+    pub fn release_asset<T: Asset>(&self, handle: Handle<T>) {
+        let name = self.assets.borrow().name(handle).map(str::to_string);
+        let released = self.assets.borrow_mut().release(handle);
+        if let (true, Some(name)) = (released, name) {
+            self.unload_asset(T::KIND, &name);
+        }
+    }
+
+    fn unload_asset(&self, kind: AssetKind, name: &str) {
+        match kind {
+            AssetKind::Image => self.renderer.remove_sprite(name),
+            AssetKind::Sound => self.audio.borrow().backend().unload(name),
+            // Page fonts can't be taken back, and scenes go with their entry.
+            AssetKind::Scene | AssetKind::Font => {}
+        }
+    }
+
+    // Loads everything in the manifest that can be, returning the first name that clashes with an
+    // asset already loaded from another path.
+    pub fn preload(&self, manifest: &Manifest) -> Result<(), AssetError> {
+        let mut results = vec![];
+        for (name, path) in manifest.images.iter() {
+            results.push(self.load_asset::<ImageAsset>(name, path).map(|_| ()));
+        }
+        for (name, path) in manifest.sounds.iter() {
+            results.push(self.load_asset::<SoundAsset>(name, path).map(|_| ()));
+        }
+        for (name, path) in manifest.scenes.iter() {
+            results.push(self.load_asset::<SceneAsset>(name, path).map(|_| ()));
+        }
+        for (name, path) in manifest.fonts.iter() {
+            results.push(self.load_asset::<FontAsset>(name, path).map(|_| ()));
+        }
+        results.into_iter().collect()
+    }
+
+    // Turns fetched bytes into the asset: images become sprites, sounds go to the audio backend and
+    // fonts to the page, all under the asset's name. Whatever finishes after its asset was released
+    // is dropped again.
+    fn asset_fetched(&self, id: u32, result: Result<Vec<u8>, AssetError>) {
+        let pending = self.assets.borrow().pending(id);
+        let Some((kind, name)) = pending else { return };
+
+        let bytes = match result {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                self.assets.borrow_mut().finish(id, Err(error));
+                return;
+            }
+        };
+
+        let engine = self.clone();
+        let finish = {
+            let name = name.clone();
+            move |result: Result<AssetData, String>| {
+                let failed = result.is_err();
+                let result = result.map_err(|reason| AssetError::Decode { name: name.clone(), reason });
+                if !engine.assets.borrow_mut().finish(id, result) && !failed {
+                    engine.unload_asset(kind, &name);
+                }
+            }
+        };
+
+        match kind {
+            AssetKind::Scene => {
+                let scene = serde_json::from_slice(&bytes).map(|scene| AssetData::Scene(Rc::new(scene)));
+                finish(scene.map_err(|error| error.to_string()));
+            }
+            AssetKind::Sound => {
+                let backend_done = Box::new(move |result: Result<(), String>| finish(result.map(|_| AssetData::Sound)));
+                self.audio.borrow().backend().load(&name, &bytes, backend_done);
+            }
+            AssetKind::Image => {
+                let engine = self.clone();
+                let sprite = name.clone();
+                let loader = self.assets.borrow().loader();
+                loader.decode_image(&name, &bytes, Box::new(move |result| {
+                    if let Ok(Some(image)) = &result {
+                        engine.add_sprite(&sprite, image.clone());
+                    }
+                    finish(result.map(AssetData::Image));
+                }));
+            }
+            AssetKind::Font => {
+                let loader = self.assets.borrow().loader();
+                loader.add_font(&name, &bytes, Box::new(move |result| finish(result.map(|_| AssetData::Font))));
+            }
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    // Queues input for the start of the next frame. Ignored while a replay is playing.
+    pub fn input(&self, event: InputEvent) {
+        self.inner.borrow_mut().pending.push(event.quantized());
+    }
+
+    fn apply(&self, input: InputEvent) {
+        match &input {
+            InputEvent::MouseMove(position) => self.set_mouse(*position),
+            // Keys only reach the top state, through `handle_input`.
+            InputEvent::KeyDown(_) => {}
+            InputEvent::Resize(size) => self.resize(*size, self.pixel_ratio.get()),
+            InputEvent::Effect { name, position } | InputEvent::RemoteEffect { name, position } => {
+                self.particle_system.play(name, *position, Point2d { x: 0., y: -1. }).ok();
+            }
+        }
+        self.defer(EngineEvent::InputAction { input });
+    }
+
+    // Puts the world back to how it was when the engine started, keeping the scene and layer setup.
+    pub fn reset(&self) {
+        self.events.borrow_mut().clear_queue();
+        self.audio.borrow_mut().stop_all();
+        let seed = self.seed.get();
+        *self.rngs.borrow_mut() = Self::seed_streams(seed);
+        self.time.set(EngineTime::default());
+        self.animations.borrow_mut().clear();
+        self.particle_system.clear();
+        self.lights.borrow_mut().reset();
+
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.mouse = None;
+            inner.frame_lights.clear();
+            inner.pending.clear();
+            inner.lag = 0.;
+        }
+
+        {
+            let mut view = self.view.borrow_mut();
+            *view = View::new(view.size);
+        }
+        self.layers.borrow_mut().invalidate_all();
+    }
+
+    // Restarts the world and records every frame's delta and input from here on.
+    pub fn start_recording(&self) {
+        self.reset();
+        let replay = Replay::new(self.seed.get(), self.view.borrow().size, &self.config);
+        self.inner.borrow_mut().recording = Some(replay);
+    }
+
+    pub fn stop_recording(&self) -> Option<Replay> {
+        let mut replay = self.inner.borrow_mut().recording.take()?;
+        replay.final_hash = Some(self.state_hash());
+        Some(replay)
+    }
 
-        let game = self.clone();
+    // Restarts the world from the replay's seed and size, then feeds it the recorded frames instead
+    // of live input until they run out. Replays recorded with other settings would play out
+    // differently, so they are refused.
+    pub fn play(&self, replay: Replay) -> Result<(), ReplayError> {
+        replay.check_config(&self.config)?;
+        self.inner.borrow_mut().recording = None;
+        self.seed.set(replay.seed);
+        self.resize(replay.size, self.pixel_ratio.get());
+        self.reset();
+        if !replay.frames.is_empty() {
+            self.inner.borrow_mut().playback = Some(Playback { replay, frame: 0 });
+        }
+        Ok(())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.inner.borrow().playback.is_some()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_recording(&self) -> bool {
+        self.inner.borrow().recording.is_some()
+    }
+
+    // The next recorded frame while playing back, otherwise the live delta and queued input.
+    fn next_frame(&self, delta: f64) -> ReplayFrame {
+        {
+            let mut inner = self.inner.borrow_mut();
+            if let Some(playback) = inner.playback.as_mut() {
+                let frame = playback.replay.frames.get(playback.frame).cloned();
+                playback.frame += 1;
+                if playback.frame >= playback.replay.frames.len() {
+                    inner.playback = None;
+                }
+                inner.pending.clear();
+
+                if let Some(frame) = frame {
+                    return frame;
+                }
+            }
+        }
+
+        let resize = self.sync_size();
+
+        let mut inner = self.inner.borrow_mut();
+        let mut inputs = std::mem::take(&mut inner.pending);
+        inputs.extend(resize.map(InputEvent::Resize));
+        let frame = ReplayFrame { delta: quantize_delta(delta), inputs };
+
+        if let Some(recording) = inner.recording.as_mut() {
+            recording.frames.push(frame.clone());
+        }
+
+        frame
+    }
+
+    // Advances the world by one frame: applies input, moves time on by `delta` milliseconds, then
+    // ticks the game and renders.
+    pub fn step(&self, delta: f64, game: &mut dyn Game) {
+        self.profiler.borrow_mut().begin_frame();
+
+        let frame = self.next_frame(delta);
+        for input in frame.inputs.iter() {
+            self.apply(input.clone());
+        }
+        self.state_input(&frame.inputs);
+
+        let updates = game_loop::updates(self.config.fixed_timestep, &mut self.inner.borrow_mut().lag, frame.delta);
+        {
+            let _scope = self.profile("update");
+            for _ in 0..updates.count {
+                if !self.world_paused() {
+                    self.time.set(self.time.get().advance(updates.dt));
+                    self.advance_animations(updates.dt);
+                }
+                game.update(self, updates.dt);
+                self.update_states(updates.dt);
+            }
+        }
+        {
+            let _scope = self.profile("network");
+            self.sync_network();
+        }
+        {
+            let _scope = self.profile("render");
+            game.render(self, updates.alpha);
+            self.draw_states();
+            self.render();
+        }
+        {
+            let _scope = self.profile("audio");
+            let listener = self.view.borrow().center;
+            self.audio.borrow_mut().update(listener);
+        }
+        {
+            let _scope = self.profile("events");
+            for event in self.particle_system.take_events() {
+                self.defer(event);
+            }
+            if std::mem::take(&mut self.inner.borrow_mut().view_changed) {
+                self.defer(EngineEvent::ViewChanged(self.view_state()));
+            }
+            self.flush_events();
+        }
+
+        self.profiler.borrow_mut().end_frame();
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let time = self.time.get();
+        let lights = self.lights.borrow();
+
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            frame: time.frame,
+            elapsed: time.elapsed,
+            seed: self.seed.get(),
+            rngs: self.rngs.borrow().clone(),
+            view: self.view_state(),
+            mouse: self.inner.borrow().mouse,
+            walls: self.scene.borrow().added_walls().to_vec(),
+            ambient: lights.ambient,
+            lights: lights.lights().iter().map(|light| light.to_state()).collect(),
+            particles: self.particle_system.states(),
+            forces: self.particle_system.forces(),
+            collisions: self.particle_system.collisions(),
+            effects: self.particle_system.active_effects(),
+        }
+    }
+
+    // Replaces the world with a snapshot. Recording and playback stop, since the run no longer
+    // follows from the start, and running animations are dropped as they can't be saved.
+    pub fn restore(&self, snapshot: Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+        if snapshot.rngs.len() != RngStream::ALL.len() {
+            return Err(SnapshotError::RngStreams { expected: RngStream::ALL.len(), found: snapshot.rngs.len() });
+        }
+        let instant = from_millis(snapshot.elapsed)?;
+        let lights = snapshot.lights.iter().map(Light::from_state).collect::<Result<Vec<_>, _>>()?;
+        let particles = snapshot.particles.iter().map(Particle::from_state).collect::<Result<Vec<_>, _>>()?;
+
+        // Whatever was queued belongs to the world being replaced.
+        self.events.borrow_mut().clear_queue();
+        self.audio.borrow_mut().stop_all();
+
+        self.time.set(EngineTime { frame: snapshot.frame, elapsed: snapshot.elapsed, delta: 0. });
+        self.seed.set(snapshot.seed);
+        *self.rngs.borrow_mut() = snapshot.rngs;
+
+        if snapshot.view.size != self.view.borrow().size {
+            self.resize(snapshot.view.size, self.pixel_ratio.get());
+        }
+        {
+            let mut view = self.view.borrow_mut();
+            view.offset = snapshot.view.offset;
+            view.center = snapshot.view.center;
+            view.zoom = snapshot.view.zoom;
+        }
+        self.animations.borrow_mut().clear();
+
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.mouse = snapshot.mouse;
+            inner.frame_lights.clear();
+            inner.pending.clear();
+            inner.lag = 0.;
+            inner.recording = None;
+            inner.playback = None;
+            inner.view_changed = true;
+        }
+
+        // Snapshots only keep the walls; the tiles stay what they were, and so do the walls around them.
+        let tilemap = self.scene.borrow().tilemap.clone();
+        self.set_scene(Scene::around(tilemap, self.config.colors.wall.0, snapshot.walls));
+        self.lights.borrow_mut().restore(snapshot.ambient, lights, instant);
+        self.particle_system.restore(particles, instant);
+        self.particle_system.set_forces(snapshot.forces);
+        self.particle_system.set_collisions(snapshot.collisions);
+        self.particle_system.set_active_effects(snapshot.effects);
+        self.layers.borrow_mut().invalidate_all();
+
+        Ok(())
+    }
+
+    // A fingerprint of the simulation state, for checking that a replay reproduces a run.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::default();
+
+        let time = self.time.get();
+        hasher.write_u64(time.frame);
+        hasher.write_f64(time.elapsed);
+        hasher.write_u64(self.seed.get());
+        self.rngs.borrow().hash(&mut hasher);
+
+        {
+            let view = self.view.borrow();
+            hasher.write_point(view.offset);
+            hasher.write_point(view.center);
+            hasher.write_point(view.size);
+            hasher.write_f64(view.zoom);
+        }
+
+        match self.inner.borrow().mouse {
+            Some(mouse) => hasher.write_point(mouse),
+            None => hasher.write_u8(0),
+        }
+
+        for particle in self.particle_system.particles().iter() {
+            hasher.write_f64(particle.velocity());
+            if let Some(pixel) = particle.pixel() {
+                hasher.write_point(pixel.position);
+                hasher.write_f64(pixel.alpha);
+                hasher.write_f64(pixel.size);
+                hasher.write_f64(pixel.rotation);
+            }
+            let dynamics = particle.dynamics();
+            hasher.write_point(dynamics.heading);
+            hasher.write_f64(dynamics.speed_scale);
+            hasher.write_point(dynamics.drift);
+        }
+
+        for light in self.lights.borrow().lights() {
+            hasher.write_point(light.position);
+            hasher.write_f64(light.radius);
+            hasher.write_i64(light.start_time().timestamp_millis());
+        }
+
+        hasher.finish()
+    }
 
-        *g.borrow_mut() = Some(Closure::new(move || {
-            tick(&game);
+    // Updates and renders `game` every animation frame; the engine owns it until the returned handle is stopped or dropped.
+    pub fn run<G: Game + 'static>(&self, mut game: G) -> Result<AnimationLoop, EngineError> {
+        let engine = self.clone();
+        let last_frame: Cell<Option<f64>> = Cell::new(None);
 
-            Browser::request_animation_frame(f.borrow().as_ref().unwrap());
-        }));
+        AnimationLoop::start(move |timestamp: f64| {
+            let due = match (engine.config.frame_interval(), last_frame.get()) {
+                // Half a millisecond of slack so a 60fps target isn't skipped on a 60Hz display.
+                (Some(interval), Some(last)) => timestamp - last >= interval - 0.5,
+                _ => true,
+            };
 
-        Browser::request_animation_frame(g.borrow().as_ref().unwrap());
+            if due {
+                let delta = last_frame.get().map_or(0., |last| timestamp - last);
+                last_frame.set(Some(timestamp));
+                engine.step(delta, &mut game);
+            }
+        })
     }
 }
@@ -0,0 +1,120 @@
+use crate::game_engine::GameEngine;
+
+// Most updates a frame may run to catch up; past that the game slows down instead of spiralling.
+pub const MAX_UPDATES_PER_FRAME: u32 = 8;
+
+// What the engine runs. It owns the game for as long as the loop goes, so state can live on `self`.
+pub trait Game {
+    // Advances the game by `dt` milliseconds of engine time.
+    fn update(&mut self, game_engine: &GameEngine, dt: f64);
+
+    // Queues this frame's drawing. `alpha` is how far engine time has got from the last update
+    // towards the next one, from 0 to 1, for interpolating between them.
+    fn render(&mut self, _game_engine: &GameEngine, _alpha: f64) {}
+}
+
+// A function or closure is a game that does all its work in `update`. With a fixed timestep it may
+// run several times in one frame or not at all, so anything drawn per frame belongs in `render`.
+impl<F: FnMut(&GameEngine)> Game for F {
+    fn update(&mut self, game_engine: &GameEngine, _dt: f64) {
+        self(game_engine)
+    }
+}
+
+// The updates one frame runs: `count` of `dt` milliseconds each, leaving `alpha` of a step over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Updates {
+    pub count: u32,
+    pub dt: f64,
+    pub alpha: f64,
+}
+
+// Without a timestep every frame is one update of its whole delta. With one, `delta` is added to
+// the time not yet simulated, `lag`, and as many whole steps as fit are taken out of it.
+pub fn updates(timestep: Option<f64>, lag: &mut f64, delta: f64) -> Updates {
+    let Some(timestep) = timestep else {
+        return Updates { count: 1, dt: delta, alpha: 1. };
+    };
+
+    *lag += delta;
+    let mut count = (*lag / timestep).floor() as u32;
+    if count > MAX_UPDATES_PER_FRAME {
+        count = MAX_UPDATES_PER_FRAME;
+        *lag = timestep * count as f64;
+    }
+    *lag -= timestep * count as f64;
+
+    Updates { count, dt: timestep, alpha: *lag / timestep }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::shapes::Point2d;
+
+    // Notes down every update's dt and every render's alpha.
+    #[derive(Default)]
+    struct Recorded {
+        updates: Vec<f64>,
+        alphas: Vec<f64>,
+    }
+
+    impl Game for Recorded {
+        fn update(&mut self, _game_engine: &GameEngine, dt: f64) {
+            self.updates.push(dt);
+        }
+
+        fn render(&mut self, _game_engine: &GameEngine, alpha: f64) {
+            self.alphas.push(alpha);
+        }
+    }
+
+    #[test]
+    fn without_a_timestep_every_frame_is_one_update() {
+        let mut lag = 0.;
+        assert_eq!(updates(None, &mut lag, 23.5), Updates { count: 1, dt: 23.5, alpha: 1. });
+        assert_eq!(updates(None, &mut lag, 0.), Updates { count: 1, dt: 0., alpha: 1. });
+        assert_eq!(lag, 0.);
+    }
+
+    #[test]
+    fn time_left_over_carries_into_the_next_frame() {
+        let mut lag = 0.;
+        assert_eq!(updates(Some(10.), &mut lag, 25.), Updates { count: 2, dt: 10., alpha: 0.5 });
+        assert_eq!(lag, 5.);
+        assert_eq!(updates(Some(10.), &mut lag, 4.), Updates { count: 0, dt: 10., alpha: 0.9 });
+        assert_eq!(updates(Some(10.), &mut lag, 1.), Updates { count: 1, dt: 10., alpha: 0. });
+
+        // However the frames fall, the same time makes the same number of updates.
+        let mut lag = 0.;
+        let total: u32 = [3., 17., 9., 11., 26., 4.].iter().map(|&delta| updates(Some(10.), &mut lag, delta).count).sum();
+        assert_eq!(total, 7);
+        assert!(lag.abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_long_frame_catches_up_only_so_far() {
+        let mut lag = 0.;
+        let slow = updates(Some(10.), &mut lag, 1000.);
+        assert_eq!(slow, Updates { count: MAX_UPDATES_PER_FRAME, dt: 10., alpha: 0. });
+        assert_eq!(lag, 0., "the rest is dropped rather than owed");
+
+        let next = updates(Some(10.), &mut lag, 15.);
+        assert_eq!((next.count, next.alpha), (1, 0.5));
+    }
+
+    #[test]
+    fn the_engine_runs_the_updates_and_renders_once_a_frame() {
+        let config = EngineConfig { seed: Some(1), fixed_timestep: Some(10.), ..EngineConfig::default() };
+        let engine = GameEngine::headless(config, Point2d { x: 640., y: 480. });
+        let mut game = Recorded::default();
+        for delta in [25., 4., 1.] {
+            engine.step(delta, &mut game);
+        }
+
+        assert_eq!(game.updates, [10., 10., 10.]);
+        assert_eq!(game.alphas, [0.5, 0.9, 0.]);
+        assert_eq!(engine.time().elapsed, 30.);
+    }
+}
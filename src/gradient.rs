@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::Color;
+use crate::tween::Lerp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Stop<T> {
+    pub at: f64,
+    pub value: T,
+}
+
+// Values placed along [0, 1] and interpolated between, e.g. over a particle's normalized lifetime.
+// Written as a list of stops, which are put in order however they come.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<Stop<T>>", into = "Vec<Stop<T>>", bound(serialize = "T: Clone + Serialize"))]
+pub struct Gradient<T> {
+    stops: Vec<Stop<T>>,
+}
+
+impl<T> From<Vec<Stop<T>>> for Gradient<T> {
+    fn from(stops: Vec<Stop<T>>) -> Self {
+        let mut stops: Vec<Stop<T>> = stops.into_iter().map(|stop| Stop { at: stop.at.clamp(0., 1.), ..stop }).collect();
+        stops.sort_by(|a, b| a.at.total_cmp(&b.at));
+        Self { stops }
+    }
+}
+
+impl<T> From<Gradient<T>> for Vec<Stop<T>> {
+    fn from(gradient: Gradient<T>) -> Self {
+        gradient.stops
+    }
+}
+
+#[allow(dead_code)]
+impl<T: Lerp> Gradient<T> {
+    pub fn new(stops: Vec<(f64, T)>) -> Self {
+        Self::from(stops.into_iter().map(|(at, value)| Stop { at, value }).collect::<Vec<_>>())
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self { stops: vec![Stop { at: 0., value }] }
+    }
+
+    // The same gradient with its last stop set to `value`, so it fades into that instead.
+    pub fn ending_in(&self, value: T) -> Self {
+        let mut stops = self.stops.clone();
+        if let Some(last) = stops.last_mut() {
+            last.value = value;
+        }
+        Self { stops }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+
+    // Before the first stop and after the last, their values hold.
+    pub fn sample(&self, t: f64) -> Option<T> {
+        let first = self.stops.first()?;
+        if t <= first.at {
+            return Some(first.value);
+        }
+
+        for pair in self.stops.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            if t <= to.at {
+                let span = to.at - from.at;
+                let local = if span > 0. { (t - from.at) / span } else { 1. };
+                return Some(T::lerp(from.value, to.value, local));
+            }
+        }
+
+        self.stops.last().map(|stop| stop.value)
+    }
+}
+
+impl Lerp for Color {
+    fn lerp(from: Self, to: Self, t: f64) -> Self {
+        Color(Lerp::lerp(from.0, to.0, t))
+    }
+}
+
+pub type ColorGradient = Gradient<Color>;
+
+#[cfg(test)]
+mod tests {
+    use rgb::RGB8;
+    use super::*;
+
+    #[test]
+    fn samples_interpolate_between_the_surrounding_stops() {
+        let gradient = Gradient::new(vec![(1., 10.), (0., 0.), (0.5, 20.)]);
+        assert_eq!(gradient.sample(0.), Some(0.));
+        assert_eq!(gradient.sample(0.25), Some(10.));
+        assert_eq!(gradient.sample(0.5), Some(20.));
+        assert_eq!(gradient.sample(0.75), Some(15.));
+        assert_eq!(gradient.sample(1.), Some(10.));
+    }
+
+    #[test]
+    fn the_end_stops_hold_beyond_them() {
+        let gradient = Gradient::new(vec![(0.2, 1.), (0.8, 3.), (-1., 7.)]);
+        // The stop placed before 0 is clamped onto it.
+        assert_eq!(gradient.sample(-5.), Some(7.));
+        assert_eq!(gradient.sample(0.9), Some(3.));
+        assert_eq!(gradient.sample(5.), Some(3.));
+
+        assert_eq!(Gradient::constant(4.).sample(0.7), Some(4.));
+        assert_eq!(Gradient::<f64>::new(vec![]).sample(0.5), None);
+    }
+
+    #[test]
+    fn stops_at_the_same_place_make_a_hard_edge() {
+        let gradient = Gradient::new(vec![(0., 0.), (0.5, 1.), (0.5, 5.), (1., 5.)]);
+        assert_eq!(gradient.sample(0.25), Some(0.5));
+        assert_eq!(gradient.sample(0.5), Some(1.));
+        assert_eq!(gradient.sample(0.5001), Some(5.));
+    }
+
+    #[test]
+    fn colours_blend_per_channel() {
+        let black = Color(RGB8 { r: 0, g: 0, b: 0 });
+        let orange = Color(RGB8 { r: 255, g: 128, b: 0 });
+        let gradient: ColorGradient = Gradient::new(vec![(0., black), (1., orange)]);
+        assert_eq!(gradient.sample(0.5), Some(Color(RGB8 { r: 128, g: 64, b: 0 })));
+    }
+
+    #[test]
+    fn stops_read_from_json_are_clamped_and_put_in_order() {
+        let gradient: Gradient<f64> = serde_json::from_str(r#"[{"at": 1.5, "value": 10}, {"at": 0.5, "value": 20}, {"at": -1, "value": 0}]"#).unwrap();
+        assert_eq!(gradient, Gradient::new(vec![(0., 0.), (0.5, 20.), (1., 10.)]));
+        assert_eq!(gradient.sample(0.25), Some(10.));
+        assert_eq!(serde_json::to_string(&gradient).unwrap(), r#"[{"at":0.0,"value":0.0},{"at":0.5,"value":20.0},{"at":1.0,"value":10.0}]"#);
+    }
+}
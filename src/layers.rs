@@ -0,0 +1,428 @@
+use crate::Draw;
+use crate::game_engine::View;
+use crate::renderer::Renderer;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompositeMode {
+    SourceOver,
+    Lighter,
+    Multiply,
+    #[allow(dead_code)]
+    Screen,
+    #[allow(dead_code)]
+    DestinationOut,
+}
+
+impl CompositeMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompositeMode::SourceOver => "source-over",
+            CompositeMode::Lighter => "lighter",
+            CompositeMode::Multiply => "multiply",
+            CompositeMode::Screen => "screen",
+            CompositeMode::DestinationOut => "destination-out",
+        }
+    }
+}
+
+pub struct Layer {
+    pub name: String,
+    pub z_index: i32,
+    pub visible: bool,
+    pub composite: CompositeMode,
+    cached: bool,
+    buffered: bool,
+    dirty: bool,
+    items: Vec<Box<dyn Draw>>,
+    buffer: Option<Box<dyn Renderer>>,
+}
+
+impl Layer {
+    pub fn new(name: &str, z_index: i32) -> Self {
+        Self {
+            name: name.to_string(),
+            z_index,
+            visible: true,
+            composite: CompositeMode::SourceOver,
+            cached: false,
+            buffered: false,
+            dirty: true,
+            items: vec![],
+            buffer: None,
+        }
+    }
+
+    // Cached layers keep their last render in an offscreen buffer until invalidated.
+    pub fn cached(mut self) -> Self {
+        self.cached = true;
+        self.buffered = true;
+        self
+    }
+
+    // Buffered layers are drawn offscreen every frame and composited as a whole.
+    pub fn buffered(mut self) -> Self {
+        self.buffered = true;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn hidden(mut self) -> Self {
+        self.visible = false;
+        self
+    }
+
+    pub fn with_composite(mut self, composite: CompositeMode) -> Self {
+        self.composite = composite;
+        self
+    }
+
+    // Hidden layers skip submission; a dirty cache is rebuilt once the layer is shown again.
+    pub fn needs_redraw(&self) -> bool {
+        self.visible && (!self.cached || self.dirty || self.buffer.is_none())
+    }
+
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn submit(&mut self, item: Box<dyn Draw>) {
+        self.items.push(item);
+    }
+
+    fn render(&mut self, renderer: &dyn Renderer, view: &View) {
+        if !self.buffered {
+            Self::draw_items(&mut self.items, renderer, view);
+            return;
+        }
+
+        if !self.cached || self.dirty || self.buffer.is_none() {
+            if self.buffer.is_none() {
+                self.buffer = renderer.create_buffer(view.size);
+            }
+
+            match &self.buffer {
+                Some(buffer) => {
+                    buffer.clear(view.size);
+                    Self::draw_items(&mut self.items, buffer.as_ref(), view);
+                    buffer.flush();
+                    self.dirty = false;
+                }
+                // The backend can't cache, so the layer is redrawn every frame. One that blends as a
+                // whole, like the light map, can't be drawn straight on, so it's left out instead.
+                None => {
+                    if self.composite == CompositeMode::SourceOver {
+                        Self::draw_items(&mut self.items, renderer, view);
+                    } else {
+                        self.items.clear();
+                    }
+                    return;
+                }
+            }
+        }
+
+        self.items.clear();
+        if let Some(buffer) = &self.buffer {
+            renderer.draw_buffer(buffer.as_ref());
+        }
+    }
+
+    fn draw_items(items: &mut Vec<Box<dyn Draw>>, renderer: &dyn Renderer, view: &View) {
+        for item in items.drain(..) {
+            if item.in_view(view) {
+                item.draw(renderer, view);
+            }
+        }
+    }
+}
+
+pub struct Layers {
+    layers: Vec<Layer>,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        let mut layers = Self { layers: vec![] };
+
+        layers.add(Layer::new("background", 0).cached());
+        layers.add(Layer::new("world", 10));
+        layers.add(Layer::new("particles", 20));
+        layers.add(Layer::new("lighting", 25).buffered().with_composite(CompositeMode::Multiply));
+        layers.add(Layer::new("ui", 30));
+        layers.add(Layer::new("debug", 40));
+
+        layers
+    }
+}
+
+impl Layers {
+    pub fn add(&mut self, layer: Layer) {
+        self.layers.retain(|existing| existing.name != layer.name);
+        self.layers.push(layer);
+        self.layers.sort_by_key(|layer| layer.z_index);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    pub fn needs_redraw(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|layer| layer.needs_redraw())
+    }
+
+    pub fn submit(&mut self, name: &str, item: Box<dyn Draw>) {
+        if let Some(layer) = self.get_mut(name) {
+            layer.submit(item);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_z_index(&mut self, name: &str, z_index: i32) {
+        if let Some(layer) = self.get_mut(name) {
+            layer.z_index = z_index;
+        }
+        self.layers.sort_by_key(|layer| layer.z_index);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_visible(&mut self, name: &str, visible: bool) {
+        if let Some(layer) = self.get_mut(name) {
+            layer.visible = visible;
+        }
+    }
+
+    pub fn toggle(&mut self, name: &str) {
+        if let Some(layer) = self.get_mut(name) {
+            layer.visible = !layer.visible;
+        }
+    }
+
+    pub fn invalidate(&mut self, name: &str) {
+        if let Some(layer) = self.get_mut(name) {
+            layer.invalidate();
+        }
+    }
+
+    // Cached layers are stored in screen space, so moving the view makes them stale.
+    pub fn invalidate_all(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.invalidate();
+        }
+    }
+
+    // Offscreen buffers are sized to the canvas, so a resize has to recreate them.
+    pub fn discard_buffers(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.buffer = None;
+        }
+    }
+
+    pub fn render(&mut self, renderer: &dyn Renderer, view: &View) {
+        for layer in self.layers.iter_mut() {
+            if !layer.visible {
+                layer.items.clear();
+                continue;
+            }
+
+            renderer.set_composite(layer.composite);
+            layer.render(renderer, view);
+        }
+
+        renderer.set_composite(CompositeMode::SourceOver);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use rgb::RGB;
+    use web_sys::HtmlCanvasElement;
+    use super::*;
+    use crate::renderer::{Fill, NullRenderer};
+    use crate::shapes::Point2d;
+
+    // Writes down what is drawn where, with buffers sharing the canvas's log.
+    struct Recording {
+        target: &'static str,
+        buffers: bool,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Recording {
+        fn new(buffers: bool) -> Self {
+            Self { target: "canvas", buffers, log: Rc::default() }
+        }
+
+        fn take(&self) -> Vec<String> {
+            self.log.take()
+        }
+
+        fn record(&self, entry: String) {
+            self.log.borrow_mut().push(format!("{} {}", self.target, entry));
+        }
+    }
+
+    impl Renderer for Recording {
+        fn set_scale(&self, _scale: f64) {}
+
+        fn clear(&self, _size: Point2d) {
+            self.record("clear".to_string());
+        }
+
+        fn line(&self, _from: Point2d, _to: Point2d, _color: RGB<u8>) {}
+        fn circle(&self, _center: Point2d, _radius: f64, _color: RGB<u8>) {}
+
+        fn rect(&self, _position: Point2d, _size: Point2d, color: RGB<u8>, _alpha: f64) {
+            self.record(format!("item {}", color.r));
+        }
+
+        fn fill_polygon(&self, _points: &[Point2d], _fill: &Fill) {}
+        fn set_composite(&self, _mode: CompositeMode) {}
+
+        fn create_buffer(&self, _size: Point2d) -> Option<Box<dyn Renderer>> {
+            self.buffers.then(|| Box::new(Recording { target: "buffer", buffers: false, log: self.log.clone() }) as Box<dyn Renderer>)
+        }
+
+        fn draw_buffer(&self, _buffer: &dyn Renderer) {
+            self.record("buffer".to_string());
+        }
+
+        fn surface(&self) -> Option<&HtmlCanvasElement> {
+            None
+        }
+
+        fn flush(&self) {}
+    }
+
+    // Draws a rect whose red channel says which item it was.
+    struct Marker(u8);
+
+    impl Draw for Marker {
+        fn draw(&self, renderer: &dyn Renderer, _view: &View) {
+            let origin = Point2d { x: 0., y: 0. };
+            renderer.rect(origin, origin, RGB { r: self.0, g: 0, b: 0 }, 1.);
+        }
+
+        fn in_view(&self, _view: &View) -> bool {
+            true
+        }
+    }
+
+    fn view() -> View {
+        View::new(Point2d { x: 100., y: 100. })
+    }
+
+    struct Counted(Rc<Cell<usize>>);
+
+    impl Draw for Counted {
+        fn draw(&self, _renderer: &dyn Renderer, _view: &View) {
+            self.0.set(self.0.get() + 1);
+        }
+
+        fn in_view(&self, _view: &View) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn layers_without_a_buffer_are_only_drawn_straight_on_when_they_blend_normally() {
+        // The null renderer has no buffers, like a WebGL2 context that can't make framebuffers.
+        let (renderer, view) = (NullRenderer, View::new(Point2d { x: 100., y: 100. }));
+        let mut layers = Layers::default();
+        let (lighting, background) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+
+        for _ in 0..2 {
+            layers.submit("lighting", Box::new(Counted(lighting.clone())));
+            layers.submit("background", Box::new(Counted(background.clone())));
+            layers.render(&renderer, &view);
+        }
+
+        assert_eq!(lighting.get(), 0, "the light map would cover the scene");
+        assert_eq!(background.get(), 2);
+        assert!(layers.get("lighting").unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn layers_draw_in_z_order_whatever_order_they_are_filled_in() {
+        let renderer = Recording::new(false);
+        let mut layers = Layers::default();
+
+        layers.submit("ui", Box::new(Marker(30)));
+        layers.submit("world", Box::new(Marker(10)));
+        layers.submit("debug", Box::new(Marker(40)));
+        layers.submit("world", Box::new(Marker(11)));
+        layers.render(&renderer, &view());
+        assert_eq!(renderer.take(), ["canvas item 10", "canvas item 11", "canvas item 30", "canvas item 40"]);
+
+        layers.set_z_index("world", 35);
+        layers.submit("world", Box::new(Marker(10)));
+        layers.submit("ui", Box::new(Marker(30)));
+        layers.render(&renderer, &view());
+        assert_eq!(renderer.take(), ["canvas item 30", "canvas item 10"]);
+    }
+
+    #[test]
+    fn hidden_layers_drop_what_was_submitted_to_them() {
+        let renderer = Recording::new(false);
+        let mut layers = Layers::default();
+
+        layers.toggle("world");
+        assert!(!layers.needs_redraw("world"));
+        layers.submit("world", Box::new(Marker(10)));
+        layers.submit("ui", Box::new(Marker(30)));
+        layers.render(&renderer, &view());
+        assert_eq!(renderer.take(), ["canvas item 30"]);
+        assert!(layers.get("world").unwrap().items.is_empty());
+
+        layers.toggle("world");
+        layers.submit("world", Box::new(Marker(10)));
+        layers.render(&renderer, &view());
+        assert_eq!(renderer.take(), ["canvas item 10"]);
+    }
+
+    #[test]
+    fn cached_layers_are_only_redrawn_once_invalidated() {
+        let renderer = Recording::new(true);
+        let mut layers = Layers { layers: vec![] };
+        layers.add(Layer::new("background", 0).cached());
+
+        assert!(layers.needs_redraw("background"));
+        layers.submit("background", Box::new(Marker(1)));
+        layers.render(&renderer, &view());
+        assert_eq!(renderer.take(), ["buffer clear", "buffer item 1", "canvas buffer"]);
+
+        // Nothing is submitted while the cache is good, and the old render is put back as it was.
+        assert!(!layers.needs_redraw("background"));
+        layers.render(&renderer, &view());
+        assert_eq!(renderer.take(), ["canvas buffer"]);
+
+        layers.invalidate("background");
+        assert!(layers.needs_redraw("background"));
+        layers.submit("background", Box::new(Marker(2)));
+        layers.render(&renderer, &view());
+        assert_eq!(renderer.take(), ["buffer clear", "buffer item 2", "canvas buffer"]);
+        assert!(!layers.needs_redraw("background"));
+
+        // A resize drops the buffer, which makes the layer stale again.
+        layers.discard_buffers();
+        assert!(layers.needs_redraw("background"));
+    }
+
+    #[test]
+    fn cached_layers_without_a_buffer_are_drawn_straight_on_every_frame() {
+        let renderer = Recording::new(false);
+        let mut layers = Layers::default();
+
+        for frame in 0..2 {
+            assert!(layers.needs_redraw("background"));
+            layers.submit("background", Box::new(Marker(frame)));
+            layers.submit("lighting", Box::new(Marker(25)));
+            layers.render(&renderer, &view());
+            assert_eq!(renderer.take(), [format!("canvas item {}", frame)]);
+        }
+    }
+}
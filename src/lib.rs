@@ -7,54 +7,374 @@ mod particle_system;
 mod draw;
 mod browser;
 mod game_engine;
+mod renderer;
+mod batch;
+mod webgl_renderer;
+mod layers;
+mod scene;
+mod lighting;
+mod config;
+mod error;
+mod engine_handle;
+mod profiler;
+mod rng;
+mod time;
+mod replay;
+mod snapshot;
+mod tween;
+mod gradient;
+mod forces;
+mod emitter;
+mod effects;
+mod events;
+mod states;
+mod game_loop;
+mod audio;
+mod web_audio;
+mod assets;
+mod web_loader;
+mod tilemap;
+mod navigation;
+mod ai;
+mod network;
+mod web_socket;
 
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
 use web_sys::{MouseEvent, KeyboardEvent};
 
 use game_engine::GameEngine;
-use crate::browser::Browser;
+use crate::browser::{console_log, Browser};
+use crate::config::EngineConfig;
+use crate::engine_handle::EngineHandle;
+use crate::error::EngineError;
+use crate::assets::Manifest;
+use crate::audio::{Bus, SoundOptions, VoiceId};
+use crate::events::{Delivery, EngineEvent, EventKind, SubscriptionId};
+use crate::navigation::PathOptions;
+use crate::replay::{InputEvent, Replay};
+use crate::snapshot::Snapshot;
+use crate::tilemap::Tilemap;
 use crate::draw::Draw;
-use crate::game::{handle_keypress, tick};
+use crate::game::{setup, Demo};
 use crate::shapes::Point2d;
 
-macro_rules! enclose {
-    ( ($( $x:ident ),*) $y:expr ) => {
-        {
-            $(let $x = $x.clone();)*
-            $y
-        }
-    };
+// A running game on one canvas. Any number can exist on a page; `stop()` or `free()` tears one down.
+#[wasm_bindgen]
+pub struct Engine {
+    handle: EngineHandle,
 }
 
-#[wasm_bindgen(start)]
-pub fn start() -> Result<(), JsValue> {
-    let game_engine = GameEngine::create("#canvas");
+#[wasm_bindgen]
+impl Engine {
+    // Takes an optional `EngineConfig` as JSON and an optional asset manifest, also JSON, to load
+    // behind a loading screen before the game starts. Invalid JSON in either is returned as an error.
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: Option<String>, manifest: Option<String>) -> Result<Engine, JsValue> {
+        let config = match config {
+            Some(json) => EngineConfig::from_json(&json).map_err(EngineError::from)?.into(),
+            None => EngineConfig::builder(),
+        };
+        let manifest = match manifest {
+            Some(json) => Manifest::from_json(&json)?,
+            None => Manifest::default(),
+        };
+
+        let game_engine = GameEngine::create(config)?;
+        game_engine.preload(&manifest)?;
+        setup(&game_engine);
+
+        let canvas = game_engine
+            .canvas()
+            .cloned()
+            .ok_or_else(|| EngineError::SelectorNotFound(game_engine.config().canvas_selector.clone()))?;
+        let mut handle = EngineHandle::new(game_engine);
+
+        handle.listen(&canvas, "mousemove", |game_engine, event: MouseEvent| {
+            game_engine.input(InputEvent::MouseMove(event.into()));
+        })?;
+
+        // Keys go to the focused canvas rather than the window, so engines on one page don't share input.
+        if !canvas.has_attribute("tabindex") {
+            canvas.set_attribute("tabindex", "0")?;
+        }
+        handle.listen(&canvas, "keydown", |game_engine, event: KeyboardEvent| {
+            game_engine.input(InputEvent::KeyDown(event.key_code()));
+        })?;
+        canvas.focus().ok();
+
+        handle.observe_size()?;
+        handle.run(Demo)?;
+
+        Ok(Engine { handle })
+    }
+
+    pub fn stop(&mut self) {
+        self.handle.stop();
+    }
+
+    // Min/avg/p95/max per engine phase over the recorded frames, as JSON.
+    #[wasm_bindgen(js_name = profileStats)]
+    pub fn profile_stats(&self) -> String {
+        self.handle.engine().profiler().to_json()
+    }
+
+    // The recorded frames in the Chrome trace event format.
+    #[wasm_bindgen(js_name = profileTrace)]
+    pub fn profile_trace(&self) -> String {
+        self.handle.engine().profiler().to_chrome_trace()
+    }
+
+    #[wasm_bindgen(js_name = clearProfile)]
+    pub fn clear_profile(&self) {
+        self.handle.engine().profiler().clear();
+    }
+
+    // Calls `callback` with each event of `type` ("rayHit", "collision", ...) or "*" for all, as a
+    // plain object with a `type` field. Callbacks run at the end of the frame; returns an id for `off`.
+    // Replays only record input, so of what a callback does to the world only `playEffect` comes
+    // back on playback; anything else it changes will make a recording play out differently.
+    pub fn on(&self, kind: &str, callback: js_sys::Function) -> Result<u32, JsValue> {
+        let kind = match kind {
+            "*" => None,
+            name => Some(EventKind::parse(name).ok_or_else(|| JsValue::from_str(&format!("unknown event type `{}`", name)))?),
+        };
+
+        let id = self.handle.engine().subscribe(kind, Delivery::Deferred, move |_, event: &EngineEvent| {
+            let value = match event.to_json().map_err(|error| JsValue::from_str(&error.to_string())).and_then(|json| js_sys::JSON::parse(&json)) {
+                Ok(value) => value,
+                Err(error) => return console_log(&format!("could not pass {} to JS: {:?}", event.kind().name(), error)),
+            };
+            if let Err(error) = callback.call1(&JsValue::NULL, &value) {
+                console_log(&format!("event handler failed: {:?}", error));
+            }
+        });
+        Ok(id.to_u32())
+    }
+
+    pub fn off(&self, id: u32) -> bool {
+        self.handle.engine().unsubscribe(SubscriptionId::from_u32(id))
+    }
+
+    // Queues a `custom` event with an optional JSON payload for the end of the next frame.
+    pub fn emit(&self, name: &str, data: Option<String>) -> Result<(), JsValue> {
+        let data = match data {
+            Some(json) => serde_json::from_str(&json).map_err(|error| JsValue::from_str(&format!("invalid event data: {}", error)))?,
+            None => serde_json::Value::Null,
+        };
+        self.handle.engine().defer(EngineEvent::Custom { name: name.to_string(), data });
+        Ok(())
+    }
+
+    // Adds or replaces effects from a JSON object of name to definition; returns the names loaded.
+    #[wasm_bindgen(js_name = loadEffects)]
+    pub fn load_effects(&self, json: &str) -> Result<Vec<String>, JsValue> {
+        Ok(self.handle.engine().particle_system().effects().load(json)?)
+    }
+
+    #[wasm_bindgen(js_name = effectNames)]
+    pub fn effect_names(&self) -> Vec<String> {
+        self.handle.engine().particle_system().effects().names()
+    }
+
+    // Plays a named effect at a point in the world on the next frame, fired upwards unless it sets
+    // its own direction. Players sharing the scene see it too, and recordings replay it.
+    #[wasm_bindgen(js_name = playEffect)]
+    pub fn play_effect(&self, name: &str, x: f64, y: f64) -> Result<(), JsValue> {
+        Ok(self.handle.engine().queue_effect(name, Point2d { x, y })?)
+    }
+
+    // Registers an image for particles drawn with the matching `sprite` shape.
+    #[wasm_bindgen(js_name = addSprite)]
+    pub fn add_sprite(&self, name: &str, image: web_sys::HtmlImageElement) {
+        self.handle.engine().add_sprite(name, image);
+    }
+
+    // Replaces the level with a map saved by Tiled as JSON. Tilesets are drawn from the sprites added
+    // under their names, with plain tiles in their place until then.
+    #[wasm_bindgen(js_name = loadTilemap)]
+    pub fn load_tilemap(&self, json: &str) -> Result<(), JsValue> {
+        self.handle.engine().set_tilemap(Tilemap::from_tiled(json)?);
+        Ok(())
+    }
+
+    // A path through the level between two world positions, as a flat array of x and y pairs; empty
+    // when there is no way through. `jumpPoints` switches to jump point search.
+    #[wasm_bindgen(js_name = findPath)]
+    pub fn find_path(&self, from_x: f64, from_y: f64, to_x: f64, to_y: f64, jump_points: Option<bool>) -> Vec<f64> {
+        let mut options = PathOptions::default();
+        if jump_points.unwrap_or(false) {
+            options = options.jump_points();
+        }
+
+        let path = self.handle.engine().find_path(Point2d { x: from_x, y: from_y }, Point2d { x: to_x, y: to_y }, options);
+        path.map_or_else(Vec::new, |path| path.points.iter().flat_map(|point| [point.x, point.y]).collect())
+    }
+
+    // Joins the shared scene of the server at `url`, e.g. `ws://127.0.0.1:9001`, as `name`. Other
+    // players show up as markers and the effects they spawn play here too.
+    pub fn connect(&self, url: &str, name: &str) -> Result<(), JsValue> {
+        Ok(self.handle.engine().connect(url, name)?)
+    }
+
+    pub fn disconnect(&self) {
+        self.handle.engine().disconnect();
+    }
+
+    // Whether the server has welcomed this player and the connection is still open.
+    #[wasm_bindgen(js_name = isConnected)]
+    pub fn is_connected(&self) -> bool {
+        self.handle.engine().network().as_ref().is_some_and(|session| session.is_connected())
+    }
+
+    // Why the connection failed, or why the server turned this player away, if it did.
+    #[wasm_bindgen(js_name = networkError)]
+    pub fn network_error(&self) -> Option<String> {
+        self.handle.engine().network().as_ref().and_then(|session| session.error())
+    }
+
+    // Starts loading the assets in a manifest; `loadingProgress` tells how far it has got.
+    pub fn preload(&self, manifest: &str) -> Result<(), JsValue> {
+        self.handle.engine().preload(&Manifest::from_json(manifest)?)?;
+        Ok(())
+    }
+
+    // How much of the asset loading is over, from 0 to 1.
+    #[wasm_bindgen(js_name = loadingProgress)]
+    pub fn loading_progress(&self) -> f64 {
+        self.handle.engine().assets().progress().fraction()
+    }
+
+    // Why each asset that failed to load did so.
+    #[wasm_bindgen(js_name = assetErrors)]
+    pub fn asset_errors(&self) -> Vec<String> {
+        self.handle.engine().assets().errors().iter().map(ToString::to_string).collect()
+    }
+
+    // Decodes an encoded sound file (wav, ogg, mp3) to play by name; the promise resolves once it can be played.
+    #[wasm_bindgen(js_name = loadSound)]
+    pub fn load_sound(&self, name: &str, data: js_sys::ArrayBuffer) -> js_sys::Promise {
+        let bytes = js_sys::Uint8Array::new(&data).to_vec();
+        let engine = self.handle.engine().clone();
+
+        js_sys::Promise::new(&mut |resolve: js_sys::Function, reject: js_sys::Function| {
+            engine.audio().backend().load(name, &bytes, Box::new(move |result| {
+                let _ = match result {
+                    Ok(()) => resolve.call0(&JsValue::NULL),
+                    Err(error) => reject.call1(&JsValue::NULL, &JsValue::from_str(&error)),
+                };
+            }));
+        })
+    }
+
+    // Plays a sound effect once, from a point in the world if `x` and `y` are given. Returns an id for
+    // `stopSound`, or nothing if the sound isn't loaded.
+    #[wasm_bindgen(js_name = playSound)]
+    pub fn play_sound(&self, name: &str, x: Option<f64>, y: Option<f64>) -> Option<u32> {
+        let mut options = SoundOptions::default();
+        if let (Some(x), Some(y)) = (x, y) {
+            options = options.at(Point2d { x, y });
+        }
+        self.handle.engine().audio().play(name, options).map(VoiceId::to_u32)
+    }
+
+    // Loops a sound on the music bus until it is stopped.
+    #[wasm_bindgen(js_name = playMusic)]
+    pub fn play_music(&self, name: &str) -> Option<u32> {
+        self.handle.engine().audio().play(name, SoundOptions::music()).map(VoiceId::to_u32)
+    }
 
-    // Mouse tracker.
-    {
-        let closure = Closure::<dyn FnMut(_)>::new(
-            enclose!( (game_engine ) move |event:MouseEvent| {
-                game_engine.set_mouse(event);
-            } ),
-        );
-        game_engine.canvas().add_event_listener_with_callback("mousemove", closure.as_ref().unchecked_ref())?;
-        closure.forget();
+    #[wasm_bindgen(js_name = stopSound)]
+    pub fn stop_sound(&self, id: u32) {
+        self.handle.engine().audio().stop(VoiceId::from_u32(id));
     }
 
-    // Keyboard listener.
-    {
-        let closure = Closure::<dyn FnMut(_)>::new(
-            enclose!( (game_engine) move |event:KeyboardEvent| {
-                handle_keypress( &game_engine, event.key_code() );
-            }),
-        );
-        Browser::window().add_event_listener_with_callback("keydown", closure.as_ref().unchecked_ref())?;
-        closure.forget();
+    // Sets the "master", "sfx" or "music" volume; 1 is unchanged.
+    #[wasm_bindgen(js_name = setVolume)]
+    pub fn set_volume(&self, bus: &str, volume: f64) -> Result<(), JsValue> {
+        let bus = Bus::parse(bus).ok_or_else(|| JsValue::from_str(&format!("unknown audio bus `{}`", bus)))?;
+        self.handle.engine().audio().set_volume(bus, volume);
+        Ok(())
     }
 
+    // The seed in use, to pass back as `seed` in the config to reproduce this run.
+    pub fn seed(&self) -> String {
+        self.handle.engine().seed().to_string()
+    }
+
+    // Restarts the world and records input from now on; `stopRecording` returns the replay file.
+    #[wasm_bindgen(js_name = startRecording)]
+    pub fn start_recording(&self) {
+        self.handle.engine().start_recording();
+    }
+
+    #[wasm_bindgen(js_name = stopRecording)]
+    pub fn stop_recording(&self) -> Option<Vec<u8>> {
+        self.handle.engine().stop_recording().map(|replay| replay.encode())
+    }
 
-    game_engine.run(tick);
+    // Plays a file from `stopRecording`; live input is ignored until it ends.
+    #[wasm_bindgen(js_name = playReplay)]
+    pub fn play_replay(&self, bytes: &[u8]) -> Result<(), JsValue> {
+        self.handle.engine().play(Replay::decode(bytes)?)?;
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = isPlaying)]
+    pub fn is_playing(&self) -> bool {
+        self.handle.engine().is_playing()
+    }
+
+    #[wasm_bindgen(js_name = stateHash)]
+    pub fn state_hash(&self) -> String {
+        self.handle.engine().state_hash().to_string()
+    }
+
+    // The world as versioned JSON, for `restore`.
+    pub fn snapshot(&self) -> Result<String, JsValue> {
+        Ok(self.handle.engine().snapshot().to_json()?)
+    }
+
+    #[wasm_bindgen(js_name = snapshotBytes)]
+    pub fn snapshot_bytes(&self) -> Result<Vec<u8>, JsValue> {
+        Ok(self.handle.engine().snapshot().to_bytes()?)
+    }
+
+    pub fn restore(&self, json: &str) -> Result<(), JsValue> {
+        self.handle.engine().restore(Snapshot::from_json(json)?).map_err(EngineError::from)?;
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = restoreBytes)]
+    pub fn restore_bytes(&self, bytes: &[u8]) -> Result<(), JsValue> {
+        self.handle.engine().restore(Snapshot::from_bytes(bytes)?).map_err(EngineError::from)?;
+        Ok(())
+    }
+
+    // Saves the world to `localStorage` under `key`.
+    pub fn save(&self, key: &str) -> Result<(), JsValue> {
+        Browser::store(key, &self.handle.engine().snapshot().to_json()?)?;
+        Ok(())
+    }
+
+    // Restores a world saved with `save`; returns false when there is nothing under `key`.
+    pub fn load(&self, key: &str) -> Result<bool, JsValue> {
+        match Browser::load(key)? {
+            Some(json) => {
+                self.restore(&json)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    #[wasm_bindgen(js_name = isRunning)]
+    pub fn is_running(&self) -> bool {
+        self.handle.is_running()
+    }
+}
 
-    Ok(())
+// Starts a game on the configured canvas and returns its `Engine`.
+#[wasm_bindgen]
+pub fn start(config: Option<String>, manifest: Option<String>) -> Result<Engine, JsValue> {
+    Engine::new(config, manifest)
 }
@@ -0,0 +1,374 @@
+use std::f64;
+use std::ops::Sub;
+use chrono::{DateTime, Utc};
+use rgb::RGB;
+use serde::{Deserialize, Serialize};
+
+use crate::{Draw, Point2d};
+use crate::game_engine::View;
+use crate::layers::CompositeMode;
+use crate::ray::Ray;
+use crate::renderer::{Fill, Renderer};
+use crate::shapes::Line;
+use crate::snapshot::SnapshotError;
+use crate::time::{epoch, from_millis, to_millis};
+
+// Rays cast around the light where no occluder corner is near, so the outline stays round.
+const RING_RAYS: usize = 48;
+// Rays are cast slightly to either side of each corner so they can slip past it.
+const CORNER_EPSILON: f64 = 0.0001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LightKind {
+    Point,
+    Spot { direction: Point2d, angle: f64 },
+}
+
+#[derive(Clone, Copy)]
+pub struct Light {
+    pub position: Point2d,
+    pub color: RGB<u8>,
+    pub radius: f64,
+    pub intensity: f64,
+    // Exponent of the radial fade; 1 is linear, higher is a tighter hot spot.
+    pub falloff: f64,
+    pub kind: LightKind,
+    start_time: DateTime<Utc>,
+    lifetime: Option<u32>,
+}
+
+// A light as saved in a snapshot, with its start time in engine milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LightState {
+    pub position: Point2d,
+    pub color: RGB<u8>,
+    pub radius: f64,
+    pub intensity: f64,
+    pub falloff: f64,
+    pub kind: LightKind,
+    pub start_time: f64,
+    pub lifetime: Option<u32>,
+}
+
+impl Light {
+    pub fn point(position: Point2d, color: RGB<u8>, radius: f64) -> Self {
+        Self {
+            position,
+            color,
+            radius,
+            intensity: 1.0,
+            falloff: 2.0,
+            kind: LightKind::Point,
+            start_time: epoch(),
+            lifetime: None,
+        }
+    }
+
+    pub fn spot(position: Point2d, direction: Point2d, angle: f64, color: RGB<u8>, radius: f64) -> Self {
+        Self {
+            kind: LightKind::Spot { direction, angle },
+            ..Self::point(position, color, radius)
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_intensity(self, intensity: f64) -> Self {
+        Self { intensity, ..self }
+    }
+
+    pub fn with_falloff(self, falloff: f64) -> Self {
+        Self { falloff, ..self }
+    }
+
+    // Short-lived lights fade out linearly over their lifetime in milliseconds, counted from when
+    // they are added to a `LightSystem`.
+    pub fn with_lifetime(self, lifetime: u32) -> Self {
+        Self { lifetime: Some(lifetime), ..self }
+    }
+
+    pub fn to_state(self) -> LightState {
+        LightState {
+            position: self.position,
+            color: self.color,
+            radius: self.radius,
+            intensity: self.intensity,
+            falloff: self.falloff,
+            kind: self.kind,
+            start_time: to_millis(self.start_time),
+            lifetime: self.lifetime,
+        }
+    }
+
+    pub fn from_state(state: &LightState) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            position: state.position,
+            color: state.color,
+            radius: state.radius,
+            intensity: state.intensity,
+            falloff: state.falloff,
+            kind: state.kind,
+            start_time: from_millis(state.start_time)?,
+            lifetime: state.lifetime,
+        })
+    }
+
+    pub fn start_time(&self) -> DateTime<Utc> {
+        self.start_time
+    }
+
+    // How the light fades out from its centre on the view.
+    pub fn glow(&self, view: &View) -> Fill {
+        Fill::Radial {
+            center: view.transform(&self.position),
+            radius: view.scale(self.radius),
+            color: self.color,
+            intensity: self.intensity,
+            falloff: self.falloff,
+        }
+    }
+
+    pub fn intensity_at(&self, time: DateTime<Utc>) -> Option<f64> {
+        match self.lifetime {
+            Some(lifetime) => {
+                let age = time.sub(self.start_time).num_milliseconds() as f64;
+                if age > lifetime as f64 {
+                    return None;
+                }
+                Some(self.intensity * (1. - age / lifetime as f64))
+            }
+            None => Some(self.intensity),
+        }
+    }
+}
+
+fn cast(origin: Point2d, angle: f64, radius: f64, occluders: &[Line]) -> Point2d {
+    let target = Point2d { x: origin.x + angle.cos(), y: origin.y + angle.sin() };
+    let ray = Ray::new(origin, target);
+
+    let distance = occluders
+        .iter()
+        .filter_map(|line| ray.intersects_line(line))
+        .map(|intersection| intersection.distance)
+        .fold(radius, f64::min);
+
+    Point2d { x: origin.x + ray.direction().x * distance, y: origin.y + ray.direction().y * distance }
+}
+
+fn normalize_angle(angle: f64) -> f64 {
+    let full = f64::consts::PI * 2.;
+    ((angle % full) + full) % full
+}
+
+// The area lit by a light: a closed ring for point lights, a fan starting at the origin for spots.
+pub fn visibility_polygon(light: &Light, occluders: &[Line]) -> Vec<Point2d> {
+    let origin = light.position;
+    let full = f64::consts::PI * 2.;
+
+    let (start, sweep) = match light.kind {
+        LightKind::Point => (0., full),
+        LightKind::Spot { direction, angle } => (normalize_angle(direction.y.atan2(direction.x) - angle / 2.), angle.min(full)),
+    };
+
+    let mut angles: Vec<f64> = (0..=RING_RAYS)
+        .map(|index| index as f64 / RING_RAYS as f64 * sweep)
+        .collect();
+
+    for line in occluders.iter() {
+        for corner in [line.from, line.to] {
+            let angle = (corner.y - origin.y).atan2(corner.x - origin.x);
+            for offset in [-CORNER_EPSILON, 0., CORNER_EPSILON] {
+                let relative = normalize_angle(angle + offset - start);
+                if relative <= sweep {
+                    angles.push(relative);
+                }
+            }
+        }
+    }
+
+    angles.sort_by(f64::total_cmp);
+    angles.dedup();
+
+    let mut polygon: Vec<Point2d> = angles
+        .iter()
+        .map(|angle| cast(origin, start + angle, light.radius, occluders))
+        .collect();
+
+    match light.kind {
+        LightKind::Point => {
+            if let Some(first) = polygon.first().copied() {
+                polygon.push(first);
+            }
+        }
+        LightKind::Spot { .. } => polygon.insert(0, origin),
+    }
+
+    polygon
+}
+
+#[derive(Clone)]
+pub struct LightSystem {
+    pub ambient: RGB<u8>,
+    lights: Vec<Light>,
+    time: DateTime<Utc>,
+}
+
+impl Default for LightSystem {
+    fn default() -> Self {
+        Self {
+            ambient: RGB { r: 90, g: 90, b: 110 },
+            lights: vec![],
+            time: epoch(),
+        }
+    }
+}
+
+impl LightSystem {
+    pub fn add(&mut self, light: Light) {
+        self.lights.push(Light { start_time: self.time, ..light });
+    }
+
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        self.lights.clear();
+    }
+
+    // Drops every light and winds the clock back to the start.
+    pub fn reset(&mut self) {
+        self.lights.clear();
+        self.time = epoch();
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn restore(&mut self, ambient: RGB<u8>, lights: Vec<Light>, time: DateTime<Utc>) {
+        self.ambient = ambient;
+        self.lights = lights;
+        self.time = time;
+    }
+
+    pub fn tick(&mut self, time: DateTime<Utc>) {
+        self.time = time;
+        self.lights.retain(|light| light.intensity_at(time).is_some());
+    }
+
+    pub fn light_map(&self, occluders: &[Line], frame_lights: &[Light]) -> LightMap {
+        let time = self.time;
+
+        let lights = self.lights
+            .iter()
+            .chain(frame_lights.iter())
+            .filter_map(|light| light.intensity_at(time).map(|intensity| Light { intensity, ..*light }))
+            .collect();
+
+        LightMap {
+            ambient: self.ambient,
+            lights,
+            occluders: occluders.to_vec(),
+        }
+    }
+}
+
+// Everything the light layer needs for one frame, drawn into its buffer and multiplied over the scene.
+pub struct LightMap {
+    ambient: RGB<u8>,
+    lights: Vec<Light>,
+    occluders: Vec<Line>,
+}
+
+impl Draw for LightMap {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        renderer.rect(Point2d { x: 0., y: 0. }, view.size, self.ambient, 1.0);
+        renderer.set_composite(CompositeMode::Lighter);
+
+        for light in self.lights.iter() {
+            let polygon: Vec<Point2d> = visibility_polygon(light, &self.occluders)
+                .iter()
+                .map(|point| view.transform(point))
+                .collect();
+
+            renderer.fill_polygon(&polygon, &light.glow(view));
+        }
+
+        renderer.set_composite(CompositeMode::SourceOver);
+    }
+
+    fn in_view(&self, _view: &View) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: RGB<u8> = RGB { r: 255, g: 255, b: 255 };
+
+    fn point(x: f64, y: f64) -> Point2d {
+        Point2d { x, y }
+    }
+
+    fn wall(from: Point2d, to: Point2d) -> Line {
+        *Line::new(from, to, WHITE)
+    }
+
+    #[test]
+    fn a_wall_casts_a_shadow() {
+        let light = Light::point(point(0., 0.), WHITE, 100.);
+        let polygon = visibility_polygon(&light, &[wall(point(50., -200.), point(50., 200.))]);
+
+        assert_eq!(polygon.first(), polygon.last(), "point lights close their outline");
+        assert!((polygon[0].distance(point(50., 0.))) < 1e-9, "straight at the wall stops on it");
+        assert!(polygon.iter().all(|corner| corner.x <= 50. + 1e-9), "nothing gets past the wall");
+        assert!(polygon.iter().any(|corner| corner.distance(point(-100., 0.)) < 1e-9), "the other side reaches the radius");
+        assert!(polygon.iter().all(|corner| corner.length() <= 100. + 1e-9));
+    }
+
+    #[test]
+    fn spot_lights_stay_inside_their_cone() {
+        let light = Light::spot(point(0., 0.), point(1., 0.), f64::consts::PI / 2., WHITE, 100.);
+        // Corners outside the cone don't add rays to it.
+        let polygon = visibility_polygon(&light, &[wall(point(-50., -10.), point(-50., 10.))]);
+
+        assert_eq!(polygon[0], point(0., 0.), "spot lights fan out from where they are");
+        for corner in &polygon[1..] {
+            assert!((corner.length() - 100.).abs() < 1e-9);
+            assert!(corner.y.atan2(corner.x).abs() <= f64::consts::PI / 4. + 1e-9, "{:?} is outside the cone", corner);
+        }
+    }
+
+    #[test]
+    fn broken_occluders_are_skipped_rather_than_panicking() {
+        let light = Light::spot(point(0., 0.), point(f64::NAN, 0.), 1., WHITE, 100.);
+        visibility_polygon(&light, &[wall(point(f64::NAN, 0.), point(10., f64::NAN))]);
+        visibility_polygon(&Light::point(point(0., 0.), WHITE, 100.), &[wall(point(f64::NAN, 0.), point(10., 10.))]);
+    }
+
+    #[test]
+    fn light_fades_from_the_centre_to_the_edge() {
+        let view = View::new(point(200., 200.));
+        let light = Light::point(point(0., 0.), WHITE, 100.).with_falloff(2.).with_intensity(0.8);
+        let glow = light.glow(&view);
+        let center = view.transform(&light.position);
+
+        assert!((glow.alpha_at(center) - 0.8).abs() < 1e-12);
+        assert!((glow.alpha_at(center + point(50., 0.)) - 0.2).abs() < 1e-12);
+        assert_eq!(glow.alpha_at(center + point(0., 100.)), 0.);
+        assert_eq!(glow.alpha_at(center + point(0., 150.)), 0., "nothing past the radius");
+    }
+
+    #[test]
+    fn short_lived_lights_fade_over_their_lifetime() {
+        let light = Light::point(point(0., 0.), WHITE, 100.).with_intensity(0.5).with_lifetime(1000);
+        let at = |milliseconds: f64| light.intensity_at(from_millis(milliseconds).unwrap());
+
+        assert_eq!(at(0.), Some(0.5));
+        assert_eq!(at(500.), Some(0.25));
+        assert_eq!(at(1000.), Some(0.));
+        assert_eq!(at(1001.), None);
+        assert_eq!(Light::point(point(0., 0.), WHITE, 100.).intensity_at(from_millis(1e9).unwrap()), Some(1.));
+    }
+}
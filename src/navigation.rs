@@ -0,0 +1,637 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f64::consts::SQRT_2;
+use std::rc::Rc;
+use rgb::RGB;
+use serde::{Deserialize, Serialize};
+
+use crate::Draw;
+use crate::game_engine::View;
+use crate::ray::Ray;
+use crate::renderer::Renderer;
+use crate::shapes::{Line, Point2d};
+
+// Cells of open space left around the walls, so the grid reaches past the outermost ones.
+const BORDER_CELLS: f64 = 4.;
+
+// How far from a blocked start or goal to look for an open cell instead, in cells.
+const SNAP_CELLS: i64 = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct NavSettings {
+    // World size of a grid cell.
+    pub cell_size: f64,
+    // World distance paths keep from walls.
+    pub clearance: f64,
+}
+
+impl Default for NavSettings {
+    fn default() -> Self {
+        Self { cell_size: 16., clearance: 8. }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Search {
+    AStar,
+    // A* that skips over runs of open cells, expanding far fewer nodes in open areas.
+    JumpPoint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathOptions {
+    pub search: Search,
+    // Cuts corners the grid makes wherever there is a clear line of sight.
+    pub smooth: bool,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        Self { search: Search::AStar, smooth: true }
+    }
+}
+
+#[allow(dead_code)]
+impl PathOptions {
+    pub fn jump_points(mut self) -> Self {
+        self.search = Search::JumpPoint;
+        self
+    }
+
+    pub fn without_smoothing(mut self) -> Self {
+        self.smooth = false;
+        self
+    }
+}
+
+// Waypoints from start to goal, and how many grid cells the search had to expand to find them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub points: Vec<Point2d>,
+    pub expanded: usize,
+}
+
+impl Path {
+    #[allow(dead_code)]
+    pub fn length(&self) -> f64 {
+        self.points.windows(2).map(|pair| pair[0].distance(pair[1])).sum()
+    }
+}
+
+type Cell = (i64, i64);
+
+// An open cell waiting to be expanded, ordered so the heap pops the lowest estimate first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Open {
+    estimate: f64,
+    index: usize,
+}
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate).then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Where the level can be walked, as a grid of open and blocked cells. The walls are rasterized into
+// it and grown by the clearance; the walls themselves are kept for line of sight checks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavGrid {
+    pub origin: Point2d,
+    pub cell_size: f64,
+    pub width: i64,
+    pub height: i64,
+    blocked: Vec<bool>,
+    walls: Vec<Line>,
+}
+
+impl NavGrid {
+    // A grid over the walls and a border of open space around them.
+    pub fn from_walls(walls: &[Line], settings: &NavSettings) -> Self {
+        let cell_size = settings.cell_size;
+        let margin = settings.clearance + cell_size * BORDER_CELLS;
+        let points = || walls.iter().flat_map(|wall| [wall.from, wall.to]);
+        let (origin, width, height) = if walls.is_empty() {
+            (Point2d { x: 0., y: 0. }, 0, 0)
+        } else {
+            let left = points().map(|point| point.x).fold(f64::INFINITY, f64::min) - margin;
+            let top = points().map(|point| point.y).fold(f64::INFINITY, f64::min) - margin;
+            let right = points().map(|point| point.x).fold(f64::NEG_INFINITY, f64::max) + margin;
+            let bottom = points().map(|point| point.y).fold(f64::NEG_INFINITY, f64::max) + margin;
+            (Point2d { x: left, y: top }, ((right - left) / cell_size).ceil() as i64, ((bottom - top) / cell_size).ceil() as i64)
+        };
+
+        let mut grid = Self {
+            origin,
+            cell_size,
+            width,
+            height,
+            blocked: vec![false; (width * height) as usize],
+            walls: walls.to_vec(),
+        };
+
+        for wall in walls {
+            for cell in grid.cells_along(wall.from, wall.to) {
+                grid.block(cell);
+            }
+        }
+        grid.grow(settings.clearance);
+        grid
+    }
+
+    pub fn contains(&self, (x, y): Cell) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+
+    pub fn is_open(&self, cell: Cell) -> bool {
+        self.contains(cell) && !self.blocked[(cell.1 * self.width + cell.0) as usize]
+    }
+
+    pub fn cell_at(&self, point: Point2d) -> Cell {
+        (
+            ((point.x - self.origin.x) / self.cell_size).floor() as i64,
+            ((point.y - self.origin.y) / self.cell_size).floor() as i64,
+        )
+    }
+
+    pub fn center(&self, (x, y): Cell) -> Point2d {
+        Point2d {
+            x: self.origin.x + (x as f64 + 0.5) * self.cell_size,
+            y: self.origin.y + (y as f64 + 0.5) * self.cell_size,
+        }
+    }
+
+    fn block(&mut self, cell: Cell) {
+        if self.contains(cell) {
+            self.blocked[(cell.1 * self.width + cell.0) as usize] = true;
+        }
+    }
+
+    // Blocks every cell within `clearance` of one blocked by a wall.
+    fn grow(&mut self, clearance: f64) {
+        let reach = (clearance / self.cell_size).ceil() as i64;
+        if reach == 0 {
+            return;
+        }
+
+        let walled: Vec<Cell> = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .filter(|&cell| !self.is_open(cell))
+            .collect();
+        for (x, y) in walled {
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    if dx * dx + dy * dy <= reach * reach {
+                        self.block((x + dx, y + dy));
+                    }
+                }
+            }
+        }
+    }
+
+    // Every cell the segment passes through, corners included, walking from one cell boundary to
+    // the next.
+    fn cells_along(&self, from: Point2d, to: Point2d) -> Vec<Cell> {
+        let start = self.cell_at(from);
+        let end = self.cell_at(to);
+        let (dx, dy) = ((to.x - from.x) / self.cell_size, (to.y - from.y) / self.cell_size);
+        let (fx, fy) = ((from.x - self.origin.x) / self.cell_size, (from.y - self.origin.y) / self.cell_size);
+
+        // Distance along the segment, from 0 to 1, to the next boundary on each axis and between them.
+        let axis = |delta: f64, position: f64, cell: i64| -> (i64, f64, f64) {
+            if delta > 0. {
+                (1, ((cell + 1) as f64 - position) / delta, 1. / delta)
+            } else if delta < 0. {
+                (-1, (cell as f64 - position) / delta, -1. / delta)
+            } else {
+                (0, f64::INFINITY, f64::INFINITY)
+            }
+        };
+        let (step_x, mut next_x, delta_x) = axis(dx, fx, start.0);
+        let (step_y, mut next_y, delta_y) = axis(dy, fy, start.1);
+
+        let mut cell = start;
+        let mut cells = vec![cell];
+        let steps = (end.0 - start.0).abs() + (end.1 - start.1).abs();
+        for _ in 0..steps {
+            match next_x.total_cmp(&next_y) {
+                Ordering::Less => {
+                    cell.0 += step_x;
+                    next_x += delta_x;
+                }
+                Ordering::Greater => {
+                    cell.1 += step_y;
+                    next_y += delta_y;
+                }
+                Ordering::Equal => {
+                    // Straight through a corner: both cells beside it are touched too.
+                    cells.push((cell.0 + step_x, cell.1));
+                    cells.push((cell.0, cell.1 + step_y));
+                    cell = (cell.0 + step_x, cell.1 + step_y);
+                    next_x += delta_x;
+                    next_y += delta_y;
+                }
+            }
+            cells.push(cell);
+            if cell == end {
+                break;
+            }
+        }
+        cells
+    }
+
+    // Nothing in the way from `from` to `to`: no wall crosses the line and every cell on it is open,
+    // so the path keeps its clearance too.
+    pub fn line_of_sight(&self, from: Point2d, to: Point2d) -> bool {
+        let length = from.distance(to);
+        if length == 0. {
+            return self.is_open(self.cell_at(from));
+        }
+
+        let ray = Ray::new(from, to);
+        let walled = self.walls.iter().any(|wall| ray.intersects_line(wall).is_some_and(|hit| hit.distance <= length));
+        !walled && self.cells_along(from, to).into_iter().all(|cell| self.is_open(cell))
+    }
+
+    // The nearest open cell to one that may be blocked, if there is one close by.
+    fn nearest_open(&self, cell: Cell) -> Option<Cell> {
+        (0..=SNAP_CELLS).find_map(|reach| {
+            let mut ring: Vec<Cell> = (-reach..=reach)
+                .flat_map(|dy| (-reach..=reach).map(move |dx| (cell.0 + dx, cell.1 + dy)))
+                .filter(|&(x, y)| (x - cell.0).abs().max((y - cell.1).abs()) == reach && self.is_open((x, y)))
+                .collect();
+            ring.sort_by_key(|&(x, y)| (x - cell.0).pow(2) + (y - cell.1).pow(2));
+            ring.first().copied()
+        })
+    }
+
+    // A walkable path between two world positions, or `None` if there is none or either end is off
+    // the grid. A start or goal just inside a wall's clearance is moved to the centre of the nearest
+    // open cell, and the path starts or ends there instead.
+    pub fn find_path(&self, from: Point2d, to: Point2d, options: PathOptions) -> Option<Path> {
+        if self.walls.is_empty() {
+            return Some(Path { points: vec![from, to], expanded: 0 });
+        }
+        let start = self.nearest_open(self.cell_at(from))?;
+        let goal = self.nearest_open(self.cell_at(to))?;
+
+        let (cells, expanded) = match options.search {
+            Search::AStar => self.search(start, goal, |cell, _| self.neighbours(cell))?,
+            Search::JumpPoint => {
+                let (jump_points, expanded) = self.search(start, goal, |cell, parent| self.jump_successors(cell, parent, goal))?;
+                (fill_in(&jump_points), expanded)
+            }
+        };
+
+        let first = if start == self.cell_at(from) { from } else { self.center(start) };
+        let last = if goal == self.cell_at(to) { to } else { self.center(goal) };
+        let mut points = vec![first];
+        points.extend(cells.iter().skip(1).take(cells.len().saturating_sub(2)).map(|&cell| self.center(cell)));
+        points.push(last);
+        if options.smooth {
+            points = self.smooth(&points);
+        }
+
+        Some(Path { points, expanded })
+    }
+
+    // Drops every waypoint that can be skipped by heading straight for a later one.
+    fn smooth(&self, points: &[Point2d]) -> Vec<Point2d> {
+        let mut smoothed = vec![points[0]];
+        let mut anchor = 0;
+        while anchor < points.len() - 1 {
+            let next = (anchor + 2..points.len())
+                .rev()
+                .find(|&index| self.line_of_sight(points[anchor], points[index]))
+                .unwrap_or(anchor + 1);
+            smoothed.push(points[next]);
+            anchor = next;
+        }
+        smoothed
+    }
+
+    // A* from `start` to `goal`; `successors` gives the cells reachable from a cell, given the one
+    // it was reached from, with the cost of getting there.
+    fn search<F>(&self, start: Cell, goal: Cell, successors: F) -> Option<(Vec<Cell>, usize)>
+    where
+        F: Fn(Cell, Option<Cell>) -> Vec<(Cell, f64)>,
+    {
+        let index = |(x, y): Cell| (y * self.width + x) as usize;
+        let mut cost = vec![f64::INFINITY; self.blocked.len()];
+        let mut came_from: Vec<Option<Cell>> = vec![None; self.blocked.len()];
+        let mut closed = vec![false; self.blocked.len()];
+        let mut open = BinaryHeap::new();
+
+        cost[index(start)] = 0.;
+        open.push(Open { estimate: octile(start, goal), index: index(start) });
+        let mut expanded = 0;
+
+        while let Some(Open { index: current, .. }) = open.pop() {
+            if closed[current] {
+                continue;
+            }
+            closed[current] = true;
+            expanded += 1;
+
+            let cell = (current as i64 % self.width, current as i64 / self.width);
+            if cell == goal {
+                let mut cells = vec![cell];
+                while let Some(previous) = came_from[index(*cells.last()?)] {
+                    cells.push(previous);
+                }
+                cells.reverse();
+                return Some((cells, expanded));
+            }
+
+            for (next, step) in successors(cell, came_from[current]) {
+                let next_cost = cost[current] + step;
+                if next_cost < cost[index(next)] {
+                    cost[index(next)] = next_cost;
+                    came_from[index(next)] = Some(cell);
+                    open.push(Open { estimate: next_cost + octile(next, goal), index: index(next) });
+                }
+            }
+        }
+        None
+    }
+
+    // Open cells around `cell`; diagonals only where neither side is blocked, so paths never
+    // squeeze past a corner.
+    fn neighbours(&self, (x, y): Cell) -> Vec<(Cell, f64)> {
+        let mut cells = vec![];
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx, dy) != (0, 0) && self.can_step((x, y), dx, dy) {
+                    cells.push(((x + dx, y + dy), if dx != 0 && dy != 0 { SQRT_2 } else { 1. }));
+                }
+            }
+        }
+        cells
+    }
+
+    fn can_step(&self, (x, y): Cell, dx: i64, dy: i64) -> bool {
+        self.is_open((x + dx, y + dy)) && (dx == 0 || dy == 0 || (self.is_open((x + dx, y)) && self.is_open((x, y + dy))))
+    }
+
+    // The directions worth searching from `cell` when arriving from `parent`: straight on, and
+    // whatever the obstacles beside the way in may have been hiding. A side cell is only worth it
+    // when the one behind it is blocked; otherwise the parent reached it more cheaply.
+    fn pruned_directions(&self, (x, y): Cell, parent: Option<Cell>) -> Vec<(i64, i64)> {
+        let Some((px, py)) = parent else {
+            return self.neighbours((x, y)).into_iter().map(|((nx, ny), _)| (nx - x, ny - y)).collect();
+        };
+
+        let (dx, dy) = ((x - px).signum(), (y - py).signum());
+        let open = |dx: i64, dy: i64| self.is_open((x + dx, y + dy));
+        let mut directions = vec![];
+        if dx != 0 && dy != 0 {
+            if open(0, dy) {
+                directions.push((0, dy));
+            }
+            if open(dx, 0) {
+                directions.push((dx, 0));
+            }
+            if open(0, dy) && open(dx, 0) && open(dx, dy) {
+                directions.push((dx, dy));
+            }
+        } else {
+            // Moving along one axis; `(sx, sy)` is to one side of it.
+            let (sx, sy) = (dy.abs(), dx.abs());
+            let ahead = open(dx, dy);
+            for side in [1, -1] {
+                let (bx, by) = (sx * side, sy * side);
+                if !open(bx, by) || open(bx - dx, by - dy) {
+                    continue;
+                }
+                directions.push((bx, by));
+                if ahead && open(dx + bx, dy + by) {
+                    directions.push((dx + bx, dy + by));
+                }
+            }
+            if ahead {
+                directions.push((dx, dy));
+            }
+        }
+        directions
+    }
+
+    fn jump_successors(&self, cell: Cell, parent: Option<Cell>, goal: Cell) -> Vec<(Cell, f64)> {
+        self.pruned_directions(cell, parent)
+            .into_iter()
+            .filter_map(|(dx, dy)| self.jump((cell.0 + dx, cell.1 + dy), dx, dy, goal))
+            .map(|jump_point| (jump_point, octile(cell, jump_point)))
+            .collect()
+    }
+
+    // Walks from `cell` in one direction until it reaches the goal, a cell where a new way opens up
+    // beside a wall, or a dead end.
+    fn jump(&self, mut cell: Cell, dx: i64, dy: i64, goal: Cell) -> Option<Cell> {
+        loop {
+            if !self.is_open(cell) {
+                return None;
+            }
+            if cell == goal {
+                return Some(cell);
+            }
+
+            let (x, y) = cell;
+            let open = |dx: i64, dy: i64| self.is_open((x + dx, y + dy));
+            if dx != 0 && dy != 0 {
+                if self.jump((x + dx, y), dx, 0, goal).is_some() || self.jump((x, y + dy), 0, dy, goal).is_some() {
+                    return Some(cell);
+                }
+            } else if dx != 0 {
+                if (open(0, -1) && !open(-dx, -1)) || (open(0, 1) && !open(-dx, 1)) {
+                    return Some(cell);
+                }
+            } else if (open(-1, 0) && !open(-1, -dy)) || (open(1, 0) && !open(1, -dy)) {
+                return Some(cell);
+            }
+
+            if !(open(dx, 0) && open(0, dy)) {
+                return None;
+            }
+            cell = (x + dx, y + dy);
+        }
+    }
+}
+
+// The cells between consecutive jump points, which are always in a straight or diagonal line.
+fn fill_in(jump_points: &[Cell]) -> Vec<Cell> {
+    let mut cells = vec![jump_points[0]];
+    for pair in jump_points.windows(2) {
+        let (dx, dy) = ((pair[1].0 - pair[0].0).signum(), (pair[1].1 - pair[0].1).signum());
+        let mut cell = pair[0];
+        while cell != pair[1] {
+            cell = (cell.0 + dx, cell.1 + dy);
+            cells.push(cell);
+        }
+    }
+    cells
+}
+
+// Distance on a grid with diagonal moves.
+fn octile(a: Cell, b: Cell) -> f64 {
+    let (dx, dy) = ((a.0 - b.0).abs() as f64, (a.1 - b.1).abs() as f64);
+    dx.max(dy) + (SQRT_2 - 1.) * dx.min(dy)
+}
+
+// Shows the blocked cells in view, the grid's bounds and, if there is one, a path with its waypoints.
+pub struct NavDebug {
+    pub grid: Rc<NavGrid>,
+    pub path: Option<Path>,
+    pub color: RGB<u8>,
+    pub path_color: RGB<u8>,
+}
+
+impl Draw for NavDebug {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        let grid = &self.grid;
+        let first = grid.cell_at(view.to_world(&Point2d { x: 0., y: 0. }));
+        let last = grid.cell_at(view.to_world(&view.size));
+        let size = Point2d { x: view.scale(grid.cell_size), y: view.scale(grid.cell_size) };
+
+        for y in first.1.max(0)..=last.1.min(grid.height - 1) {
+            for x in first.0.max(0)..=last.0.min(grid.width - 1) {
+                if !grid.is_open((x, y)) {
+                    let corner = grid.center((x, y));
+                    let corner = Point2d { x: corner.x - grid.cell_size / 2., y: corner.y - grid.cell_size / 2. };
+                    renderer.rect(view.transform(&corner), size, self.color, 0.25);
+                }
+            }
+        }
+
+        let corners = [(0, 0), (grid.width, 0), (grid.width, grid.height), (0, grid.height), (0, 0)]
+            .map(|(x, y)| view.transform(&Point2d { x: grid.origin.x + x as f64 * grid.cell_size, y: grid.origin.y + y as f64 * grid.cell_size }));
+        for pair in corners.windows(2) {
+            renderer.line(pair[0], pair[1], self.color);
+        }
+
+        let Some(path) = &self.path else { return };
+        for pair in path.points.windows(2) {
+            renderer.line(view.transform(&pair[0]), view.transform(&pair[1]), self.path_color);
+        }
+        for point in path.points.iter() {
+            renderer.circle(view.transform(point), 3., self.path_color);
+        }
+    }
+
+    fn in_view(&self, _view: &View) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: RGB<u8> = RGB { r: 0, g: 0, b: 0 };
+
+    fn wall(x1: f64, y1: f64, x2: f64, y2: f64) -> Line {
+        Line { from: Point2d { x: x1, y: y1 }, to: Point2d { x: x2, y: y2 }, color: BLACK }
+    }
+
+    fn point(x: f64, y: f64) -> Point2d {
+        Point2d { x, y }
+    }
+
+    // The grid only reaches a little past the walls, so every layout is inside a room.
+    fn room(mut walls: Vec<Line>) -> Vec<Line> {
+        walls.extend([wall(0., 0., 420., 0.), wall(420., 0., 420., 420.), wall(420., 420., 0., 420.), wall(0., 420., 0., 0.)]);
+        walls
+    }
+
+    fn layouts() -> Vec<Vec<Line>> {
+        [
+            // A single wall across the way.
+            vec![wall(200., 100., 200., 320.)],
+            // A cup opening away from the start.
+            vec![wall(160., 100., 160., 300.), wall(160., 100., 300., 100.), wall(160., 300., 300., 300.)],
+            // Staggered baffles.
+            vec![wall(100., 0., 100., 300.), wall(220., 100., 220., 420.), wall(340., 0., 340., 300.)],
+            // A diagonal wall with a gap beside it.
+            vec![wall(120., 60., 300., 340.), wall(80., 360., 340., 360.)],
+        ]
+        .into_iter()
+        .map(room)
+        .collect()
+    }
+
+    #[test]
+    fn jump_point_search_finds_paths_as_short_as_a_star() {
+        // On cell centres, so equally short routes through different cells measure the same.
+        let (from, to) = (point(48., 208.), point(368., 208.));
+        for walls in layouts() {
+            let grid = NavGrid::from_walls(&walls, &NavSettings::default());
+            let a_star = grid.find_path(from, to, PathOptions::default().without_smoothing()).unwrap();
+            let jump = grid.find_path(from, to, PathOptions::default().jump_points().without_smoothing()).unwrap();
+            assert!((a_star.length() - jump.length()).abs() < 1e-6, "{:?}: {} vs {}", walls, a_star.length(), jump.length());
+            assert!(jump.expanded <= a_star.expanded);
+        }
+    }
+
+    #[test]
+    fn smoothing_cuts_corners_without_losing_sight_of_the_next_point() {
+        let (from, to) = (point(40., 200.), point(380., 210.));
+        for walls in layouts() {
+            let grid = NavGrid::from_walls(&walls, &NavSettings::default());
+            let rough = grid.find_path(from, to, PathOptions::default().without_smoothing()).unwrap();
+            let smooth = grid.find_path(from, to, PathOptions::default()).unwrap();
+            assert!(smooth.points.len() <= rough.points.len());
+            assert!(smooth.length() <= rough.length() + 1e-6);
+            assert_eq!((smooth.points.first(), smooth.points.last()), (rough.points.first(), rough.points.last()));
+            for pair in smooth.points.windows(2) {
+                assert!(grid.line_of_sight(pair[0], pair[1]), "{:?}", pair);
+            }
+        }
+    }
+
+    #[test]
+    fn ends_inside_the_clearance_are_moved_to_an_open_cell_centre() {
+        let grid = NavGrid::from_walls(&room(vec![wall(200., 100., 200., 320.)]), &NavSettings::default());
+        let (from, to) = (point(196., 200.), point(40., 200.));
+        assert!(!grid.is_open(grid.cell_at(from)));
+
+        let path = grid.find_path(from, to, PathOptions::default()).unwrap();
+        let start = path.points[0];
+        assert_eq!(start, grid.center(grid.cell_at(start)));
+        assert!(grid.is_open(grid.cell_at(start)));
+        assert_eq!(path.points.last(), Some(&to), "an open goal is kept as it is");
+    }
+
+    #[test]
+    fn paths_without_walls_go_straight_there() {
+        let grid = NavGrid::from_walls(&[], &NavSettings::default());
+        let path = grid.find_path(point(0., 0.), point(30., 40.), PathOptions::default()).unwrap();
+        assert_eq!(path.points, vec![point(0., 0.), point(30., 40.)]);
+        assert_eq!(path.length(), 50.);
+    }
+
+    #[test]
+    fn cells_along_a_segment_include_the_corners_it_cuts_through() {
+        let grid = NavGrid::from_walls(&[wall(0., 0., 64., 64.)], &NavSettings { cell_size: 16., clearance: 0. });
+        let at = |x: f64, y: f64| grid.cell_at(point(x, y));
+
+        let straight = grid.cells_along(point(8., 8.), point(56., 8.));
+        assert_eq!(straight, vec![at(8., 8.), at(24., 8.), at(40., 8.), at(56., 8.)]);
+
+        // Exactly through the corner between four cells: both cells beside it are touched.
+        let diagonal = grid.cells_along(point(8., 8.), point(24., 24.));
+        assert_eq!(diagonal, vec![at(8., 8.), at(24., 8.), at(8., 24.), at(24., 24.)]);
+
+        let mut backwards = grid.cells_along(point(56., 8.), point(8., 8.));
+        backwards.reverse();
+        assert_eq!(backwards, straight);
+
+        assert_eq!(grid.cells_along(point(20., 20.), point(20., 20.)), vec![at(20., 20.)]);
+    }
+}
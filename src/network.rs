@@ -0,0 +1,399 @@
+use protocol::{
+    decode, encode, ClientMessage, Command, EffectSpawn, Entity, EntityId, PlayerId, ServerMessage, Vec2, View, WorldState,
+    PROTOCOL_VERSION,
+};
+use rgb::RGB8;
+
+use crate::draw::Draw;
+use crate::game_engine::View as EngineView;
+use crate::renderer::Renderer;
+use crate::shapes::Point2d;
+
+const MARKER_RADIUS: f64 = 8.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connection {
+    Connecting,
+    Open,
+    Closed,
+}
+
+// Carries encoded messages to and from a server. `WebSocketTransport` is the one used in the browser.
+pub trait Transport {
+    fn connection(&self) -> Connection;
+    fn send(&self, bytes: Vec<u8>);
+    // Everything received since the last call, oldest first.
+    fn receive(&self) -> Vec<Vec<u8>>;
+    fn close(&self);
+
+    // Why the connection failed or was closed from the other end, if it was.
+    fn failure(&self) -> Option<String> {
+        None
+    }
+}
+
+impl From<Point2d> for Vec2 {
+    fn from(point: Point2d) -> Self {
+        Vec2 { x: point.x, y: point.y }
+    }
+}
+
+impl From<Vec2> for Point2d {
+    fn from(point: Vec2) -> Self {
+        Point2d { x: point.x, y: point.y }
+    }
+}
+
+// This engine's seat in a shared scene: greets the server once connected, mirrors the world from
+// its deltas and sends what the local player does.
+pub struct NetSession {
+    transport: Box<dyn Transport>,
+    name: String,
+    greeted: bool,
+    joined: Option<(PlayerId, EntityId)>,
+    state: WorldState,
+    sequence: u32,
+    // The last of each sent, so unchanged positions and views aren't sent every frame.
+    sent_position: Option<Vec2>,
+    sent_view: Option<View>,
+    error: Option<String>,
+}
+
+impl NetSession {
+    pub fn new(transport: Box<dyn Transport>, name: &str) -> Self {
+        Self {
+            transport,
+            name: name.to_string(),
+            greeted: false,
+            joined: None,
+            state: WorldState::default(),
+            sequence: 0,
+            sent_position: None,
+            sent_view: None,
+            error: None,
+        }
+    }
+
+    // Handles everything the server sent since the last poll and returns the effects other players
+    // spawned in the meantime.
+    pub fn poll(&mut self) -> Vec<EffectSpawn> {
+        if self.transport.connection() == Connection::Open && !self.greeted {
+            self.greeted = true;
+            self.transmit(ClientMessage::Hello { version: PROTOCOL_VERSION, name: self.name.clone() });
+        }
+
+        let mut effects = vec![];
+        for bytes in self.transport.receive() {
+            match decode(&bytes) {
+                Ok(ServerMessage::Welcome { player, entity, tick }) => {
+                    self.joined = Some((player, entity));
+                    // Effects spawned before joining are history, not something to play.
+                    self.state.advance(tick);
+                }
+                Ok(ServerMessage::Rejected { reason }) => {
+                    self.error = Some(reason);
+                    self.transport.close();
+                }
+                Ok(ServerMessage::State(delta)) => match self.state.apply(delta) {
+                    Ok(fresh) => {
+                        effects.extend(fresh);
+                        self.transmit(ClientMessage::Ack { tick: self.state.tick });
+                    }
+                    Err(error) => self.error = Some(error.to_string()),
+                },
+                Err(error) => self.error = Some(error.to_string()),
+            }
+        }
+
+        let player = self.player();
+        effects.retain(|effect| Some(effect.player) != player);
+        effects
+    }
+
+    // Moves the local player's entity, if it isn't there already.
+    pub fn move_to(&mut self, position: Point2d) {
+        let position = Vec2::from(position);
+        if self.joined.is_some() && self.sent_position != Some(position) {
+            self.sent_position = Some(position);
+            self.send(Command::Move { position });
+        }
+    }
+
+    pub fn set_view(&mut self, center: Point2d, zoom: f64) {
+        let view = View { center: center.into(), zoom };
+        if self.joined.is_some() && self.sent_view != Some(view) {
+            self.sent_view = Some(view);
+            self.send(Command::SetView(view));
+        }
+    }
+
+    // Commands before the server has welcomed the player are dropped.
+    pub fn send(&mut self, command: Command) {
+        if self.joined.is_some() {
+            self.sequence += 1;
+            self.transmit(ClientMessage::Input { sequence: self.sequence, command });
+        }
+    }
+
+    fn transmit(&mut self, message: ClientMessage) {
+        match encode(&message) {
+            Ok(bytes) => self.transport.send(bytes),
+            Err(error) => self.error = Some(error.to_string()),
+        }
+    }
+
+    pub fn player(&self) -> Option<PlayerId> {
+        self.joined.map(|(player, _)| player)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.joined.is_some() && self.transport.connection() == Connection::Open
+    }
+
+    // What went wrong first: the server turning the player away, a message that made no sense, or
+    // the connection itself.
+    pub fn error(&self) -> Option<String> {
+        self.error.clone().or_else(|| self.transport.failure())
+    }
+
+    // Entities belonging to everyone but the local player.
+    pub fn remote_entities(&self, color: RGB8) -> RemoteEntities {
+        let player = self.player();
+        let entities = self.state.entities.values().filter(|entity| Some(entity.owner) != player).copied().collect();
+        RemoteEntities { entities, color }
+    }
+
+    pub fn close(&self) {
+        self.transport.close();
+    }
+}
+
+// Where the other players are, drawn as markers.
+pub struct RemoteEntities {
+    entities: Vec<Entity>,
+    color: RGB8,
+}
+
+impl RemoteEntities {
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+impl Draw for RemoteEntities {
+    fn draw(&self, renderer: &dyn Renderer, view: &EngineView) {
+        for entity in self.entities.iter() {
+            let center = view.transform(&entity.position.into());
+            let radius = view.scale(MARKER_RADIUS);
+            renderer.circle(center, radius, self.color);
+            renderer.circle(center, radius / 3., self.color);
+        }
+    }
+
+    fn in_view(&self, view: &EngineView) -> bool {
+        let reach = view.scale(MARKER_RADIUS);
+        self.entities.iter().any(|entity| {
+            let center = view.transform(&entity.position.into());
+            center.x > -reach && center.y > -reach && center.x < view.size.x + reach && center.y < view.size.y + reach
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::net::{SocketAddr, TcpStream};
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use server::{Server, ServerHandle};
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::{Error, Message, WebSocket};
+    use super::*;
+
+    const PATIENCE: Duration = Duration::from_secs(5);
+
+    // The browser's WebSocket stood in for by tungstenite, keeping what the session sent.
+    struct Loopback {
+        socket: RefCell<WebSocket<MaybeTlsStream<TcpStream>>>,
+        open: Cell<bool>,
+        sent: Rc<RefCell<Vec<ClientMessage>>>,
+    }
+
+    impl Loopback {
+        fn open(addr: SocketAddr) -> (Self, Rc<RefCell<Vec<ClientMessage>>>) {
+            let (socket, _) = tungstenite::connect(format!("ws://{}", addr)).expect("connects over loopback");
+            if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+                stream.set_nonblocking(true).unwrap();
+            }
+            let sent = Rc::new(RefCell::new(vec![]));
+            (Self { socket: RefCell::new(socket), open: Cell::new(true), sent: sent.clone() }, sent)
+        }
+    }
+
+    impl Transport for Loopback {
+        fn connection(&self) -> Connection {
+            if self.open.get() { Connection::Open } else { Connection::Closed }
+        }
+
+        fn send(&self, bytes: Vec<u8>) {
+            self.sent.borrow_mut().push(decode(&bytes).unwrap());
+            // Would-block only means the frame is queued until the next flush.
+            self.socket.borrow_mut().send(Message::Binary(bytes)).ok();
+        }
+
+        fn receive(&self) -> Vec<Vec<u8>> {
+            let mut socket = self.socket.borrow_mut();
+            socket.flush().ok();
+            let mut received = vec![];
+            loop {
+                match socket.read() {
+                    Ok(Message::Binary(bytes)) => received.push(bytes),
+                    Ok(Message::Close(_)) => self.open.set(false),
+                    Ok(_) => {}
+                    Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::WouldBlock => return received,
+                    Err(_) => {
+                        self.open.set(false);
+                        return received;
+                    }
+                }
+            }
+        }
+
+        fn close(&self) {
+            self.open.set(false);
+            self.socket.borrow_mut().close(None).ok();
+        }
+    }
+
+    // Hands over whatever the test put in `inbox`; the connection is open until closed.
+    #[derive(Default)]
+    struct Scripted {
+        inbox: RefCell<Vec<Vec<u8>>>,
+        closed: Rc<Cell<bool>>,
+        failure: Option<String>,
+    }
+
+    impl Transport for Scripted {
+        fn connection(&self) -> Connection {
+            if self.closed.get() { Connection::Closed } else { Connection::Open }
+        }
+
+        fn send(&self, _bytes: Vec<u8>) {}
+
+        fn receive(&self) -> Vec<Vec<u8>> {
+            std::mem::take(&mut self.inbox.borrow_mut())
+        }
+
+        fn close(&self) {
+            self.closed.set(true);
+        }
+
+        fn failure(&self) -> Option<String> {
+            self.failure.clone()
+        }
+    }
+
+    fn start() -> ServerHandle {
+        Server::bind("127.0.0.1:0", Duration::from_millis(5)).and_then(Server::spawn).expect("server starts")
+    }
+
+    fn join(server: &ServerHandle, name: &str) -> (NetSession, Rc<RefCell<Vec<ClientMessage>>>) {
+        let (transport, sent) = Loopback::open(server.addr());
+        (NetSession::new(Box::new(transport), name), sent)
+    }
+
+    // Polls the sessions until `done` holds, returning the effects each was handed.
+    fn poll_until(sessions: &mut [&mut NetSession], what: &str, done: impl Fn(&[&mut NetSession]) -> bool) -> Vec<Vec<EffectSpawn>> {
+        let deadline = Instant::now() + PATIENCE;
+        let mut effects = vec![vec![]; sessions.len()];
+        while !done(sessions) {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            for (session, played) in sessions.iter_mut().zip(effects.iter_mut()) {
+                played.extend(session.poll());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        effects
+    }
+
+    fn point(x: f64, y: f64) -> Point2d {
+        Point2d { x, y }
+    }
+
+    #[test]
+    fn joins_the_server_and_mirrors_its_world() {
+        let server = start();
+        let (mut alice, sent) = join(&server, "alice");
+        assert!(!alice.is_connected());
+
+        poll_until(&mut [&mut alice], "the first state", |sessions| {
+            sessions[0].player().is_some_and(|player| sessions[0].state.players.contains_key(&player))
+        });
+        assert!(alice.is_connected());
+        assert_eq!(alice.error(), None);
+
+        let sent = sent.borrow().clone();
+        assert_eq!(sent[0], ClientMessage::Hello { version: PROTOCOL_VERSION, name: "alice".to_string() });
+        assert!(sent.iter().any(|message| matches!(message, ClientMessage::Ack { .. })));
+        assert_eq!(server.world().published_at(alice.state.tick), Some(&alice.state));
+
+        // Moves reach the server once, however often the same position is asked for.
+        alice.move_to(point(30., -12.));
+        alice.move_to(point(30., -12.));
+        alice.set_view(point(5., 5.), 2.);
+        let (_, entity) = alice.joined.unwrap();
+        poll_until(&mut [&mut alice], "the move", |sessions| {
+            sessions[0].state.entities.get(&entity).map(|entity| entity.position) == Some(Vec2 { x: 30., y: -12. })
+        });
+        assert_eq!(alice.sequence, 2);
+        server.stop();
+    }
+
+    #[test]
+    fn plays_effects_of_other_players_only() {
+        let server = start();
+        let (mut alice, _) = join(&server, "alice");
+        let (mut bob, _) = join(&server, "bob");
+        poll_until(&mut [&mut alice, &mut bob], "both to join", |sessions| {
+            sessions.iter().all(|session| session.state.players.len() == 2)
+        });
+
+        alice.send(Command::SpawnEffect { name: "smoke".to_string(), position: Vec2 { x: 1., y: 2. } });
+        bob.send(Command::SpawnEffect { name: "dust".to_string(), position: Vec2 { x: 3., y: 4. } });
+        let tick = server.world().published().map_or(0, |state| state.tick);
+        let effects = poll_until(&mut [&mut alice, &mut bob], "a few more ticks", |sessions| {
+            sessions.iter().all(|session| session.state.tick > tick + 5)
+        });
+
+        let names = |effects: &[EffectSpawn]| effects.iter().map(|effect| effect.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&effects[0]), ["dust"]);
+        assert_eq!(names(&effects[1]), ["smoke"]);
+        assert_eq!(bob.remote_entities(RGB8::default()).entities.len(), 1);
+        server.stop();
+    }
+
+    #[test]
+    fn rejections_and_dropped_connections_are_errors() {
+        let transport = Scripted::default();
+        let closed = transport.closed.clone();
+        let reason = "protocol version 1 is not supported".to_string();
+        transport.inbox.borrow_mut().push(encode(&ServerMessage::Rejected { reason: reason.clone() }).unwrap());
+
+        let mut session = NetSession::new(Box::new(transport), "late");
+        assert!(session.poll().is_empty());
+        assert_eq!(session.error(), Some(reason));
+        assert!(closed.get() && !session.is_connected());
+
+        // A connection that failed shows up the same way, as does nonsense from the server.
+        let failure = "could not reach the server".to_string();
+        let session = NetSession::new(Box::new(Scripted { failure: Some(failure.clone()), ..Scripted::default() }), "lost");
+        assert_eq!(session.error(), Some(failure));
+
+        let transport = Scripted::default();
+        transport.inbox.borrow_mut().push(vec![0xff, 0xff, 0xff]);
+        let mut session = NetSession::new(Box::new(transport), "confused");
+        session.poll();
+        assert!(session.error().is_some_and(|error| error.starts_with("could not decode")));
+    }
+}
@@ -1,24 +1,50 @@
 use std::cell::{RefCell, RefMut};
+use std::collections::VecDeque;
 use std::ops::{Sub};
 use std::rc::Rc;
 use chrono::{DateTime, Duration, Utc};
 use rgb::RGB;
-use web_sys::CanvasRenderingContext2d;
+use serde::{Deserialize, Serialize};
 
 use crate::game_engine::View;
 use crate::{Draw, Point2d};
-use crate::shapes::rgb;
+use crate::batch::circle_outline;
+use crate::emitter::Emitter;
+use crate::forces::{CollisionResponse, ForceContext, ForceField, Hit};
+use crate::gradient::{ColorGradient, Gradient};
+use crate::renderer::{Fill, Renderer};
+use crate::particle_animation::{ParticleMotion, VelocityCurve};
+use crate::ray::Ray;
+use crate::shapes::Line;
+use crate::snapshot::SnapshotError;
+use crate::time::{epoch, from_millis, to_millis};
 
+// Ignores walls closer than this along the way, so particles spawned on a wall can leave it.
+const COLLISION_EPSILON: f64 = 0.000001;
+// How far from a wall a bounced particle is put back, so it doesn't hit the same wall again.
+const SURFACE_OFFSET: f64 = 0.01;
+// Velocities are in pixels per 60th of a second, the frame rate the effects were tuned at.
+const FRAME: f64 = 1000. / 60.;
+// Most walls a particle bounces off in one step; past that, as in a tight corner, it stays by the
+// last one.
+const MAX_BOUNCES: usize = 4;
+
+#[derive(Default)]
 struct Render {
     pixel: Option<ParticlePixel>,
 }
 
-impl Default for Render {
-    fn default() -> Self {
-        Render {
-            pixel: None
-        }
-    }
+// The parts of a particle's motion that forces and collisions change as it goes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dynamics {
+    // Starts out as the particle's direction and turns when it bounces.
+    pub heading: Point2d,
+    // Multiplies the velocity curve; bounces take some of it away.
+    pub speed_scale: f64,
+    // Velocity picked up from force fields, in pixels per second.
+    pub drift: Point2d,
+    pub stuck: bool,
 }
 
 #[derive(Clone)]
@@ -29,19 +55,36 @@ pub struct Particle {
     direction: Point2d,
     velocity: Rc<RefCell<f64>>,
     render: Rc<RefCell<Render>>,
-    tick: fn(
-        start_pixel: &ParticlePixel,
-        current_pixel: Option<ParticlePixel>,
-        direction: &Point2d,
-        velocity: &f64,
-        delta: Duration,
-        lifetime: &u32,
-    ) -> Option<ParticlePixel>,
-    update_velocity: fn(
-        velocity: &f64,
-        delta: Duration,
-    ) -> f64,
+    motion: ParticleMotion,
+    velocity_curve: VelocityCurve,
     lifetime: u32,
+    style: Rc<ParticleStyle>,
+    dynamics: Rc<RefCell<Dynamics>>,
+    // Past positions, oldest first, kept only when the style has a trail.
+    trail: Rc<RefCell<VecDeque<TrailPoint>>>,
+    on_death: Option<Rc<Emitter>>,
+    // Applied on top of the system's force fields.
+    forces: Rc<Vec<ForceField>>,
+}
+
+// A particle as saved in a snapshot, with its start time in engine milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParticleState {
+    pub start_pixel: ParticlePixel,
+    pub pixel: Option<ParticlePixel>,
+    pub start_time: f64,
+    pub start_velocity: f64,
+    pub velocity: f64,
+    pub direction: Point2d,
+    pub motion: ParticleMotion,
+    pub velocity_curve: VelocityCurve,
+    pub lifetime: u32,
+    pub style: ParticleStyle,
+    pub dynamics: Dynamics,
+    pub trail: Vec<TrailPoint>,
+    pub on_death: Option<Emitter>,
+    pub forces: Vec<ForceField>,
 }
 
 impl PartialEq for Particle {
@@ -54,26 +97,98 @@ impl PartialEq for Particle {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ParticlePixel {
     pub position: Point2d,
     pub color: RGB<u8>,
     pub alpha: f64,
+    pub size: f64,
+    // Radians, around the particle's center.
+    pub rotation: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ParticleShape {
+    #[default]
+    Square,
+    Circle,
+    // Stretched out behind the particle along its direction of travel.
+    Streak,
+    // An image added to the renderer under `name`; drawn as a square where it isn't available.
+    Sprite { name: String },
+}
+
+// How a particle looks over its life. Both curves are sampled at the normalized age: the colour
+// replaces the pixel colour, the size multiplies the starting size.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct ParticleStyle {
+    pub shape: ParticleShape,
+    pub color: Option<ColorGradient>,
+    pub size: Option<Gradient<f64>>,
+    // Radians per second.
+    pub spin: f64,
+    pub trail: Option<Trail>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TrailShape {
+    // Even width, fading out towards the tail.
+    #[default]
+    Line,
+    // Fading and narrowing to a point at the tail.
+    Ribbon,
+}
+
+// A particle's recent path, drawn behind it in its current colour. `length` is in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct Trail {
+    pub length: f64,
+    pub width: f64,
+    pub shape: TrailShape,
+}
+
+impl Default for Trail {
+    fn default() -> Self {
+        Self { length: 130., width: 1., shape: TrailShape::Line }
+    }
+}
+
+// Where a particle was at `time`, in engine milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrailPoint {
+    pub time: f64,
+    pub position: Point2d,
 }
 
 impl Draw for Particle {
-    fn draw(&self, context: &CanvasRenderingContext2d, view: &View) -> () {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
         let pixel: Option<ParticlePixel> = self.render.borrow().pixel;
-        match pixel {
-            Some(pixel) => {
-                let color: String = rgb(pixel.color);
-                let coords: Point2d = view.transform(&pixel.position);
-                context.set_global_alpha(pixel.alpha);
-                context.set_fill_style(&color.into());
-                context.fill_rect(coords.x, coords.y, 2., 2.);
-                context.set_global_alpha(1.0);
+        let Some(pixel) = pixel else { return };
+
+        if let Some(trail) = self.style.trail {
+            self.draw_trail(renderer, view, &trail, &pixel);
+        }
+
+        // Every shape is centred on the particle.
+        let center: Point2d = view.transform(&pixel.position);
+        let size = view.scale(pixel.size);
+        let corner = Point2d { x: center.x - size / 2., y: center.y - size / 2. };
+        let fill = Fill::Solid { color: pixel.color, alpha: pixel.alpha };
+
+        match &self.style.shape {
+            ParticleShape::Square if pixel.rotation == 0. => renderer.rect(corner, Point2d { x: size, y: size }, pixel.color, pixel.alpha),
+            ParticleShape::Square => renderer.fill_polygon(&rotated_square(center, size, pixel.rotation), &fill),
+            ParticleShape::Circle => renderer.fill_polygon(&circle_outline(center, size / 2.), &fill),
+            ParticleShape::Streak => renderer.fill_polygon(&streak(center, self.heading(), size), &fill),
+            ParticleShape::Sprite { name } => {
+                if !renderer.sprite(name, center, size, pixel.rotation, pixel.alpha) {
+                    renderer.rect(corner, Point2d { x: size, y: size }, pixel.color, pixel.alpha);
+                }
             }
-            None => ()
         }
     }
 
@@ -90,38 +205,134 @@ impl Draw for Particle {
     }
 }
 
+fn rotated_square(center: Point2d, size: f64, rotation: f64) -> Vec<Point2d> {
+    let half = size / 2.;
+
+    [(-half, -half), (half, -half), (half, half), (-half, half)]
+        .iter()
+        .map(|&(x, y)| center + Point2d { x, y }.rotate(rotation))
+        .collect()
+}
+
+// A thin quad trailing `length` times four behind `head`.
+fn streak(head: Point2d, direction: Point2d, size: f64) -> Vec<Point2d> {
+    let length = size * 4.;
+    let half_width = size / 4.;
+    let normal = direction.perpendicular() * half_width;
+    let tail = head - direction * length;
+
+    vec![head + normal, tail + normal, tail - normal, head - normal]
+}
+
+// The nearest wall crossed going from `from` to `to`, and where.
+fn first_hit(from: Point2d, to: Point2d, walls: &[Line]) -> Option<(Point2d, Line)> {
+    let travel = from.distance(to);
+    if travel < COLLISION_EPSILON {
+        return None;
+    }
+
+    let ray = Ray::new(from, to);
+    walls
+        .iter()
+        .filter_map(|wall| ray.intersects_line(wall))
+        .filter(|intersection| intersection.distance > COLLISION_EPSILON && intersection.distance <= travel)
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+        .map(|intersection| (intersection.point, intersection.target))
+}
+
+// The wall's unit normal on the side `from` is on; a wall with no length has none.
+fn wall_normal(wall: &Line, from: Point2d, point: Point2d) -> Option<Point2d> {
+    let normal = (wall.to - wall.from).unit()?.perpendicular();
+    Some(if normal.dot(from - point) < 0. { -normal } else { normal })
+}
+
 impl Particle {
     pub fn new(
         pixel: ParticlePixel,
         direction: Point2d,
         velocity: f64,
         lifetime: u32,
-        update_velocity: fn(
-            velocity: &f64,
-            delta: Duration,
-        ) -> f64,
-        tick: fn(
-            start_pixel: &ParticlePixel,
-            current_pixel: Option<ParticlePixel>,
-            direction: &Point2d,
-            velocity: &f64,
-            delta: Duration,
-            lifetime: &u32,
-        ) -> Option<ParticlePixel>,
+        velocity_curve: VelocityCurve,
+        motion: ParticleMotion,
     ) -> Self {
         Self {
             render: Rc::new(RefCell::new(Render { pixel: Some(pixel) })),
             start_pixel: pixel,
-            start_time: Utc::now(),
-            tick,
+            start_time: epoch(),
+            motion,
             direction,
             velocity: Rc::new(RefCell::new(velocity)),
             start_velocity: velocity,
-            update_velocity,
+            velocity_curve,
             lifetime,
+            style: Rc::new(ParticleStyle::default()),
+            dynamics: Rc::new(RefCell::new(Dynamics { heading: direction, speed_scale: 1., drift: Point2d { x: 0., y: 0. }, stuck: false })),
+            trail: Rc::new(RefCell::new(VecDeque::new())),
+            on_death: None,
+            forces: Rc::new(vec![]),
+        }
+    }
+
+    pub fn with_forces(self, forces: Rc<Vec<ForceField>>) -> Self {
+        Self { forces, ..self }
+    }
+
+    pub fn with_style(self, style: impl Into<Rc<ParticleStyle>>) -> Self {
+        Self { style: style.into(), ..self }
+    }
+
+    // Fired where the particle was when its lifetime runs out or a wall kills it.
+    pub fn with_on_death(self, on_death: Option<Rc<Emitter>>) -> Self {
+        Self { on_death, ..self }
+    }
+
+    pub fn on_death(&self) -> Option<&Rc<Emitter>> {
+        self.on_death.as_ref()
+    }
+
+    pub fn to_state(&self) -> ParticleState {
+        ParticleState {
+            start_pixel: self.start_pixel,
+            pixel: self.pixel(),
+            start_time: to_millis(self.start_time),
+            start_velocity: self.start_velocity,
+            velocity: self.velocity(),
+            direction: self.direction,
+            motion: self.motion,
+            velocity_curve: self.velocity_curve,
+            lifetime: self.lifetime,
+            style: self.style.as_ref().clone(),
+            dynamics: *self.dynamics.borrow(),
+            trail: self.trail.borrow().iter().copied().collect(),
+            on_death: self.on_death.as_deref().cloned(),
+            forces: self.forces.as_ref().clone(),
         }
     }
 
+    pub fn from_state(state: &ParticleState) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            render: Rc::new(RefCell::new(Render { pixel: state.pixel })),
+            start_pixel: state.start_pixel,
+            start_time: from_millis(state.start_time)?,
+            motion: state.motion,
+            direction: state.direction,
+            velocity: Rc::new(RefCell::new(state.velocity)),
+            start_velocity: state.start_velocity,
+            velocity_curve: state.velocity_curve,
+            lifetime: state.lifetime,
+            style: Rc::new(state.style.clone()),
+            dynamics: Rc::new(RefCell::new(state.dynamics)),
+            trail: Rc::new(RefCell::new(state.trail.iter().copied().collect())),
+            on_death: state.on_death.clone().map(Rc::new),
+            forces: Rc::new(state.forces.clone()),
+        })
+    }
+
+    // Particles are stamped with the particle system's time when they are added.
+    pub fn with_start_time(self, start_time: DateTime<Utc>) -> Self {
+        Self { start_time, ..self }
+    }
+
     pub fn delta(&self, time: DateTime<Utc>) -> Duration {
         time.sub(self.start_time)
     }
@@ -134,19 +345,233 @@ impl Particle {
         self.render.borrow().pixel
     }
 
-    pub fn tick(&self, time: DateTime<Utc>) -> Option<ParticlePixel> {
-        self.set_velocity((self.update_velocity)(&self.start_velocity, self.delta(time)));
-        let pixel: Option<ParticlePixel> = (self.tick)(&self.start_pixel(), self.pixel(), &self.direction, &self.velocity(), self.delta(time), &self.lifetime);
+    pub fn heading(&self) -> Point2d {
+        self.dynamics.borrow().heading
+    }
+
+    pub fn dynamics(&self) -> Dynamics {
+        *self.dynamics.borrow()
+    }
+
+    pub fn tick(&self, time: DateTime<Utc>, context: &ForceContext) -> Option<ParticlePixel> {
+        self.set_velocity(self.velocity_curve.apply(&self.start_velocity, self.delta(time)));
+
+        let from = self.pixel().unwrap_or(self.start_pixel).position;
+        if let Some(trail) = self.style.trail {
+            let now = to_millis(time);
+            let mut history = self.trail.borrow_mut();
+            history.push_back(TrailPoint { time: now - context.delta * 1000., position: from });
+            while history.front().is_some_and(|point| now - point.time > trail.length) {
+                history.pop_front();
+            }
+        }
+        let Dynamics { heading, speed_scale, stuck, .. } = self.dynamics();
+        let speed = if stuck { 0. } else { self.velocity() * speed_scale };
+        let distance = speed * context.delta * 1000. / FRAME;
+
+        let pixel: Option<ParticlePixel> = self.motion
+            .tick(&self.start_pixel(), self.pixel(), &heading, &distance, self.delta(time), &self.lifetime)
+            .map(|pixel| self.styled(pixel, self.delta(time)))
+            .and_then(|pixel| self.simulate(pixel, from, context));
 
         let mut render: RefMut<Render> = self.render.borrow_mut();
         render.pixel = pixel;
         pixel
     }
 
+    // One quad per step of the history, more transparent (and for ribbons narrower) towards the tail.
+    fn draw_trail(&self, renderer: &dyn Renderer, view: &View, trail: &Trail, pixel: &ParticlePixel) {
+        let history = self.trail.borrow();
+        let points: Vec<Point2d> = history.iter().map(|point| &point.position).chain(std::iter::once(&pixel.position)).map(|point| view.transform(point)).collect();
+        let steps = points.len().saturating_sub(1);
+        let width = view.scale(trail.width);
+
+        let weight = |index: usize| index as f64 / steps as f64;
+        let half_width = |index: usize| match trail.shape {
+            TrailShape::Line => width / 2.,
+            TrailShape::Ribbon => width * weight(index) / 2.,
+        };
+
+        for (index, segment) in points.windows(2).enumerate() {
+            let (from, to) = (segment[0], segment[1]);
+            let Some(along) = (to - from).unit() else { continue };
+
+            let normal = along.perpendicular();
+            let (tail, head) = (normal * half_width(index), normal * half_width(index + 1));
+            let quad = [from + tail, to + head, to - head, from - tail];
+            renderer.fill_polygon(&quad, &Fill::Solid { color: pixel.color, alpha: pixel.alpha * weight(index + 1) });
+        }
+    }
+
+    // Adds the drift from force fields to the scripted move from `from`, then settles any wall hit.
+    fn simulate(&self, pixel: ParticlePixel, from: Point2d, context: &ForceContext) -> Option<ParticlePixel> {
+        let mut dynamics = self.dynamics.borrow_mut();
+        if dynamics.stuck {
+            return Some(ParticlePixel { position: from, ..pixel });
+        }
+
+        for field in context.fields.iter().chain(self.forces.iter()) {
+            let acceleration = field.acceleration(from, dynamics.drift, context.time);
+            dynamics.drift = dynamics.drift + acceleration * context.delta;
+        }
+
+        let to = pixel.position + dynamics.drift * context.delta;
+
+        let Some(collisions) = context.collisions else { return Some(ParticlePixel { position: to, ..pixel }) };
+        // Each bounce sends what is left of the step's travel off the wall, scaled like the velocity.
+        let (mut from, mut to) = (from, to);
+        for _ in 0..MAX_BOUNCES {
+            let Some((point, wall)) = first_hit(from, to, context.walls) else { return Some(ParticlePixel { position: to, ..pixel }) };
+            context.hits.borrow_mut().push(Hit { position: point, wall, response: collisions.response });
+
+            match collisions.response {
+                CollisionResponse::Die => return None,
+                CollisionResponse::Stick => {
+                    dynamics.stuck = true;
+                    dynamics.drift = Point2d { x: 0., y: 0. };
+                    return Some(ParticlePixel { position: point, ..pixel });
+                }
+                CollisionResponse::Bounce => {
+                    let Some(normal) = wall_normal(&wall, from, point) else { return Some(ParticlePixel { position: point, ..pixel }) };
+                    let scripted = collisions.reflect(dynamics.heading * dynamics.speed_scale, normal);
+                    let speed_scale = scripted.length();
+                    if speed_scale > 0. {
+                        dynamics.heading = scripted * (1. / speed_scale);
+                    }
+                    dynamics.speed_scale = speed_scale;
+                    dynamics.drift = collisions.reflect(dynamics.drift, normal);
+
+                    let rest = collisions.reflect(to - point, normal);
+                    from = point + normal * SURFACE_OFFSET;
+                    to = from + rest;
+                }
+            }
+        }
+
+        Some(ParticlePixel { position: from, ..pixel })
+    }
+
+    fn styled(&self, pixel: ParticlePixel, delta: Duration) -> ParticlePixel {
+        let age = delta.num_milliseconds() as f64;
+        let life = if self.lifetime > 0 { (age / self.lifetime as f64).clamp(0., 1.) } else { 1. };
+        let style = self.style.as_ref();
+
+        ParticlePixel {
+            color: style.color.as_ref().and_then(|gradient| gradient.sample(life)).map_or(pixel.color, |color| color.0),
+            size: style.size.as_ref().and_then(|curve| curve.sample(life)).map_or(pixel.size, |scale| self.start_pixel.size * scale),
+            rotation: self.start_pixel.rotation + style.spin * age / 1000.,
+            ..pixel
+        }
+    }
+
     fn start_pixel(&self) -> ParticlePixel {
         self.start_pixel
     }
-    fn set_velocity(&self, velocity: f64) -> () {
+    fn set_velocity(&self, velocity: f64) {
         *self.velocity.borrow_mut() = velocity;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forces::Collisions;
+    use crate::time::from_millis;
+
+    const WHITE: RGB<u8> = RGB { r: 255, g: 255, b: 255 };
+    const ZERO: Point2d = Point2d { x: 0., y: 0. };
+
+    fn particle(position: Point2d, direction: Point2d, velocity: f64) -> Particle {
+        let pixel = ParticlePixel { position, color: WHITE, alpha: 1., size: 1., rotation: 0. };
+        Particle::new(pixel, direction, velocity, 10_000, VelocityCurve::Decreasing, ParticleMotion::Move)
+    }
+
+    fn context<'a>(walls: &'a [Line], collisions: Option<Collisions>, delta: f64) -> ForceContext<'a> {
+        ForceContext { fields: &[], collisions, walls, delta: delta / 1000., time: 0., hits: RefCell::new(vec![]) }
+    }
+
+    fn wall(from: Point2d, to: Point2d) -> Line {
+        Line { from, to, color: WHITE }
+    }
+
+    #[test]
+    fn particles_move_by_the_time_that_passed() {
+        let right = Point2d { x: 1., y: 0. };
+        let (short, long) = (particle(ZERO, right, 6.), particle(ZERO, right, 6.));
+        let time = from_millis(50.).unwrap();
+
+        let near = short.tick(time, &context(&[], None, 1000. / 60.)).unwrap().position;
+        let far = long.tick(time, &context(&[], None, 2000. / 60.)).unwrap().position;
+        assert!((near.x - 6. * 0.95).abs() < 1e-9, "{:?}", near);
+        assert!((far.x - 2. * near.x).abs() < 1e-9, "{:?}", far);
+    }
+
+    #[test]
+    fn trails_cover_the_same_time_at_any_frame_rate() {
+        let style = ParticleStyle { trail: Some(Trail { length: 50., ..Trail::default() }), ..ParticleStyle::default() };
+        let spans: Vec<(usize, f64)> = [8., 16., 25.].iter().map(|&step| {
+            let particle = particle(ZERO, Point2d { x: 1., y: 0. }, 1.).with_style(style.clone());
+            for tick in 1..=20 {
+                particle.tick(from_millis(tick as f64 * step).unwrap(), &context(&[], None, step));
+            }
+            let trail = particle.to_state().trail;
+            (trail.len(), 20. * step - trail[0].time)
+        }).collect();
+
+        // The head is drawn where the particle is now, so it is not in the history.
+        assert_eq!(spans, [(6, 48.), (3, 48.), (2, 50.)]);
+    }
+
+    #[test]
+    fn bounces_carry_the_rest_of_the_step_on_off_the_wall() {
+        // A corner: one wall to the right, one above.
+        let walls = [wall(Point2d { x: 10., y: -50. }, Point2d { x: 10., y: 50. }), wall(Point2d { x: -50., y: 0. }, Point2d { x: 50., y: 0. })];
+        let heading = Point2d { x: 2., y: -1. }.unit().unwrap();
+        let particle = particle(Point2d { x: 8., y: 3. }, heading, 10. / 0.95);
+        let context = context(&walls, Some(Collisions::bounce(1., 0.)), 1000. / 60.);
+
+        let position = particle.tick(from_millis(16.).unwrap(), &context).unwrap().position;
+        assert_eq!(context.hits.borrow().len(), 2, "off both walls in one step");
+        // Ten pixels of travel: √5 to the first wall, 2√5 more to the next.
+        let rest = 10. - 3. * 5f64.sqrt();
+        let expected = Point2d { x: 6. + heading.x * -rest, y: -heading.y * rest };
+        assert!(position.distance(expected) < 0.05, "{:?} vs {:?}", position, expected);
+        assert!(particle.heading().x < 0. && particle.heading().y > 0.);
+    }
+
+    #[test]
+    fn sticking_and_dying_stop_at_the_first_wall() {
+        let walls = [wall(Point2d { x: 10., y: -50. }, Point2d { x: 10., y: 50. })];
+        let right = Point2d { x: 1., y: 0. };
+        let time = from_millis(16.).unwrap();
+
+        let stuck = particle(ZERO, right, 20.);
+        let position = stuck.tick(time, &context(&walls, Some(Collisions::stick()), 1000. / 60.)).unwrap().position;
+        assert!(position.distance(Point2d { x: 10., y: 0. }) < 1e-9);
+        assert!(stuck.dynamics().stuck);
+        assert_eq!(stuck.tick(from_millis(32.).unwrap(), &context(&walls, Some(Collisions::stick()), 1000. / 60.)).unwrap().position, position);
+
+        let dead = particle(ZERO, right, 20.);
+        assert_eq!(dead.tick(time, &context(&walls, Some(Collisions::die()), 1000. / 60.)), None);
+    }
+
+    #[test]
+    fn shapes_are_laid_out_around_the_direction_of_travel() {
+        let (head, right) = (Point2d { x: 10., y: 0. }, Point2d { x: 1., y: 0. });
+        assert_eq!(streak(head, right, 4.), [
+            Point2d { x: 10., y: 1. },
+            Point2d { x: -6., y: 1. },
+            Point2d { x: -6., y: -1. },
+            Point2d { x: 10., y: -1. },
+        ]);
+
+        let square = rotated_square(head, 2., std::f64::consts::FRAC_PI_2);
+        assert!(square[0].distance(Point2d { x: 11., y: -1. }) < 1e-9, "{:?}", square);
+
+        let vertical = wall(Point2d { x: 5., y: -5. }, Point2d { x: 5., y: 5. });
+        let point = Point2d { x: 5., y: 0. };
+        assert_eq!(wall_normal(&vertical, ZERO, point), Some(Point2d { x: -1., y: 0. }), "faces back where the particle came from");
+        assert_eq!(wall_normal(&vertical, Point2d { x: 9., y: 3. }, point), Some(right));
+        assert_eq!(wall_normal(&wall(point, point), ZERO, point), None);
+    }
+}
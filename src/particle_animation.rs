@@ -1,4 +1,5 @@
 use chrono::{Duration};
+use serde::{Deserialize, Serialize};
 use crate::particle::ParticlePixel;
 use crate::Point2d;
 
@@ -9,7 +10,7 @@ pub fn particle_tick_move(start_pixel: &ParticlePixel, current_pixel: Option<Par
         return None;
     }
 
-    let pixel: ParticlePixel = if current_pixel.is_some() { current_pixel.unwrap() } else { *start_pixel };
+    let pixel: ParticlePixel = current_pixel.unwrap_or(*start_pixel);
 
     let new = ParticlePixel {
         position: Point2d {
@@ -18,6 +19,8 @@ pub fn particle_tick_move(start_pixel: &ParticlePixel, current_pixel: Option<Par
         },
         color: pixel.color,
         alpha: 1.0,
+        size: pixel.size,
+        rotation: pixel.rotation,
     };
 
     Some(new)
@@ -50,7 +53,7 @@ pub fn particle_velocity_increasing(
 ) -> f64 {
     let multiplier: f64 = 1.10;
     let division: i64 = delta.num_milliseconds() / 100;
-    return velocity * (multiplier.powi(division as i32 + 1));
+    velocity * (multiplier.powi(division as i32 + 1))
 }
 
 #[allow(dead_code)]
@@ -60,5 +63,38 @@ pub fn particle_velocity_decreasing(
 ) -> f64 {
     let multiplier: f64 = 0.95;
     let division: i64 = delta.num_milliseconds() / 100;
-    return velocity * (multiplier.powi(division as i32 + 1));
+    velocity * (multiplier.powi(division as i32 + 1))
+}
+
+// How a particle moves over its lifetime; an enum rather than a function pointer so it can be saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ParticleMotion {
+    Move,
+    MoveAndFadeOut,
+}
+
+impl ParticleMotion {
+    pub fn tick(self, start_pixel: &ParticlePixel, current_pixel: Option<ParticlePixel>, direction: &Point2d, velocity: &f64, delta: Duration, lifetime: &u32) -> Option<ParticlePixel> {
+        match self {
+            ParticleMotion::Move => particle_tick_move(start_pixel, current_pixel, direction, velocity, delta, lifetime),
+            ParticleMotion::MoveAndFadeOut => particle_tick_move_and_fade_out(start_pixel, current_pixel, direction, velocity, delta, lifetime),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VelocityCurve {
+    Increasing,
+    Decreasing,
+}
+
+impl VelocityCurve {
+    pub fn apply(self, velocity: &f64, delta: Duration) -> f64 {
+        match self {
+            VelocityCurve::Increasing => particle_velocity_increasing(velocity, delta),
+            VelocityCurve::Decreasing => particle_velocity_decreasing(velocity, delta),
+        }
+    }
 }
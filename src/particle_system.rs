@@ -1,73 +1,203 @@
-use std::cell::{RefCell, RefMut};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::rc::Rc;
-use chrono::Utc;
-use crate::{Draw, GameEngine};
-use crate::particle::Particle;
+use chrono::{DateTime, Utc};
+use crate::Draw;
+use crate::effects::{ActiveEffect, Effect, EffectError, EffectLibrary};
+use crate::events::EngineEvent;
+use crate::forces::{Collisions, ForceContext, ForceField};
+use crate::game_engine::View;
+use crate::particle::{Particle, ParticleState};
+use crate::renderer::Renderer;
+use crate::rng::Rng;
+use crate::shapes::{Line, Point2d};
+use crate::time::epoch;
 
 #[derive(Clone)]
 pub struct ParticleContainer {
-    particles: Vec<Box<Particle>>,
+    particles: Vec<Particle>,
+    max_particles: usize,
 }
 
 #[derive(Clone)]
 pub struct ParticleSystem {
     pub container: Rc<RefCell<ParticleContainer>>,
+    time: Rc<Cell<DateTime<Utc>>>,
+    forces: Rc<RefCell<Vec<ForceField>>>,
+    collisions: Rc<Cell<Option<Collisions>>>,
+    effects: Rc<RefCell<EffectLibrary>>,
+    active: Rc<RefCell<Vec<ActiveEffect>>>,
+    // Deaths and collisions since the engine last collected them.
+    events: Rc<RefCell<Vec<EngineEvent>>>,
 }
 
-impl Default for ParticleSystem {
-    fn default() -> Self {
+fn seconds(duration: chrono::Duration) -> f64 {
+    duration.num_microseconds().unwrap_or(0) as f64 / 1_000_000.
+}
+
+impl ParticleSystem {
+    pub fn new(max_particles: usize) -> Self {
         Self {
-            container: Rc::new(RefCell::new(ParticleContainer { particles: vec![] })),
+            container: Rc::new(RefCell::new(ParticleContainer { particles: vec![], max_particles })),
+            time: Rc::new(Cell::new(epoch())),
+            forces: Rc::new(RefCell::new(vec![])),
+            collisions: Rc::new(Cell::new(None)),
+            effects: Rc::new(RefCell::new(EffectLibrary::default())),
+            active: Rc::new(RefCell::new(vec![])),
+            events: Rc::new(RefCell::new(vec![])),
         }
     }
-}
 
-impl ParticleSystem {
-    pub fn add_particle(&self, particle: Particle) -> () {
+    pub fn effects(&self) -> RefMut<'_, EffectLibrary> {
+        self.effects.borrow_mut()
+    }
+
+    // Starts the named effect; it fires on the next tick.
+    pub fn play(&self, name: &str, position: Point2d, direction: Point2d) -> Result<(), EffectError> {
+        let effect = self.effects.borrow().get(name)?;
+        self.start(effect, position, direction);
+        Ok(())
+    }
+
+    // Starts an effect that isn't in the library, e.g. one tinted from a preset.
+    pub fn start(&self, effect: Rc<Effect>, position: Point2d, direction: Point2d) {
+        self.active.borrow_mut().push(ActiveEffect::new(effect, position, direction));
+    }
+
+    pub fn take_events(&self) -> Vec<EngineEvent> {
+        std::mem::take(&mut self.events.borrow_mut())
+    }
+
+    pub fn active_effects(&self) -> Vec<ActiveEffect> {
+        self.active.borrow().clone()
+    }
+
+    pub fn set_active_effects(&self, active: Vec<ActiveEffect>) {
+        *self.active.borrow_mut() = active;
+    }
+
+    pub fn add_force(&self, force: ForceField) {
+        self.forces.borrow_mut().push(force);
+    }
+
+    pub fn set_forces(&self, forces: Vec<ForceField>) {
+        *self.forces.borrow_mut() = forces;
+    }
+
+    pub fn forces(&self) -> Vec<ForceField> {
+        self.forces.borrow().clone()
+    }
+
+    // With `None`, particles pass through walls.
+    pub fn set_collisions(&self, collisions: Option<Collisions>) {
+        self.collisions.set(collisions);
+    }
+
+    pub fn collisions(&self) -> Option<Collisions> {
+        self.collisions.get()
+    }
+
+    pub fn add_particle(&self, particle: Particle) {
         let mut container = self.container.borrow_mut();
 
-        if container.particles.len() > 2000 {
+        if container.particles.len() >= container.max_particles {
             container.particles.remove(0);
         }
 
-        container.particles.push(Box::new(particle));
+        container.particles.push(particle.with_start_time(self.time.get()));
+    }
+
+    pub fn particles(&self) -> Ref<'_, [Particle]> {
+        Ref::map(self.container.borrow(), |container| container.particles.as_slice())
     }
 
-    fn remove_particles(mut container: RefMut<ParticleContainer>, indices: Vec<Option<usize>>) -> () {
+    pub fn states(&self) -> Vec<ParticleState> {
+        self.particles().iter().map(Particle::to_state).collect()
+    }
+
+    // Replaces the pool, keeping the newest particles if there are more than it holds.
+    pub fn restore(&self, mut particles: Vec<Particle>, time: DateTime<Utc>) {
+        let mut container = self.container.borrow_mut();
+        let skip = particles.len().saturating_sub(container.max_particles);
+        particles.drain(..skip);
+        container.particles = particles;
+        self.time.set(time);
+    }
+
+    pub fn clear(&self) {
+        self.container.borrow_mut().particles.clear();
+        self.active.borrow_mut().clear();
+        self.events.borrow_mut().clear();
+        self.time.set(epoch());
+    }
+
+    fn remove_particles(mut container: RefMut<ParticleContainer>, indices: Vec<Option<usize>>) {
         let mut removed = 0;
-        for index in indices.iter() {
-            match index {
-                Some(index) => {
-                    let remove = *index - removed;
-                    if container.particles.len() >= remove {
-                        container.particles.remove(remove);
-                        removed += 1;
-                    }
-                }
-                None => ()
+        for index in indices.iter().flatten() {
+            let remove = *index - removed;
+            if container.particles.len() >= remove {
+                container.particles.remove(remove);
+                removed += 1;
             }
         }
     }
 
-    pub fn tick(&self, game: &GameEngine) -> () {
-        let context = game.context();
-        let view = game.view();
-        let time = Utc::now();
+    // Moves every particle on to `time`, colliding with `walls` if collisions are on. Particles
+    // that die fire their death emitter, drawing from `rng`.
+    pub fn tick(&self, time: DateTime<Utc>, walls: &[Line], rng: &mut Rng) {
+        let previous = self.time.replace(time);
+        let forces = self.forces.borrow();
+        let context = ForceContext {
+            fields: &forces,
+            collisions: self.collisions.get(),
+            walls,
+            delta: seconds(time - previous),
+            time: seconds(time - epoch()),
+            hits: RefCell::new(vec![]),
+        };
+
+        // Taken out while they run, as emitting adds particles and may not touch the list.
+        let mut active = std::mem::take(&mut *self.active.borrow_mut());
+        active.retain_mut(|effect| effect.advance(self, rng, context.delta * 1000.));
+        self.active.borrow_mut().splice(0..0, active);
 
         let container = self.container.borrow_mut();
         let mut remove: Vec<Option<usize>> = vec![];
+        let mut bursts = vec![];
+
+        let mut events = self.events.borrow_mut();
 
         for (index, particle) in container.particles.iter().enumerate() {
-            match particle.tick(time) {
-                Some(_) => {
-                    particle.draw(context, &view);
-                }
-                None => {
-                    remove.push(Some(index));
+            let last = particle.pixel();
+            if particle.tick(time, &context).is_none() {
+                remove.push(Some(index));
+                if let Some(pixel) = last {
+                    events.push(EngineEvent::ParticleDied { position: pixel.position, heading: particle.heading() });
+                    if let Some(emitter) = particle.on_death() {
+                        bursts.push((emitter.clone(), pixel.position, particle.heading()));
+                    }
                 }
             }
         }
 
+        events.extend(context.hits.take().into_iter().map(|hit| EngineEvent::Collision { position: hit.position, wall: hit.wall, response: hit.response }));
+        drop(events);
         Self::remove_particles(container, remove);
+        drop(forces);
+
+        for (emitter, position, heading) in bursts {
+            emitter.emit(self, rng, position, heading);
+        }
+    }
+}
+
+impl Draw for ParticleSystem {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        for particle in self.container.borrow().particles.iter() {
+            particle.draw(renderer, view);
+        }
+    }
+
+    fn in_view(&self, _view: &View) -> bool {
+        true
     }
 }
@@ -0,0 +1,386 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use serde::Serialize;
+
+use crate::browser::Browser;
+
+// Milliseconds from an arbitrary origin; only differences are meaningful.
+pub trait Clock {
+    fn now(&self) -> f64;
+}
+
+// `performance.now()`, falling back to `Date.now()` where there is no Performance API.
+pub struct BrowserClock;
+
+impl Clock for BrowserClock {
+    fn now(&self) -> f64 {
+        Browser::window()
+            .ok()
+            .and_then(|window| window.performance())
+            .map(|performance| performance.now())
+            .unwrap_or_else(js_sys::Date::now)
+    }
+}
+
+// A clock that only moves when told to, for headless runs and native tests.
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct ManualClock {
+    time: Cell<f64>,
+}
+
+#[allow(dead_code)]
+impl ManualClock {
+    pub fn advance(&self, milliseconds: f64) {
+        self.time.set(self.time.get() + milliseconds);
+    }
+
+    pub fn set(&self, time: f64) {
+        self.time.set(time);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> f64 {
+        self.time.get()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Rc<C> {
+    fn now(&self) -> f64 {
+        self.as_ref().now()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseTiming {
+    pub name: &'static str,
+    pub start: f64,
+    pub duration: f64,
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameProfile {
+    pub start: f64,
+    pub duration: f64,
+    pub phases: Vec<PhaseTiming>,
+}
+
+impl FrameProfile {
+    // Total time spent in a phase during the frame; a phase may be entered more than once.
+    pub fn phase_total(&self, name: &str) -> Option<f64> {
+        let mut durations = self.phases.iter().filter(|phase| phase.name == name).map(|phase| phase.duration).peekable();
+        durations.peek()?;
+        Some(durations.sum())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseStats {
+    pub name: String,
+    pub samples: usize,
+    pub min: f64,
+    pub avg: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl PhaseStats {
+    fn from_samples(name: &str, mut samples: Vec<f64>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(f64::total_cmp);
+
+        let count = samples.len();
+        let p95_index = ((count as f64 * 0.95).ceil() as usize).clamp(1, count) - 1;
+
+        Some(Self {
+            name: name.to_string(),
+            samples: count,
+            min: samples[0],
+            avg: samples.iter().sum::<f64>() / count as f64,
+            p95: samples[p95_index],
+            max: samples[count - 1],
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    ph: &'static str,
+    // Microseconds, as the trace viewer expects.
+    ts: f64,
+    dur: f64,
+    pid: u32,
+    tid: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace<'a> {
+    trace_events: Vec<TraceEvent<'a>>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    frames: usize,
+    stats: Vec<PhaseStats>,
+    recent: Option<&'a FrameProfile>,
+}
+
+struct OpenFrame {
+    start: f64,
+    depth: usize,
+    phases: Vec<PhaseTiming>,
+}
+
+// Keeps per-phase timings for the last `capacity` frames. A capacity of 0 disables recording.
+pub struct Profiler {
+    clock: Box<dyn Clock>,
+    capacity: usize,
+    frames: VecDeque<FrameProfile>,
+    current: Option<OpenFrame>,
+}
+
+impl Profiler {
+    pub fn new(clock: Box<dyn Clock>, capacity: usize) -> Self {
+        Self { clock, capacity, frames: VecDeque::with_capacity(capacity), current: None }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn now(&self) -> f64 {
+        self.clock.now()
+    }
+
+    pub fn begin_frame(&mut self) {
+        if self.is_enabled() {
+            self.current = Some(OpenFrame { start: self.clock.now(), depth: 0, phases: vec![] });
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        let Some(frame) = self.current.take() else { return };
+
+        if self.frames.len() >= self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(FrameProfile {
+            start: frame.start,
+            duration: self.clock.now() - frame.start,
+            phases: frame.phases,
+        });
+    }
+
+    // Returns the nesting depth to hand back to `exit`, or `None` outside a frame.
+    fn enter(&mut self) -> Option<usize> {
+        let frame = self.current.as_mut()?;
+        frame.depth += 1;
+        Some(frame.depth - 1)
+    }
+
+    fn exit(&mut self, name: &'static str, start: f64, depth: usize) {
+        let now = self.clock.now();
+        if let Some(frame) = self.current.as_mut() {
+            frame.depth = depth;
+            frame.phases.push(PhaseTiming { name, start, duration: now - start, depth });
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn frames(&self) -> impl Iterator<Item = &FrameProfile> {
+        self.frames.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // Whole-frame statistics first, then each phase in the order it was first seen.
+    pub fn stats(&self) -> Vec<PhaseStats> {
+        let mut names: Vec<&'static str> = vec![];
+        for phase in self.frames.iter().flat_map(|frame| frame.phases.iter()) {
+            if !names.contains(&phase.name) {
+                names.push(phase.name);
+            }
+        }
+
+        let frame = PhaseStats::from_samples("frame", self.frames.iter().map(|frame| frame.duration).collect());
+        let phases = names.into_iter().filter_map(|name| {
+            PhaseStats::from_samples(name, self.frames.iter().filter_map(|frame| frame.phase_total(name)).collect())
+        });
+
+        frame.into_iter().chain(phases).collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        let report = Report { frames: self.frames.len(), stats: self.stats(), recent: self.frames.back() };
+        serde_json::to_string(&report).unwrap_or_default()
+    }
+
+    // Chrome trace event format, loadable in chrome://tracing or Perfetto.
+    pub fn to_chrome_trace(&self) -> String {
+        let mut trace_events = vec![];
+
+        for frame in self.frames.iter() {
+            trace_events.push(TraceEvent { name: "frame", ph: "X", ts: frame.start * 1000., dur: frame.duration * 1000., pid: 1, tid: 1 });
+            for phase in frame.phases.iter() {
+                trace_events.push(TraceEvent { name: phase.name, ph: "X", ts: phase.start * 1000., dur: phase.duration * 1000., pid: 1, tid: 1 });
+            }
+        }
+
+        serde_json::to_string(&Trace { trace_events }).unwrap_or_default()
+    }
+}
+
+// Times a phase until dropped. Scopes nest, and do nothing outside `begin_frame`/`end_frame`.
+pub struct Scope {
+    profiler: Rc<RefCell<Profiler>>,
+    name: &'static str,
+    start: f64,
+    depth: Option<usize>,
+}
+
+impl Scope {
+    pub fn new(profiler: &Rc<RefCell<Profiler>>, name: &'static str) -> Self {
+        let (start, depth) = {
+            let mut inner = profiler.borrow_mut();
+            let depth = inner.enter();
+            (if depth.is_some() { inner.now() } else { 0. }, depth)
+        };
+
+        Self { profiler: profiler.clone(), name, start, depth }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if let Some(depth) = self.depth {
+            self.profiler.borrow_mut().exit(self.name, self.start, depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiler(capacity: usize) -> (Rc<ManualClock>, Rc<RefCell<Profiler>>) {
+        let clock = Rc::new(ManualClock::default());
+        (clock.clone(), Rc::new(RefCell::new(Profiler::new(Box::new(clock), capacity))))
+    }
+
+    // A frame of `update` (with `rays` inside it) then `draw`, taking the given milliseconds each.
+    fn frame(clock: &ManualClock, profiler: &Rc<RefCell<Profiler>>, update: f64, rays: f64, draw: f64) {
+        profiler.borrow_mut().begin_frame();
+        {
+            let _update = Scope::new(profiler, "update");
+            clock.advance(update - rays);
+            let _rays = Scope::new(profiler, "rays");
+            clock.advance(rays);
+        }
+        {
+            let _draw = Scope::new(profiler, "draw");
+            clock.advance(draw);
+        }
+        profiler.borrow_mut().end_frame();
+    }
+
+    #[test]
+    fn scopes_record_nested_phases_by_the_clock() {
+        let (clock, profiler) = profiler(4);
+        clock.set(100.);
+        frame(&clock, &profiler, 5., 2., 3.);
+
+        let profiler = profiler.borrow();
+        let frame = profiler.frames().next().unwrap();
+        assert_eq!((frame.start, frame.duration), (100., 8.));
+        let phases: Vec<(&str, f64, f64, usize)> = frame.phases.iter().map(|phase| (phase.name, phase.start, phase.duration, phase.depth)).collect();
+        // Phases are recorded as they end, so the inner one comes first.
+        assert_eq!(phases, vec![("rays", 103., 2., 1), ("update", 100., 5., 0), ("draw", 105., 3., 0)]);
+    }
+
+    #[test]
+    fn a_phase_entered_twice_adds_up() {
+        let (clock, profiler) = profiler(4);
+        profiler.borrow_mut().begin_frame();
+        for duration in [1., 2.5] {
+            let _scope = Scope::new(&profiler, "particles");
+            clock.advance(duration);
+        }
+        profiler.borrow_mut().end_frame();
+
+        let profiler = profiler.borrow();
+        let frame = profiler.frames().next().unwrap();
+        assert_eq!(frame.phase_total("particles"), Some(3.5));
+        assert_eq!(frame.phase_total("lighting"), None);
+    }
+
+    #[test]
+    fn only_the_last_frames_are_kept() {
+        let (clock, profiler) = profiler(3);
+        for draw in 1..=5 {
+            frame(&clock, &profiler, 1., 0.5, draw as f64);
+        }
+
+        let draws: Vec<f64> = profiler.borrow().frames().map(|frame| frame.phase_total("draw").unwrap()).collect();
+        assert_eq!(draws, vec![3., 4., 5.]);
+    }
+
+    #[test]
+    fn stats_cover_the_frame_then_each_phase() {
+        let (clock, profiler) = profiler(20);
+        for draw in 1..=20 {
+            frame(&clock, &profiler, 2., 1., draw as f64);
+        }
+
+        let stats = profiler.borrow().stats();
+        let names: Vec<&str> = stats.iter().map(|stats| stats.name.as_str()).collect();
+        assert_eq!(names, vec!["frame", "rays", "update", "draw"]);
+
+        let draw = &stats[3];
+        assert_eq!((draw.samples, draw.min, draw.avg, draw.p95, draw.max), (20, 1., 10.5, 19., 20.));
+        assert_eq!((stats[0].min, stats[0].max), (3., 22.));
+        assert_eq!((stats[2].min, stats[2].p95), (2., 2.));
+    }
+
+    #[test]
+    fn nothing_is_recorded_outside_a_frame_or_when_disabled() {
+        let (clock, idle) = profiler(4);
+        {
+            let _scope = Scope::new(&idle, "update");
+            clock.advance(5.);
+        }
+        idle.borrow_mut().end_frame();
+        assert_eq!(idle.borrow().frames().count(), 0);
+
+        let (clock, disabled) = profiler(0);
+        assert!(!disabled.borrow().is_enabled());
+        frame(&clock, &disabled, 1., 0.5, 1.);
+        assert_eq!(disabled.borrow().frames().count(), 0);
+        assert!(disabled.borrow().stats().is_empty());
+    }
+
+    #[test]
+    fn traces_are_in_microseconds() {
+        let (clock, profiler) = profiler(4);
+        clock.set(2.);
+        frame(&clock, &profiler, 1., 0.5, 0.25);
+
+        let trace: serde_json::Value = serde_json::from_str(&profiler.borrow().to_chrome_trace()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!((events[0]["name"].as_str(), events[0]["ts"].as_f64(), events[0]["dur"].as_f64()), (Some("frame"), Some(2000.), Some(1250.)));
+        assert_eq!((events[1]["name"].as_str(), events[1]["ts"].as_f64(), events[1]["dur"].as_f64()), (Some("rays"), Some(2500.), Some(500.)));
+
+        let report: serde_json::Value = serde_json::from_str(&profiler.borrow().to_json()).unwrap();
+        assert_eq!(report["frames"], 1);
+        assert_eq!(report["recent"]["phases"].as_array().map(Vec::len), Some(3));
+    }
+}
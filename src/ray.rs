@@ -1,6 +1,6 @@
 use std::cell::RefCell;
 use std::rc::Rc;
-use crate::browser::console_log;
+use serde::Serialize;
 use crate::Point2d;
 use crate::shapes::Line;
 
@@ -13,6 +13,9 @@ pub struct Ray {
     direction: Rc<RefCell<Direction>>,
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Intersection {
     pub point: Point2d,
     pub direction: Point2d,
@@ -64,7 +67,7 @@ impl Ray {
 
         let t = (e * c - f * d) / denominator;
         // It should be on the target line.
-        if t < 0.0 || t > 1.0 {
+        if !(0.0..=1.0).contains(&t) {
             return None;
         }
 
@@ -87,17 +90,15 @@ impl Ray {
 
         let angle = (a / (x1 * x2)).cos();
 
-        console_log(&angle.to_string());
-
         Some(
             Intersection {
                 point,
-                target: line.clone(),
+                target: *line,
                 place_on_line: t,
                 distance: u,
                 angle,
                 angle_direction: Point2d { x: angle.cos(), y: angle.sin() },
-                direction: direction.clone(),
+                direction,
             }
         )
     }
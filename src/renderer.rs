@@ -0,0 +1,272 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::f64;
+use rgb::RGB;
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, HtmlImageElement, WebGlTexture};
+
+use crate::{Browser, Point2d};
+use crate::error::EngineError;
+use crate::layers::CompositeMode;
+use crate::shapes::rgb;
+
+// Stops used to approximate the falloff curve with a linear canvas gradient.
+const GRADIENT_STOPS: usize = 8;
+
+pub enum Fill {
+    Solid { color: RGB<u8>, alpha: f64 },
+    // Fades from `intensity` at the center to nothing at `radius`, shaped by the `falloff` exponent.
+    Radial { center: Point2d, radius: f64, color: RGB<u8>, intensity: f64, falloff: f64 },
+}
+
+impl Fill {
+    pub fn alpha_at(&self, point: Point2d) -> f64 {
+        match self {
+            Fill::Solid { alpha, .. } => *alpha,
+            Fill::Radial { center, radius, intensity, falloff, .. } => {
+                Self::radial_alpha(point.distance(*center) / radius, *intensity, *falloff)
+            }
+        }
+    }
+
+    pub fn color(&self) -> RGB<u8> {
+        match self {
+            Fill::Solid { color, .. } => *color,
+            Fill::Radial { color, .. } => *color,
+        }
+    }
+
+    fn radial_alpha(t: f64, intensity: f64, falloff: f64) -> f64 {
+        (intensity * (1. - t.clamp(0., 1.)).powf(falloff)).clamp(0., 1.)
+    }
+}
+
+pub trait Renderer {
+    // Ratio between backing store pixels and the CSS pixels everything is drawn in.
+    fn set_scale(&self, scale: f64);
+    fn clear(&self, size: Point2d);
+    fn line(&self, from: Point2d, to: Point2d, color: RGB<u8>);
+    fn circle(&self, center: Point2d, radius: f64, color: RGB<u8>);
+    fn rect(&self, position: Point2d, size: Point2d, color: RGB<u8>, alpha: f64);
+    fn fill_polygon(&self, points: &[Point2d], fill: &Fill);
+    fn set_composite(&self, mode: CompositeMode);
+    // Offscreen buffer to cache static layers in, if the backend supports it.
+    fn create_buffer(&self, size: Point2d) -> Option<Box<dyn Renderer>>;
+    fn draw_buffer(&self, buffer: &dyn Renderer);
+    fn surface(&self) -> Option<&HtmlCanvasElement>;
+
+    // The texture a WebGL2 buffer draws into.
+    fn buffer_texture(&self) -> Option<&WebGlTexture> {
+        None
+    }
+
+    // Draws the image added under `name`, centred and rotated; false when the backend can't.
+    fn sprite(&self, _name: &str, _center: Point2d, _size: f64, _rotation: f64, _alpha: f64) -> bool {
+        false
+    }
+
+    // Draws the part of the image under `name` at `source`, `source_size` big, into the rect at
+    // `position`; false when the backend can't.
+    fn tile(&self, _name: &str, _source: Point2d, _source_size: Point2d, _position: Point2d, _size: Point2d, _alpha: f64) -> bool {
+        false
+    }
+
+    fn add_sprite(&self, _name: &str, _image: HtmlImageElement) {}
+
+    fn remove_sprite(&self, _name: &str) {}
+
+    // Submits anything that was batched during the frame.
+    fn flush(&self);
+}
+
+pub struct Canvas2dRenderer {
+    context: CanvasRenderingContext2d,
+    canvas: HtmlCanvasElement,
+    scale: Cell<f64>,
+    // Shared with any buffers created from this renderer.
+    sprites: Rc<RefCell<HashMap<String, HtmlImageElement>>>,
+}
+
+impl Canvas2dRenderer {
+    pub fn new(canvas: HtmlCanvasElement) -> Result<Self, EngineError> {
+        let context = Browser::context(&canvas)?;
+        Ok(Self { context, canvas, scale: Cell::new(1.), sprites: Rc::new(RefCell::new(HashMap::new())) })
+    }
+}
+
+impl Renderer for Canvas2dRenderer {
+    fn set_scale(&self, scale: f64) {
+        self.scale.set(scale);
+    }
+
+    fn clear(&self, size: Point2d) {
+        let scale = self.scale.get();
+        self.context.set_transform(scale, 0., 0., scale, 0., 0.).ok();
+        self.context.clear_rect(0., 0., size.x, size.y);
+    }
+
+    fn line(&self, from: Point2d, to: Point2d, color: RGB<u8>) {
+        self.context.begin_path();
+        self.context.set_stroke_style(&rgb(color).into());
+        self.context.move_to(from.x, from.y);
+        self.context.line_to(to.x, to.y);
+        self.context.stroke();
+    }
+
+    fn circle(&self, center: Point2d, radius: f64, color: RGB<u8>) {
+        self.context.begin_path();
+        self.context.set_stroke_style(&rgb(color).into());
+        self.context.move_to(center.x + radius, center.y);
+        self.context
+            .arc(center.x, center.y, radius, 0., f64::consts::PI * 2.0)
+            .unwrap();
+        self.context.stroke();
+    }
+
+    fn rect(&self, position: Point2d, size: Point2d, color: RGB<u8>, alpha: f64) {
+        self.context.set_global_alpha(alpha);
+        self.context.set_fill_style(&rgb(color).into());
+        self.context.fill_rect(position.x, position.y, size.x, size.y);
+        self.context.set_global_alpha(1.0);
+    }
+
+    fn fill_polygon(&self, points: &[Point2d], fill: &Fill) {
+        if points.len() < 3 {
+            return;
+        }
+
+        self.context.begin_path();
+        self.context.move_to(points[0].x, points[0].y);
+        for point in points[1..].iter() {
+            self.context.line_to(point.x, point.y);
+        }
+        self.context.close_path();
+
+        match fill {
+            Fill::Solid { color, alpha } => {
+                self.context.set_global_alpha(*alpha);
+                self.context.set_fill_style(&rgb(*color).into());
+                self.context.fill();
+                self.context.set_global_alpha(1.0);
+            }
+            Fill::Radial { center, radius, color, intensity, falloff } => {
+                let gradient = match self.context.create_radial_gradient(center.x, center.y, 0., center.x, center.y, *radius) {
+                    Ok(gradient) => gradient,
+                    Err(_) => return,
+                };
+
+                for stop in 0..=GRADIENT_STOPS {
+                    let t = stop as f64 / GRADIENT_STOPS as f64;
+                    let alpha = Fill::radial_alpha(t, *intensity, *falloff);
+                    let style = format!("rgba({}, {}, {}, {})", color.r, color.g, color.b, alpha);
+                    gradient.add_color_stop(t as f32, &style).ok();
+                }
+
+                self.context.set_fill_style(&gradient.into());
+                self.context.fill();
+            }
+        }
+    }
+
+    fn set_composite(&self, mode: CompositeMode) {
+        self.context.set_global_composite_operation(mode.as_str()).ok();
+    }
+
+    fn create_buffer(&self, size: Point2d) -> Option<Box<dyn Renderer>> {
+        let canvas = Browser::document()
+            .ok()?
+            .create_element("canvas")
+            .ok()?
+            .dyn_into::<HtmlCanvasElement>()
+            .ok()?;
+        let scale = self.scale.get();
+        canvas.set_width((size.x * scale) as u32);
+        canvas.set_height((size.y * scale) as u32);
+
+        let mut buffer = Canvas2dRenderer::new(canvas).ok()?;
+        buffer.set_scale(scale);
+        buffer.sprites = self.sprites.clone();
+        Some(Box::new(buffer))
+    }
+
+    fn draw_buffer(&self, buffer: &dyn Renderer) {
+        if let Some(surface) = buffer.surface() {
+            let scale = self.scale.get();
+            let (width, height) = (surface.width() as f64 / scale, surface.height() as f64 / scale);
+            self.context.draw_image_with_html_canvas_element_and_dw_and_dh(surface, 0., 0., width, height).ok();
+        }
+    }
+
+    fn surface(&self) -> Option<&HtmlCanvasElement> {
+        Some(&self.canvas)
+    }
+
+    fn sprite(&self, name: &str, center: Point2d, size: f64, rotation: f64, alpha: f64) -> bool {
+        let sprites = self.sprites.borrow();
+        let Some(image) = sprites.get(name) else { return false };
+        if !image.complete() {
+            return false;
+        }
+
+        self.context.save();
+        self.context.translate(center.x, center.y).ok();
+        self.context.rotate(rotation).ok();
+        self.context.set_global_alpha(alpha);
+        self.context.draw_image_with_html_image_element_and_dw_and_dh(image, -size / 2., -size / 2., size, size).ok();
+        self.context.restore();
+        true
+    }
+
+    fn tile(&self, name: &str, source: Point2d, source_size: Point2d, position: Point2d, size: Point2d, alpha: f64) -> bool {
+        let sprites = self.sprites.borrow();
+        let Some(image) = sprites.get(name) else { return false };
+        if !image.complete() {
+            return false;
+        }
+
+        self.context.set_global_alpha(alpha);
+        self.context
+            .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                image, source.x, source.y, source_size.x, source_size.y, position.x, position.y, size.x, size.y,
+            )
+            .ok();
+        self.context.set_global_alpha(1.0);
+        true
+    }
+
+    fn add_sprite(&self, name: &str, image: HtmlImageElement) {
+        self.sprites.borrow_mut().insert(name.to_string(), image);
+    }
+
+    fn remove_sprite(&self, name: &str) {
+        self.sprites.borrow_mut().remove(name);
+    }
+
+    fn flush(&self) {}
+}
+
+// Draws nothing; lets the engine run headless, outside the browser.
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn set_scale(&self, _scale: f64) {}
+    fn clear(&self, _size: Point2d) {}
+    fn line(&self, _from: Point2d, _to: Point2d, _color: RGB<u8>) {}
+    fn circle(&self, _center: Point2d, _radius: f64, _color: RGB<u8>) {}
+    fn rect(&self, _position: Point2d, _size: Point2d, _color: RGB<u8>, _alpha: f64) {}
+    fn fill_polygon(&self, _points: &[Point2d], _fill: &Fill) {}
+    fn set_composite(&self, _mode: CompositeMode) {}
+
+    fn create_buffer(&self, _size: Point2d) -> Option<Box<dyn Renderer>> {
+        None
+    }
+
+    fn draw_buffer(&self, _buffer: &dyn Renderer) {}
+
+    fn surface(&self) -> Option<&HtmlCanvasElement> {
+        None
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,417 @@
+use std::fmt;
+use std::hash::Hasher;
+use serde::Serialize;
+use wasm_bindgen::JsValue;
+
+use crate::config::{ConfigError, EngineConfig};
+use crate::game_engine::GameEngine;
+use crate::game_loop::Game;
+use crate::shapes::Point2d;
+
+const MAGIC: &[u8; 4] = b"RPLY";
+// Version 2 added remote effects and version 3 effects played from JS; older replays are a subset
+// and still play.
+const VERSION: u8 = 3;
+
+const TAG_MOUSE: u8 = 0;
+const TAG_KEY: u8 = 1;
+const TAG_RESIZE: u8 = 2;
+const TAG_REMOTE_EFFECT: u8 = 3;
+const TAG_EFFECT: u8 = 4;
+
+// Everything from outside the simulation that can change it. Inputs are queued and applied at the
+// start of the next frame, live or replayed alike.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InputEvent {
+    MouseMove(Point2d),
+    KeyDown(u32),
+    Resize(Point2d),
+    // An effect another player spawned in the shared scene.
+    RemoteEffect { name: String, position: Point2d },
+    // An effect played from JS, which may be an event handler reacting to the world.
+    Effect { name: String, position: Point2d },
+}
+
+impl InputEvent {
+    // Positions are stored as f32 in replays, so live input is rounded the same way.
+    pub fn quantized(self) -> Self {
+        let round = |point: Point2d| Point2d { x: point.x as f32 as f64, y: point.y as f32 as f64 };
+        match self {
+            InputEvent::MouseMove(point) => InputEvent::MouseMove(round(point)),
+            InputEvent::Resize(size) => InputEvent::Resize(round(size)),
+            InputEvent::RemoteEffect { name, position } => InputEvent::RemoteEffect { name, position: round(position) },
+            InputEvent::Effect { name, position } => InputEvent::Effect { name, position: round(position) },
+            key => key,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayFrame {
+    pub delta: f64,
+    pub inputs: Vec<InputEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownInput(u8),
+    InvalidConfig(ConfigError),
+    // The engine asked to play the replay doesn't simulate with the settings it was recorded with.
+    ConfigMismatch,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Truncated => write!(f, "replay ends unexpectedly"),
+            ReplayError::BadMagic => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(version) => write!(f, "unsupported replay version {}", version),
+            ReplayError::UnknownInput(tag) => write!(f, "unknown replay input type {}", tag),
+            ReplayError::InvalidConfig(error) => write!(f, "replay config: {}", error),
+            ReplayError::ConfigMismatch => write!(f, "replay was recorded with different engine settings"),
+        }
+    }
+}
+
+impl From<ReplayError> for JsValue {
+    fn from(error: ReplayError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+// A recorded run: the seed, view size and config it started from, then each frame's delta and inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub size: Point2d,
+    pub config: String,
+    pub frames: Vec<ReplayFrame>,
+    // State hash at the end of recording, to check a playback against.
+    pub final_hash: Option<u64>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, size: Point2d, config: &EngineConfig) -> Self {
+        Self {
+            seed,
+            size,
+            config: serde_json::to_string(config).unwrap_or_default(),
+            frames: vec![],
+            final_hash: None,
+        }
+    }
+
+    pub fn config(&self) -> Result<EngineConfig, ReplayError> {
+        EngineConfig::from_json(&self.config).map_err(ReplayError::InvalidConfig)
+    }
+
+    // Whether an engine with `config` plays the replay back as it was recorded. Where and how the
+    // game is shown doesn't matter, and the replay brings its own seed.
+    pub fn check_config(&self, config: &EngineConfig) -> Result<(), ReplayError> {
+        let recorded = EngineConfig {
+            canvas_selector: config.canvas_selector.clone(),
+            renderer: config.renderer,
+            background: config.background,
+            dpr: config.dpr,
+            target_fps: config.target_fps,
+            auto_resize: config.auto_resize,
+            resize_anchor: config.resize_anchor,
+            profile_frames: config.profile_frames,
+            seed: config.seed,
+            ..self.config()?
+        };
+        if recorded == *config { Ok(()) } else { Err(ReplayError::ConfigMismatch) }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(32 + self.config.len() + self.frames.len() * 4);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        write_point(&mut out, self.size);
+        write_varint(&mut out, self.config.len() as u64);
+        out.extend_from_slice(self.config.as_bytes());
+
+        write_varint(&mut out, self.frames.len() as u64);
+        for frame in self.frames.iter() {
+            write_varint(&mut out, (frame.delta * 1000.).round() as u64);
+            write_varint(&mut out, frame.inputs.len() as u64);
+            for input in frame.inputs.iter() {
+                match input {
+                    InputEvent::MouseMove(point) => {
+                        out.push(TAG_MOUSE);
+                        write_point(&mut out, *point);
+                    }
+                    InputEvent::KeyDown(key) => {
+                        out.push(TAG_KEY);
+                        write_varint(&mut out, *key as u64);
+                    }
+                    InputEvent::Resize(size) => {
+                        out.push(TAG_RESIZE);
+                        write_point(&mut out, *size);
+                    }
+                    InputEvent::RemoteEffect { name, position } => {
+                        out.push(TAG_REMOTE_EFFECT);
+                        write_effect(&mut out, name, *position);
+                    }
+                    InputEvent::Effect { name, position } => {
+                        out.push(TAG_EFFECT);
+                        write_effect(&mut out, name, *position);
+                    }
+                }
+            }
+        }
+
+        match self.final_hash {
+            Some(hash) => {
+                out.push(1);
+                out.extend_from_slice(&hash.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        let version = reader.byte()?;
+        if version == 0 || version > VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        let seed = reader.u64()?;
+        let size = reader.point()?;
+        let config_length = reader.varint()? as usize;
+        let config = String::from_utf8_lossy(reader.take(config_length)?).into_owned();
+
+        let frame_count = reader.varint()? as usize;
+        // Don't trust the count for the allocation; every frame takes at least two bytes.
+        let mut frames = Vec::with_capacity(frame_count.min(bytes.len() / 2));
+        for _ in 0..frame_count {
+            let delta = reader.varint()? as f64 / 1000.;
+            let input_count = reader.varint()? as usize;
+            let mut inputs = Vec::with_capacity(input_count.min(bytes.len()));
+            for _ in 0..input_count {
+                let input = match reader.byte()? {
+                    TAG_MOUSE => InputEvent::MouseMove(reader.point()?),
+                    TAG_KEY => InputEvent::KeyDown(reader.varint()? as u32),
+                    TAG_RESIZE => InputEvent::Resize(reader.point()?),
+                    TAG_REMOTE_EFFECT => {
+                        let (name, position) = reader.effect()?;
+                        InputEvent::RemoteEffect { name, position }
+                    }
+                    TAG_EFFECT => {
+                        let (name, position) = reader.effect()?;
+                        InputEvent::Effect { name, position }
+                    }
+                    tag => return Err(ReplayError::UnknownInput(tag)),
+                };
+                inputs.push(input);
+            }
+            frames.push(ReplayFrame { delta, inputs });
+        }
+
+        let final_hash = match reader.byte()? {
+            0 => None,
+            _ => Some(reader.u64()?),
+        };
+
+        Ok(Self { seed, size, config, frames, final_hash })
+    }
+}
+
+// Plays a replay to the end on a headless engine and returns the state hash after every frame.
+#[allow(dead_code)]
+pub fn run_headless<G: Game>(replay: &Replay, setup: fn(&GameEngine), mut game: G) -> Result<Vec<u64>, ReplayError> {
+    let engine = GameEngine::headless(replay.config()?, replay.size);
+    setup(&engine);
+    engine.play(replay.clone())?;
+
+    let mut hashes = Vec::with_capacity(replay.frames.len());
+    while engine.is_playing() {
+        engine.step(0., &mut game);
+        hashes.push(engine.state_hash());
+    }
+
+    Ok(hashes)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_point(out: &mut Vec<u8>, point: Point2d) {
+    out.extend_from_slice(&(point.x as f32).to_le_bytes());
+    out.extend_from_slice(&(point.y as f32).to_le_bytes());
+}
+
+fn write_effect(out: &mut Vec<u8>, name: &str, position: Point2d) {
+    write_varint(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+    write_point(out, position);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ReplayError> {
+        let end = self.position.checked_add(count).ok_or(ReplayError::Truncated)?;
+        let slice = self.bytes.get(self.position..end).ok_or(ReplayError::Truncated)?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, ReplayError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, ReplayError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, ReplayError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn point(&mut self) -> Result<Point2d, ReplayError> {
+        Ok(Point2d { x: self.f32()? as f64, y: self.f32()? as f64 })
+    }
+
+    fn effect(&mut self) -> Result<(String, Point2d), ReplayError> {
+        let length = self.varint()? as usize;
+        let name = String::from_utf8_lossy(self.take(length)?).into_owned();
+        Ok((name, self.point()?))
+    }
+
+    fn varint(&mut self) -> Result<u64, ReplayError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ReplayError::Truncated)
+    }
+}
+
+// FNV-1a: stable across platforms and compiler versions, unlike `DefaultHasher`.
+pub struct StateHasher(u64);
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl StateHasher {
+    pub fn write_f64(&mut self, value: f64) {
+        self.write_u64(value.to_bits());
+    }
+
+    pub fn write_point(&mut self, point: Point2d) {
+        self.write_f64(point.x);
+        self.write_f64(point.y);
+    }
+}
+
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{setup, Demo};
+
+    const SIZE: Point2d = Point2d { x: 640., y: 480. };
+
+    fn config() -> EngineConfig {
+        EngineConfig { seed: Some(7), fixed_timestep: Some(1000. / 60.), ..EngineConfig::default() }
+    }
+
+    // Plays the demo for a while with input going in, returning the recording and the state hash
+    // after every frame.
+    fn record(frames: usize) -> (Replay, Vec<u64>) {
+        let engine = GameEngine::headless(config(), SIZE);
+        setup(&engine);
+        engine.start_recording();
+
+        let mut game = Demo;
+        let mut hashes = vec![];
+        for frame in 0..frames {
+            match frame {
+                2 => engine.input(InputEvent::MouseMove(Point2d { x: 500., y: 120. })),
+                5 => engine.input(InputEvent::KeyDown(49)),
+                10 => engine.input(InputEvent::KeyDown(66)),
+                15 => engine.input(InputEvent::MouseMove(Point2d { x: 100.3, y: 400.7 })),
+                20 => engine.input(InputEvent::RemoteEffect { name: "smoke".to_string(), position: Point2d { x: 10., y: -20. } }),
+                25 => engine.queue_effect("smoke", Point2d { x: -40., y: 60.5 }).unwrap(),
+                30 => engine.input(InputEvent::KeyDown(39)),
+                _ => {}
+            }
+            engine.step(16. + (frame % 3) as f64 * 0.7, &mut game);
+            hashes.push(engine.state_hash());
+        }
+
+        (engine.stop_recording().unwrap(), hashes)
+    }
+
+    #[test]
+    fn headless_replays_match_the_recording_frame_by_frame() {
+        let (replay, hashes) = record(90);
+        assert_eq!(replay.final_hash, hashes.last().copied());
+        assert!(hashes.windows(2).any(|pair| pair[0] != pair[1]), "the input should change the world");
+
+        let replayed = run_headless(&Replay::decode(&replay.encode()).unwrap(), setup, Demo).unwrap();
+        assert_eq!(replayed, hashes);
+    }
+
+    #[test]
+    fn replays_survive_encoding() {
+        let (replay, _) = record(40);
+        assert_eq!(Replay::decode(&replay.encode()), Ok(replay.clone()));
+
+        let mut bytes = replay.encode();
+        bytes.truncate(bytes.len() - 3);
+        assert_eq!(Replay::decode(&bytes), Err(ReplayError::Truncated));
+        assert_eq!(Replay::decode(b"NOPE"), Err(ReplayError::BadMagic));
+    }
+
+    #[test]
+    fn replays_only_play_with_the_settings_they_were_recorded_with() {
+        let (replay, _) = record(5);
+
+        let shown_elsewhere = EngineConfig { canvas_selector: "#other".to_string(), seed: None, profile_frames: 0, ..config() };
+        assert_eq!(GameEngine::headless(shown_elsewhere, SIZE).play(replay.clone()), Ok(()));
+
+        let fewer_particles = EngineConfig { max_particles: 10, ..config() };
+        assert_eq!(GameEngine::headless(fewer_particles, SIZE).play(replay), Err(ReplayError::ConfigMismatch));
+    }
+}
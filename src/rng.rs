@@ -0,0 +1,200 @@
+use std::f64::consts::TAU;
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+use crate::shapes::Point2d;
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+// Independent sequences drawn from the one engine seed, so spawning more particles never changes
+// what gameplay code sees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngStream {
+    Gameplay,
+    Particles,
+}
+
+impl RngStream {
+    pub const ALL: [RngStream; 2] = [RngStream::Gameplay, RngStream::Particles];
+
+    fn id(self) -> u64 {
+        self as u64
+    }
+}
+
+// PCG32 (XSH RR): small, fast and the same on every platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+#[allow(dead_code)]
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self::with_stream(seed, 0)
+    }
+
+    pub fn with_stream(seed: u64, stream: u64) -> Self {
+        let mut rng = Self { state: 0, increment: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn for_stream(seed: u64, stream: RngStream) -> Self {
+        Self::with_stream(seed, stream.id())
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let state = self.state;
+        self.state = state.wrapping_mul(MULTIPLIER).wrapping_add(self.increment);
+
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        let rotation = (state >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    // Uniform in [0, 1), with the full 53 bits of precision.
+    pub fn next_f64(&mut self) -> f64 {
+        let high = (self.next_u32() >> 5) as u64;
+        let low = (self.next_u32() >> 6) as u64;
+        ((high << 26) | low) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [min, max).
+    pub fn range(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.next_f64()
+    }
+
+    // Uniform in [0, max) without modulo bias; 0 when `max` is 0.
+    pub fn below(&mut self, max: u32) -> u32 {
+        if max == 0 {
+            return 0;
+        }
+
+        let threshold = max.wrapping_neg() % max;
+        loop {
+            let value = self.next_u32();
+            if value >= threshold {
+                return value % max;
+            }
+        }
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+
+    // Radians in [0, 2π).
+    pub fn angle(&mut self) -> f64 {
+        self.next_f64() * TAU
+    }
+
+    pub fn unit_vector(&mut self) -> Point2d {
+        let angle = self.angle();
+        Point2d { x: angle.cos(), y: angle.sin() }
+    }
+
+    // Moves each channel by up to `amount` either way, clamped to the valid range.
+    pub fn jitter_color(&mut self, color: RGB8, amount: u8) -> RGB8 {
+        let mut jitter = |channel: u8| {
+            let offset = self.below(amount as u32 * 2 + 1) as i32 - amount as i32;
+            (channel as i32 + offset).clamp(0, 255) as u8
+        };
+
+        RGB8 { r: jitter(color.r), g: jitter(color.g), b: jitter(color.b) }
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            return None;
+        }
+        items.get(self.below(items.len() as u32) as usize)
+    }
+
+    // Chooses an item with probability proportional to its weight; non-positive weights are never chosen.
+    pub fn weighted<'a, T>(&mut self, items: &'a [(T, f64)]) -> Option<&'a T> {
+        let total: f64 = items.iter().map(|(_, weight)| weight.max(0.)).sum();
+        if total <= 0. {
+            return None;
+        }
+
+        let mut target = self.next_f64() * total;
+        let mut last = None;
+        for (item, weight) in items.iter().filter(|(_, weight)| *weight > 0.) {
+            if target < *weight {
+                return Some(item);
+            }
+            target -= weight;
+            last = Some(item);
+        }
+
+        // Rounding can leave `target` just past the final weight.
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_pcg32_reference_output() {
+        // The first numbers from the reference implementation's `pcg32_srandom(42, 54)`.
+        let mut rng = Rng::with_stream(42, 54);
+        let expected = [0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e];
+        let actual: Vec<u32> = (0..expected.len()).map(|_| rng.next_u32()).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn streams_from_one_seed_are_independent() {
+        let draw = |mut rng: Rng| -> Vec<u32> { (0..64).map(|_| rng.next_u32()).collect() };
+        let gameplay = draw(Rng::for_stream(7, RngStream::Gameplay));
+        let particles = draw(Rng::for_stream(7, RngStream::Particles));
+        assert_ne!(gameplay, particles);
+        assert!(gameplay.iter().zip(&particles).filter(|(a, b)| a == b).count() < 2);
+
+        // Drawing from one stream leaves the other where it was.
+        let mut streams = [Rng::for_stream(7, RngStream::Gameplay), Rng::for_stream(7, RngStream::Particles)];
+        for _ in 0..100 {
+            streams[1].next_u32();
+        }
+        assert_eq!(draw(streams[0]), gameplay);
+
+        assert_eq!(draw(Rng::for_stream(7, RngStream::Gameplay)), gameplay, "the same seed repeats itself");
+        assert_ne!(draw(Rng::for_stream(8, RngStream::Gameplay)), gameplay);
+    }
+
+    #[test]
+    fn ranges_stay_in_bounds() {
+        let mut rng = Rng::new(3);
+        let mut seen = [0; 6];
+        for _ in 0..6000 {
+            let unit = rng.next_f64();
+            assert!((0. ..1.).contains(&unit));
+            assert!((-2. ..5.).contains(&rng.range(-2., 5.)));
+            seen[rng.below(6) as usize] += 1;
+        }
+        assert!(seen.iter().all(|&count| (900..1100).contains(&count)), "{:?}", seen);
+        assert_eq!(rng.below(0), 0);
+    }
+
+    #[test]
+    fn picks_skip_what_can_never_be_chosen() {
+        let mut rng = Rng::new(5);
+        assert_eq!(rng.pick::<u8>(&[]), None);
+        assert_eq!(rng.weighted(&[("never", 0.), ("nope", -1.)]), None);
+        for _ in 0..200 {
+            assert_eq!(rng.weighted(&[("never", 0.), ("always", 2.), ("nope", -3.)]), Some(&"always"));
+            let color = rng.jitter_color(RGB8 { r: 0, g: 128, b: 255 }, 10);
+            assert!(color.r <= 10 && (118..=138).contains(&color.g) && color.b >= 245);
+        }
+    }
+}
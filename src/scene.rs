@@ -0,0 +1,65 @@
+use std::rc::Rc;
+use rgb::RGB;
+use serde::{Deserialize, Serialize};
+
+use crate::Draw;
+use crate::game_engine::View;
+use crate::renderer::Renderer;
+use crate::shapes::Line;
+use crate::tilemap::Tilemap;
+
+// Static level geometry: drawn into the cached background and used as occluders. Scene assets are
+// this as JSON, without a tilemap.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+    pub walls: Vec<Line>,
+    // Drawn under the walls.
+    #[serde(skip)]
+    pub tilemap: Option<Rc<Tilemap>>,
+    // How many of the walls, from the start, were made around the tilemap's solid tiles.
+    #[serde(skip)]
+    tile_walls: usize,
+}
+
+impl Scene {
+    // A level made of a tilemap, walled in along the edges of its solid tiles.
+    pub fn from_tilemap(tilemap: Tilemap, wall_color: RGB<u8>) -> Self {
+        Self::around(Some(Rc::new(tilemap)), wall_color, vec![])
+    }
+
+    // `walls`, and the walls around the solid tiles of the tilemap if there is one.
+    pub fn around(tilemap: Option<Rc<Tilemap>>, wall_color: RGB<u8>, walls: Vec<Line>) -> Self {
+        let mut all = tilemap.as_ref().map_or(vec![], |tilemap| tilemap.collision_lines(wall_color));
+        let tile_walls = all.len();
+        all.extend(walls);
+        Self { walls: all, tilemap, tile_walls }
+    }
+
+    // The walls that weren't made from the tilemap.
+    pub fn added_walls(&self) -> &[Line] {
+        self.walls.get(self.tile_walls..).unwrap_or(&[])
+    }
+
+    pub fn add_wall(&mut self, line: Line) {
+        self.walls.push(line);
+    }
+}
+
+impl Draw for Scene {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        if let Some(tilemap) = self.tilemap.as_ref().filter(|tilemap| tilemap.in_view(view)) {
+            tilemap.draw(renderer, view);
+        }
+
+        for wall in self.walls.iter() {
+            if wall.in_view(view) {
+                wall.draw(renderer, view);
+            }
+        }
+    }
+
+    fn in_view(&self, _view: &View) -> bool {
+        true
+    }
+}
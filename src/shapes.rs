@@ -1,17 +1,15 @@
-#[allow(dead_code)]
-use std::f64;
-use js_sys::Math::{max, min};
+use std::ops::{Add, Mul, Neg, Sub};
 use rgb::RGB;
-use web_sys::CanvasRenderingContext2d;
-use crate::browser::console_log;
+use serde::{Deserialize, Serialize};
 use crate::Draw;
 use crate::game_engine::View;
+use crate::renderer::Renderer;
 
 pub fn rgb(rgb: RGB<u8>) -> String {
     format!("#{:02X}{:02X}{:02X}", rgb.r, rgb.g, rgb.b)
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Point2d {
     pub x: f64,
     pub y: f64,
@@ -22,9 +20,75 @@ impl Point2d {
         let u: f64 = (x.powi(2) + y.powi(2)).sqrt();
         Point2d { x: x / u, y: y / u }
     }
+
+    pub fn dot(self, other: Self) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn distance(self, other: Self) -> f64 {
+        (other - self).length()
+    }
+
+    // `None` for the zero vector, which has no direction.
+    pub fn unit(self) -> Option<Self> {
+        let length = self.length();
+        (length > 0.).then(|| self * (1. / length))
+    }
+
+    // Shortened to `max` if it is any longer.
+    pub fn limit(self, max: f64) -> Self {
+        let length = self.length();
+        if length > max { self * (max / length) } else { self }
+    }
+
+    // A quarter turn, from +x towards +y.
+    pub fn perpendicular(self) -> Self {
+        Point2d { x: -self.y, y: self.x }
+    }
+
+    pub fn rotate(self, angle: f64) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Point2d { x: self.x * cos - self.y * sin, y: self.x * sin + self.y * cos }
+    }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+impl Add for Point2d {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Point2d { x: self.x + other.x, y: self.y + other.y }
+    }
+}
+
+impl Sub for Point2d {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Point2d { x: self.x - other.x, y: self.y - other.y }
+    }
+}
+
+impl Mul<f64> for Point2d {
+    type Output = Self;
+
+    fn mul(self, factor: f64) -> Self {
+        Point2d { x: self.x * factor, y: self.y * factor }
+    }
+}
+
+impl Neg for Point2d {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Point2d { x: -self.x, y: -self.y }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Line {
     pub from: Point2d,
     pub to: Point2d,
@@ -57,8 +121,8 @@ pub struct CollisionRectangle {
 
 impl CollisionRectangle {
     pub fn new(a: Point2d, b: Point2d) -> Self {
-        let top_left = Point2d { x: min(a.x, b.x), y: min(a.y, b.y) };
-        let bottom_right = Point2d { x: max(a.x, b.x), y: max(a.y, b.y) };
+        let top_left = Point2d { x: a.x.min(b.x), y: a.y.min(b.y) };
+        let bottom_right = Point2d { x: a.x.max(b.x), y: a.y.max(b.y) };
 
         Self {
             top_left,
@@ -95,10 +159,10 @@ pub struct Shapes {
 }
 
 impl Draw for Shapes {
-    fn draw(&self, context: &CanvasRenderingContext2d, view: &View) -> () {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
         for item in self.items.iter() {
             if item.in_view(view) {
-                item.draw(context, view);
+                item.draw(renderer, view);
             }
         }
     }
@@ -109,16 +173,11 @@ impl Draw for Shapes {
 }
 
 impl Draw for Line {
-    fn draw(&self, context: &CanvasRenderingContext2d, view: &View) -> () {
-        let color = rgb(self.color);
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
         let from = view.transform(&self.from);
         let to = view.transform(&self.to);
 
-        context.begin_path();
-        context.set_stroke_style(&color.into());
-        context.move_to(from.x, from.y);
-        context.line_to(to.x, to.y);
-        context.stroke();
+        renderer.line(from, to, self.color);
     }
 
     fn in_view(&self, view: &View) -> bool {
@@ -133,26 +192,10 @@ impl Draw for Line {
 }
 
 impl Draw for Circle {
-    fn draw(&self, context: &CanvasRenderingContext2d, view: &View) -> () {
-        let color = rgb(self.color);
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
         let center = view.transform(&self.center_point);
 
-        context.begin_path();
-        context.set_stroke_style(&color.into());
-        context.move_to(
-            center.x + self.radius as f64,
-            center.y,
-        );
-        context
-            .arc(
-                center.x,
-                center.y,
-                self.radius as f64,
-                0.,
-                f64::consts::PI * 2.0,
-            )
-            .unwrap();
-        context.stroke();
+        renderer.circle(center, view.scale(self.radius as f64), self.color);
     }
 
     fn in_view(&self, view: &View) -> bool {
@@ -0,0 +1,221 @@
+use std::fmt;
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::effects::ActiveEffect;
+use crate::forces::{Collisions, ForceField};
+use crate::lighting::LightState;
+use crate::particle::ParticleState;
+use crate::rng::Rng;
+use crate::shapes::{Line, Point2d};
+
+// Bumped whenever the layout below changes; older snapshots are rejected rather than misread.
+pub const SNAPSHOT_VERSION: u32 = 8;
+
+const MAGIC: &[u8; 4] = b"SNAP";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Json(String),
+    Binary(String),
+    RngStreams { expected: usize, found: usize },
+    InvalidTime(f64),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {} (expected {})", version, SNAPSHOT_VERSION),
+            SnapshotError::Json(error) => write!(f, "invalid snapshot JSON: {}", error),
+            SnapshotError::Binary(error) => write!(f, "invalid binary snapshot: {}", error),
+            SnapshotError::RngStreams { expected, found } => write!(f, "snapshot has {} random streams, expected {}", found, expected),
+            SnapshotError::InvalidTime(time) => write!(f, "snapshot time {} ms is out of range", time),
+        }
+    }
+}
+
+impl From<SnapshotError> for JsValue {
+    fn from(error: SnapshotError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewState {
+    pub offset: Point2d,
+    pub center: Point2d,
+    pub size: Point2d,
+    pub zoom: f64,
+}
+
+// The whole simulated world at one frame. Times are engine milliseconds, so particle and light
+// ages carry over exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub version: u32,
+    pub frame: u64,
+    pub elapsed: f64,
+    pub seed: u64,
+    pub rngs: Vec<Rng>,
+    pub view: ViewState,
+    pub mouse: Option<Point2d>,
+    // Walls other than those around the tilemap, which is kept out of snapshots.
+    pub walls: Vec<Line>,
+    pub ambient: RGB8,
+    pub lights: Vec<LightState>,
+    pub particles: Vec<ParticleState>,
+    pub forces: Vec<ForceField>,
+    pub collisions: Option<Collisions>,
+    pub effects: Vec<ActiveEffect>,
+}
+
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: u32,
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> Result<String, SnapshotError> {
+        serde_json::to_string(self).map_err(|error| SnapshotError::Json(error.to_string()))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SnapshotError> {
+        // Check the version first so an old layout reports that, not whichever field moved.
+        let probe: VersionProbe = serde_json::from_str(json).map_err(|error| SnapshotError::Json(error.to_string()))?;
+        if probe.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(probe.version));
+        }
+
+        serde_json::from_str(json).map_err(|error| SnapshotError::Json(error.to_string()))
+    }
+
+    // "SNAP", the version as a little-endian u32, then the snapshot in bincode.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        out.extend(bincode::serialize(self).map_err(|error| SnapshotError::Binary(error.to_string()))?);
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        bincode::deserialize(&bytes[8..]).map_err(|error| SnapshotError::Binary(error.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::game::{setup, Demo};
+    use crate::game_engine::GameEngine;
+    use crate::replay::InputEvent;
+    use crate::tilemap::Tilemap;
+
+    const SIZE: Point2d = Point2d { x: 640., y: 480. };
+
+    fn engine() -> GameEngine {
+        let engine = GameEngine::headless(EngineConfig { seed: Some(3), ..EngineConfig::default() }, SIZE);
+        setup(&engine);
+        engine
+    }
+
+    // A world with particles, lights and effects going.
+    fn busy() -> (GameEngine, Demo) {
+        let (engine, mut game) = (engine(), Demo);
+        engine.input(InputEvent::MouseMove(Point2d { x: 300., y: 200. }));
+        engine.input(InputEvent::KeyDown(49));
+        engine.input(InputEvent::RemoteEffect { name: "smoke".to_string(), position: Point2d { x: 20., y: 20. } });
+        for _ in 0..40 {
+            engine.step(16., &mut game);
+        }
+        (engine, game)
+    }
+
+    #[test]
+    fn snapshots_survive_json_and_bincode() {
+        let (engine, _) = busy();
+        let snapshot = engine.snapshot();
+        assert!(!snapshot.particles.is_empty());
+
+        assert_eq!(Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap(), snapshot);
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn restored_worlds_carry_on_like_the_original() {
+        let (original, mut game) = busy();
+        let restored = engine();
+        restored.restore(Snapshot::from_bytes(&original.snapshot().to_bytes().unwrap()).unwrap()).unwrap();
+        assert_eq!(restored.state_hash(), original.state_hash());
+
+        let mut restored_game = Demo;
+        for _ in 0..20 {
+            original.step(16., &mut game);
+            restored.step(16., &mut restored_game);
+        }
+        assert_eq!(restored.state_hash(), original.state_hash());
+    }
+
+    #[test]
+    fn broken_snapshots_are_rejected() {
+        let (engine, _) = busy();
+        let bytes = engine.snapshot().to_bytes().unwrap();
+
+        assert_eq!(Snapshot::from_bytes(b"SNA"), Err(SnapshotError::BadMagic));
+        let mut old = bytes.clone();
+        old[4..8].copy_from_slice(&(SNAPSHOT_VERSION - 1).to_le_bytes());
+        assert_eq!(Snapshot::from_bytes(&old), Err(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION - 1)));
+        assert!(matches!(Snapshot::from_bytes(&bytes[..bytes.len() / 2]), Err(SnapshotError::Binary(_))));
+        assert!(matches!(Snapshot::from_json("{\"version\": 1}"), Err(SnapshotError::UnsupportedVersion(1))));
+
+        let hash = engine.state_hash();
+        let mut nan = engine.snapshot();
+        nan.elapsed = f64::NAN;
+        assert!(matches!(engine.restore(nan), Err(SnapshotError::InvalidTime(_))));
+        let mut far = engine.snapshot();
+        far.particles[0].start_time = 1e300;
+        assert_eq!(engine.restore(far), Err(SnapshotError::InvalidTime(1e300)));
+        assert_eq!(engine.state_hash(), hash, "a rejected snapshot leaves the world alone");
+    }
+
+    #[test]
+    fn walls_around_the_tilemap_follow_the_tilemap_in_place() {
+        let tilemap = |solid: &[u32]| {
+            let tiles: Vec<String> = (0..4).map(|x| (solid.contains(&x) as u32).to_string()).collect();
+            let json = format!(
+                r#"{{"width": 4, "height": 1, "tilewidth": 8, "tileheight": 8, "layers": [{{"type": "tilelayer", "name": "walls", "data": [{}], "properties": [{{"name": "collision", "value": true}}]}}]}}"#,
+                tiles.join(", "),
+            );
+            Tilemap::from_tiled(&json).unwrap()
+        };
+
+        let engine = engine();
+        engine.set_tilemap(tilemap(&[0]));
+        let mut scene = (*engine.scene()).clone();
+        scene.add_wall(Line { from: Point2d { x: 0., y: 0. }, to: Point2d { x: 5., y: 5. }, color: RGB8 { r: 0, g: 0, b: 0 } });
+        engine.set_scene(scene);
+
+        let snapshot = engine.snapshot();
+        assert_eq!(snapshot.walls.len(), 1, "only walls that weren't made from the tilemap are kept");
+
+        engine.set_tilemap(tilemap(&[0, 2]));
+        let expected = engine.scene().walls.len() + 1;
+        engine.restore(snapshot).unwrap();
+        assert_eq!(engine.scene().walls.len(), expected);
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::VecDeque;
+use rgb::RGB8;
+
+use crate::{Draw, Point2d};
+use crate::game_engine::{GameEngine, View};
+use crate::renderer::Renderer;
+use crate::replay::InputEvent;
+
+// A screen or mode of the game: a menu, a level, a pause overlay. The engine runs whichever is on
+// top of its stack; `delta` is the frame time in milliseconds.
+pub trait GameState {
+    fn enter(&mut self, _game_engine: &GameEngine) {}
+
+    fn exit(&mut self, _game_engine: &GameEngine) {}
+
+    fn update(&mut self, game_engine: &GameEngine, delta: f64);
+
+    fn draw(&mut self, _game_engine: &GameEngine) {}
+
+    // Gets the frame's input before `update`, only while on top.
+    fn handle_input(&mut self, _game_engine: &GameEngine, _input: &InputEvent) {}
+
+    // Overlays let the states below them keep drawing, though only the top one updates.
+    fn is_overlay(&self) -> bool {
+        false
+    }
+
+    // While on top, engine time stands still: no animations, particles or fading lights.
+    fn pauses_world(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    Cut,
+    // Fades to `color` and back over `duration` milliseconds, switching at the midpoint.
+    Fade { duration: f64, color: RGB8 },
+}
+
+impl Transition {
+    pub fn fade(duration: f64) -> Self {
+        Transition::Fade { duration, color: RGB8 { r: 0, g: 0, b: 0 } }
+    }
+}
+
+pub enum StateChange {
+    Push(Box<dyn GameState>),
+    Pop,
+    Replace(Box<dyn GameState>),
+}
+
+struct Fade {
+    // Applied at the midpoint, then `None`.
+    change: Option<StateChange>,
+    elapsed: f64,
+    duration: f64,
+    color: RGB8,
+}
+
+// The engine's states, bottom first. Changes are queued and applied between frames, one fade at
+// a time; states are taken out while they run so they can queue changes of their own.
+#[derive(Default)]
+pub struct StateStack {
+    states: Vec<Box<dyn GameState>>,
+    // States taken out to run, which still count as on the stack.
+    running: usize,
+    pending: VecDeque<(StateChange, Transition)>,
+    fade: Option<Fade>,
+}
+
+impl StateStack {
+    pub fn request(&mut self, change: StateChange, transition: Transition) {
+        self.pending.push_back((change, transition));
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len() + self.running
+    }
+
+    // How many states from the top draw: the top one and those it overlays, down to the first that
+    // isn't an overlay itself.
+    pub fn drawn(&self) -> usize {
+        self.states.iter().rev().position(|state| !state.is_overlay()).map_or(self.states.len(), |index| index + 1)
+    }
+
+    pub fn pauses_world(&self) -> bool {
+        self.states.last().is_some_and(|state| state.pauses_world())
+    }
+
+    // Moves a running fade on by `delta` and returns the changes to make this frame.
+    pub fn due_changes(&mut self, delta: f64) -> Vec<StateChange> {
+        let mut due = vec![];
+
+        if let Some(fade) = &mut self.fade {
+            fade.elapsed += delta;
+            if fade.elapsed >= fade.duration / 2. {
+                due.extend(fade.change.take());
+            }
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+
+        while self.fade.is_none() {
+            let Some((change, transition)) = self.pending.pop_front() else { break };
+            match transition {
+                Transition::Cut => due.push(change),
+                Transition::Fade { duration, color } => self.fade = Some(Fade { change: Some(change), elapsed: 0., duration, color }),
+            }
+        }
+
+        due
+    }
+
+    pub fn push(&mut self, state: Box<dyn GameState>) {
+        self.states.push(state);
+    }
+
+    // Takes out up to `count` states from the top to run, bottom first.
+    pub fn take_top(&mut self, count: usize) -> Vec<Box<dyn GameState>> {
+        let top = self.states.split_off(self.states.len().saturating_sub(count));
+        self.running += top.len();
+        top
+    }
+
+    pub fn put_back(&mut self, top: Vec<Box<dyn GameState>>) {
+        self.running -= top.len();
+        self.states.extend(top);
+    }
+
+    // Drops states taken out with `take_top` instead of putting them back.
+    pub fn remove(&mut self, top: Vec<Box<dyn GameState>>) {
+        self.running -= top.len();
+    }
+
+    // How far into a fade the screen is covered: 0 at either end, 1 at the midpoint.
+    pub fn fade_tint(&self) -> Option<ScreenTint> {
+        let fade = self.fade.as_ref()?;
+        let progress = if fade.duration > 0. { (fade.elapsed / fade.duration).clamp(0., 1.) } else { 1. };
+        Some(ScreenTint { color: fade.color, alpha: 1. - (progress * 2. - 1.).abs() })
+    }
+}
+
+// A flat colour over the whole canvas, for fades and dimming behind overlays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenTint {
+    pub color: RGB8,
+    pub alpha: f64,
+}
+
+impl Draw for ScreenTint {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        renderer.rect(Point2d { x: 0., y: 0. }, view.size, self.color, self.alpha);
+    }
+
+    fn in_view(&self, _view: &View) -> bool {
+        self.alpha > 0.
+    }
+}
+
+// Shows how far asset loading has got and moves on to `next` once nothing is left loading, failed
+// assets included; `assets().errors()` has what went wrong.
+pub struct LoadingScreen<S: GameState + 'static> {
+    next: Option<S>,
+    transition: Transition,
+}
+
+impl<S: GameState + 'static> LoadingScreen<S> {
+    pub fn new(next: S, transition: Transition) -> Self {
+        Self { next: Some(next), transition }
+    }
+}
+
+impl<S: GameState + 'static> GameState for LoadingScreen<S> {
+    fn update(&mut self, game_engine: &GameEngine, _delta: f64) {
+        if game_engine.assets().is_loading() {
+            return;
+        }
+        if let Some(next) = self.next.take() {
+            game_engine.replace_state(next, self.transition);
+        }
+    }
+
+    fn draw(&mut self, game_engine: &GameEngine) {
+        let progress = game_engine.assets().progress().fraction();
+        let color = game_engine.config().colors.light.0;
+        game_engine.draw_on("ui", Box::new(ScreenTint { color: RGB8 { r: 0, g: 0, b: 0 }, alpha: 1. }));
+        game_engine.draw_on("ui", Box::new(ProgressBar { progress, color }));
+    }
+
+    fn pauses_world(&self) -> bool {
+        true
+    }
+}
+
+// A bar across the middle of the screen, filled up to `progress` from 0 to 1.
+pub struct ProgressBar {
+    pub progress: f64,
+    pub color: RGB8,
+}
+
+impl Draw for ProgressBar {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        let size = Point2d { x: (view.size.x / 2.).min(300.), y: 6. };
+        let position = Point2d { x: (view.size.x - size.x) / 2., y: (view.size.y - size.y) / 2. };
+
+        renderer.rect(position, size, self.color, 0.25);
+        renderer.rect(position, Point2d { x: size.x * self.progress.clamp(0., 1.), y: size.y }, self.color, 1.);
+    }
+
+    fn in_view(&self, _view: &View) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::game::Demo;
+
+    const BLACK: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+
+    // Notes down how many states the engine counts whenever it enters, exits or updates.
+    struct Counted {
+        name: &'static str,
+        overlay: bool,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Counted {
+        fn new(name: &'static str, log: &Rc<RefCell<Vec<String>>>) -> Self {
+            Self { name, overlay: false, log: log.clone() }
+        }
+
+        fn note(&self, game_engine: &GameEngine, what: &str) {
+            self.log.borrow_mut().push(format!("{} {} {}", what, self.name, game_engine.state_count()));
+        }
+    }
+
+    impl GameState for Counted {
+        fn enter(&mut self, game_engine: &GameEngine) {
+            self.note(game_engine, "enter");
+        }
+
+        fn exit(&mut self, game_engine: &GameEngine) {
+            self.note(game_engine, "exit");
+        }
+
+        fn update(&mut self, game_engine: &GameEngine, _delta: f64) {
+            self.note(game_engine, "update");
+        }
+
+        fn is_overlay(&self) -> bool {
+            self.overlay
+        }
+    }
+
+    fn changes(stack: &mut StateStack, delta: f64) -> Vec<&'static str> {
+        stack.due_changes(delta).iter().map(|change| match change {
+            StateChange::Push(_) => "push",
+            StateChange::Pop => "pop",
+            StateChange::Replace(_) => "replace",
+        }).collect()
+    }
+
+    #[test]
+    fn cuts_happen_at_once_and_fades_switch_halfway() {
+        let mut stack = StateStack::default();
+        stack.request(StateChange::Pop, Transition::Cut);
+        stack.request(StateChange::Pop, Transition::Cut);
+        assert_eq!(changes(&mut stack, 0.), ["pop", "pop"]);
+
+        stack.request(StateChange::Pop, Transition::Fade { duration: 100., color: BLACK });
+        stack.request(StateChange::Pop, Transition::Cut);
+        assert!(changes(&mut stack, 0.).is_empty());
+        assert!(changes(&mut stack, 40.).is_empty());
+        assert_eq!(changes(&mut stack, 10.), ["pop"], "the change comes at the midpoint");
+        assert!(changes(&mut stack, 40.).is_empty(), "and the next waits for the fade to end");
+        assert_eq!(changes(&mut stack, 10.), ["pop"]);
+        assert!(stack.fade_tint().is_none());
+    }
+
+    #[test]
+    fn a_long_frame_makes_a_whole_fade_at_once() {
+        let mut stack = StateStack::default();
+        stack.request(StateChange::Pop, Transition::fade(100.));
+        stack.request(StateChange::Pop, Transition::fade(100.));
+        assert!(changes(&mut stack, 0.).is_empty());
+        assert_eq!(changes(&mut stack, 500.), ["pop"]);
+        assert!(stack.fade_tint().is_some(), "the second fade starts fresh");
+        assert_eq!(changes(&mut stack, 50.), ["pop"]);
+    }
+
+    #[test]
+    fn fades_cover_the_screen_at_the_midpoint() {
+        let mut stack = StateStack::default();
+        assert_eq!(stack.fade_tint(), None);
+
+        stack.request(StateChange::Pop, Transition::Fade { duration: 200., color: BLACK });
+        let mut alphas = vec![];
+        for delta in [0., 50., 50., 50., 49.] {
+            stack.due_changes(delta);
+            alphas.push(stack.fade_tint().unwrap().alpha);
+        }
+        assert_eq!(alphas[..4], [0., 0.5, 1., 0.5]);
+        assert!((alphas[4] - 0.01).abs() < 1e-9);
+
+        let mut instant = StateStack::default();
+        instant.request(StateChange::Pop, Transition::fade(0.));
+        assert!(changes(&mut instant, 0.).is_empty());
+        assert_eq!(instant.fade_tint().map(|tint| tint.alpha), Some(0.), "an instant fade never shows");
+    }
+
+    #[test]
+    fn overlays_draw_over_the_state_below_them() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut stack = StateStack::default();
+        assert_eq!(stack.drawn(), 0);
+
+        stack.push(Box::new(Counted::new("level", &log)));
+        stack.push(Box::new(Counted::new("menu", &log)));
+        assert_eq!(stack.drawn(), 1);
+
+        stack.push(Box::new(Counted { overlay: true, ..Counted::new("pause", &log) }));
+        assert_eq!(stack.drawn(), 2);
+    }
+
+    #[test]
+    fn states_count_as_on_the_stack_while_they_run() {
+        let engine = GameEngine::headless(EngineConfig { seed: Some(1), ..EngineConfig::default() }, Point2d { x: 640., y: 480. });
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut game = Demo;
+
+        engine.push_state(Counted::new("level", &log), Transition::Cut);
+        engine.push_state(Counted::new("pause", &log), Transition::Cut);
+        engine.step(16., &mut game);
+        engine.replace_state(Counted::new("menu", &log), Transition::Cut);
+        engine.step(16., &mut game);
+        engine.pop_state(Transition::Cut);
+        engine.step(16., &mut game);
+
+        assert_eq!(*log.borrow(), [
+            "enter level 1",
+            "enter pause 2",
+            "update pause 2",
+            "exit pause 2",
+            "enter menu 2",
+            "update menu 2",
+            "exit menu 2",
+            "update level 1",
+        ]);
+        assert_eq!(engine.state_count(), 1);
+    }
+}
@@ -0,0 +1,645 @@
+use std::collections::HashSet;
+use std::fmt;
+use rgb::RGB;
+use serde::Deserialize;
+use serde_json::Value;
+use wasm_bindgen::JsValue;
+
+use crate::Draw;
+use crate::game_engine::View;
+use crate::renderer::Renderer;
+use crate::shapes::{Line, Point2d};
+
+// Tiles per side of a chunk, the unit the map is culled in.
+pub const CHUNK_SIZE: u32 = 16;
+
+// Drawn in place of tiles whose tileset image isn't loaded, or on backends that can't draw images.
+const FALLBACK_COLOR: RGB<u8> = RGB { r: 90, g: 90, b: 110 };
+
+// Tiled keeps flips and rotation in the top four bits of a tile id; they are ignored here.
+const TILED_FLAGS: u32 = 0xF000_0000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TilemapError {
+    Parse(String),
+    Unsupported(String),
+    InvalidLayer { name: String, reason: String },
+    TooLarge { width: u32, height: u32 },
+}
+
+impl fmt::Display for TilemapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TilemapError::Parse(error) => write!(f, "invalid Tiled map: {}", error),
+            TilemapError::Unsupported(what) => write!(f, "unsupported Tiled map: {}", what),
+            TilemapError::InvalidLayer { name, reason } => write!(f, "invalid tile layer `{}`: {}", name, reason),
+            TilemapError::TooLarge { width, height } => write!(f, "a {}x{} map has too many tiles", width, height),
+        }
+    }
+}
+
+impl From<TilemapError> for JsValue {
+    fn from(error: TilemapError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+// A grid of equally sized tiles cut from the sprite added under `name`. Tiles are numbered from
+// `first_id` on across the map's tilesets, row by row through the image; 0 is no tile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tileset {
+    pub name: String,
+    pub first_id: u32,
+    pub tile_count: u32,
+    pub columns: u32,
+    pub tile_size: Point2d,
+    // Border around the image and gap between tiles, in image pixels.
+    pub margin: f64,
+    pub spacing: f64,
+    // Tiles, counted from 0 within the set, that walls are made around.
+    pub solid: HashSet<u32>,
+}
+
+impl Tileset {
+    pub fn new(name: &str, first_id: u32, tile_count: u32, columns: u32, tile_size: Point2d) -> Self {
+        Self {
+            name: name.to_string(),
+            first_id,
+            tile_count,
+            columns: columns.max(1),
+            tile_size,
+            margin: 0.,
+            spacing: 0.,
+            solid: HashSet::new(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_spacing(mut self, margin: f64, spacing: f64) -> Self {
+        self.margin = margin;
+        self.spacing = spacing;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_solid(mut self, tiles: &[u32]) -> Self {
+        self.solid.extend(tiles);
+        self
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        id >= self.first_id && id - self.first_id < self.tile_count
+    }
+
+    pub fn is_solid(&self, id: u32) -> bool {
+        self.contains(id) && self.solid.contains(&(id - self.first_id))
+    }
+
+    // Where tile `id` is in the image.
+    pub fn source(&self, id: u32) -> Point2d {
+        let index = id - self.first_id;
+        let (column, row) = ((index % self.columns) as f64, (index / self.columns) as f64);
+        Point2d {
+            x: self.margin + column * (self.tile_size.x + self.spacing),
+            y: self.margin + row * (self.tile_size.y + self.spacing),
+        }
+    }
+}
+
+// One grid of tile ids the size of the map, row by row. Every tile on a collision layer is solid,
+// whatever its tileset says.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileLayer {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f64,
+    pub collision: bool,
+    tiles: Vec<u32>,
+}
+
+impl TileLayer {
+    #[allow(dead_code)]
+    pub fn tiles(&self) -> &[u32] {
+        &self.tiles
+    }
+}
+
+// A non-empty tile, as kept per chunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ChunkTile {
+    layer: usize,
+    x: u32,
+    y: u32,
+    id: u32,
+}
+
+// A grid level: layers of tiles drawn from tilesets, with `origin` as the world position of the
+// top left corner. Drawing only visits the chunks in view, and only their non-empty tiles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tilemap {
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: Point2d,
+    pub origin: Point2d,
+    tilesets: Vec<Tileset>,
+    layers: Vec<TileLayer>,
+    // Per chunk, row by row, its tiles in layer order.
+    chunks: Vec<Vec<ChunkTile>>,
+}
+
+impl Tilemap {
+    pub fn new(width: u32, height: u32, tile_size: Point2d) -> Result<Self, TilemapError> {
+        // Each layer holds a u32 per tile, so its size in bytes has to fit as well.
+        (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|tiles| tiles.checked_mul(std::mem::size_of::<u32>()))
+            .filter(|&bytes| bytes <= isize::MAX as usize)
+            .ok_or(TilemapError::TooLarge { width, height })?;
+        let chunks = width.div_ceil(CHUNK_SIZE) as usize * height.div_ceil(CHUNK_SIZE) as usize;
+
+        Ok(Self {
+            width,
+            height,
+            tile_size,
+            origin: Point2d { x: 0., y: 0. },
+            tilesets: vec![],
+            layers: vec![],
+            chunks: vec![vec![]; chunks],
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn with_origin(mut self, origin: Point2d) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn add_tileset(&mut self, tileset: Tileset) {
+        self.tilesets.push(tileset);
+    }
+
+    // Adds an empty layer on top and returns its index.
+    pub fn add_layer(&mut self, name: &str, collision: bool) -> usize {
+        self.layers.push(TileLayer {
+            name: name.to_string(),
+            visible: true,
+            opacity: 1.,
+            collision,
+            tiles: vec![0; self.width as usize * self.height as usize],
+        });
+        self.layers.len() - 1
+    }
+
+    #[allow(dead_code)]
+    pub fn tilesets(&self) -> &[Tileset] {
+        &self.tilesets
+    }
+
+    #[allow(dead_code)]
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+
+    pub fn tileset(&self, id: u32) -> Option<&Tileset> {
+        self.tilesets.iter().find(|tileset| tileset.contains(id))
+    }
+
+    pub fn tile(&self, layer: usize, x: u32, y: u32) -> u32 {
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+        self.layers.get(layer).map_or(0, |layer| layer.tiles[self.index(x, y)])
+    }
+
+    // Where tile (x, y) is in a layer; `new` made sure this can't overflow.
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    // Walls made from the map before this don't follow; make them again with `collision_lines`.
+    #[allow(dead_code)]
+    pub fn set_tile(&mut self, layer: usize, x: u32, y: u32, id: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let index = self.index(x, y);
+        let Some(tiles) = self.layers.get_mut(layer).map(|layer| &mut layer.tiles) else { return };
+        tiles[index] = id;
+        self.rebuild_chunk(x / CHUNK_SIZE, y / CHUNK_SIZE);
+    }
+
+    pub fn is_solid(&self, x: u32, y: u32) -> bool {
+        (0..self.layers.len()).any(|index| {
+            let id = self.tile(index, x, y);
+            id != 0 && (self.layers[index].collision || self.tileset(id).is_some_and(|tileset| tileset.is_solid(id)))
+        })
+    }
+
+    // The tile a world position falls in.
+    #[allow(dead_code)]
+    pub fn tile_at(&self, point: Point2d) -> Option<(u32, u32)> {
+        let x = ((point.x - self.origin.x) / self.tile_size.x).floor();
+        let y = ((point.y - self.origin.y) / self.tile_size.y).floor();
+        if x < 0. || y < 0. || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
+        }
+        Some((x as u32, y as u32))
+    }
+
+    // The world position of a corner between tiles.
+    pub fn corner(&self, x: u32, y: u32) -> Point2d {
+        Point2d { x: self.origin.x + x as f64 * self.tile_size.x, y: self.origin.y + y as f64 * self.tile_size.y }
+    }
+
+    // Walls along every edge between a solid tile and anything else, the map's border included.
+    // Edges in a row are merged into one line for as long as the solid side stays the same, so a
+    // block of tiles is outlined by four lines rather than four per tile.
+    pub fn collision_lines(&self, color: RGB<u8>) -> Vec<Line> {
+        let solid = |x: i64, y: i64| x >= 0 && y >= 0 && self.is_solid(x as u32, y as u32);
+        let mut lines = vec![];
+
+        // Runs of edges along one row or column: `edge(along, i)` is which side is solid, if any.
+        let mut merge = |count: u32, length: u32, edge: &dyn Fn(u32, u32) -> Option<bool>, line: &dyn Fn(u32, u32, u32) -> Line| {
+            for along in 0..=count {
+                let mut run: Option<(u32, bool)> = None;
+                for i in 0..=length {
+                    let side = if i < length { edge(along, i) } else { None };
+                    if let Some((start, solid_side)) = run {
+                        if side == Some(solid_side) {
+                            continue;
+                        }
+                        lines.push(line(along, start, i));
+                        run = None;
+                    }
+                    if let Some(side) = side {
+                        run = Some((i, side));
+                    }
+                }
+            }
+        };
+
+        let horizontal = |y: u32, x: u32| {
+            let (above, below) = (solid(x as i64, y as i64 - 1), solid(x as i64, y as i64));
+            (above != below).then_some(above)
+        };
+        merge(self.height, self.width, &horizontal, &|y, from, to| Line { from: self.corner(from, y), to: self.corner(to, y), color });
+
+        let vertical = |x: u32, y: u32| {
+            let (left, right) = (solid(x as i64 - 1, y as i64), solid(x as i64, y as i64));
+            (left != right).then_some(left)
+        };
+        merge(self.width, self.height, &vertical, &|x, from, to| Line { from: self.corner(x, from), to: self.corner(x, to), color });
+
+        lines
+    }
+
+    fn chunks_across(&self) -> u32 {
+        self.width.div_ceil(CHUNK_SIZE)
+    }
+
+    fn rebuild_chunk(&mut self, chunk_x: u32, chunk_y: u32) {
+        let mut tiles = vec![];
+        for (index, layer) in self.layers.iter().enumerate() {
+            for y in chunk_y * CHUNK_SIZE..(chunk_y + 1).saturating_mul(CHUNK_SIZE).min(self.height) {
+                for x in chunk_x * CHUNK_SIZE..(chunk_x + 1).saturating_mul(CHUNK_SIZE).min(self.width) {
+                    let id = layer.tiles[self.index(x, y)];
+                    if id != 0 {
+                        tiles.push(ChunkTile { layer: index, x, y, id });
+                    }
+                }
+            }
+        }
+        let index = chunk_y as usize * self.chunks_across() as usize + chunk_x as usize;
+        self.chunks[index] = tiles;
+    }
+
+    fn rebuild_chunks(&mut self) {
+        for chunk_y in 0..self.height.div_ceil(CHUNK_SIZE) {
+            for chunk_x in 0..self.chunks_across() {
+                self.rebuild_chunk(chunk_x, chunk_y);
+            }
+        }
+    }
+
+    // First and last chunk, as (x, y), that the view shows any of.
+    fn visible_chunks(&self, view: &View) -> Option<((u32, u32), (u32, u32))> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let top_left = view.to_world(&Point2d { x: 0., y: 0. });
+        let bottom_right = view.to_world(&view.size);
+
+        let first_x = ((top_left.x - self.origin.x) / self.tile_size.x).floor();
+        let first_y = ((top_left.y - self.origin.y) / self.tile_size.y).floor();
+        let last_x = ((bottom_right.x - self.origin.x) / self.tile_size.x).floor();
+        let last_y = ((bottom_right.y - self.origin.y) / self.tile_size.y).floor();
+        if last_x < 0. || last_y < 0. || first_x >= self.width as f64 || first_y >= self.height as f64 {
+            return None;
+        }
+
+        let clamp = |tile: f64, size: u32| tile.clamp(0., (size - 1) as f64) as u32 / CHUNK_SIZE;
+        Some((
+            (clamp(first_x, self.width), clamp(first_y, self.height)),
+            (clamp(last_x, self.width), clamp(last_y, self.height)),
+        ))
+    }
+
+    fn draw_tile(&self, renderer: &dyn Renderer, view: &View, tile: &ChunkTile, alpha: f64) {
+        let position = view.transform(&self.corner(tile.x, tile.y));
+        let size = Point2d { x: view.scale(self.tile_size.x), y: view.scale(self.tile_size.y) };
+
+        let drawn = self
+            .tileset(tile.id)
+            .is_some_and(|tileset| renderer.tile(&tileset.name, tileset.source(tile.id), tileset.tile_size, position, size, alpha));
+        if !drawn {
+            renderer.rect(position, size, FALLBACK_COLOR, alpha);
+        }
+    }
+
+    // Reads a map saved by Tiled as JSON, with its tilesets embedded and layer data as CSV (the
+    // JSON array), not base64. Tilesets are drawn from the sprite added under the tileset's name.
+    // Tiles with a `solid` property set to true are solid, as are all tiles on layers with a
+    // `collision` property set to true. Object and image layers are skipped; group layers are
+    // flattened into their tile layers, which take on the group's visibility and opacity.
+    pub fn from_tiled(json: &str) -> Result<Self, TilemapError> {
+        let map: TiledMap = serde_json::from_str(json).map_err(|error| TilemapError::Parse(error.to_string()))?;
+        if map.infinite {
+            return Err(TilemapError::Unsupported("infinite maps".to_string()));
+        }
+        if map.orientation != "orthogonal" {
+            return Err(TilemapError::Unsupported(format!("{} orientation", map.orientation)));
+        }
+
+        let mut tilemap = Tilemap::new(map.width, map.height, Point2d { x: map.tilewidth, y: map.tileheight })?;
+        for tileset in map.tilesets {
+            if let Some(source) = tileset.source {
+                return Err(TilemapError::Unsupported(format!("external tileset `{}`, embed it in the map", source)));
+            }
+            let mut set = Tileset::new(
+                &tileset.name,
+                tileset.firstgid,
+                tileset.tilecount,
+                tileset.columns,
+                Point2d { x: tileset.tilewidth, y: tileset.tileheight },
+            );
+            set.margin = tileset.margin;
+            set.spacing = tileset.spacing;
+            set.solid = tileset.tiles.iter().filter(|tile| property(&tile.properties, "solid")).map(|tile| tile.id).collect();
+            tilemap.add_tileset(set);
+        }
+
+        tilemap.add_tiled_layers(map.layers, true, 1.)?;
+        tilemap.rebuild_chunks();
+        Ok(tilemap)
+    }
+
+    fn add_tiled_layers(&mut self, layers: Vec<TiledLayer>, visible: bool, opacity: f64) -> Result<(), TilemapError> {
+        for layer in layers {
+            let (visible, opacity) = (visible && layer.visible, opacity * layer.opacity);
+            match layer.kind.as_str() {
+                "group" => self.add_tiled_layers(layer.layers, visible, opacity)?,
+                "tilelayer" => {
+                    let invalid = |reason: String| TilemapError::InvalidLayer { name: layer.name.clone(), reason };
+                    if layer.encoding.as_deref().is_some_and(|encoding| encoding != "csv") {
+                        return Err(invalid("only CSV layer data is supported".to_string()));
+                    }
+                    let tiles: Vec<u32> = serde_json::from_value(layer.data.clone()).map_err(|error| invalid(error.to_string()))?;
+                    if tiles.len() != self.width as usize * self.height as usize {
+                        return Err(invalid(format!("has {} tiles for a {}x{} map", tiles.len(), self.width, self.height)));
+                    }
+
+                    let index = self.add_layer(&layer.name, property(&layer.properties, "collision"));
+                    let added = &mut self.layers[index];
+                    added.visible = visible;
+                    added.opacity = opacity;
+                    added.tiles = tiles.into_iter().map(|id| id & !TILED_FLAGS).collect();
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Draw for Tilemap {
+    fn draw(&self, renderer: &dyn Renderer, view: &View) {
+        let Some((first, last)) = self.visible_chunks(view) else { return };
+
+        for chunk_y in first.1..=last.1 {
+            for chunk_x in first.0..=last.0 {
+                for tile in self.chunks[chunk_y as usize * self.chunks_across() as usize + chunk_x as usize].iter() {
+                    let layer = &self.layers[tile.layer];
+                    if layer.visible {
+                        self.draw_tile(renderer, view, tile, layer.opacity);
+                    }
+                }
+            }
+        }
+    }
+
+    fn in_view(&self, view: &View) -> bool {
+        self.visible_chunks(view).is_some()
+    }
+}
+
+fn property(properties: &[TiledProperty], name: &str) -> bool {
+    properties.iter().any(|property| property.name == name && property.value == Value::Bool(true))
+}
+
+// The parts of Tiled's JSON map format that are used; the rest is ignored.
+#[derive(Deserialize)]
+struct TiledMap {
+    width: u32,
+    height: u32,
+    tilewidth: f64,
+    tileheight: f64,
+    #[serde(default = "orthogonal")]
+    orientation: String,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    layers: Vec<TiledLayer>,
+    #[serde(default)]
+    tilesets: Vec<TiledTileset>,
+}
+
+fn orthogonal() -> String {
+    "orthogonal".to_string()
+}
+
+fn visible() -> bool {
+    true
+}
+
+fn opaque() -> f64 {
+    1.
+}
+
+#[derive(Deserialize)]
+struct TiledLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    data: Value,
+    #[serde(default)]
+    encoding: Option<String>,
+    #[serde(default = "visible")]
+    visible: bool,
+    #[serde(default = "opaque")]
+    opacity: f64,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+    #[serde(default)]
+    layers: Vec<TiledLayer>,
+}
+
+#[derive(Deserialize)]
+struct TiledTileset {
+    firstgid: u32,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    tilewidth: f64,
+    #[serde(default)]
+    tileheight: f64,
+    #[serde(default)]
+    tilecount: u32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    margin: f64,
+    #[serde(default)]
+    spacing: f64,
+    #[serde(default)]
+    tiles: Vec<TiledTile>,
+}
+
+#[derive(Deserialize)]
+struct TiledTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+struct TiledProperty {
+    name: String,
+    value: Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALL: RGB<u8> = RGB { r: 1, g: 2, b: 3 };
+
+    fn map(width: u32, height: u32, solid: &[(u32, u32)]) -> Tilemap {
+        let mut map = Tilemap::new(width, height, Point2d { x: 10., y: 10. }).unwrap();
+        let layer = map.add_layer("walls", true);
+        for &(x, y) in solid {
+            map.set_tile(layer, x, y, 1);
+        }
+        map
+    }
+
+    fn line(map: &Tilemap, from: (u32, u32), to: (u32, u32)) -> Line {
+        Line { from: map.corner(from.0, from.1), to: map.corner(to.0, to.1), color: WALL }
+    }
+
+    #[test]
+    fn a_block_of_tiles_is_outlined_by_four_lines() {
+        let map = map(4, 4, &[(1, 1), (2, 1), (1, 2), (2, 2)]);
+        assert_eq!(map.collision_lines(WALL), vec![
+            line(&map, (1, 1), (3, 1)),
+            line(&map, (1, 3), (3, 3)),
+            line(&map, (1, 1), (1, 3)),
+            line(&map, (3, 1), (3, 3)),
+        ]);
+    }
+
+    #[test]
+    fn the_map_border_walls_in_solid_tiles_along_it() {
+        let map = map(3, 2, &[(0, 0), (1, 0), (2, 0)]);
+        assert_eq!(map.collision_lines(WALL), vec![
+            line(&map, (0, 0), (3, 0)),
+            line(&map, (0, 1), (3, 1)),
+            line(&map, (0, 0), (0, 1)),
+            line(&map, (3, 0), (3, 1)),
+        ]);
+    }
+
+    #[test]
+    fn edges_only_merge_while_the_solid_side_stays_the_same() {
+        // Diagonal neighbours share the line at y = 1, but on opposite sides of it.
+        let map = map(2, 2, &[(0, 0), (1, 1)]);
+        let lines = map.collision_lines(WALL);
+        assert_eq!(lines.len(), 8);
+        assert!(lines.contains(&line(&map, (0, 1), (1, 1))));
+        assert!(lines.contains(&line(&map, (1, 1), (2, 1))));
+    }
+
+    #[test]
+    fn maps_with_more_tiles_than_memory_are_errors() {
+        let error = Tilemap::new(u32::MAX, u32::MAX, Point2d { x: 1., y: 1. }).unwrap_err();
+        assert_eq!(error, TilemapError::TooLarge { width: u32::MAX, height: u32::MAX });
+    }
+
+    const TILED: &str = r#"{
+        "width": 4, "height": 2, "tilewidth": 16, "tileheight": 16,
+        "tilesets": [{
+            "firstgid": 1, "name": "tiles", "tilewidth": 16, "tileheight": 16, "tilecount": 4, "columns": 2,
+            "margin": 1, "spacing": 2,
+            "tiles": [{ "id": 1, "properties": [{ "name": "solid", "type": "bool", "value": true }] }]
+        }],
+        "layers": [
+            { "type": "tilelayer", "name": "ground", "data": [1, 1, 1, 1, 1, 2, 1, 1] },
+            { "type": "group", "name": "upper", "opacity": 0.5, "layers": [
+                { "type": "tilelayer", "name": "rocks", "opacity": 0.5, "data": [0, 0, 0, 2147483651, 268435459, 0, 0, 0],
+                  "properties": [{ "name": "collision", "type": "bool", "value": true }] },
+                { "type": "group", "name": "hidden", "visible": false, "layers": [
+                    { "type": "tilelayer", "name": "secrets", "data": [0, 0, 0, 0, 0, 0, 0, 4] }
+                ] }
+            ] },
+            { "type": "objectgroup", "name": "spawns", "objects": [] }
+        ]
+    }"#;
+
+    #[test]
+    fn tiled_maps_keep_their_tilesets_layers_and_solid_tiles() {
+        let map = Tilemap::from_tiled(TILED).unwrap();
+        assert_eq!((map.width, map.height, map.tile_size), (4, 2, Point2d { x: 16., y: 16. }));
+
+        let tileset = &map.tilesets()[0];
+        assert_eq!(tileset.solid, HashSet::from([1]));
+        assert_eq!(tileset.source(4), Point2d { x: 19., y: 19. });
+
+        let layers: Vec<(&str, bool, f64)> = map.layers().iter().map(|layer| (layer.name.as_str(), layer.visible, layer.opacity)).collect();
+        assert_eq!(layers, vec![("ground", true, 1.), ("rocks", true, 0.25), ("secrets", false, 0.5)]);
+
+        // Flip and rotation flags are dropped.
+        assert_eq!(map.tile(1, 3, 0), 3);
+        assert_eq!(map.tile(1, 0, 1), 3);
+
+        let solid: Vec<(u32, u32)> = (0..2).flat_map(|y| (0..4).map(move |x| (x, y))).filter(|&(x, y)| map.is_solid(x, y)).collect();
+        assert_eq!(solid, vec![(3, 0), (0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn unsupported_tiled_maps_are_errors() {
+        let with = |from: &str, to: &str| Tilemap::from_tiled(&TILED.replacen(from, to, 1));
+
+        assert!(matches!(with(r#""width": 4"#, r#""width": 4, "infinite": true"#), Err(TilemapError::Unsupported(_))));
+        assert!(matches!(with(r#""width": 4"#, r#""width": 4, "orientation": "isometric""#), Err(TilemapError::Unsupported(_))));
+        assert!(matches!(with(r#""firstgid": 1"#, r#""firstgid": 1, "source": "tiles.tsx""#), Err(TilemapError::Unsupported(_))));
+        assert_eq!(
+            with("[1, 1, 1, 1, 1, 2, 1, 1]", "[1, 1]"),
+            Err(TilemapError::InvalidLayer { name: "ground".to_string(), reason: "has 2 tiles for a 4x2 map".to_string() }),
+        );
+        assert!(matches!(with(r#""name": "ground","#, r#""name": "ground", "encoding": "base64","#), Err(TilemapError::InvalidLayer { .. })));
+        assert!(matches!(Tilemap::from_tiled("{"), Err(TilemapError::Parse(_))));
+    }
+}
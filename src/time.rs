@@ -0,0 +1,74 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use crate::snapshot::SnapshotError;
+
+// Longest step a single frame may take, so a backgrounded tab doesn't resume with a huge jump.
+pub const MAX_FRAME_DELTA: f64 = 250.;
+
+// Simulated time, advanced only by frame deltas so that a run can be replayed exactly.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EngineTime {
+    pub frame: u64,
+    // Milliseconds since the engine started or was last reset.
+    pub elapsed: f64,
+    pub delta: f64,
+}
+
+impl EngineTime {
+    pub fn advance(self, delta: f64) -> Self {
+        Self { frame: self.frame + 1, elapsed: self.elapsed + delta, delta }
+    }
+
+    // As a timestamp, for the particle and light code that works in chrono types. Restoring checks
+    // the elapsed time, and frames can't add up to anything out of range.
+    pub fn instant(&self) -> DateTime<Utc> {
+        from_millis(self.elapsed).unwrap_or_else(|_| epoch())
+    }
+}
+
+pub fn epoch() -> DateTime<Utc> {
+    Utc.timestamp_opt(0, 0).unwrap()
+}
+
+// Engine milliseconds to and from the chrono timestamps they are carried in, to the microsecond.
+// Times too far out for a timestamp, or not numbers at all, can only come from a broken snapshot.
+pub fn from_millis(milliseconds: f64) -> Result<DateTime<Utc>, SnapshotError> {
+    let microseconds = (milliseconds * 1000.).round();
+    if !microseconds.is_finite() || microseconds.abs() >= i64::MAX as f64 {
+        return Err(SnapshotError::InvalidTime(milliseconds));
+    }
+
+    epoch()
+        .checked_add_signed(Duration::microseconds(microseconds as i64))
+        .ok_or(SnapshotError::InvalidTime(milliseconds))
+}
+
+pub fn to_millis(instant: DateTime<Utc>) -> f64 {
+    (instant - epoch()).num_microseconds().unwrap_or(0) as f64 / 1000.
+}
+
+// Deltas are kept to whole microseconds so a recorded run stores them exactly.
+pub fn quantize_delta(delta: f64) -> f64 {
+    (delta.clamp(0., MAX_FRAME_DELTA) * 1000.).round() / 1000.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn milliseconds_round_trip_to_the_microsecond() {
+        for milliseconds in [0., 16.667, 1234.5678, -250.25, 86_400_000.] {
+            let expected = (milliseconds * 1000_f64).round() / 1000.;
+            assert_eq!(to_millis(from_millis(milliseconds).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn times_no_timestamp_can_hold_are_errors() {
+        for milliseconds in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e20, -1e20] {
+            assert!(matches!(from_millis(milliseconds), Err(SnapshotError::InvalidTime(_))), "{}", milliseconds);
+        }
+        assert_eq!(EngineTime { elapsed: f64::NAN, ..EngineTime::default() }.instant(), epoch());
+    }
+}
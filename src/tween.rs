@@ -0,0 +1,608 @@
+use std::f64::consts::PI;
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+use crate::game_engine::GameEngine;
+use crate::shapes::Point2d;
+
+// Easing curves map linear progress in [0, 1] to eased progress. Most start at 0 and end at 1;
+// elastic and back overshoot in between.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Easing {
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    ElasticIn,
+    ElasticOut,
+    ElasticInOut,
+    BounceIn,
+    BounceOut,
+    BounceInOut,
+    BackIn,
+    BackOut,
+    BackInOut,
+    // Control points of a CSS-style `cubic-bezier(x1, y1, x2, y2)`.
+    CubicBezier(f64, f64, f64, f64),
+}
+
+const BACK: f64 = 1.70158;
+
+fn bounce_out(t: f64) -> f64 {
+    const N: f64 = 7.5625;
+    const D: f64 = 2.75;
+
+    if t < 1. / D {
+        N * t * t
+    } else if t < 2. / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+
+// Finds the curve parameter whose x is `x` (Newton's method, then bisection if that stalls) and
+// returns the y there.
+fn cubic_bezier(x1: f64, y1: f64, x2: f64, y2: f64, x: f64) -> f64 {
+    let curve = |a: f64, b: f64, t: f64| 3. * a * (1. - t) * (1. - t) * t + 3. * b * (1. - t) * t * t + t * t * t;
+    let slope = |a: f64, b: f64, t: f64| 3. * a * (1. - t) * (1. - t) + 6. * (b - a) * (1. - t) * t + 3. * (1. - b) * t * t;
+
+    let mut t = x;
+    for _ in 0..8 {
+        let error = curve(x1, x2, t) - x;
+        if error.abs() < 1e-7 {
+            return curve(y1, y2, t);
+        }
+        let derivative = slope(x1, x2, t);
+        if derivative.abs() < 1e-6 {
+            break;
+        }
+        t -= error / derivative;
+    }
+
+    let (mut low, mut high) = (0., 1.);
+    t = x;
+    for _ in 0..40 {
+        let value = curve(x1, x2, t);
+        if (value - x).abs() < 1e-7 {
+            break;
+        }
+        if value < x {
+            low = t;
+        } else {
+            high = t;
+        }
+        t = (low + high) / 2.;
+    }
+
+    curve(y1, y2, t)
+}
+
+impl Easing {
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0., 1.);
+
+        match self {
+            Easing::Linear => t,
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1. - (1. - t) * (1. - t),
+            Easing::QuadInOut => if t < 0.5 { 2. * t * t } else { 1. - (-2. * t + 2.).powi(2) / 2. },
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => 1. - (1. - t).powi(3),
+            Easing::CubicInOut => if t < 0.5 { 4. * t * t * t } else { 1. - (-2. * t + 2.).powi(3) / 2. },
+            Easing::ElasticIn | Easing::ElasticOut | Easing::ElasticInOut if t == 0. || t == 1. => t,
+            Easing::ElasticIn => -(2f64.powf(10. * t - 10.)) * ((t * 10. - 10.75) * (2. * PI / 3.)).sin(),
+            Easing::ElasticOut => 2f64.powf(-10. * t) * ((t * 10. - 0.75) * (2. * PI / 3.)).sin() + 1.,
+            Easing::ElasticInOut => {
+                let wave = ((20. * t - 11.125) * (2. * PI / 4.5)).sin();
+                if t < 0.5 {
+                    -(2f64.powf(20. * t - 10.) * wave) / 2.
+                } else {
+                    2f64.powf(-20. * t + 10.) * wave / 2. + 1.
+                }
+            }
+            Easing::BounceIn => 1. - bounce_out(1. - t),
+            Easing::BounceOut => bounce_out(t),
+            Easing::BounceInOut => if t < 0.5 { (1. - bounce_out(1. - 2. * t)) / 2. } else { (1. + bounce_out(2. * t - 1.)) / 2. },
+            Easing::BackIn => (BACK + 1.) * t * t * t - BACK * t * t,
+            Easing::BackOut => 1. + (BACK + 1.) * (t - 1.).powi(3) + BACK * (t - 1.).powi(2),
+            Easing::BackInOut => {
+                let c = BACK * 1.525;
+                if t < 0.5 {
+                    (2. * t).powi(2) * ((c + 1.) * 2. * t - c) / 2.
+                } else {
+                    ((2. * t - 2.).powi(2) * ((c + 1.) * (t * 2. - 2.) + c) + 2.) / 2.
+                }
+            }
+            Easing::CubicBezier(x1, y1, x2, y2) => cubic_bezier(x1.clamp(0., 1.), y1, x2.clamp(0., 1.), y2, t),
+        }
+    }
+}
+
+// Values a tween can interpolate between.
+pub trait Lerp: Copy {
+    fn lerp(from: Self, to: Self, t: f64) -> Self;
+}
+
+impl Lerp for f64 {
+    fn lerp(from: Self, to: Self, t: f64) -> Self {
+        from + (to - from) * t
+    }
+}
+
+impl Lerp for Point2d {
+    fn lerp(from: Self, to: Self, t: f64) -> Self {
+        Point2d { x: f64::lerp(from.x, to.x, t), y: f64::lerp(from.y, to.y, t) }
+    }
+}
+
+impl Lerp for RGB8 {
+    fn lerp(from: Self, to: Self, t: f64) -> Self {
+        let channel = |from: u8, to: u8| f64::lerp(from as f64, to as f64, t).round().clamp(0., 255.) as u8;
+        RGB8 { r: channel(from.r, to.r), g: channel(from.g, to.g), b: channel(from.b, to.b) }
+    }
+}
+
+// Something that plays out over engine time. `advance` returns `None` while running, and once
+// finished the part of `delta` it didn't need, so sequences hand the remainder to the next step.
+pub trait Animation {
+    fn advance(&mut self, engine: &GameEngine, delta: f64) -> Option<f64>;
+}
+
+type Setter<T> = Box<dyn FnMut(&GameEngine, T)>;
+type Callback = Box<dyn FnOnce(&GameEngine)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repeat {
+    Times(u32),
+    Forever,
+}
+
+// Drives one value from `from` to `to` over `duration` milliseconds, handing each step to `setter`.
+pub struct Tween<T: Lerp> {
+    from: T,
+    to: T,
+    duration: f64,
+    easing: Easing,
+    delay: f64,
+    yoyo: bool,
+    repeat: Repeat,
+    elapsed: f64,
+    setter: Setter<T>,
+    on_complete: Option<Callback>,
+}
+
+#[allow(dead_code)]
+impl<T: Lerp> Tween<T> {
+    pub fn new<F: FnMut(&GameEngine, T) + 'static>(from: T, to: T, duration: f64, setter: F) -> Self {
+        Self {
+            from,
+            to,
+            duration: duration.max(0.),
+            easing: Easing::Linear,
+            delay: 0.,
+            yoyo: false,
+            repeat: Repeat::Times(0),
+            elapsed: 0.,
+            setter: Box::new(setter),
+            on_complete: None,
+        }
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    pub fn with_delay(mut self, delay: f64) -> Self {
+        self.delay = delay.max(0.);
+        self
+    }
+
+    // Every other repetition plays backwards.
+    pub fn yoyo(mut self) -> Self {
+        self.yoyo = true;
+        self
+    }
+
+    // Plays `times` more times after the first.
+    pub fn repeat(mut self, times: u32) -> Self {
+        self.repeat = Repeat::Times(times);
+        self
+    }
+
+    pub fn repeat_forever(mut self) -> Self {
+        self.repeat = Repeat::Forever;
+        self
+    }
+
+    pub fn on_complete<F: FnOnce(&GameEngine) + 'static>(mut self, callback: F) -> Self {
+        self.on_complete = Some(Box::new(callback));
+        self
+    }
+
+    fn value(&self, cycle: u64, progress: f64) -> T {
+        let progress = if self.yoyo && cycle % 2 == 1 { 1. - progress } else { progress };
+        T::lerp(self.from, self.to, self.easing.apply(progress))
+    }
+}
+
+impl<T: Lerp> Animation for Tween<T> {
+    fn advance(&mut self, engine: &GameEngine, delta: f64) -> Option<f64> {
+        self.elapsed += delta;
+        let active = self.elapsed - self.delay;
+        if active < 0. {
+            return None;
+        }
+
+        let cycles = match self.repeat {
+            Repeat::Times(times) => Some(times as u64 + 1),
+            Repeat::Forever => None,
+        };
+
+        let total = cycles.map(|cycles| cycles as f64 * self.duration);
+        if let (Some(cycles), Some(total)) = (cycles, total) {
+            if active >= total {
+                let end = self.value(cycles - 1, 1.);
+                (self.setter)(engine, end);
+                if let Some(callback) = self.on_complete.take() {
+                    callback(engine);
+                }
+                return Some(active - total);
+            }
+        }
+
+        if self.duration <= 0. {
+            return None;
+        }
+
+        let cycle = (active / self.duration).floor();
+        let value = self.value(cycle as u64, active / self.duration - cycle);
+        (self.setter)(engine, value);
+        None
+    }
+}
+
+// Waits, for spacing out the steps of a sequence.
+pub struct Delay {
+    remaining: f64,
+}
+
+#[allow(dead_code)]
+impl Delay {
+    pub fn new(duration: f64) -> Self {
+        Self { remaining: duration.max(0.) }
+    }
+}
+
+impl Animation for Delay {
+    fn advance(&mut self, _engine: &GameEngine, delta: f64) -> Option<f64> {
+        self.remaining -= delta;
+        if self.remaining <= 0. {
+            Some(-self.remaining)
+        } else {
+            None
+        }
+    }
+}
+
+// Runs a callback once, as a step in a sequence.
+pub struct Call {
+    callback: Option<Callback>,
+}
+
+#[allow(dead_code)]
+impl Call {
+    pub fn new<F: FnOnce(&GameEngine) + 'static>(callback: F) -> Self {
+        Self { callback: Some(Box::new(callback)) }
+    }
+}
+
+impl Animation for Call {
+    fn advance(&mut self, engine: &GameEngine, delta: f64) -> Option<f64> {
+        if let Some(callback) = self.callback.take() {
+            callback(engine);
+        }
+        Some(delta)
+    }
+}
+
+// Plays its steps one after another.
+#[derive(Default)]
+pub struct Sequence {
+    steps: Vec<Box<dyn Animation>>,
+    current: usize,
+}
+
+#[allow(dead_code)]
+impl Sequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then<A: Animation + 'static>(mut self, step: A) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+}
+
+impl Animation for Sequence {
+    fn advance(&mut self, engine: &GameEngine, delta: f64) -> Option<f64> {
+        let mut remaining = delta;
+        while let Some(step) = self.steps.get_mut(self.current) {
+            remaining = step.advance(engine, remaining)?;
+            self.current += 1;
+        }
+        Some(remaining)
+    }
+}
+
+// Plays its parts together; done when the longest one is.
+#[derive(Default)]
+pub struct Parallel {
+    parts: Vec<(Box<dyn Animation>, Option<f64>)>,
+}
+
+#[allow(dead_code)]
+impl Parallel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<A: Animation + 'static>(mut self, part: A) -> Self {
+        self.parts.push((Box::new(part), None));
+        self
+    }
+}
+
+impl Animation for Parallel {
+    fn advance(&mut self, engine: &GameEngine, delta: f64) -> Option<f64> {
+        for (part, leftover) in self.parts.iter_mut() {
+            match leftover {
+                Some(leftover) => *leftover += delta,
+                None => *leftover = part.advance(engine, delta),
+            }
+        }
+
+        let mut shortest: Option<f64> = Some(delta);
+        for (_, leftover) in self.parts.iter() {
+            shortest = match (shortest, leftover) {
+                (Some(shortest), Some(leftover)) => Some(shortest.min(*leftover)),
+                _ => None,
+            };
+        }
+        shortest
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnimationId(u64);
+
+// The engine's running animations. Animations may start or cancel others from their callbacks,
+// so the list is taken out while advancing and merged back afterwards.
+#[derive(Default)]
+pub struct Animations {
+    running: Vec<(AnimationId, Box<dyn Animation>)>,
+    cancelled: Vec<AnimationId>,
+    cleared: bool,
+    next_id: u64,
+}
+
+impl Animations {
+    pub fn add(&mut self, animation: Box<dyn Animation>) -> AnimationId {
+        let id = AnimationId(self.next_id);
+        self.next_id += 1;
+        self.running.push((id, animation));
+        id
+    }
+
+    pub fn cancel(&mut self, id: AnimationId) {
+        self.running.retain(|(running, _)| *running != id);
+        self.cancelled.push(id);
+    }
+
+    pub fn clear(&mut self) {
+        self.running.clear();
+        self.cleared = true;
+    }
+
+    pub fn take(&mut self) -> Vec<(AnimationId, Box<dyn Animation>)> {
+        self.cancelled.clear();
+        self.cleared = false;
+        std::mem::take(&mut self.running)
+    }
+
+    // Puts back what is still running, ahead of anything started meanwhile.
+    pub fn merge(&mut self, mut running: Vec<(AnimationId, Box<dyn Animation>)>) {
+        if self.cleared {
+            running.clear();
+        }
+        running.retain(|(id, _)| !self.cancelled.contains(id));
+        running.append(&mut self.running);
+        self.running = running;
+        self.cancelled.clear();
+        self.cleared = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::config::EngineConfig;
+
+    const ALL: [Easing; 17] = [
+        Easing::Linear,
+        Easing::QuadIn,
+        Easing::QuadOut,
+        Easing::QuadInOut,
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::ElasticIn,
+        Easing::ElasticOut,
+        Easing::ElasticInOut,
+        Easing::BounceIn,
+        Easing::BounceOut,
+        Easing::BounceInOut,
+        Easing::BackIn,
+        Easing::BackOut,
+        Easing::BackInOut,
+        Easing::CubicBezier(0.25, 0.1, 0.25, 1.),
+    ];
+
+    fn engine() -> GameEngine {
+        GameEngine::headless(EngineConfig { seed: Some(1), ..EngineConfig::default() }, Point2d { x: 640., y: 480. })
+    }
+
+    // A tween from 0 to 100 that notes down every value it sets.
+    fn recorded(duration: f64) -> (Tween<f64>, Rc<RefCell<Vec<f64>>>) {
+        let values = Rc::new(RefCell::new(vec![]));
+        let log = values.clone();
+        (Tween::new(0., 100., duration, move |_: &GameEngine, value| log.borrow_mut().push(value)), values)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn every_easing_starts_at_0_and_ends_at_1() {
+        for easing in ALL {
+            assert!(close(easing.apply(0.), 0.), "{:?}", easing);
+            assert!(close(easing.apply(1.), 1.), "{:?}", easing);
+            assert!(close(easing.apply(-1.), 0.) && close(easing.apply(2.), 1.), "{:?} clamps", easing);
+        }
+    }
+
+    #[test]
+    fn easings_have_their_shapes() {
+        assert!(close(Easing::QuadIn.apply(0.5), 0.25));
+        assert!(close(Easing::QuadOut.apply(0.5), 0.75));
+        assert!(close(Easing::CubicIn.apply(0.5), 0.125));
+        for easing in [Easing::QuadInOut, Easing::CubicInOut, Easing::ElasticInOut, Easing::BounceInOut, Easing::BackInOut] {
+            assert!(close(easing.apply(0.5), 0.5), "{:?}", easing);
+            assert!(close(easing.apply(0.3), 1. - easing.apply(0.7)), "{:?} is symmetric", easing);
+        }
+
+        assert!(Easing::BackIn.apply(0.2) < 0., "back pulls away first");
+        assert!(Easing::BackOut.apply(0.8) > 1., "and overshoots at the end");
+        assert!((0..=100).map(|step| Easing::ElasticOut.apply(step as f64 / 100.)).any(|value| value > 1.));
+        for step in 0..=100 {
+            let t = step as f64 / 100.;
+            assert!((0. ..=1.).contains(&Easing::BounceOut.apply(t)), "bounces stay in range");
+        }
+    }
+
+    #[test]
+    fn cubic_beziers_match_css() {
+        for step in 0..=20 {
+            let t = step as f64 / 20.;
+            assert!(close(Easing::CubicBezier(0., 0., 1., 1.).apply(t), t));
+        }
+        // CSS `ease` halfway through.
+        assert!((Easing::CubicBezier(0.25, 0.1, 0.25, 1.).apply(0.5) - 0.8024).abs() < 1e-3);
+        // Flat spots where Newton's method stalls fall back to bisection.
+        let steep = Easing::CubicBezier(1., 0., 0., 1.);
+        assert!(close(steep.apply(0.5), 0.5));
+        assert!(steep.apply(0.25) < 0.1);
+    }
+
+    #[test]
+    fn a_tween_ends_on_its_target_and_hands_back_the_rest() {
+        let engine = engine();
+        let completed = Rc::new(Cell::new(0));
+        let counter = completed.clone();
+        let (tween, values) = recorded(100.);
+        let mut tween = tween.with_delay(20.).on_complete(move |_| counter.set(counter.get() + 1));
+
+        assert_eq!(tween.advance(&engine, 10.), None);
+        assert!(values.borrow().is_empty(), "nothing moves during the delay");
+        assert_eq!(tween.advance(&engine, 60.), None);
+        assert_eq!(tween.advance(&engine, 100.), Some(50.));
+        assert_eq!(*values.borrow(), [50., 100.]);
+        assert_eq!(completed.get(), 1);
+    }
+
+    #[test]
+    fn repeats_and_yoyos_play_back_and_forth() {
+        let engine = engine();
+        let (tween, values) = recorded(100.);
+        let mut tween = tween.yoyo().repeat(2);
+
+        let steps: Vec<Option<f64>> = [25., 100., 100., 100.].iter().map(|&delta| tween.advance(&engine, delta)).collect();
+        assert_eq!(steps, [None, None, None, Some(25.)]);
+        assert_eq!(*values.borrow(), [25., 75., 25., 100.], "odd cycles run backwards, and three cycles end forwards");
+
+        let (tween, values) = recorded(100.);
+        let mut tween = tween.yoyo().repeat(1);
+        assert_eq!(tween.advance(&engine, 250.), Some(50.));
+        assert_eq!(*values.borrow(), [0.], "two cycles end back at the start");
+    }
+
+    #[test]
+    fn forever_never_finishes_and_instant_tweens_finish_at_once() {
+        let engine = engine();
+        let (tween, values) = recorded(100.);
+        let mut forever = tween.repeat_forever();
+        for _ in 0..100 {
+            assert_eq!(forever.advance(&engine, 30.), None);
+        }
+        assert_eq!(values.borrow().last().copied(), Some(0.), "3000 ms is a whole number of cycles");
+
+        let (tween, values) = recorded(0.);
+        let mut instant = tween;
+        assert_eq!(instant.advance(&engine, 5.), Some(5.));
+        assert_eq!(*values.borrow(), [100.]);
+    }
+
+    #[test]
+    fn sequences_pass_time_on_and_parallels_wait_for_the_longest() {
+        let engine = engine();
+        let (first, first_values) = recorded(50.);
+        let (second, second_values) = recorded(100.);
+        let mut sequence = Sequence::new().then(first).then(Delay::new(10.)).then(second);
+
+        assert_eq!(sequence.advance(&engine, 80.), None);
+        assert_eq!(*first_values.borrow(), [100.]);
+        assert_eq!(*second_values.borrow(), [20.], "the 20 ms after the delay went on to the next step");
+        assert_eq!(sequence.advance(&engine, 90.), Some(10.));
+
+        let (short, _) = recorded(50.);
+        let (long, _) = recorded(100.);
+        let mut parallel = Parallel::new().with(short).with(long);
+        assert_eq!(parallel.advance(&engine, 60.), None);
+        assert_eq!(parallel.advance(&engine, 60.), Some(20.));
+    }
+
+    #[test]
+    fn animations_cancelled_or_cleared_while_running_stay_stopped() {
+        let mut animations = Animations::default();
+        let first = animations.add(Box::new(Delay::new(10.)));
+        animations.add(Box::new(Delay::new(10.)));
+
+        let running = animations.take();
+        animations.cancel(first);
+        let started = animations.add(Box::new(Delay::new(10.)));
+        animations.merge(running);
+        let ids: Vec<AnimationId> = animations.take().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [AnimationId(1), started]);
+
+        animations.add(Box::new(Delay::new(10.)));
+        let running = animations.take();
+        animations.clear();
+        animations.merge(running);
+        assert!(animations.take().is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use js_sys::Uint8Array;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{AudioBuffer, AudioBufferSourceNode, AudioContext, AudioContextState, GainNode, StereoPannerNode};
+
+use crate::audio::{AudioBackend, VoiceId};
+use crate::browser::Browser;
+use crate::error::{describe, EngineError};
+
+struct WebVoice {
+    source: AudioBufferSourceNode,
+    gain: GainNode,
+    panner: StereoPannerNode,
+    // Kept alive until the voice is forgotten; reports the end of the sound.
+    _on_ended: Closure<dyn FnMut()>,
+}
+
+// Every voice is a buffer source through its own gain and stereo panner into the speakers.
+pub struct WebAudio {
+    context: AudioContext,
+    sounds: Rc<RefCell<HashMap<String, AudioBuffer>>>,
+    voices: RefCell<HashMap<VoiceId, WebVoice>>,
+    finished: Rc<RefCell<Vec<VoiceId>>>,
+}
+
+impl WebAudio {
+    pub fn new() -> Result<Self, EngineError> {
+        let context = AudioContext::new().map_err(|error| EngineError::Audio(describe(&error)))?;
+        Ok(Self {
+            context,
+            sounds: Rc::new(RefCell::new(HashMap::new())),
+            voices: RefCell::new(HashMap::new()),
+            finished: Rc::new(RefCell::new(vec![])),
+        })
+    }
+
+    fn voice(&self, id: VoiceId, buffer: &AudioBuffer, looping: bool) -> Result<WebVoice, JsValue> {
+        let source = self.context.create_buffer_source()?;
+        source.set_buffer(Some(buffer));
+        source.set_loop(looping);
+
+        let gain = self.context.create_gain()?;
+        let panner = self.context.create_stereo_panner()?;
+        source.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&panner)?;
+        panner.connect_with_audio_node(&self.context.destination())?;
+
+        let finished = self.finished.clone();
+        let on_ended = Closure::<dyn FnMut()>::new(move || finished.borrow_mut().push(id));
+        source.set_onended(Some(on_ended.as_ref().unchecked_ref()));
+
+        Ok(WebVoice { source, gain, panner, _on_ended: on_ended })
+    }
+}
+
+// Browsers only allow a handful of live contexts per page, so each engine gives its own back.
+impl Drop for WebAudio {
+    fn drop(&mut self) {
+        for (_, voice) in self.voices.borrow_mut().drain() {
+            voice.source.set_onended(None);
+        }
+        self.context.close().ok();
+    }
+}
+
+impl AudioBackend for WebAudio {
+    fn has_sound(&self, name: &str) -> bool {
+        self.sounds.borrow().contains_key(name)
+    }
+
+    fn start(&self, voice: VoiceId, sound: &str, looping: bool, gain: f64, pan: f64) -> bool {
+        // Browsers keep a new context suspended until the page has been interacted with.
+        if self.context.state() == AudioContextState::Suspended {
+            self.context.resume().ok();
+        }
+
+        let Some(buffer) = self.sounds.borrow().get(sound).cloned() else { return false };
+        let started = self.voice(voice, &buffer, looping).and_then(|web_voice| {
+            web_voice.source.start()?;
+            Ok(web_voice)
+        });
+
+        match started {
+            Ok(web_voice) => {
+                self.voices.borrow_mut().insert(voice, web_voice);
+                self.set(voice, gain, pan);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn set(&self, voice: VoiceId, gain: f64, pan: f64) {
+        if let Some(web_voice) = self.voices.borrow().get(&voice) {
+            web_voice.gain.gain().set_value(gain as f32);
+            web_voice.panner.pan().set_value(pan as f32);
+        }
+    }
+
+    fn stop(&self, voice: VoiceId) {
+        if let Some(web_voice) = self.voices.borrow_mut().remove(&voice) {
+            web_voice.source.set_onended(None);
+            web_voice.source.stop().ok();
+        }
+    }
+
+    fn take_finished(&self) -> Vec<VoiceId> {
+        let finished = std::mem::take(&mut *self.finished.borrow_mut());
+        let mut voices = self.voices.borrow_mut();
+        for id in finished.iter() {
+            voices.remove(id);
+        }
+        finished
+    }
+
+    fn load(&self, name: &str, data: &[u8], done: Box<dyn FnOnce(Result<(), String>)>) {
+        // Decoding takes ownership of the buffer, so it gets a copy of its own.
+        let buffer = Uint8Array::from(data).buffer();
+        let decoding = match self.context.decode_audio_data(&buffer) {
+            Ok(decoding) => decoding,
+            Err(error) => return done(Err(describe(&error))),
+        };
+
+        let sounds = self.sounds.clone();
+        let name = name.to_string();
+        Browser::settle(decoding, move |result| {
+            done(match result.map(|buffer| buffer.dyn_into::<AudioBuffer>()) {
+                Ok(Ok(buffer)) => {
+                    sounds.borrow_mut().insert(name, buffer);
+                    Ok(())
+                }
+                Ok(Err(_)) => Err("decoding gave no audio".to_string()),
+                Err(error) => Err(describe(&error)),
+            })
+        });
+    }
+
+    fn unload(&self, name: &str) {
+        self.sounds.borrow_mut().remove(name);
+    }
+}
@@ -0,0 +1,90 @@
+use js_sys::{Array, ArrayBuffer, Uint8Array};
+use wasm_bindgen::JsCast;
+use web_sys::{Blob, FontFace, HtmlImageElement, Response, Url};
+
+use crate::assets::{AssetError, AssetLoader, Fetched};
+use crate::browser::Browser;
+use crate::error::describe;
+
+// Loads assets over HTTP with `fetch`, relative to the page unless `base` says otherwise.
+pub struct FetchLoader {
+    base: String,
+}
+
+impl FetchLoader {
+    pub fn new(base: &str) -> Self {
+        Self { base: base.to_string() }
+    }
+}
+
+impl AssetLoader for FetchLoader {
+    fn fetch(&self, path: &str, done: Fetched) {
+        let window = match Browser::window() {
+            Ok(window) => window,
+            Err(error) => return done(Err(AssetError::Fetch { path: path.to_string(), reason: error.to_string() })),
+        };
+
+        let path = path.to_string();
+        let failed = |path: &str, reason: String| Err(AssetError::Fetch { path: path.to_string(), reason });
+
+        Browser::settle(window.fetch_with_str(&format!("{}{}", self.base, path)), move |result| {
+            let response = match result.map(|response| response.dyn_into::<Response>()) {
+                Ok(Ok(response)) => response,
+                Ok(Err(_)) => return done(failed(&path, "not a response".to_string())),
+                Err(error) => return done(failed(&path, describe(&error))),
+            };
+            if !response.ok() {
+                return done(failed(&path, format!("HTTP {}", response.status())));
+            }
+
+            let body = match response.array_buffer() {
+                Ok(body) => body,
+                Err(error) => return done(failed(&path, describe(&error))),
+            };
+            Browser::settle(body, move |result| {
+                done(match result {
+                    Ok(buffer) => Ok(Uint8Array::new(&buffer).to_vec()),
+                    Err(error) => failed(&path, describe(&error)),
+                })
+            });
+        });
+    }
+
+    fn decode_image(&self, _name: &str, data: &[u8], done: Box<dyn FnOnce(Result<Option<HtmlImageElement>, String>)>) {
+        let parts = Array::of1(&Uint8Array::from(data));
+        let url = match Blob::new_with_u8_array_sequence(&parts).and_then(|blob| Url::create_object_url_with_blob(&blob)) {
+            Ok(url) => url,
+            Err(error) => return done(Err(describe(&error))),
+        };
+        let image = match HtmlImageElement::new() {
+            Ok(image) => image,
+            Err(error) => return done(Err(describe(&error))),
+        };
+        image.set_src(&url);
+
+        let decoded = image.clone();
+        Browser::settle(image.decode(), move |result| {
+            Url::revoke_object_url(&url).ok();
+            done(result.map(|_| Some(decoded)).map_err(|error| describe(&error)));
+        });
+    }
+
+    fn add_font(&self, family: &str, data: &[u8], done: Box<dyn FnOnce(Result<(), String>)>) {
+        let buffer: ArrayBuffer = Uint8Array::from(data).buffer();
+        let face = match FontFace::new_with_array_buffer(family, &buffer) {
+            Ok(face) => face,
+            Err(error) => return done(Err(describe(&error))),
+        };
+        let loading = match face.load() {
+            Ok(loading) => loading,
+            Err(error) => return done(Err(describe(&error))),
+        };
+
+        Browser::settle(loading, move |result| {
+            done(result.map_err(|error| describe(&error)).and_then(|_| {
+                let document = Browser::document().map_err(|error| error.to_string())?;
+                document.fonts().add(&face).map_err(|error| describe(&error))
+            }));
+        });
+    }
+}
@@ -0,0 +1,104 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use js_sys::{ArrayBuffer, Uint8Array};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+
+use crate::error::{describe, EngineError};
+use crate::network::{Connection, Transport};
+
+// A browser WebSocket carrying binary messages. Messages arrive between frames and wait in
+// `received` until the session polls for them.
+pub struct WebSocketTransport {
+    socket: WebSocket,
+    received: Rc<RefCell<Vec<Vec<u8>>>>,
+    failure: Rc<RefCell<Option<String>>>,
+    // Set once this side closes, so the close that follows isn't reported as a failure.
+    closing: Rc<Cell<bool>>,
+    // Kept alive as long as the socket can call them.
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+    _on_error: Closure<dyn FnMut(Event)>,
+}
+
+impl WebSocketTransport {
+    pub fn open(url: &str) -> Result<Self, EngineError> {
+        let socket = WebSocket::new(url).map_err(|error| EngineError::Network(describe(&error)))?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+
+        let received = Rc::new(RefCell::new(vec![]));
+        let inbox = received.clone();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            if let Ok(buffer) = event.data().dyn_into::<ArrayBuffer>() {
+                inbox.borrow_mut().push(Uint8Array::new(&buffer).to_vec());
+            }
+        });
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let failure: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+        let closing = Rc::new(Cell::new(false));
+
+        // Browsers don't say why a WebSocket failed; the close event that follows has the code.
+        let (failed, closed) = (failure.clone(), closing.clone());
+        let on_error = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+            if !closed.get() {
+                failed.borrow_mut().get_or_insert_with(|| "could not reach the server".to_string());
+            }
+        });
+        socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+        let (failed, closed) = (failure.clone(), closing.clone());
+        let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+            if closed.get() {
+                return;
+            }
+            let reason = match event.reason() {
+                reason if reason.is_empty() => format!("the server closed the connection (code {})", event.code()),
+                reason => format!("the server closed the connection: {}", reason),
+            };
+            failed.borrow_mut().get_or_insert(reason);
+        });
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        Ok(Self { socket, received, failure, closing, _on_message: on_message, _on_close: on_close, _on_error: on_error })
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn connection(&self) -> Connection {
+        match self.socket.ready_state() {
+            WebSocket::CONNECTING => Connection::Connecting,
+            WebSocket::OPEN => Connection::Open,
+            _ => Connection::Closed,
+        }
+    }
+
+    fn send(&self, bytes: Vec<u8>) {
+        if self.connection() == Connection::Open {
+            self.socket.send_with_u8_array(&bytes).ok();
+        }
+    }
+
+    fn receive(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.received.borrow_mut())
+    }
+
+    fn close(&self) {
+        self.closing.set(true);
+        self.socket.close().ok();
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.failure.borrow().clone()
+    }
+}
+
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+        self.socket.set_onerror(None);
+        self.close();
+    }
+}
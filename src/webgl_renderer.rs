@@ -0,0 +1,567 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use js_sys::Float32Array;
+use rgb::RGB;
+use web_sys::{
+    HtmlCanvasElement, HtmlImageElement, WebGl2RenderingContext as Gl, WebGlBuffer, WebGlFramebuffer, WebGlProgram, WebGlShader,
+    WebGlTexture, WebGlUniformLocation, WebGlVertexArrayObject,
+};
+
+use crate::Point2d;
+use crate::browser::console_log;
+use crate::batch::{color_to_f32, Batch, Primitive, QUAD_INSTANCE_SIZE, VERTEX_SIZE};
+use crate::layers::CompositeMode;
+use crate::renderer::{Fill, Renderer};
+
+const FLOAT_SIZE: i32 = 4;
+
+const COLORED_VERTEX_SHADER: &str = r#"#version 300 es
+uniform vec2 u_resolution;
+in vec2 a_position;
+in vec4 a_color;
+out vec4 v_color;
+void main() {
+    vec2 clip = a_position / u_resolution * 2.0 - 1.0;
+    gl_Position = vec4(clip * vec2(1.0, -1.0), 0.0, 1.0);
+    v_color = a_color;
+}
+"#;
+
+const QUAD_VERTEX_SHADER: &str = r#"#version 300 es
+uniform vec2 u_resolution;
+in vec2 a_corner;
+in vec4 a_rect;
+in vec4 a_color;
+out vec4 v_color;
+void main() {
+    vec2 position = a_rect.xy + a_corner * a_rect.zw;
+    vec2 clip = position / u_resolution * 2.0 - 1.0;
+    gl_Position = vec4(clip * vec2(1.0, -1.0), 0.0, 1.0);
+    v_color = a_color;
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"#version 300 es
+precision mediump float;
+in vec4 v_color;
+out vec4 out_color;
+void main() {
+    out_color = v_color;
+}
+"#;
+
+const TEXTURED_VERTEX_SHADER: &str = r#"#version 300 es
+uniform vec2 u_resolution;
+in vec2 a_position;
+in vec2 a_uv;
+in float a_alpha;
+out vec2 v_uv;
+out float v_alpha;
+void main() {
+    vec2 clip = a_position / u_resolution * 2.0 - 1.0;
+    gl_Position = vec4(clip * vec2(1.0, -1.0), 0.0, 1.0);
+    v_uv = a_uv;
+    v_alpha = a_alpha;
+}
+"#;
+
+const TEXTURED_FRAGMENT_SHADER: &str = r#"#version 300 es
+precision mediump float;
+uniform sampler2D u_texture;
+in vec2 v_uv;
+in float v_alpha;
+out vec4 out_color;
+void main() {
+    vec4 color = texture(u_texture, v_uv);
+    out_color = vec4(color.rgb, color.a * v_alpha);
+}
+"#;
+
+// x, y, u, v, alpha
+const TEXTURED_VERTEX_SIZE: usize = 5;
+
+// Unit quad as a triangle strip, scaled per instance.
+const QUAD_CORNERS: [f32; 8] = [0., 0., 1., 0., 0., 1., 1., 1.];
+
+struct Pass {
+    program: WebGlProgram,
+    vao: WebGlVertexArrayObject,
+    buffer: WebGlBuffer,
+    resolution: Option<WebGlUniformLocation>,
+}
+
+// An image added under a name. It is uploaded the first time it is drawn after it has loaded.
+struct Sprite {
+    image: HtmlImageElement,
+    texture: Option<WebGlTexture>,
+}
+
+// Textured quads waiting to be drawn, all from the same texture.
+#[derive(Default)]
+struct TexturedBatch {
+    texture: Option<WebGlTexture>,
+    vertices: Vec<f32>,
+}
+
+// What the canvas renderer and the offscreen buffers it creates share: the context, the programs
+// and the sprite textures.
+struct Shared {
+    gl: Gl,
+    lines: Pass,
+    quads: Pass,
+    triangles: Pass,
+    textured: Pass,
+    sprites: RefCell<HashMap<String, Sprite>>,
+    // Set once a buffer couldn't be made, so the warning is only logged the first time.
+    buffers_failed: Cell<bool>,
+}
+
+// The texture an offscreen buffer draws into, through its framebuffer.
+struct Target {
+    framebuffer: WebGlFramebuffer,
+    texture: WebGlTexture,
+}
+
+// Draws on the canvas, or into a texture when it is a buffer. Every renderer binds its own target
+// and blending before it draws, as buffers are filled in between the canvas's draws.
+pub struct WebGl2Renderer {
+    shared: Rc<Shared>,
+    target: Option<Target>,
+    batch: RefCell<Batch>,
+    // Drawn in turn with `batch`: only one of the two has anything in it at a time.
+    textured_batch: RefCell<TexturedBatch>,
+    size: Cell<Point2d>,
+    scale: Cell<f64>,
+    composite: Cell<CompositeMode>,
+}
+
+fn compile_shader(gl: &Gl, kind: u32, source: &str) -> Result<WebGlShader, String> {
+    let shader = gl.create_shader(kind).ok_or("Could not create shader.")?;
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+
+    if gl.get_shader_parameter(&shader, Gl::COMPILE_STATUS).as_bool().unwrap_or(false) {
+        Ok(shader)
+    } else {
+        Err(gl.get_shader_info_log(&shader).unwrap_or_default())
+    }
+}
+
+fn link_program(gl: &Gl, vertex: &str, fragment: &str) -> Result<WebGlProgram, String> {
+    let program = gl.create_program().ok_or("Could not create program.")?;
+    gl.attach_shader(&program, &compile_shader(gl, Gl::VERTEX_SHADER, vertex)?);
+    gl.attach_shader(&program, &compile_shader(gl, Gl::FRAGMENT_SHADER, fragment)?);
+    gl.link_program(&program);
+
+    if gl.get_program_parameter(&program, Gl::LINK_STATUS).as_bool().unwrap_or(false) {
+        Ok(program)
+    } else {
+        Err(gl.get_program_info_log(&program).unwrap_or_default())
+    }
+}
+
+fn attribute(gl: &Gl, program: &WebGlProgram, name: &str) -> Result<u32, String> {
+    let location = gl.get_attrib_location(program, name);
+    if location < 0 {
+        return Err(format!("Attribute {} not found.", name));
+    }
+    Ok(location as u32)
+}
+
+impl Pass {
+    fn new(gl: &Gl, vertex: &str, fragment: &str) -> Result<Self, String> {
+        let program = link_program(gl, vertex, fragment)?;
+        let vao = gl.create_vertex_array().ok_or("Could not create vertex array.")?;
+        let buffer = gl.create_buffer().ok_or("Could not create buffer.")?;
+        let resolution = gl.get_uniform_location(&program, "u_resolution");
+
+        Ok(Self { program, vao, buffer, resolution })
+    }
+
+    fn upload(&self, gl: &Gl, data: &[f32]) {
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&self.buffer));
+        gl.buffer_data_with_array_buffer_view(Gl::ARRAY_BUFFER, &Float32Array::from(data), Gl::STREAM_DRAW);
+    }
+
+    fn use_program(&self, gl: &Gl, size: Point2d) {
+        gl.use_program(Some(&self.program));
+        gl.uniform2f(self.resolution.as_ref(), size.x as f32, size.y as f32);
+        gl.bind_vertex_array(Some(&self.vao));
+    }
+}
+
+// Blending for `mode`. Buffers hold colour already multiplied by alpha, so drawing one back needs
+// the premultiplied form of the modes that scale by the source alpha.
+fn blend(gl: &Gl, mode: CompositeMode, premultiplied: bool) {
+    match (mode, premultiplied) {
+        (CompositeMode::SourceOver, false) => gl.blend_func_separate(Gl::SRC_ALPHA, Gl::ONE_MINUS_SRC_ALPHA, Gl::ONE, Gl::ONE_MINUS_SRC_ALPHA),
+        (CompositeMode::SourceOver, true) => gl.blend_func(Gl::ONE, Gl::ONE_MINUS_SRC_ALPHA),
+        (CompositeMode::Lighter, false) => gl.blend_func(Gl::SRC_ALPHA, Gl::ONE),
+        (CompositeMode::Lighter, true) => gl.blend_func(Gl::ONE, Gl::ONE),
+        (CompositeMode::Multiply, _) => gl.blend_func(Gl::DST_COLOR, Gl::ONE_MINUS_SRC_ALPHA),
+        (CompositeMode::Screen, _) => gl.blend_func(Gl::ONE, Gl::ONE_MINUS_SRC_COLOR),
+        (CompositeMode::DestinationOut, _) => gl.blend_func(Gl::ZERO, Gl::ONE_MINUS_SRC_ALPHA),
+    }
+}
+
+// A transparent texture of `width` by `height` pixels with a framebuffer to draw into it.
+fn create_target(gl: &Gl, width: i32, height: i32) -> Result<Target, String> {
+    let texture = gl.create_texture().ok_or("Could not create texture.")?;
+    gl.bind_texture(Gl::TEXTURE_2D, Some(&texture));
+    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        Gl::TEXTURE_2D, 0, Gl::RGBA as i32, width, height, 0, Gl::RGBA, Gl::UNSIGNED_BYTE, None,
+    )
+    .map_err(|_| "Could not allocate texture.")?;
+    gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_MIN_FILTER, Gl::LINEAR as i32);
+    gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_MAG_FILTER, Gl::LINEAR as i32);
+    gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_WRAP_S, Gl::CLAMP_TO_EDGE as i32);
+    gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_WRAP_T, Gl::CLAMP_TO_EDGE as i32);
+
+    let Some(framebuffer) = gl.create_framebuffer() else {
+        gl.delete_texture(Some(&texture));
+        return Err("Could not create framebuffer.".to_string());
+    };
+    gl.bind_framebuffer(Gl::FRAMEBUFFER, Some(&framebuffer));
+    gl.framebuffer_texture_2d(Gl::FRAMEBUFFER, Gl::COLOR_ATTACHMENT0, Gl::TEXTURE_2D, Some(&texture), 0);
+    let status = gl.check_framebuffer_status(Gl::FRAMEBUFFER);
+    gl.bind_framebuffer(Gl::FRAMEBUFFER, None);
+
+    if status != Gl::FRAMEBUFFER_COMPLETE {
+        gl.delete_framebuffer(Some(&framebuffer));
+        gl.delete_texture(Some(&texture));
+        return Err(format!("Framebuffer incomplete ({:#x}).", status));
+    }
+    Ok(Target { framebuffer, texture })
+}
+
+impl WebGl2Renderer {
+    pub fn new(gl: Gl) -> Result<Self, String> {
+        let lines = Pass::new(&gl, COLORED_VERTEX_SHADER, FRAGMENT_SHADER)?;
+        let quads = Pass::new(&gl, QUAD_VERTEX_SHADER, FRAGMENT_SHADER)?;
+        let triangles = Pass::new(&gl, COLORED_VERTEX_SHADER, FRAGMENT_SHADER)?;
+        let textured = Pass::new(&gl, TEXTURED_VERTEX_SHADER, TEXTURED_FRAGMENT_SHADER)?;
+
+        let shared = Shared { gl, lines, quads, triangles, textured, sprites: RefCell::new(HashMap::new()), buffers_failed: Cell::new(false) };
+        let renderer = Self::drawing_into(Rc::new(shared), None);
+        renderer.setup_quad_corners()?;
+        renderer.gl().enable(Gl::BLEND);
+
+        Ok(renderer)
+    }
+
+    fn drawing_into(shared: Rc<Shared>, target: Option<Target>) -> Self {
+        Self {
+            shared,
+            target,
+            batch: RefCell::new(Batch::default()),
+            textured_batch: RefCell::new(TexturedBatch::default()),
+            size: Cell::new(Point2d { x: 0., y: 0. }),
+            scale: Cell::new(1.),
+            composite: Cell::new(CompositeMode::SourceOver),
+        }
+    }
+
+    fn gl(&self) -> &Gl {
+        &self.shared.gl
+    }
+
+    // Points drawing at this renderer's canvas or texture, with its blending.
+    fn bind(&self, premultiplied: bool) {
+        let (gl, size, scale) = (self.gl(), self.size.get(), self.scale.get());
+        gl.bind_framebuffer(Gl::FRAMEBUFFER, self.target.as_ref().map(|target| &target.framebuffer));
+        gl.viewport(0, 0, (size.x * scale) as i32, (size.y * scale) as i32);
+        blend(gl, self.composite.get(), premultiplied);
+    }
+
+    // The corner geometry is static, so it gets its own buffer bound once into the quad VAO.
+    fn setup_quad_corners(&self) -> Result<(), String> {
+        let (gl, quads) = (self.gl(), &self.shared.quads);
+        let corner = attribute(gl, &quads.program, "a_corner")?;
+        let corners = gl.create_buffer().ok_or("Could not create buffer.")?;
+
+        gl.bind_vertex_array(Some(&quads.vao));
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&corners));
+        gl.buffer_data_with_array_buffer_view(Gl::ARRAY_BUFFER, &Float32Array::from(&QUAD_CORNERS[..]), Gl::STATIC_DRAW);
+        gl.enable_vertex_attrib_array(corner);
+        gl.vertex_attrib_pointer_with_i32(corner, 2, Gl::FLOAT, false, 0, 0);
+        gl.bind_vertex_array(None);
+
+        Ok(())
+    }
+
+    fn draw_vertices(&self, pass: &Pass, mode: u32, first: usize, count: usize) {
+        let gl = self.gl();
+        let stride = VERTEX_SIZE as i32 * FLOAT_SIZE;
+        let position = gl.get_attrib_location(&pass.program, "a_position") as u32;
+        let color = gl.get_attrib_location(&pass.program, "a_color") as u32;
+
+        pass.use_program(gl, self.size.get());
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&pass.buffer));
+        gl.enable_vertex_attrib_array(position);
+        gl.vertex_attrib_pointer_with_i32(position, 2, Gl::FLOAT, false, stride, 0);
+        gl.enable_vertex_attrib_array(color);
+        gl.vertex_attrib_pointer_with_i32(color, 4, Gl::FLOAT, false, stride, 2 * FLOAT_SIZE);
+        gl.draw_arrays(mode, first as i32, count as i32);
+    }
+
+    // WebGL2 has no base instance, so each run re-points the instance attributes at its offset.
+    fn draw_quads(&self, start: usize, count: usize) {
+        let (gl, quads) = (self.gl(), &self.shared.quads);
+        let stride = QUAD_INSTANCE_SIZE as i32 * FLOAT_SIZE;
+        let offset = start as i32 * stride;
+        let rect = gl.get_attrib_location(&quads.program, "a_rect") as u32;
+        let color = gl.get_attrib_location(&quads.program, "a_color") as u32;
+
+        quads.use_program(gl, self.size.get());
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&quads.buffer));
+        gl.enable_vertex_attrib_array(rect);
+        gl.vertex_attrib_pointer_with_i32(rect, 4, Gl::FLOAT, false, stride, offset);
+        gl.vertex_attrib_divisor(rect, 1);
+        gl.enable_vertex_attrib_array(color);
+        gl.vertex_attrib_pointer_with_i32(color, 4, Gl::FLOAT, false, stride, offset + 4 * FLOAT_SIZE);
+        gl.vertex_attrib_divisor(color, 1);
+        gl.draw_arrays_instanced(Gl::TRIANGLE_STRIP, 0, 4, count as i32);
+    }
+
+    // The texture for `name`, uploading the image first if it has only just finished loading.
+    fn texture(&self, name: &str) -> Option<(WebGlTexture, Point2d)> {
+        let mut sprites = self.shared.sprites.borrow_mut();
+        let sprite = sprites.get_mut(name)?;
+        let size = Point2d { x: sprite.image.natural_width() as f64, y: sprite.image.natural_height() as f64 };
+        if let Some(texture) = &sprite.texture {
+            return Some((texture.clone(), size));
+        }
+        if !sprite.image.complete() || size.x == 0. || size.y == 0. {
+            return None;
+        }
+
+        let gl = self.gl();
+        let texture = gl.create_texture()?;
+        gl.bind_texture(Gl::TEXTURE_2D, Some(&texture));
+        gl.tex_image_2d_with_u32_and_u32_and_html_image_element(Gl::TEXTURE_2D, 0, Gl::RGBA as i32, Gl::RGBA, Gl::UNSIGNED_BYTE, &sprite.image)
+            .ok()?;
+        gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_MIN_FILTER, Gl::LINEAR as i32);
+        gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_MAG_FILTER, Gl::LINEAR as i32);
+        gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_WRAP_S, Gl::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_WRAP_T, Gl::CLAMP_TO_EDGE as i32);
+        sprite.texture = Some(texture.clone());
+        Some((texture, size))
+    }
+
+    // Queues a textured quad after whatever was batched before it. `corners` and `uv` go around the
+    // quad in triangle strip order.
+    fn push_textured(&self, texture: WebGlTexture, corners: [Point2d; 4], uv: [Point2d; 4], alpha: f64) {
+        self.flush_batch();
+        if self.textured_batch.borrow().texture.as_ref().is_some_and(|batched| *batched != texture) {
+            self.flush_textured();
+        }
+
+        let mut textured = self.textured_batch.borrow_mut();
+        textured.texture = Some(texture);
+        let alpha = alpha.clamp(0., 1.) as f32;
+        for corner in [0, 1, 2, 2, 1, 3] {
+            let (position, uv) = (corners[corner], uv[corner]);
+            textured.vertices.extend_from_slice(&[position.x as f32, position.y as f32, uv.x as f32, uv.y as f32, alpha]);
+        }
+    }
+
+    fn flush_textured(&self) {
+        self.draw_textured(false);
+    }
+
+    fn draw_textured(&self, premultiplied: bool) {
+        let mut textured = self.textured_batch.borrow_mut();
+        let Some(texture) = textured.texture.take() else { return };
+
+        self.bind(premultiplied);
+        let (gl, pass) = (self.gl(), &self.shared.textured);
+        let stride = TEXTURED_VERTEX_SIZE as i32 * FLOAT_SIZE;
+        let position = gl.get_attrib_location(&pass.program, "a_position") as u32;
+        let coordinates = gl.get_attrib_location(&pass.program, "a_uv") as u32;
+        let alpha = gl.get_attrib_location(&pass.program, "a_alpha") as u32;
+
+        pass.use_program(gl, self.size.get());
+        pass.upload(gl, &textured.vertices);
+        gl.enable_vertex_attrib_array(position);
+        gl.vertex_attrib_pointer_with_i32(position, 2, Gl::FLOAT, false, stride, 0);
+        gl.enable_vertex_attrib_array(coordinates);
+        gl.vertex_attrib_pointer_with_i32(coordinates, 2, Gl::FLOAT, false, stride, 2 * FLOAT_SIZE);
+        gl.enable_vertex_attrib_array(alpha);
+        gl.vertex_attrib_pointer_with_i32(alpha, 1, Gl::FLOAT, false, stride, 4 * FLOAT_SIZE);
+
+        gl.active_texture(Gl::TEXTURE0);
+        gl.bind_texture(Gl::TEXTURE_2D, Some(&texture));
+        gl.uniform1i(gl.get_uniform_location(&pass.program, "u_texture").as_ref(), 0);
+        gl.draw_arrays(Gl::TRIANGLES, 0, (textured.vertices.len() / TEXTURED_VERTEX_SIZE) as i32);
+        gl.bind_vertex_array(None);
+        textured.vertices.clear();
+    }
+
+    fn flush_batch(&self) {
+        let mut batch = self.batch.borrow_mut();
+        if batch.is_empty() {
+            return;
+        }
+
+        self.bind(false);
+        let shared = &self.shared;
+        shared.lines.upload(&shared.gl, batch.line_vertices());
+        shared.quads.upload(&shared.gl, batch.quad_instances());
+        shared.triangles.upload(&shared.gl, batch.triangle_vertices());
+
+        for run in batch.runs() {
+            match run.primitive {
+                Primitive::Lines => self.draw_vertices(&shared.lines, Gl::LINES, run.start * 2, run.count * 2),
+                Primitive::Quads => self.draw_quads(run.start, run.count),
+                Primitive::Triangles => self.draw_vertices(&shared.triangles, Gl::TRIANGLES, run.start * 3, run.count * 3),
+            }
+        }
+
+        shared.gl.bind_vertex_array(None);
+        batch.clear();
+    }
+}
+
+impl Renderer for WebGl2Renderer {
+    fn set_scale(&self, scale: f64) {
+        self.scale.set(scale);
+    }
+
+    // Geometry stays in CSS pixels; only the viewport covers the scaled backing store.
+    fn clear(&self, size: Point2d) {
+        self.size.set(size);
+        self.batch.borrow_mut().clear();
+        *self.textured_batch.borrow_mut() = TexturedBatch::default();
+
+        self.bind(false);
+        self.gl().clear_color(0., 0., 0., 0.);
+        self.gl().clear(Gl::COLOR_BUFFER_BIT);
+    }
+
+    fn line(&self, from: Point2d, to: Point2d, color: RGB<u8>) {
+        self.flush_textured();
+        self.batch.borrow_mut().push_line(from, to, color, 1.0);
+    }
+
+    fn circle(&self, center: Point2d, radius: f64, color: RGB<u8>) {
+        self.flush_textured();
+        self.batch.borrow_mut().push_circle(center, radius, color, 1.0);
+    }
+
+    fn rect(&self, position: Point2d, size: Point2d, color: RGB<u8>, alpha: f64) {
+        self.flush_textured();
+        self.batch.borrow_mut().push_rect(position, size, color, alpha);
+    }
+
+    fn fill_polygon(&self, points: &[Point2d], fill: &Fill) {
+        if points.len() < 3 {
+            return;
+        }
+
+        let vertex = |point: Point2d| (point, color_to_f32(fill.color(), fill.alpha_at(point)));
+        let (hub, rim) = match fill {
+            Fill::Radial { center, .. } => (*center, points),
+            Fill::Solid { .. } => (points[0], &points[1..]),
+        };
+        let rim: Vec<(Point2d, [f32; 4])> = rim.iter().map(|point| vertex(*point)).collect();
+
+        self.flush_textured();
+        self.batch.borrow_mut().push_fan(vertex(hub), &rim);
+    }
+
+    // Blending applies at draw time, so pending geometry is flushed with the previous mode first.
+    fn set_composite(&self, mode: CompositeMode) {
+        self.flush();
+        self.composite.set(mode);
+    }
+
+    fn create_buffer(&self, size: Point2d) -> Option<Box<dyn Renderer>> {
+        let scale = self.scale.get();
+        match create_target(self.gl(), (size.x * scale) as i32, (size.y * scale) as i32) {
+            Ok(target) => {
+                let buffer = Self::drawing_into(self.shared.clone(), Some(target));
+                buffer.set_scale(scale);
+                buffer.size.set(size);
+                Some(Box::new(buffer))
+            }
+            Err(error) => {
+                if !self.shared.buffers_failed.replace(true) {
+                    console_log(&format!("WebGL2 offscreen buffers unavailable, so cached layers redraw every frame and the light map is left out: {}", error));
+                }
+                None
+            }
+        }
+    }
+
+    // Covers the canvas with the buffer's texture, which is upside down as GL stores it.
+    fn draw_buffer(&self, buffer: &dyn Renderer) {
+        let Some(texture) = buffer.buffer_texture() else { return };
+        self.flush();
+
+        let size = self.size.get();
+        let corners = [Point2d { x: 0., y: 0. }, Point2d { x: size.x, y: 0. }, Point2d { x: 0., y: size.y }, Point2d { x: size.x, y: size.y }];
+        let uv = [Point2d { x: 0., y: 1. }, Point2d { x: 1., y: 1. }, Point2d { x: 0., y: 0. }, Point2d { x: 1., y: 0. }];
+        self.push_textured(texture.clone(), corners, uv, 1.);
+        self.draw_textured(true);
+    }
+
+    fn buffer_texture(&self) -> Option<&WebGlTexture> {
+        self.target.as_ref().map(|target| &target.texture)
+    }
+
+    fn surface(&self) -> Option<&HtmlCanvasElement> {
+        None
+    }
+
+    fn sprite(&self, name: &str, center: Point2d, size: f64, rotation: f64, alpha: f64) -> bool {
+        let Some((texture, _)) = self.texture(name) else { return false };
+
+        let corner = |x: f64, y: f64| center + (Point2d { x, y } * (size / 2.)).rotate(rotation);
+        let corners = [corner(-1., -1.), corner(1., -1.), corner(-1., 1.), corner(1., 1.)];
+        let uv = [Point2d { x: 0., y: 0. }, Point2d { x: 1., y: 0. }, Point2d { x: 0., y: 1. }, Point2d { x: 1., y: 1. }];
+        self.push_textured(texture, corners, uv, alpha);
+        true
+    }
+
+    fn tile(&self, name: &str, source: Point2d, source_size: Point2d, position: Point2d, size: Point2d, alpha: f64) -> bool {
+        let Some((texture, image)) = self.texture(name) else { return false };
+
+        let corner = |x: f64, y: f64| Point2d { x: position.x + x * size.x, y: position.y + y * size.y };
+        let uv = |x: f64, y: f64| Point2d { x: (source.x + x * source_size.x) / image.x, y: (source.y + y * source_size.y) / image.y };
+        let corners = [corner(0., 0.), corner(1., 0.), corner(0., 1.), corner(1., 1.)];
+        self.push_textured(texture, corners, [uv(0., 0.), uv(1., 0.), uv(0., 1.), uv(1., 1.)], alpha);
+        true
+    }
+
+    fn add_sprite(&self, name: &str, image: HtmlImageElement) {
+        let replaced = self.shared.sprites.borrow_mut().insert(name.to_string(), Sprite { image, texture: None });
+        if let Some(texture) = replaced.and_then(|sprite| sprite.texture) {
+            self.flush_textured();
+            self.gl().delete_texture(Some(&texture));
+        }
+    }
+
+    fn remove_sprite(&self, name: &str) {
+        let removed = self.shared.sprites.borrow_mut().remove(name);
+        if let Some(texture) = removed.and_then(|sprite| sprite.texture) {
+            self.flush_textured();
+            self.gl().delete_texture(Some(&texture));
+        }
+    }
+
+    fn flush(&self) {
+        self.flush_batch();
+        self.flush_textured();
+    }
+}
+
+// Buffers are dropped when the canvas is resized, and take their texture with them.
+impl Drop for WebGl2Renderer {
+    fn drop(&mut self) {
+        if let Some(target) = self.target.take() {
+            self.gl().delete_framebuffer(Some(&target.framebuffer));
+            self.gl().delete_texture(Some(&target.texture));
+        }
+    }
+}